@@ -0,0 +1,24 @@
+#![no_main]
+
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::{full_table_scan, read_record, Page, SqliteSchema};
+use codecrafters_sqlite::pager::Pager;
+use libfuzzer_sys::fuzz_target;
+
+// Any input may be rejected, but none may panic, hang or blow the stack.
+fuzz_target!(|data: &[u8]| {
+    let Ok(pager) = Pager::from_bytes(data.to_vec()) else {
+        return;
+    };
+    if let Ok(page) = Page::from_pager(&pager, 1) {
+        for i in 0..page.pointer_array.len() {
+            let _ = read_record(&pager, &page, i, 1);
+        }
+        let _ = SqliteSchema::from_page(&pager, &page);
+    }
+    let page_count = pager.page_count().unwrap_or(0).min(64);
+    for n in 1..=page_count {
+        let _ = full_table_scan(&pager, n);
+    }
+    let _ = integrity_check(&pager);
+});
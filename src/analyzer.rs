@@ -0,0 +1,229 @@
+use crate::error::{Error, Result};
+use crate::page_io::{
+    full_table_scan, read_freelist, OverflowPage, Page, SqlSchemaElement, MAX_BTREE_DEPTH,
+};
+use crate::pager::Pager;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    TableInterior,
+    TableLeaf,
+    IndexInterior,
+    IndexLeaf,
+    Overflow,
+    FreelistTrunk,
+    FreelistLeaf,
+    PointerMap,
+    LockByte,
+    /// Not reachable from the schema or the freelist.
+    Orphan,
+}
+
+impl PageKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PageKind::TableInterior => "table interior",
+            PageKind::TableLeaf => "table leaf",
+            PageKind::IndexInterior => "index interior",
+            PageKind::IndexLeaf => "index leaf",
+            PageKind::Overflow => "overflow",
+            PageKind::FreelistTrunk => "freelist trunk",
+            PageKind::FreelistLeaf => "freelist leaf",
+            PageKind::PointerMap => "pointer-map",
+            PageKind::LockByte => "lock-byte",
+            PageKind::Orphan => "orphan",
+        }
+    }
+}
+
+/// Space used by one table or index, like a section of `sqlite3_analyzer` output.
+#[derive(Debug, Default)]
+pub struct ObjectUsage {
+    pub name: String,
+    pub is_index: bool,
+    pub interior_pages: u64,
+    pub leaf_pages: u64,
+    pub overflow_pages: u64,
+    pub entries: u64,
+    pub payload_bytes: u64,
+    /// Bytes on the object's pages that hold neither cells nor page headers.
+    pub unused_bytes: u64,
+}
+
+impl ObjectUsage {
+    pub fn pages(&self) -> u64 {
+        self.interior_pages + self.leaf_pages + self.overflow_pages
+    }
+
+    /// Percentage of the object's usable page space that is in use.
+    pub fn fill_factor(&self, usable_size: usize) -> f64 {
+        let total = self.pages() * usable_size as u64;
+        if total == 0 {
+            return 0.0;
+        }
+        100.0 * (total - self.unused_bytes) as f64 / total as f64
+    }
+}
+
+#[derive(Debug)]
+pub struct PageUsage {
+    pub usable_size: usize,
+    /// Classification of every page in the file; page N is at index N - 1.
+    pub pages: Vec<PageKind>,
+    pub objects: Vec<ObjectUsage>,
+}
+
+impl PageUsage {
+    pub fn count(&self, kind: PageKind) -> u64 {
+        self.pages.iter().filter(|k| **k == kind).count() as u64
+    }
+
+    /// Share of the file sitting on the freelist, i.e. what a VACUUM would give back.
+    pub fn free_percent(&self) -> f64 {
+        if self.pages.is_empty() {
+            return 0.0;
+        }
+        let free = self.count(PageKind::FreelistTrunk) + self.count(PageKind::FreelistLeaf);
+        100.0 * free as f64 / self.pages.len() as f64
+    }
+}
+
+/// Classifies every page in the file and totals up the space used by each table and index.
+pub fn page_usage(pager: &Pager) -> Result<PageUsage> {
+    let page_count = pager.page_count()?;
+    let mut analyzer = Analyzer {
+        pager,
+        usable_size: pager.header().usable_size(),
+        pages: vec![None; page_count as usize],
+    };
+
+    for trunk in read_freelist(pager)? {
+        analyzer.classify(trunk.page_number, PageKind::FreelistTrunk)?;
+        for leaf in trunk.leaves {
+            analyzer.classify(leaf as u64, PageKind::FreelistLeaf)?;
+        }
+    }
+    let header = pager.header();
+    for page in header.ptrmap_pages(page_count) {
+        analyzer.classify(page, PageKind::PointerMap)?;
+    }
+    if header.pending_byte_page() <= page_count {
+        analyzer.classify(header.pending_byte_page(), PageKind::LockByte)?;
+    }
+
+    let mut objects = vec![ObjectUsage {
+        name: "sqlite_schema".to_string(),
+        ..Default::default()
+    }];
+    analyzer.walk(1, 0, &mut objects[0])?;
+    for row in full_table_scan(pager, 1)? {
+        let element = SqlSchemaElement::from_row(row)?;
+        if element.rootpage == 0 {
+            continue;
+        }
+        let mut usage = ObjectUsage {
+            name: element.name,
+            is_index: element.element_type == "index",
+            ..Default::default()
+        };
+        analyzer.walk(element.rootpage, 0, &mut usage)?;
+        objects.push(usage);
+    }
+
+    Ok(PageUsage {
+        usable_size: analyzer.usable_size,
+        pages: analyzer
+            .pages
+            .into_iter()
+            .map(|kind| kind.unwrap_or(PageKind::Orphan))
+            .collect(),
+        objects,
+    })
+}
+
+struct Analyzer<'a> {
+    pager: &'a Pager,
+    usable_size: usize,
+    pages: Vec<Option<PageKind>>,
+}
+
+impl Analyzer<'_> {
+    fn classify(&mut self, page_number: u64, kind: PageKind) -> Result<()> {
+        let slot = page_number
+            .checked_sub(1)
+            .and_then(|i| self.pages.get_mut(i as usize))
+            .ok_or_else(|| Error::corrupt_page(page_number, "page number out of range"))?;
+        if slot.is_some() {
+            return Err(Error::corrupt_page(
+                page_number,
+                "page is referenced more than once",
+            ));
+        }
+        *slot = Some(kind);
+        Ok(())
+    }
+
+    fn walk(&mut self, page_number: u64, depth: usize, usage: &mut ObjectUsage) -> Result<()> {
+        if depth > MAX_BTREE_DEPTH {
+            return Err(Error::corrupt_page(page_number, "b-tree is too deep"));
+        }
+        let page = Page::from_pager(self.pager, page_number)?;
+        let kind = match page.header.page_type {
+            0x05 => PageKind::TableInterior,
+            0x0d => PageKind::TableLeaf,
+            0x02 => PageKind::IndexInterior,
+            _ => PageKind::IndexLeaf,
+        };
+        self.classify(page_number, kind)?;
+        if page.header.is_interior() {
+            usage.interior_pages += 1;
+        } else {
+            usage.leaf_pages += 1;
+        }
+
+        let header_start = if page_number == 1 { 100 } else { 0 };
+        let mut used = header_start + page.header.size() + page.pointer_array.len() * 2;
+        for i in 0..page.pointer_array.len() {
+            let cell = page.cell_info(i, self.usable_size)?;
+            used += cell.size;
+            // Table interior cells only hold a key; everything else is an entry with a payload.
+            if kind != PageKind::TableInterior {
+                usage.entries += 1;
+                usage.payload_bytes += cell.payload_size;
+            }
+            if let Some(overflow_page) = cell.overflow_page {
+                let spilled = cell.payload_size as usize - cell.local_size;
+                self.walk_overflow(overflow_page as u64, spilled, usage)?;
+            }
+        }
+        usage.unused_bytes += self.usable_size.saturating_sub(used) as u64;
+
+        if page.header.is_interior() {
+            for i in 0..page.pointer_array.len() {
+                self.walk(page.left_child(i) as u64, depth + 1, usage)?;
+            }
+            self.walk(page.right_child()? as u64, depth + 1, usage)?;
+        }
+        Ok(())
+    }
+
+    fn walk_overflow(
+        &mut self,
+        page_number: u64,
+        spilled: usize,
+        usage: &mut ObjectUsage,
+    ) -> Result<()> {
+        let content_size = self.usable_size - 4;
+        let mut page_number = page_number;
+        let mut remaining = spilled;
+        while remaining > 0 {
+            self.classify(page_number, PageKind::Overflow)?;
+            usage.overflow_pages += 1;
+            let take = remaining.min(content_size);
+            usage.unused_bytes += (content_size - take) as u64;
+            remaining -= take;
+            page_number = OverflowPage::from_pager(self.pager, page_number)?.next_page() as u64;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,966 @@
+use crate::error::{Error, Result};
+use crate::page_io::{
+    local_payload_size, read_overflow, KeyOrder, OverflowPage, Page, MAX_BTREE_DEPTH,
+};
+use crate::pager::Pager;
+use crate::typecodes::{decode_record, encode_record, SqlValue};
+use crate::utils::{decode_varint_checked, encode_varint};
+use std::cmp::Ordering;
+use std::ops::Range;
+
+const INDEX_INTERIOR: u8 = 0x02;
+const TABLE_INTERIOR: u8 = 0x05;
+const INDEX_LEAF: u8 = 0x0a;
+const TABLE_LEAF: u8 = 0x0d;
+
+/// The interior pages passed through on the way down from the root, each with the child slot
+/// that was followed from it.
+type Path = Vec<(u64, usize)>;
+
+/// The cells of a b-tree page, copied out so they can be rearranged and written back to the
+/// same page or spread over several.
+#[derive(Debug, Clone)]
+struct Node {
+    page_type: u8,
+    /// Raw cell bytes, each at least four bytes long.
+    cells: Vec<Vec<u8>>,
+    right_child: Option<u32>,
+}
+
+impl Node {
+    fn read(pager: &Pager, page_number: u64) -> Result<Node> {
+        let page = Page::from_pager(pager, page_number)?;
+        let usable_size = pager.header().usable_size();
+        let mut cells = Vec::with_capacity(page.pointer_array.len());
+        for (i, pointer) in page.pointer_array.iter().enumerate() {
+            let start = *pointer as usize;
+            let size = page.cell_info(i, usable_size)?.size;
+            let cell = page.data.get(start..start + size).ok_or_else(|| {
+                Error::corrupt_page(page_number, "cell runs past the end of the page")
+            })?;
+            cells.push(cell.to_vec());
+        }
+        Ok(Node {
+            page_type: page.header.page_type,
+            cells,
+            right_child: page.header.rightmost_pointer,
+        })
+    }
+
+    fn is_interior(&self) -> bool {
+        self.page_type & 0x08 == 0
+    }
+
+    fn header_size(&self) -> usize {
+        if self.is_interior() {
+            12
+        } else {
+            8
+        }
+    }
+
+    /// Bytes the node needs on a page, not counting the database header on page 1.
+    fn size(&self) -> usize {
+        self.header_size() + self.cells.iter().map(|c| c.len() + 2).sum::<usize>()
+    }
+
+    /// Points the child slot at `index` (the right child when `index` is past the last cell)
+    /// at a different page.
+    fn set_child(&mut self, index: usize, page_number: u32) {
+        match self.cells.get_mut(index) {
+            Some(cell) => cell[..4].copy_from_slice(&page_number.to_be_bytes()),
+            None => self.right_child = Some(page_number),
+        }
+    }
+
+    fn child(&self, index: usize) -> Result<u32> {
+        match self.cells.get(index) {
+            Some(cell) => Ok(u32::from_be_bytes(cell[..4].try_into().unwrap())),
+            None => self
+                .right_child
+                .ok_or_else(|| Error::Corrupt("leaf page has no right child".to_string())),
+        }
+    }
+
+    /// Lays the cells out from the end of the usable area down, leaving no free blocks.
+    fn write(&self, pager: &Pager, page_number: u64) -> Result<()> {
+        let usable_size = pager.header().usable_size();
+        let header_start = if page_number == 1 { 100 } else { 0 };
+        if header_start + self.size() > usable_size {
+            return Err(Error::corrupt_page(
+                page_number,
+                "cells do not fit on the page",
+            ));
+        }
+        let mut data = vec![0; pager.page_size()];
+        if page_number == 1 {
+            data[..100].copy_from_slice(&pager.get_page(1)?[..100]);
+        }
+        let mut content_start = usable_size;
+        let mut pointer = header_start + self.header_size();
+        for cell in &self.cells {
+            content_start -= cell.len();
+            data[content_start..content_start + cell.len()].copy_from_slice(cell);
+            data[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+            pointer += 2;
+        }
+        let header = &mut data[header_start..];
+        header[0] = self.page_type;
+        header[3..5].copy_from_slice(&(self.cells.len() as u16).to_be_bytes());
+        // A content area starting at 65536 is written as 0.
+        header[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+        if let Some(right_child) = self.right_child {
+            header[8..12].copy_from_slice(&right_child.to_be_bytes());
+        }
+        pager.write_page(page_number, data)
+    }
+}
+
+/// Rowid of a table b-tree cell, from either a leaf or an interior page.
+fn cell_rowid(page_type: u8, cell: &[u8]) -> Result<i64> {
+    let truncated = || Error::Corrupt("truncated varint in cell".to_string());
+    let rowid_start = match page_type {
+        TABLE_INTERIOR => 4,
+        _ => decode_varint_checked(cell).ok_or_else(truncated)?.1,
+    };
+    let (rowid, _) = cell
+        .get(rowid_start..)
+        .and_then(decode_varint_checked)
+        .ok_or_else(truncated)?;
+    Ok(rowid as i64)
+}
+
+/// Builds a leaf cell for `payload`, spilling whatever does not fit locally onto new overflow
+/// pages. Table leaf cells carry a rowid; index leaf cells do not.
+fn leaf_cell(pager: &Pager, rowid: Option<i64>, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut cell = encode_varint(payload.len() as u64);
+    if let Some(rowid) = rowid {
+        cell.extend(encode_varint(rowid as u64));
+    }
+    let local_size =
+        local_payload_size(pager.header().usable_size(), payload.len(), rowid.is_some());
+    cell.extend_from_slice(&payload[..local_size]);
+    if local_size < payload.len() {
+        let first_page = write_overflow(pager, &payload[local_size..])?;
+        cell.extend_from_slice(&(first_page as u32).to_be_bytes());
+    }
+    // SQLite never allocates less than four bytes for a cell.
+    cell.resize(cell.len().max(4), 0);
+    Ok(cell)
+}
+
+/// Where a cell's payload starts, its full size, and how much of it is on the page.
+fn payload_layout(pager: &Pager, page_type: u8, cell: &[u8]) -> Result<(usize, usize, usize)> {
+    let truncated = || Error::Corrupt("truncated varint in cell".to_string());
+    let varint_at = |offset: usize| {
+        cell.get(offset..)
+            .and_then(decode_varint_checked)
+            .ok_or_else(truncated)
+    };
+    let (size_offset, skip_rowid) = match page_type {
+        TABLE_INTERIOR => return Ok((0, 0, 0)),
+        TABLE_LEAF => (0, true),
+        INDEX_LEAF => (0, false),
+        _ => (4, false),
+    };
+    let (payload_size, size_len) = varint_at(size_offset)?;
+    let mut start = size_offset + size_len;
+    if skip_rowid {
+        start += varint_at(start)?.1;
+    }
+    let payload_size = payload_size as usize;
+    let local_size = local_payload_size(
+        pager.header().usable_size(),
+        payload_size,
+        page_type == TABLE_LEAF,
+    );
+    Ok((start, payload_size, local_size))
+}
+
+/// The full payload of a cell, with any overflow read back in.
+fn cell_payload(pager: &Pager, page_type: u8, cell: &[u8]) -> Result<Vec<u8>> {
+    let (start, payload_size, local_size) = payload_layout(pager, page_type, cell)?;
+    let end = start + local_size;
+    let mut payload = cell
+        .get(start..end)
+        .ok_or_else(|| Error::Corrupt("cell runs past the end of the page".to_string()))?
+        .to_vec();
+    if local_size < payload_size {
+        let overflow_page = cell.get(end..end + 4).ok_or_else(|| {
+            Error::Corrupt("overflow pointer runs past the end of the page".to_string())
+        })?;
+        let overflow_page = u32::from_be_bytes(overflow_page.try_into().unwrap());
+        payload.extend(read_overflow(
+            pager,
+            overflow_page as u64,
+            payload_size - local_size,
+        )?);
+    }
+    Ok(payload)
+}
+
+/// Key of an index cell: the indexed values followed by the rowid.
+fn index_key(pager: &Pager, page_type: u8, cell: &[u8]) -> Result<Vec<SqlValue>> {
+    let payload = cell_payload(pager, page_type, cell)?;
+    decode_record(&payload, pager.header().text_encoding)
+}
+
+/// Returns the overflow pages of a cell that is being dropped to the freelist.
+fn free_overflow(pager: &Pager, page_type: u8, cell: &[u8]) -> Result<()> {
+    let (start, payload_size, local_size) = payload_layout(pager, page_type, cell)?;
+    if local_size >= payload_size {
+        return Ok(());
+    }
+    let end = start + local_size;
+    let mut page_number = cell
+        .get(end..end + 4)
+        .map(|p| u32::from_be_bytes(p.try_into().unwrap()) as u64)
+        .ok_or_else(|| {
+            Error::Corrupt("overflow pointer runs past the end of the page".to_string())
+        })?;
+    let content_size = pager.header().usable_size() - 4;
+    for _ in 0..(payload_size - local_size).div_ceil(content_size) {
+        let next_page = OverflowPage::from_pager(pager, page_number)?.next_page();
+        pager.free_page(page_number)?;
+        page_number = next_page as u64;
+    }
+    Ok(())
+}
+
+/// Writes a chain of overflow pages holding `data` and returns the first page number.
+fn write_overflow(pager: &Pager, data: &[u8]) -> Result<u64> {
+    let content_size = pager.header().usable_size() - 4;
+    let mut pages = Vec::new();
+    for _ in data.chunks(content_size) {
+        pages.push(pager.allocate_page()?);
+    }
+    for (i, chunk) in data.chunks(content_size).enumerate() {
+        let next_page = pages.get(i + 1).copied().unwrap_or(0) as u32;
+        let mut page = vec![0; pager.page_size()];
+        page[..4].copy_from_slice(&next_page.to_be_bytes());
+        page[4..4 + chunk.len()].copy_from_slice(chunk);
+        pager.write_page(pages[i], page)?;
+    }
+    Ok(pages[0])
+}
+
+/// Allocates the root page of a new, empty b-tree: a table b-tree, or an index b-tree if
+/// `index` is set. Returns the root page number.
+pub fn create_btree(pager: &Pager, index: bool) -> Result<u64> {
+    let root = pager.allocate_page()?;
+    let node = Node {
+        page_type: if index { INDEX_LEAF } else { TABLE_LEAF },
+        cells: Vec::new(),
+        right_child: None,
+    };
+    node.write(pager, root)?;
+    Ok(root)
+}
+
+/// Largest rowid in the table b-tree rooted at `root`, or None if the table is empty.
+pub fn max_rowid(pager: &Pager, root: u64) -> Result<Option<i64>> {
+    let mut page_number = root;
+    for _ in 0..=MAX_BTREE_DEPTH {
+        let node = Node::read(pager, page_number)?;
+        if !node.is_interior() {
+            return match node.cells.last() {
+                Some(cell) => cell_rowid(node.page_type, cell).map(Some),
+                None => Ok(None),
+            };
+        }
+        page_number = node.child(node.cells.len())? as u64;
+    }
+    Err(Error::corrupt_page(page_number, "b-tree is too deep"))
+}
+
+/// Where a search of a b-tree stopped: the interior pages passed through on the way, and the
+/// page that holds the key or would hold it if it were inserted.
+struct Seek {
+    path: Path,
+    page_number: u64,
+    node: Node,
+    /// The cell holding the key, or where on the (leaf) page it would go.
+    index: std::result::Result<usize, usize>,
+}
+
+fn seek_row(pager: &Pager, root: u64, rowid: i64) -> Result<Seek> {
+    let mut path = Vec::new();
+    let mut page_number = root;
+    let mut node = Node::read(pager, page_number)?;
+    while node.is_interior() {
+        if path.len() >= MAX_BTREE_DEPTH {
+            return Err(Error::corrupt_page(page_number, "b-tree is too deep"));
+        }
+        let mut index = node.cells.len();
+        for (i, cell) in node.cells.iter().enumerate() {
+            if cell_rowid(node.page_type, cell)? >= rowid {
+                index = i;
+                break;
+            }
+        }
+        let child = node.child(index)? as u64;
+        path.push((page_number, index));
+        page_number = child;
+        node = Node::read(pager, page_number)?;
+    }
+    if node.page_type != TABLE_LEAF {
+        return Err(Error::corrupt_page(page_number, "not a table b-tree page"));
+    }
+
+    let mut index = Err(node.cells.len());
+    for (i, cell) in node.cells.iter().enumerate() {
+        match cell_rowid(node.page_type, cell)?.cmp(&rowid) {
+            Ordering::Less => continue,
+            Ordering::Equal => index = Ok(i),
+            Ordering::Greater => index = Err(i),
+        }
+        break;
+    }
+    Ok(Seek {
+        path,
+        page_number,
+        node,
+        index,
+    })
+}
+
+/// Adds a row to the table b-tree rooted at `root`, splitting pages on the way back up as
+/// needed. `payload` is an encoded record.
+pub fn insert_row(pager: &Pager, root: u64, rowid: i64, payload: &[u8]) -> Result<()> {
+    let Seek {
+        path,
+        page_number,
+        mut node,
+        index,
+    } = seek_row(pager, root, rowid)?;
+    let Err(position) = index else {
+        return Err(Error::Constraint(format!("rowid {} already exists", rowid)));
+    };
+    let change = if position == node.cells.len() {
+        Change::Appended
+    } else {
+        Change::Inserted
+    };
+    node.cells
+        .insert(position, leaf_cell(pager, Some(rowid), payload)?);
+    balance(pager, path, page_number, node, change)
+}
+
+/// Removes a row from the table b-tree rooted at `root`, returning whether it was there.
+pub fn delete_row(pager: &Pager, root: u64, rowid: i64) -> Result<bool> {
+    let Seek {
+        path,
+        page_number,
+        node,
+        index,
+    } = seek_row(pager, root, rowid)?;
+    let Ok(index) = index else {
+        return Ok(false);
+    };
+    free_overflow(pager, node.page_type, &node.cells[index])?;
+    remove_leaf_cell(pager, path, page_number, node, index)?;
+    Ok(true)
+}
+
+fn seek_index_entry(
+    pager: &Pager,
+    root: u64,
+    entry: &[SqlValue],
+    order: &KeyOrder,
+) -> Result<Seek> {
+    let encoding = pager.header().text_encoding;
+    let mut path = Vec::new();
+    let mut page_number = root;
+    let mut node = Node::read(pager, page_number)?;
+    loop {
+        if !matches!(node.page_type, INDEX_INTERIOR | INDEX_LEAF) {
+            return Err(Error::corrupt_page(page_number, "not an index b-tree page"));
+        }
+        let mut index = Err(node.cells.len());
+        for (i, cell) in node.cells.iter().enumerate() {
+            let key = index_key(pager, node.page_type, cell)?;
+            match order.compare(&key, entry, encoding) {
+                Ordering::Less => continue,
+                Ordering::Equal => index = Ok(i),
+                Ordering::Greater => index = Err(i),
+            }
+            break;
+        }
+        let child_index = match index {
+            Err(child_index) if node.is_interior() => child_index,
+            _ => {
+                return Ok(Seek {
+                    path,
+                    page_number,
+                    node,
+                    index,
+                })
+            }
+        };
+        if path.len() >= MAX_BTREE_DEPTH {
+            return Err(Error::corrupt_page(page_number, "b-tree is too deep"));
+        }
+        let child = node.child(child_index)? as u64;
+        path.push((page_number, child_index));
+        page_number = child;
+        node = Node::read(pager, page_number)?;
+    }
+}
+
+/// Adds an entry (the indexed values followed by the rowid) to the index b-tree rooted at
+/// `root`, whose entries are in `order`, splitting pages on the way back up as needed.
+pub fn insert_index_entry(
+    pager: &Pager,
+    root: u64,
+    entry: &[SqlValue],
+    order: &KeyOrder,
+) -> Result<()> {
+    let Seek {
+        path,
+        page_number,
+        mut node,
+        index,
+    } = seek_index_entry(pager, root, entry, order)?;
+    let Err(index) = index else {
+        return Err(Error::Constraint("index entry already exists".to_string()));
+    };
+    let header = pager.header();
+    let payload = encode_record(entry, header.text_encoding, header.schema_format);
+    node.cells.insert(index, leaf_cell(pager, None, &payload)?);
+    balance(pager, path, page_number, node, Change::Inserted)
+}
+
+/// Removes an entry from the index b-tree rooted at `root`, whose entries are in `order`,
+/// returning whether it was there. An entry on an interior page is replaced by the largest
+/// entry of its left subtree.
+pub fn delete_index_entry(
+    pager: &Pager,
+    root: u64,
+    entry: &[SqlValue],
+    order: &KeyOrder,
+) -> Result<bool> {
+    let Seek {
+        mut path,
+        page_number,
+        mut node,
+        index,
+    } = seek_index_entry(pager, root, entry, order)?;
+    let Ok(index) = index else {
+        return Ok(false);
+    };
+    free_overflow(pager, node.page_type, &node.cells[index])?;
+    if !node.is_interior() {
+        remove_leaf_cell(pager, path, page_number, node, index)?;
+        return Ok(true);
+    }
+
+    path.push((page_number, index));
+    let (_, leaf_number, mut leaf) = rightmost_leaf(pager, path.clone(), node.child(index)?)?;
+    let predecessor = leaf
+        .cells
+        .pop()
+        .ok_or_else(|| Error::corrupt_page(leaf_number, "no entry to move up"))?;
+    let moved = index_key(pager, leaf.page_type, &predecessor)?;
+    leaf.write(pager, leaf_number)?;
+    path.pop();
+    let mut cell = node.cells[index][..4].to_vec();
+    cell.extend(predecessor);
+    node.cells[index] = cell;
+    balance(pager, path, page_number, node, Change::Removed)?;
+
+    // Balancing may have moved the leaf that gave up the entry, so find it again, below the
+    // entry that replaced the deleted one, and give it its own chance to merge.
+    let Seek {
+        mut path,
+        page_number,
+        node,
+        index,
+    } = seek_index_entry(pager, root, &moved, order)?;
+    let Ok(index) = index else {
+        return Err(Error::corrupt_page(page_number, "moved entry is missing"));
+    };
+    path.push((page_number, index));
+    let (path, leaf_number, leaf) = rightmost_leaf(pager, path, node.child(index)?)?;
+    balance(pager, path, leaf_number, leaf, Change::Removed)?;
+    Ok(true)
+}
+
+/// Copies every entry of the b-tree rooted at `root` in `source` into a new b-tree in
+/// `target`, and returns the new root page. Entries are added in order after the last one, so
+/// the copy is packed into pages allocated one after another and no keys are compared.
+pub fn copy_btree(source: &Pager, root: u64, target: &Pager) -> Result<u64> {
+    let node = Node::read(source, root)?;
+    let index = matches!(node.page_type, INDEX_INTERIOR | INDEX_LEAF);
+    let new_root = create_btree(target, index)?;
+    copy_entries(source, root, node, target, new_root, 0)?;
+    Ok(new_root)
+}
+
+fn copy_entries(
+    source: &Pager,
+    page_number: u64,
+    node: Node,
+    target: &Pager,
+    root: u64,
+    depth: usize,
+) -> Result<()> {
+    if depth >= MAX_BTREE_DEPTH {
+        return Err(Error::corrupt_page(page_number, "b-tree is too deep"));
+    }
+    for (i, cell) in node.cells.iter().enumerate() {
+        if node.is_interior() {
+            let child = node.child(i)? as u64;
+            let child_node = Node::read(source, child)?;
+            copy_entries(source, child, child_node, target, root, depth + 1)?;
+        }
+        // Only table interior cells hold nothing but a copy of a key.
+        let rowid = match node.page_type {
+            TABLE_INTERIOR => continue,
+            TABLE_LEAF => Some(cell_rowid(node.page_type, cell)?),
+            _ => None,
+        };
+        let payload = cell_payload(source, node.page_type, cell)?;
+        let (path, leaf_number, mut leaf) = rightmost_leaf(target, Vec::new(), root as u32)?;
+        leaf.cells.push(leaf_cell(target, rowid, &payload)?);
+        balance(target, path, leaf_number, leaf, Change::Appended)?;
+    }
+    if let Some(child) = node.right_child {
+        let child_node = Node::read(source, child as u64)?;
+        copy_entries(source, child as u64, child_node, target, root, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Follows right children from `page_number` down to a leaf, adding each interior page passed
+/// through to `path`.
+fn rightmost_leaf(pager: &Pager, mut path: Path, page_number: u32) -> Result<(Path, u64, Node)> {
+    let mut page_number = page_number as u64;
+    let mut node = Node::read(pager, page_number)?;
+    while node.is_interior() {
+        if path.len() >= MAX_BTREE_DEPTH {
+            return Err(Error::corrupt_page(page_number, "b-tree is too deep"));
+        }
+        path.push((page_number, node.cells.len()));
+        page_number = node.child(node.cells.len())? as u64;
+        node = Node::read(pager, page_number)?;
+    }
+    Ok((path, page_number, node))
+}
+
+/// Takes cell `index` off a leaf. The space it used joins the page's freeblock list, unless
+/// that leaves the page so sparse that it is merged with a sibling instead.
+fn remove_leaf_cell(
+    pager: &Pager,
+    path: Path,
+    page_number: u64,
+    mut node: Node,
+    index: usize,
+) -> Result<()> {
+    node.cells.remove(index);
+    if path.is_empty() || node.size() * 3 >= pager.header().usable_size() {
+        return drop_cell(pager, page_number, index);
+    }
+    balance(pager, path, page_number, node, Change::Removed)
+}
+
+/// Removes cell `index` from a page in place, as SQLite's `dropCell` does: its pointer is taken
+/// out of the array and its bytes are handed to [`free_space`].
+fn drop_cell(pager: &Pager, page_number: u64, index: usize) -> Result<()> {
+    let page = Page::from_pager(pager, page_number)?;
+    let start = *page
+        .pointer_array
+        .get(index)
+        .ok_or_else(|| Error::corrupt_page(page_number, "cell index out of range"))?
+        as usize;
+    let size = page.cell_info(index, pager.header().usable_size())?.size;
+    let header_start = if page_number == 1 { 100 } else { 0 };
+    let pointers = header_start + page.header.size();
+    let cells = page.pointer_array.len();
+    let mut data = page.data.to_vec();
+    data.copy_within(
+        pointers + 2 * (index + 1)..pointers + 2 * cells,
+        pointers + 2 * index,
+    );
+    data[pointers + 2 * (cells - 1)..pointers + 2 * cells].fill(0);
+    data[header_start + 3..header_start + 5].copy_from_slice(&(cells as u16 - 1).to_be_bytes());
+    free_space(&mut data, header_start, start, size)
+        .map_err(|reason| Error::corrupt_page(page_number, reason))?;
+    pager.write_page(page_number, data)
+}
+
+/// Adds the `size` bytes at `start` to the freeblock list of the b-tree page in `data`, merging
+/// them with free space on either side. Gaps of under four bytes between them and a neighbouring
+/// freeblock are fragments, which are merged in too. Space next to the start of the cell content
+/// area goes back to the unallocated region instead of onto the list.
+fn free_space(
+    data: &mut [u8],
+    header_start: usize,
+    start: usize,
+    size: usize,
+) -> std::result::Result<(), &'static str> {
+    let read = |data: &[u8], at: usize| -> std::result::Result<usize, &'static str> {
+        let bytes = data.get(at..at + 2).ok_or("freeblock list is corrupt")?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let content_start = match read(data, header_start + 5)? {
+        0 => 65536,
+        n => n,
+    };
+    if start < content_start || start + size > data.len() {
+        return Err("freed cell is outside the content area");
+    }
+
+    // The list is kept in address order; the link to a freeblock is the first two bytes of the
+    // block before it, or of the page header.
+    let mut link = header_start + 1;
+    let mut next = read(data, link)?;
+    while next != 0 && next < start {
+        if next < content_start.max(link + 4) {
+            return Err("freeblock list is corrupt");
+        }
+        link = next;
+        next = read(data, next)?;
+    }
+    let (mut start, mut end) = (start, start + size);
+    let mut fragments = 0;
+    if next != 0 {
+        if next < end {
+            return Err("freed cell overlaps a freeblock");
+        }
+        if next <= end + 3 {
+            fragments += next - end;
+            end = next + read(data, next + 2)?;
+            next = read(data, next)?;
+        }
+    }
+    if link != header_start + 1 {
+        let previous_end = link + read(data, link + 2)?;
+        if previous_end > start {
+            return Err("freed cell overlaps a freeblock");
+        }
+        if previous_end + 3 >= start {
+            fragments += start - previous_end;
+            start = link;
+        }
+    }
+    let fragmented = &mut data[header_start + 7];
+    *fragmented = fragmented
+        .checked_sub(fragments as u8)
+        .ok_or("fragmented byte count is too small")?;
+
+    if start == content_start {
+        data[header_start + 1..header_start + 3].copy_from_slice(&(next as u16).to_be_bytes());
+        // A content area starting at 65536 is written as 0.
+        data[header_start + 5..header_start + 7].copy_from_slice(&(end as u16).to_be_bytes());
+        return Ok(());
+    }
+    if start != link {
+        data[link..link + 2].copy_from_slice(&(start as u16).to_be_bytes());
+    }
+    data[start..start + 2].copy_from_slice(&(next as u16).to_be_bytes());
+    data[start + 2..start + 4].copy_from_slice(&((end - start) as u16).to_be_bytes());
+    Ok(())
+}
+
+/// How the node handed to [`balance`] was changed, which decides how it may be rebalanced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    /// A cell was added after the last one.
+    Appended,
+    /// A cell was added anywhere else.
+    Inserted,
+    /// Cells were removed or replaced, so the node may also have become too sparse.
+    Removed,
+}
+
+/// Writes `node` back to `page_number`, first splitting it and pushing dividers into the
+/// parent if it no longer fits, or merging it with a sibling if it has become sparse, and so on
+/// up to the root.
+fn balance(
+    pager: &Pager,
+    mut path: Path,
+    mut page_number: u64,
+    mut node: Node,
+    change: Change,
+) -> Result<()> {
+    let usable_size = pager.header().usable_size();
+    let mut change = change;
+    loop {
+        let header_start = if page_number == 1 { 100 } else { 0 };
+        let fits = header_start + node.size() <= usable_size;
+        // As in SQLite, a page is left alone until less than a third of it is in use.
+        let sparse = change == Change::Removed && node.size() * 3 < usable_size;
+        if fits && !sparse {
+            return node.write(pager, page_number);
+        }
+        let Some((parent_number, child_index)) = path.pop() else {
+            if fits {
+                return collapse_root(pager, page_number, node);
+            }
+            // The root keeps its page number, so its contents move down into a new child and
+            // the root becomes an interior page with that child as its only pointer.
+            let child = pager.allocate_page()?;
+            let root = Node {
+                page_type: node.page_type & !0x08,
+                cells: Vec::new(),
+                right_child: Some(child as u32),
+            };
+            root.write(pager, page_number)?;
+            path.push((page_number, 0));
+            page_number = child;
+            continue;
+        };
+
+        let mut parent = Node::read(pager, parent_number)?;
+        if !fits {
+            let (siblings, dividers) = split(
+                &node,
+                usable_size,
+                change == Change::Appended,
+                child_index == parent.cells.len(),
+            )?;
+            // The first sibling stays on this page; the rest go to new pages, the last of
+            // which takes over this page's slot in the parent.
+            place_siblings(
+                pager,
+                &mut parent,
+                child_index,
+                siblings,
+                dividers,
+                vec![page_number],
+            )?;
+        } else if parent.cells.is_empty() {
+            // An only child has no sibling to merge with; its parent is a root that can
+            // instead take over its contents.
+            node.write(pager, page_number)?;
+        } else {
+            merge_siblings(pager, &mut parent, child_index, page_number, node)?;
+        }
+        page_number = parent_number;
+        node = parent;
+        // The parent gained or lost dividers, so it may now be too full or too sparse.
+        change = Change::Removed;
+    }
+}
+
+/// Writes the root back, first pulling its only child's contents up into it if it has no cells
+/// left, so the tree gets shallower again as it empties.
+fn collapse_root(pager: &Pager, page_number: u64, node: Node) -> Result<()> {
+    if node.is_interior() && node.cells.is_empty() {
+        let child_number = node.child(0)? as u64;
+        let child = Node::read(pager, child_number)?;
+        let header_start = if page_number == 1 { 100 } else { 0 };
+        if header_start + child.size() <= pager.header().usable_size() {
+            child.write(pager, page_number)?;
+            return pager.free_page(child_number);
+        }
+    }
+    node.write(pager, page_number)
+}
+
+/// Combines a sparse child of `parent` with its left neighbour (or right, for the first
+/// child) and the divider between them, splitting the result again if it does not fit.
+fn merge_siblings(
+    pager: &Pager,
+    parent: &mut Node,
+    child_index: usize,
+    page_number: u64,
+    node: Node,
+) -> Result<()> {
+    let (left_number, left, right_number, right) = if child_index == 0 {
+        let right_number = parent.child(1)? as u64;
+        (
+            page_number,
+            node,
+            right_number,
+            Node::read(pager, right_number)?,
+        )
+    } else {
+        let left_number = parent.child(child_index - 1)? as u64;
+        (
+            left_number,
+            Node::read(pager, left_number)?,
+            page_number,
+            node,
+        )
+    };
+    if left.page_type != right.page_type {
+        return Err(Error::corrupt_page(
+            right_number,
+            "sibling pages differ in type",
+        ));
+    }
+    let left_index = child_index.saturating_sub(1);
+    let divider = parent.cells.remove(left_index);
+    let mut merged = Node {
+        page_type: left.page_type,
+        cells: left.cells,
+        right_child: right.right_child,
+    };
+    match merged.page_type {
+        // The divider only repeats the largest rowid on the left.
+        TABLE_LEAF => {}
+        INDEX_LEAF => merged.cells.push(divider[4..].to_vec()),
+        _ => {
+            let left_child = left
+                .right_child
+                .ok_or_else(|| Error::corrupt_page(left_number, "no right child"))?;
+            let mut cell = divider;
+            cell[..4].copy_from_slice(&left_child.to_be_bytes());
+            merged.cells.push(cell);
+        }
+    }
+    merged.cells.extend(right.cells);
+
+    let usable_size = pager.header().usable_size();
+    let (siblings, dividers) = if merged.size() <= usable_size {
+        (vec![merged], Vec::new())
+    } else {
+        split(&merged, usable_size, false, false)?
+    };
+    place_siblings(
+        pager,
+        parent,
+        left_index,
+        siblings,
+        dividers,
+        vec![left_number, right_number],
+    )
+}
+
+/// Writes `siblings` out, reusing `pages` and allocating or freeing pages so there is one for
+/// each, and links them into `parent` from child slot `slot` on: the last sibling takes that
+/// slot and the others get a divider each in front of it.
+fn place_siblings(
+    pager: &Pager,
+    parent: &mut Node,
+    slot: usize,
+    siblings: Vec<Node>,
+    dividers: Vec<Vec<u8>>,
+    mut pages: Vec<u64>,
+) -> Result<()> {
+    while pages.len() < siblings.len() {
+        pages.push(pager.allocate_page()?);
+    }
+    for unused in pages.split_off(siblings.len()) {
+        pager.free_page(unused)?;
+    }
+    parent.set_child(slot, *pages.last().unwrap() as u32);
+    for (i, mut divider) in dividers.into_iter().enumerate().rev() {
+        divider[..4].copy_from_slice(&(pages[i] as u32).to_be_bytes());
+        parent.cells.insert(slot, divider);
+    }
+    for (sibling, page_number) in siblings.iter().zip(&pages) {
+        sibling.write(pager, *page_number)?;
+    }
+    Ok(())
+}
+
+/// Splits an overfull node into siblings that each fit on a page, along with the interior cell
+/// that separates each sibling from the next. Divider child pointers are left for the caller.
+fn split(
+    node: &Node,
+    usable_size: usize,
+    appended: bool,
+    rightmost: bool,
+) -> Result<(Vec<Node>, Vec<Vec<u8>>)> {
+    let capacity = usable_size - node.header_size();
+    let sizes: Vec<usize> = node.cells.iter().map(|c| c.len() + 2).collect();
+    // Interior pages and index leaves promote the cell between two siblings into the parent,
+    // while table leaves keep every cell and copy the largest rowid up instead.
+    let promote = node.page_type != TABLE_LEAF;
+    let runs = if appended && rightmost && !promote {
+        // Rows arriving in rowid order: leave the full page as it is and start a fresh one
+        // with just the new row, as SQLite's quick balance does.
+        let last = node.cells.len() - 1;
+        vec![0..last, last..last + 1]
+    } else if appended && rightmost && !node.is_interior() {
+        // Index entries only arrive in order when a whole b-tree is being copied, which
+        // likewise keeps pages full, with the cell before the new one as the divider.
+        let last = node.cells.len() - 1;
+        vec![0..last - 1, last..last + 1]
+    } else {
+        partition(&sizes, capacity, promote)
+            .ok_or_else(|| Error::Corrupt("cell is too large to split the page".to_string()))?
+    };
+
+    let mut siblings = Vec::new();
+    let mut dividers = Vec::new();
+    for (i, run) in runs.iter().enumerate() {
+        let mut sibling = Node {
+            page_type: node.page_type,
+            cells: node.cells[run.clone()].to_vec(),
+            right_child: node.right_child,
+        };
+        if i + 1 < runs.len() {
+            let divider = if node.is_interior() {
+                let separator = &node.cells[run.end];
+                sibling.right_child = Some(u32::from_be_bytes(separator[..4].try_into().unwrap()));
+                separator.clone()
+            } else if promote {
+                // An index interior cell is a leaf cell behind a child pointer.
+                let mut divider = vec![0; 4];
+                divider.extend_from_slice(&node.cells[run.end]);
+                divider
+            } else {
+                let last = sibling.cells.last().unwrap();
+                let mut divider = vec![0; 4];
+                divider.extend(encode_varint(cell_rowid(node.page_type, last)? as u64));
+                divider
+            };
+            dividers.push(divider);
+        }
+        siblings.push(sibling);
+    }
+    Ok((siblings, dividers))
+}
+
+/// Divides cells into as few runs as will fit in `capacity` bytes each, keeping the runs close
+/// to the same size. With `promote`, the cell after each run but the last is left out of all
+/// of them, to become the divider in the parent.
+fn partition(sizes: &[usize], capacity: usize, promote: bool) -> Option<Vec<Range<usize>>> {
+    let total: usize = sizes.iter().sum();
+    for parts in 2..=sizes.len() {
+        let target = total.div_ceil(parts);
+        let mut runs = Vec::new();
+        let mut start = 0;
+        let mut used = 0;
+        let mut i = 0;
+        while i < sizes.len() {
+            if used + sizes[i] > capacity {
+                if i == start {
+                    return None;
+                }
+                runs.push(start..i);
+                if promote {
+                    i += 1;
+                }
+                start = i;
+                used = 0;
+                continue;
+            }
+            used += sizes[i];
+            i += 1;
+            // Close the run once it reaches its share, as long as something is left over for
+            // the runs after it (and for the divider in front of them).
+            if used >= target && i + (promote as usize) < sizes.len() {
+                runs.push(start..i);
+                if promote {
+                    i += 1;
+                }
+                start = i;
+                used = 0;
+            }
+        }
+        if start < sizes.len() {
+            runs.push(start..sizes.len());
+        } else if promote {
+            // The last cell ended up as a divider with nothing after it.
+            continue;
+        }
+        if runs.len() <= parts {
+            return Some(runs);
+        }
+    }
+    None
+}
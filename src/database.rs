@@ -0,0 +1,144 @@
+use crate::error::{Error, Result};
+use crate::execute;
+use crate::page_io::{DbHeader, TextEncoding};
+use crate::pager::{Pager, PagerOptions};
+use crate::syntax;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Recorded in the header of new files as the SQLite version that last wrote them.
+const SQLITE_VERSION_NUMBER: u32 = 3045000;
+
+/// Settings fixed when a database file is created.
+#[derive(Debug, Clone, Copy)]
+pub struct CreateOptions {
+    /// A power of two from 512 to 65536.
+    pub page_size: u32,
+    /// Bytes left unused at the end of every page, for extensions such as encryption.
+    pub reserved_bytes: u8,
+    pub text_encoding: TextEncoding,
+    pub user_version: u32,
+    pub application_id: u32,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        CreateOptions {
+            page_size: 4096,
+            reserved_bytes: 0,
+            text_encoding: TextEncoding::Utf8,
+            user_version: 0,
+            application_id: 0,
+        }
+    }
+}
+
+/// A database opened for writing, which runs SQL statements against its pager.
+pub struct Database {
+    pager: Pager,
+}
+
+impl CreateOptions {
+    /// The settings a copy of a database with this header needs to come out the same.
+    pub fn from_header(header: &DbHeader) -> CreateOptions {
+        CreateOptions {
+            page_size: header.page_size,
+            reserved_bytes: header.reserved_bytes,
+            text_encoding: header.text_encoding,
+            user_version: header.user_version,
+            application_id: header.application_id,
+        }
+    }
+
+    /// Page 1 of a new database holding nothing but an empty schema, which is the whole file.
+    pub fn empty_database(&self) -> Result<Vec<u8>> {
+        let page_size = self.page_size;
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            return Err(Error::InvalidStatement(format!(
+                "invalid page size {}",
+                page_size
+            )));
+        }
+        // SQLite needs at least 480 usable bytes per page.
+        let usable_size = page_size - self.reserved_bytes as u32;
+        if usable_size < 480 {
+            return Err(Error::InvalidStatement(format!(
+                "{} reserved bytes leave too little of a {} byte page",
+                self.reserved_bytes, page_size
+            )));
+        }
+        let header = DbHeader {
+            page_size,
+            write_version: 1,
+            read_version: 1,
+            reserved_bytes: self.reserved_bytes,
+            max_payload_fraction: 64,
+            min_payload_fraction: 32,
+            leaf_payload_fraction: 32,
+            file_change_counter: 1,
+            database_size: 1,
+            first_freelist_trunk: 0,
+            freelist_count: 0,
+            schema_cookie: 0,
+            schema_format: 4,
+            default_cache_size: 0,
+            autovacuum_top_root: 0,
+            text_encoding: self.text_encoding,
+            user_version: self.user_version,
+            incremental_vacuum: false,
+            application_id: self.application_id,
+            version_valid_for: 1,
+            sqlite_version_number: SQLITE_VERSION_NUMBER,
+        };
+        let mut page = vec![0; page_size as usize];
+        page[..DbHeader::len() as usize].copy_from_slice(&header.to_bytes());
+        // Page 1 is the root of sqlite_schema, an empty table b-tree leaf whose content area
+        // starts at the end of the usable space (written as 0 if that is 65536).
+        let btree_header = &mut page[DbHeader::len() as usize..];
+        btree_header[0] = 0x0d;
+        btree_header[5..7].copy_from_slice(&(usable_size as u16).to_be_bytes());
+        Ok(page)
+    }
+}
+
+impl Database {
+    /// Creates a new database file at `path` holding nothing but an empty schema. Fails if the
+    /// file already exists.
+    pub fn create<P: AsRef<Path>>(path: P, options: CreateOptions) -> Result<Database> {
+        let page = options.empty_database()?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        file.write_all(&page)?;
+        file.sync_all()?;
+        drop(file);
+        Self::open(path)
+    }
+
+    /// Opens an existing database file for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Database> {
+        let options = PagerOptions {
+            writable: true,
+            ..Default::default()
+        };
+        Ok(Database {
+            pager: Pager::open_with(path, options)?,
+        })
+    }
+
+    pub fn pager(&self) -> &Pager {
+        &self.pager
+    }
+
+    /// Runs each statement of a script in turn, stopping at the first that fails, and returns
+    /// the number of rows changed.
+    pub fn execute(&self, script: &str) -> Result<usize> {
+        let mut changed = 0;
+        for sql in syntax::split_script(script) {
+            changed += execute::execute_sql(&self.pager, &syntax::parse(sql)?, sql)?;
+        }
+        Ok(changed)
+    }
+}
@@ -0,0 +1,44 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("file is not a database: {0}")]
+    NotADatabase(String),
+    #[error("database disk image is malformed: {0}")]
+    Corrupt(String),
+    #[error("database disk image is malformed: page {page}: {reason}")]
+    CorruptPage { page: u64, reason: String },
+    #[error("bad serial type: {0}")]
+    BadSerialType(u64),
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+    /// `position` is the index of the offending token in the statement.
+    #[error("parse error at token {position}: {message}")]
+    Parse { position: usize, message: String },
+    #[error("no such table: {0}")]
+    UnknownTable(String),
+    #[error("no such column: {0}")]
+    UnknownColumn(String),
+    #[error("attempt to write a readonly database")]
+    ReadOnly,
+    /// Another connection held a lock we needed for longer than the busy timeout.
+    #[error("database is locked")]
+    Busy,
+    #[error("{0}")]
+    InvalidStatement(String),
+    #[error("{0}")]
+    Constraint(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn corrupt_page(page: u64, reason: impl Into<String>) -> Error {
+        Error::CorruptPage {
+            page,
+            reason: reason.into(),
+        }
+    }
+}
@@ -0,0 +1,1591 @@
+use crate::error::{Error, Result};
+use crate::page_io::{TableSchema, TextEncoding};
+use crate::syntax::expr::{BinaryOp, Expr, FunctionArgs, InList, LikeOp, UnaryOp};
+use crate::syntax::insert::Value;
+use crate::typecodes::SqlValue;
+use crate::utils::format_real;
+use std::cmp::Ordering;
+
+/// The names SQLite gives the rowid of a table that has no column called that.
+const ROWID_NAMES: [&str; 3] = ["rowid", "oid", "_rowid_"];
+
+/// The kind of value a column prefers, which decides how values are converted when they are
+/// stored in it or compared with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Integer,
+    Real,
+    Numeric,
+    Text,
+    Blob,
+}
+
+impl Affinity {
+    /// The affinity of a column declared with the given type, by SQLite's rules, tried in
+    /// order: INT anywhere in the name means INTEGER, then CHAR, CLOB or TEXT means TEXT,
+    /// BLOB or no type at all means BLOB, REAL, FLOA or DOUB means REAL, and anything else
+    /// is NUMERIC.
+    pub fn from_type_name(type_name: Option<&str>) -> Affinity {
+        let type_name = match type_name {
+            Some(type_name) => type_name.to_uppercase(),
+            None => return Affinity::Blob,
+        };
+        let contains_any = |words: &[&str]| words.iter().any(|word| type_name.contains(word));
+        if contains_any(&["INT"]) {
+            Affinity::Integer
+        } else if contains_any(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if contains_any(&["BLOB"]) {
+            Affinity::Blob
+        } else if contains_any(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Integer | Affinity::Real | Affinity::Numeric)
+    }
+
+    /// Converts a value the way a column with this affinity does before storing it. Text that
+    /// does not spell a number is left alone by the numeric affinities, and blobs and NULL are
+    /// never converted.
+    pub fn apply(self, value: SqlValue) -> SqlValue {
+        match (self, value) {
+            (_, value @ (SqlValue::Null | SqlValue::Blob(_))) | (Affinity::Blob, value) => value,
+            (Affinity::Text, SqlValue::Text(text)) => SqlValue::Text(text),
+            (Affinity::Text, number) => SqlValue::Text(number_text(&number)),
+            (affinity, SqlValue::Text(text)) => match parse_numeric(&text) {
+                Some(number) => affinity.apply(number),
+                None => SqlValue::Text(text),
+            },
+            (Affinity::Real, SqlValue::F64(n)) => SqlValue::F64(n),
+            (Affinity::Real, int) => SqlValue::F64(int.as_i64().unwrap_or_default() as f64),
+            // INTEGER and NUMERIC keep a real as an integer when nothing is lost.
+            (_, SqlValue::F64(n)) => match exact_integer(n) {
+                Some(n) => SqlValue::I64(n),
+                None => SqlValue::F64(n),
+            },
+            (_, int) => int,
+        }
+    }
+
+    /// The form in which a column with this affinity stores a value it has converted. Like
+    /// SQLite, a REAL column saves a real with no fractional part as an integer when that fits
+    /// in six bytes, which [`table_row`] turns back into a real.
+    pub fn stored(self, value: SqlValue) -> SqlValue {
+        const SIX_BYTES: std::ops::RangeInclusive<i64> = -(1 << 47)..=(1 << 47) - 1;
+        match (self, value) {
+            (Affinity::Real, SqlValue::F64(n)) => match exact_integer(n) {
+                Some(int) if SIX_BYTES.contains(&int) => SqlValue::I64(int),
+                _ => SqlValue::F64(n),
+            },
+            (_, value) => value,
+        }
+    }
+}
+
+/// A built-in collating sequence, which decides how two text values compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// The bytes of the text in the database encoding.
+    #[default]
+    Binary,
+    /// Like BINARY, but with ASCII letters folded to lower case.
+    NoCase,
+    /// Like BINARY, but ignoring trailing spaces.
+    RTrim,
+}
+
+impl Collation {
+    pub fn from_name(name: &str) -> Result<Collation> {
+        match name.to_lowercase().as_str() {
+            "binary" => Ok(Collation::Binary),
+            "nocase" => Ok(Collation::NoCase),
+            "rtrim" => Ok(Collation::RTrim),
+            _ => Err(Error::InvalidStatement(format!(
+                "no such collation sequence: {}",
+                name
+            ))),
+        }
+    }
+
+    /// Orders two values of any storage class: NULLs first, then numbers, then text under
+    /// this collation, then blobs.
+    pub fn compare(self, a: &SqlValue, b: &SqlValue, encoding: TextEncoding) -> Ordering {
+        match (self, a, b) {
+            (Collation::NoCase, SqlValue::Text(a), SqlValue::Text(b)) => {
+                encoding.compare(&a.to_ascii_lowercase(), &b.to_ascii_lowercase())
+            }
+            (Collation::RTrim, SqlValue::Text(a), SqlValue::Text(b)) => {
+                encoding.compare(a.trim_end_matches(' '), b.trim_end_matches(' '))
+            }
+            _ => a.cmp_storage(b, encoding),
+        }
+    }
+}
+
+/// A column an expression can refer to.
+#[derive(Debug, Clone)]
+pub struct ScopeColumn {
+    /// The table the column belongs to, for qualified references such as `t.a`.
+    pub table: Option<String>,
+    pub name: String,
+    pub affinity: Affinity,
+    pub collation: Collation,
+    /// The rowid, found by the names SQLite gives it only when no other column has the name.
+    pub is_rowid: bool,
+    /// A column a USING join matched with one of an earlier table, which only a qualified
+    /// reference can name.
+    pub merged: bool,
+    /// The call whose result the column holds, for the rows an aggregate query outputs.
+    pub aggregate: Option<Expr>,
+}
+
+/// The columns of the rows an expression is evaluated against, in the order a row holds their
+/// values.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub columns: Vec<ScopeColumn>,
+    encoding: TextEncoding,
+}
+
+impl Scope {
+    pub fn new(encoding: TextEncoding) -> Scope {
+        Scope {
+            columns: Vec::new(),
+            encoding,
+        }
+    }
+
+    /// Adds the columns of a table under the name queries know it by, followed by its rowid
+    /// unless it is a WITHOUT ROWID table. Rows hold them as [`table_row`] lays them out.
+    pub fn add_table(&mut self, name: &str, schema: &TableSchema) {
+        for column in &schema.columns {
+            self.columns.push(ScopeColumn {
+                table: Some(name.to_string()),
+                name: column.name.clone(),
+                affinity: column.affinity,
+                collation: column.collation,
+                is_rowid: false,
+                merged: false,
+                aggregate: None,
+            });
+        }
+        if !schema.without_rowid {
+            self.columns.push(ScopeColumn {
+                table: Some(name.to_string()),
+                name: "rowid".to_string(),
+                affinity: Affinity::Integer,
+                collation: Collation::Binary,
+                is_rowid: true,
+                merged: false,
+                aggregate: None,
+            });
+        }
+    }
+
+    /// Adds a column for the result of an aggregate call, which evaluating the call then reads.
+    pub fn add_aggregate(&mut self, call: &Expr) {
+        self.columns.push(ScopeColumn {
+            table: None,
+            name: String::new(),
+            affinity: Affinity::Blob,
+            collation: Collation::Binary,
+            is_rowid: false,
+            merged: false,
+            aggregate: Some(call.clone()),
+        });
+    }
+
+    fn aggregate_position(&self, call: &Expr) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.aggregate.as_ref() == Some(call))
+    }
+
+    /// Position in the row of the value a column reference names.
+    pub fn resolve(&self, table: Option<&str>, name: &str) -> Result<usize> {
+        let in_table = |column: &ScopeColumn| match table {
+            Some(table) => column
+                .table
+                .as_deref()
+                .is_some_and(|t| t.eq_ignore_ascii_case(table)),
+            None => !column.merged,
+        };
+        let is_rowid_name = ROWID_NAMES.contains(&name.to_lowercase().as_str());
+        for rowid in [false, true] {
+            let mut found = self.columns.iter().enumerate().filter(|(_, column)| {
+                let named = if rowid {
+                    is_rowid_name
+                } else {
+                    column.name.eq_ignore_ascii_case(name)
+                };
+                column.is_rowid == rowid && column.aggregate.is_none() && named && in_table(column)
+            });
+            if let Some((i, _)) = found.next() {
+                if found.next().is_some() {
+                    return Err(Error::InvalidStatement(format!(
+                        "ambiguous column name: {}",
+                        name
+                    )));
+                }
+                return Ok(i);
+            }
+        }
+        Err(Error::UnknownColumn(match table {
+            Some(table) => format!("{}.{}", table, name),
+            None => name.to_string(),
+        }))
+    }
+
+    /// Fails if the expression names a column that is not in scope, so a statement is rejected
+    /// even when there are no rows to evaluate it on. Subqueries are rejected here too: there
+    /// is no way to run the SELECT inside an `IN (SELECT ...)`, `EXISTS (...)` or scalar
+    /// subquery from within an expression.
+    pub fn check(&self, expr: &Expr) -> Result<()> {
+        match expr {
+            Expr::Exists(_)
+            | Expr::Subquery(_)
+            | Expr::In {
+                list: InList::Select(_),
+                ..
+            } => return Err(unsupported_subquery()),
+            Expr::Column { table, name } => {
+                self.resolve(table.as_deref(), name)?;
+            }
+            Expr::Function { .. } if self.aggregate_position(expr).is_some() => return Ok(()),
+            Expr::Function { name, args } if is_aggregate_call(name, args) => {
+                return Err(misuse_of_aggregate(name))
+            }
+            _ => {}
+        }
+        children(expr)
+            .into_iter()
+            .try_for_each(|child| self.check(child))
+    }
+
+    /// Positions in the row of the columns an expression reads.
+    pub fn columns_read(&self, expr: &Expr) -> Vec<usize> {
+        match expr {
+            Expr::Column { table, name } => {
+                self.resolve(table.as_deref(), name).into_iter().collect()
+            }
+            _ => children(expr)
+                .into_iter()
+                .flat_map(|child| self.columns_read(child))
+                .collect(),
+        }
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Whether a row meets a condition. A NULL result counts as false, as in a WHERE clause.
+    pub fn is_true(&self, expr: &Expr, row: &[SqlValue]) -> Result<bool> {
+        Ok(truth(&self.evaluate(expr, row)?).unwrap_or(false))
+    }
+
+    pub fn evaluate(&self, expr: &Expr, row: &[SqlValue]) -> Result<SqlValue> {
+        Ok(match expr {
+            Expr::Literal(value) => literal(value),
+            Expr::Column { table, name } => {
+                let i = self.resolve(table.as_deref(), name)?;
+                row.get(i).cloned().unwrap_or(SqlValue::Null)
+            }
+            Expr::Unary { op, expr } => self.unary(*op, self.evaluate(expr, row)?),
+            Expr::Binary { op, left, right } => self.binary(*op, left, right, row)?,
+            Expr::IsNull { expr, negated } => {
+                boolean(matches!(self.evaluate(expr, row)?, SqlValue::Null) != *negated)
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let value = self.evaluate(expr, row)?;
+                let low_value = self.evaluate(low, row)?;
+                let high_value = self.evaluate(high, row)?;
+                let above_low = self
+                    .compare((expr, &value), (low, &low_value))
+                    .map(|ordering| ordering != Ordering::Less);
+                let below_high = self
+                    .compare((expr, &value), (high, &high_value))
+                    .map(|ordering| ordering != Ordering::Greater);
+                negate(and(above_low, below_high), *negated)
+            }
+            Expr::In {
+                expr,
+                list,
+                negated,
+            } => {
+                let items = match list {
+                    InList::Values(items) => items,
+                    InList::Select(_) => return Err(unsupported_subquery()),
+                };
+                let value = self.evaluate(expr, row)?;
+                let mut found = Some(false);
+                for item in items {
+                    let item_value = self.evaluate(item, row)?;
+                    match self.compare((expr, &value), (item, &item_value)) {
+                        Some(Ordering::Equal) => {
+                            found = Some(true);
+                            break;
+                        }
+                        Some(_) => {}
+                        None => found = None,
+                    }
+                }
+                // Nothing is in an empty list, not even NULL.
+                if items.is_empty() {
+                    found = Some(false);
+                }
+                negate(found, *negated)
+            }
+            Expr::Like {
+                op,
+                expr,
+                pattern,
+                escape,
+                negated,
+            } => {
+                let value = self.evaluate(expr, row)?;
+                let pattern = self.evaluate(pattern, row)?;
+                let escape = match escape {
+                    Some(escape) => match self.evaluate(escape, row)? {
+                        SqlValue::Null => return Ok(SqlValue::Null),
+                        escape => Some(self.text(&escape)),
+                    },
+                    None => None,
+                };
+                if matches!(value, SqlValue::Null) || matches!(pattern, SqlValue::Null) {
+                    return Ok(SqlValue::Null);
+                }
+                let (value, pattern) = (self.text(&value), self.text(&pattern));
+                let matched = match op {
+                    LikeOp::Like => like(&pattern, &value, escape.as_deref())?,
+                    LikeOp::Glob => glob(&pattern, &value),
+                    LikeOp::Regexp => return Err(no_such_function("regexp")),
+                    LikeOp::Match => return Err(no_such_function("match")),
+                };
+                boolean(matched != *negated)
+            }
+            Expr::Function { name, args } => match self.aggregate_position(expr) {
+                Some(i) => row.get(i).cloned().unwrap_or(SqlValue::Null),
+                None => self.function(name, args, row)?,
+            },
+            Expr::Case {
+                operand,
+                branches,
+                else_,
+            } => {
+                let operand = match operand {
+                    Some(operand) => Some((operand, self.evaluate(operand, row)?)),
+                    None => None,
+                };
+                for (when, then) in branches {
+                    let when_value = self.evaluate(when, row)?;
+                    let chosen = match &operand {
+                        Some((operand, value)) => {
+                            self.compare((operand, value), (when, &when_value))
+                                == Some(Ordering::Equal)
+                        }
+                        None => truth(&when_value).unwrap_or(false),
+                    };
+                    if chosen {
+                        return self.evaluate(then, row);
+                    }
+                }
+                match else_ {
+                    Some(else_) => self.evaluate(else_, row)?,
+                    None => SqlValue::Null,
+                }
+            }
+            Expr::Cast { expr, type_name } => self.cast(self.evaluate(expr, row)?, Some(type_name)),
+            Expr::Collate { expr, collation } => {
+                Collation::from_name(collation)?;
+                self.evaluate(expr, row)?
+            }
+            Expr::Exists(_) | Expr::Subquery(_) => return Err(unsupported_subquery()),
+        })
+    }
+
+    /// The affinity an expression gives its value in comparisons: a column's own, or that of
+    /// the type named in a CAST. Anything else has none.
+    pub fn affinity(&self, expr: &Expr) -> Option<Affinity> {
+        match expr {
+            Expr::Column { table, name } => self
+                .resolve(table.as_deref(), name)
+                .ok()
+                .map(|i| self.columns[i].affinity),
+            Expr::Cast { type_name, .. } => Some(Affinity::from_type_name(Some(type_name))),
+            Expr::Collate { expr, .. } => self.affinity(expr),
+            _ => None,
+        }
+    }
+
+    /// The collation an expression asks for, and whether it was asked for with COLLATE rather
+    /// than coming from a column's declaration.
+    pub fn collation(&self, expr: &Expr) -> Option<(Collation, bool)> {
+        match expr {
+            Expr::Collate { collation, .. } => {
+                Some((Collation::from_name(collation).unwrap_or_default(), true))
+            }
+            Expr::Column { table, name } => self
+                .resolve(table.as_deref(), name)
+                .ok()
+                .map(|i| (self.columns[i].collation, false)),
+            // Neither changes how the value underneath compares.
+            Expr::Unary {
+                op: UnaryOp::Plus,
+                expr,
+            }
+            | Expr::Cast { expr, .. } => self.collation(expr),
+            _ => None,
+        }
+    }
+
+    /// Compares two operands by SQLite's rules, or returns None if either is NULL. A numeric
+    /// affinity on one side first converts the other; failing that, a TEXT affinity converts
+    /// an operand that has none. Text compares with the operands' comparison collation.
+    fn compare(&self, left: (&Expr, &SqlValue), right: (&Expr, &SqlValue)) -> Option<Ordering> {
+        let (left_expr, left) = left;
+        let (right_expr, right) = right;
+        if matches!(left, SqlValue::Null) || matches!(right, SqlValue::Null) {
+            return None;
+        }
+        let left_affinity = self.affinity(left_expr);
+        let right_affinity = self.affinity(right_expr);
+        let numeric = |affinity: Option<Affinity>| affinity.is_some_and(Affinity::is_numeric);
+        let (left, right) = if numeric(left_affinity) && !numeric(right_affinity) {
+            (left.clone(), Affinity::Numeric.apply(right.clone()))
+        } else if numeric(right_affinity) && !numeric(left_affinity) {
+            (Affinity::Numeric.apply(left.clone()), right.clone())
+        } else if left_affinity == Some(Affinity::Text) && right_affinity.is_none() {
+            (left.clone(), Affinity::Text.apply(right.clone()))
+        } else if right_affinity == Some(Affinity::Text) && left_affinity.is_none() {
+            (Affinity::Text.apply(left.clone()), right.clone())
+        } else {
+            (left.clone(), right.clone())
+        };
+        let collation = self.comparison_collation(left_expr, right_expr);
+        Some(collation.compare(&left, &right, self.encoding))
+    }
+
+    /// The collation text compares with between two operands: an explicit COLLATE from either
+    /// side, else the collation of a column on the left, then the right, else BINARY.
+    pub fn comparison_collation(&self, left: &Expr, right: &Expr) -> Collation {
+        match (self.collation(left), self.collation(right)) {
+            (Some((collation, true)), _) | (_, Some((collation, true))) => collation,
+            (Some((collation, _)), _) | (_, Some((collation, _))) => collation,
+            _ => Collation::Binary,
+        }
+    }
+
+    fn unary(&self, op: UnaryOp, value: SqlValue) -> SqlValue {
+        match op {
+            UnaryOp::Plus => value,
+            UnaryOp::Not => negate(truth(&value), true),
+            UnaryOp::Negate => match number(&value) {
+                Some(Number::Integer(n)) => match n.checked_neg() {
+                    Some(n) => SqlValue::I64(n),
+                    None => SqlValue::F64(-(n as f64)),
+                },
+                Some(Number::Real(n)) => SqlValue::F64(-n),
+                None => SqlValue::Null,
+            },
+            UnaryOp::BitNot => match integer(&value) {
+                Some(n) => SqlValue::I64(!n),
+                None => SqlValue::Null,
+            },
+        }
+    }
+
+    fn binary(
+        &self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        row: &[SqlValue],
+    ) -> Result<SqlValue> {
+        // AND and OR only look at the right operand when the left does not settle the answer.
+        match op {
+            BinaryOp::And => {
+                let left = truth(&self.evaluate(left, row)?);
+                if left == Some(false) {
+                    return Ok(boolean(false));
+                }
+                return Ok(from_truth(and(left, truth(&self.evaluate(right, row)?))));
+            }
+            BinaryOp::Or => {
+                let left = truth(&self.evaluate(left, row)?);
+                if left == Some(true) {
+                    return Ok(boolean(true));
+                }
+                let right = truth(&self.evaluate(right, row)?);
+                return Ok(negate(and(left.map(|b| !b), right.map(|b| !b)), true));
+            }
+            _ => {}
+        }
+        let left_value = self.evaluate(left, row)?;
+        let right_value = self.evaluate(right, row)?;
+        let ordering = || self.compare((left, &left_value), (right, &right_value));
+        Ok(match op {
+            BinaryOp::Equal => from_truth(ordering().map(|o| o == Ordering::Equal)),
+            BinaryOp::NotEqual => from_truth(ordering().map(|o| o != Ordering::Equal)),
+            BinaryOp::Less => from_truth(ordering().map(|o| o == Ordering::Less)),
+            BinaryOp::LessEqual => from_truth(ordering().map(|o| o != Ordering::Greater)),
+            BinaryOp::Greater => from_truth(ordering().map(|o| o == Ordering::Greater)),
+            BinaryOp::GreaterEqual => from_truth(ordering().map(|o| o != Ordering::Less)),
+            // IS and IS NOT treat two NULLs as equal and never give NULL.
+            BinaryOp::Is | BinaryOp::IsNot => {
+                let same = match (&left_value, &right_value) {
+                    (SqlValue::Null, SqlValue::Null) => true,
+                    (SqlValue::Null, _) | (_, SqlValue::Null) => false,
+                    _ => ordering() == Some(Ordering::Equal),
+                };
+                boolean(same == (op == BinaryOp::Is))
+            }
+            BinaryOp::Concat => match (&left_value, &right_value) {
+                (SqlValue::Null, _) | (_, SqlValue::Null) => SqlValue::Null,
+                (a, b) => SqlValue::Text(self.text(a) + &self.text(b)),
+            },
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+                match (integer(&left_value), integer(&right_value)) {
+                    (Some(a), Some(b)) => SqlValue::I64(bitwise(op, a, b)),
+                    _ => SqlValue::Null,
+                }
+            }
+            _ => match (number(&left_value), number(&right_value)) {
+                (Some(a), Some(b)) => arithmetic(op, a, b),
+                _ => SqlValue::Null,
+            },
+        })
+    }
+
+    fn function(&self, name: &str, args: &FunctionArgs, row: &[SqlValue]) -> Result<SqlValue> {
+        let lower = name.to_lowercase();
+        let args = match args {
+            FunctionArgs::List {
+                distinct: false,
+                args,
+            } if !is_aggregate(&lower, args.len()) => args,
+            _ if is_aggregate(&lower, 1) => return Err(misuse_of_aggregate(name)),
+            _ => return Err(no_such_function(name)),
+        };
+        let values = args
+            .iter()
+            .map(|arg| self.evaluate(arg, row))
+            .collect::<Result<Vec<_>>>()?;
+        let arity = |counts: &[usize]| {
+            if counts.contains(&values.len()) {
+                Ok(())
+            } else {
+                Err(Error::InvalidStatement(format!(
+                    "wrong number of arguments to function {}()",
+                    name
+                )))
+            }
+        };
+        // The string and number functions are NULL if any argument is.
+        let strict_arity: Option<&[usize]> = match lower.as_str() {
+            "abs" | "length" | "lower" | "upper" | "hex" => Some(&[1]),
+            "substr" | "substring" => Some(&[2, 3]),
+            "trim" | "ltrim" | "rtrim" | "round" => Some(&[1, 2]),
+            "replace" => Some(&[3]),
+            "instr" => Some(&[2]),
+            _ => None,
+        };
+        if let Some(counts) = strict_arity {
+            arity(counts)?;
+            if values.iter().any(|v| matches!(v, SqlValue::Null)) {
+                return Ok(SqlValue::Null);
+            }
+        }
+        Ok(match lower.as_str() {
+            "coalesce" | "ifnull" => {
+                if lower == "ifnull" || values.len() < 2 {
+                    arity(&[2])?;
+                }
+                values
+                    .into_iter()
+                    .find(|v| !matches!(v, SqlValue::Null))
+                    .unwrap_or(SqlValue::Null)
+            }
+            "iif" => {
+                arity(&[3])?;
+                let chosen = if truth(&values[0]) == Some(true) {
+                    1
+                } else {
+                    2
+                };
+                values[chosen].clone()
+            }
+            "nullif" => {
+                arity(&[2])?;
+                let equal = self.compare((&args[0], &values[0]), (&args[1], &values[1]))
+                    == Some(Ordering::Equal);
+                if equal {
+                    SqlValue::Null
+                } else {
+                    values[0].clone()
+                }
+            }
+            "typeof" => {
+                arity(&[1])?;
+                SqlValue::Text(type_name(&values[0]).to_string())
+            }
+            "min" | "max" => {
+                // With one argument these are the aggregates, turned away above.
+                if values.iter().any(|v| matches!(v, SqlValue::Null)) {
+                    return Ok(SqlValue::Null);
+                }
+                let wanted = if lower == "min" {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+                let collation = args
+                    .iter()
+                    .find_map(|arg| self.collation(arg))
+                    .map_or(Collation::Binary, |(collation, _)| collation);
+                let mut best = values[0].clone();
+                for value in &values[1..] {
+                    if collation.compare(value, &best, self.encoding) == wanted {
+                        best = value.clone();
+                    }
+                }
+                best
+            }
+            "abs" => match number(&values[0]) {
+                Some(Number::Integer(n)) => SqlValue::I64(
+                    n.checked_abs()
+                        .ok_or_else(|| Error::InvalidStatement("integer overflow".to_string()))?,
+                ),
+                Some(Number::Real(n)) => SqlValue::F64(n.abs()),
+                None => SqlValue::Null,
+            },
+            "length" => {
+                let length = match &values[0] {
+                    SqlValue::Blob(blob) => blob.len(),
+                    value => self.text(value).chars().count(),
+                };
+                SqlValue::I64(length as i64)
+            }
+            "lower" | "upper" => {
+                let text = self.text(&values[0]);
+                // Like SQLite without ICU, only ASCII letters change case.
+                SqlValue::Text(if lower == "lower" {
+                    text.to_ascii_lowercase()
+                } else {
+                    text.to_ascii_uppercase()
+                })
+            }
+            "substr" | "substring" => {
+                let start = integer(&values[1]).unwrap_or_default();
+                let length = values.get(2).map(|v| integer(v).unwrap_or_default());
+                match &values[0] {
+                    SqlValue::Blob(blob) => {
+                        let (from, to) = substr_range(blob.len(), start, length);
+                        SqlValue::Blob(blob[from..to].to_vec())
+                    }
+                    value => {
+                        let chars = self.text(value).chars().collect::<Vec<_>>();
+                        let (from, to) = substr_range(chars.len(), start, length);
+                        SqlValue::Text(chars[from..to].iter().collect())
+                    }
+                }
+            }
+            "trim" | "ltrim" | "rtrim" => {
+                let text = self.text(&values[0]);
+                let set = match values.get(1) {
+                    Some(set) => self.text(set).chars().collect::<Vec<_>>(),
+                    None => vec![' '],
+                };
+                let trimmed = match lower.as_str() {
+                    "ltrim" => text.trim_start_matches(&set[..]),
+                    "rtrim" => text.trim_end_matches(&set[..]),
+                    _ => text.trim_matches(&set[..]),
+                };
+                SqlValue::Text(trimmed.to_string())
+            }
+            "replace" => {
+                let text = self.text(&values[0]);
+                let from = self.text(&values[1]);
+                if from.is_empty() {
+                    SqlValue::Text(text)
+                } else {
+                    SqlValue::Text(text.replace(&from, &self.text(&values[2])))
+                }
+            }
+            "instr" => {
+                let position = match (&values[0], &values[1]) {
+                    (SqlValue::Blob(haystack), SqlValue::Blob(needle)) => {
+                        find_position(haystack, needle)
+                    }
+                    (haystack, needle) => {
+                        let haystack = self.text(haystack).chars().collect::<Vec<_>>();
+                        let needle = self.text(needle).chars().collect::<Vec<_>>();
+                        find_position(&haystack, &needle)
+                    }
+                };
+                SqlValue::I64(position.map_or(0, |i| i as i64 + 1))
+            }
+            "hex" => {
+                let bytes = match &values[0] {
+                    SqlValue::Blob(blob) => blob.clone(),
+                    value => self.encoding.encode(&self.text(value)),
+                };
+                SqlValue::Text(bytes.iter().map(|b| format!("{:02X}", b)).collect())
+            }
+            "quote" => {
+                arity(&[1])?;
+                SqlValue::Text(match &values[0] {
+                    SqlValue::Null => "NULL".to_string(),
+                    SqlValue::Text(text) => format!("'{}'", text.replace('\'', "''")),
+                    SqlValue::Blob(blob) => {
+                        let hex = blob.iter().map(|b| format!("{:02X}", b));
+                        format!("X'{}'", hex.collect::<String>())
+                    }
+                    number => number_text(number),
+                })
+            }
+            "round" => {
+                let n = real(&values[0]).unwrap_or_default();
+                let digits = values.get(1).map_or(0, |v| integer(v).unwrap_or_default());
+                SqlValue::F64(round(n, digits))
+            }
+            _ => return Err(no_such_function(name)),
+        })
+    }
+
+    /// Converts a value the way `CAST(value AS type_name)` does.
+    pub fn cast(&self, value: SqlValue, type_name: Option<&str>) -> SqlValue {
+        if matches!(value, SqlValue::Null) {
+            return SqlValue::Null;
+        }
+        match Affinity::from_type_name(type_name) {
+            Affinity::Text => SqlValue::Text(self.text(&value)),
+            Affinity::Blob => match value {
+                SqlValue::Blob(blob) => SqlValue::Blob(blob),
+                value => SqlValue::Blob(self.encoding.encode(&self.text(&value))),
+            },
+            Affinity::Integer => SqlValue::I64(integer(&value).unwrap_or_default()),
+            Affinity::Real => SqlValue::F64(real(&value).unwrap_or_default()),
+            Affinity::Numeric => match number(&value) {
+                Some(Number::Integer(n)) => SqlValue::I64(n),
+                Some(Number::Real(n)) => Affinity::Numeric.apply(SqlValue::F64(n)),
+                None => SqlValue::Null,
+            },
+        }
+    }
+
+    /// A value as text, the way SQLite converts it for concatenation and string functions.
+    pub fn text(&self, value: &SqlValue) -> String {
+        match value {
+            SqlValue::Null => String::new(),
+            SqlValue::Text(text) => text.clone(),
+            SqlValue::Blob(blob) => self.encoding.decode(blob),
+            number => number_text(number),
+        }
+    }
+}
+
+/// The running state of one aggregate call over the rows of a group.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    name: String,
+    args: Vec<Expr>,
+    /// How the argument's values compare, for min(), max() and DISTINCT.
+    collation: Collation,
+    /// The values seen so far, kept sorted, when the call is DISTINCT.
+    seen: Option<Vec<SqlValue>>,
+    count: i64,
+    sum: Sum,
+    best: SqlValue,
+    concat: Option<String>,
+}
+
+/// A sum kept as an integer until a real or an overflow turns it approximate, as SQLite keeps
+/// it, with the error of the floating point additions carried alongside.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sum {
+    integer: i64,
+    real: f64,
+    error: f64,
+    approximate: bool,
+    overflowed: bool,
+}
+
+impl Sum {
+    /// Adds a value as an integer if it is one or is text spelling one, else as a real.
+    fn add(&mut self, value: &SqlValue) {
+        let value = match value {
+            SqlValue::Text(_) => Affinity::Numeric.apply(value.clone()),
+            value => value.clone(),
+        };
+        match value.as_i64() {
+            Some(n) if !self.approximate => match self.integer.checked_add(n) {
+                Some(sum) => self.integer = sum,
+                None => {
+                    self.overflowed = true;
+                    self.make_approximate();
+                    self.add_integer(n);
+                }
+            },
+            Some(n) => self.add_integer(n),
+            None => {
+                self.make_approximate();
+                self.add_real(real(&value).unwrap_or_default());
+            }
+        }
+    }
+
+    fn make_approximate(&mut self) {
+        if !self.approximate {
+            self.approximate = true;
+            let integer = std::mem::take(&mut self.integer);
+            self.add_integer(integer);
+        }
+    }
+
+    /// Adds an integer in two parts when it is too large for a real to hold exactly.
+    fn add_integer(&mut self, n: i64) {
+        const EXACT: i64 = 1 << 52;
+        if !(-EXACT..EXACT).contains(&n) {
+            let small = n % 16384;
+            self.add_real((n - small) as f64);
+            self.add_real(small as f64);
+        } else {
+            self.add_real(n as f64);
+        }
+    }
+
+    /// Kahan-Babuska-Neumaier summation.
+    fn add_real(&mut self, n: f64) {
+        let sum = self.real + n;
+        if self.real.abs() > n.abs() {
+            self.error += (self.real - sum) + n;
+        } else {
+            self.error += (n - sum) + self.real;
+        }
+        self.real = sum;
+    }
+
+    fn total(&self) -> f64 {
+        if self.approximate {
+            self.real + self.error
+        } else {
+            self.integer as f64
+        }
+    }
+}
+
+impl Accumulator {
+    /// Starts an aggregate call found by [`aggregate_calls`], checking its arguments against
+    /// the scope of the rows it will see.
+    pub fn new(scope: &Scope, call: &Expr) -> Result<Accumulator> {
+        let (name, args, distinct) = match call {
+            Expr::Function {
+                name,
+                args: FunctionArgs::Star,
+            } => (name.to_lowercase(), Vec::new(), false),
+            Expr::Function {
+                name,
+                args: FunctionArgs::List { distinct, args },
+            } => (name.to_lowercase(), args.clone(), *distinct),
+            _ => return Err(Error::Unsupported(format!("aggregate {:?}", call))),
+        };
+        let star = matches!(
+            call,
+            Expr::Function {
+                args: FunctionArgs::Star,
+                ..
+            }
+        );
+        let counts: &[usize] = match name.as_str() {
+            "count" => &[0, 1],
+            "group_concat" => &[1, 2],
+            _ => &[1],
+        };
+        if !counts.contains(&args.len()) || (star && name != "count") {
+            return Err(Error::InvalidStatement(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )));
+        }
+        if distinct && args.len() != 1 {
+            return Err(Error::InvalidStatement(
+                "DISTINCT aggregates must have exactly one argument".to_string(),
+            ));
+        }
+        args.iter().try_for_each(|arg| scope.check(arg))?;
+        let collation = args
+            .first()
+            .and_then(|arg| scope.collation(arg))
+            .map_or(Collation::Binary, |(collation, _)| collation);
+        Ok(Accumulator {
+            name,
+            args,
+            collation,
+            seen: distinct.then(Vec::new),
+            count: 0,
+            sum: Sum::default(),
+            best: SqlValue::Null,
+            concat: None,
+        })
+    }
+
+    /// Whether this is min() or max(), whose row a query's other columns are taken from.
+    pub fn is_min_max(&self) -> bool {
+        self.name == "min" || self.name == "max"
+    }
+
+    /// Feeds the call one row, returning whether the row gave min() or max() a new result.
+    pub fn step(&mut self, scope: &Scope, row: &[SqlValue]) -> Result<bool> {
+        let Some(arg) = self.args.first() else {
+            // count(*) counts rows rather than values.
+            self.count += 1;
+            return Ok(false);
+        };
+        let value = scope.evaluate(arg, row)?;
+        if matches!(value, SqlValue::Null) {
+            return Ok(false);
+        }
+        if let Some(seen) = &mut self.seen {
+            let collation = self.collation;
+            match seen.binary_search_by(|v| collation.compare(v, &value, scope.encoding)) {
+                Ok(_) => return Ok(false),
+                Err(i) => seen.insert(i, value.clone()),
+            }
+        }
+        self.count += 1;
+        match self.name.as_str() {
+            "sum" | "total" | "avg" => self.sum.add(&value),
+            "min" | "max" => {
+                let wanted = if self.name == "min" {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+                let better = matches!(self.best, SqlValue::Null)
+                    || self.collation.compare(&value, &self.best, scope.encoding) == wanted;
+                if better {
+                    self.best = value;
+                }
+                return Ok(better);
+            }
+            "group_concat" => {
+                let text = scope.text(&value);
+                match &mut self.concat {
+                    Some(concat) => {
+                        match self.args.get(1) {
+                            Some(separator) => {
+                                concat.push_str(&scope.text(&scope.evaluate(separator, row)?))
+                            }
+                            None => concat.push(','),
+                        }
+                        concat.push_str(&text);
+                    }
+                    None => self.concat = Some(text),
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// The result of the call over the rows it has seen.
+    pub fn finish(&self) -> Result<SqlValue> {
+        let empty = self.count == 0;
+        Ok(match self.name.as_str() {
+            "count" => SqlValue::I64(self.count),
+            "sum" if empty => SqlValue::Null,
+            "sum" if self.sum.overflowed => {
+                return Err(Error::InvalidStatement("integer overflow".to_string()))
+            }
+            "sum" if self.sum.approximate => SqlValue::F64(self.sum.total()),
+            "sum" => SqlValue::I64(self.sum.integer),
+            "total" => SqlValue::F64(self.sum.total()),
+            "avg" if empty => SqlValue::Null,
+            "avg" => SqlValue::F64(self.sum.total() / self.count as f64),
+            "min" | "max" => self.best.clone(),
+            _ => self.concat.clone().map_or(SqlValue::Null, SqlValue::Text),
+        })
+    }
+}
+
+/// The row [`Scope::add_table`] describes for a stored record of `schema`, whose values are
+/// in declaration order: every column, with the rowid in place of the NULL stored for an
+/// INTEGER PRIMARY KEY, then the rowid itself.
+pub fn table_row(
+    schema: &TableSchema,
+    mut values: Vec<SqlValue>,
+    rowid: Option<i64>,
+) -> Vec<SqlValue> {
+    // Rows written before a column was added have no value for it.
+    values.resize(schema.columns.len(), SqlValue::Null);
+    // A REAL column may store a whole number as an integer to save space.
+    for (value, column) in values.iter_mut().zip(&schema.columns) {
+        if column.affinity == Affinity::Real && value.as_i64().is_some() {
+            *value = Affinity::Real.apply(std::mem::replace(value, SqlValue::Null));
+        }
+    }
+    if let Some(rowid) = rowid {
+        if let Some(alias) = schema.rowid_alias() {
+            values[alias] = SqlValue::I64(rowid);
+        }
+        values.push(SqlValue::I64(rowid));
+    }
+    values
+}
+
+/// The value a literal in a statement stands for.
+pub fn literal(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Integer(n) => SqlValue::I64(*n),
+        Value::Real(n) => SqlValue::F64(*n),
+        Value::Text(text) => SqlValue::Text(text.clone()),
+        Value::Blob(blob) => SqlValue::Blob(blob.clone()),
+    }
+}
+
+/// A value read as true or false, or None for NULL. Text and blobs count by the number they
+/// start with, so 'abc' is false and '1abc' true.
+pub fn truth(value: &SqlValue) -> Option<bool> {
+    match number(value)? {
+        Number::Integer(n) => Some(n != 0),
+        Number::Real(n) => Some(n != 0.0),
+    }
+}
+
+pub fn type_name(value: &SqlValue) -> &'static str {
+    match value {
+        SqlValue::Null => "null",
+        SqlValue::F64(_) => "real",
+        SqlValue::Text(_) => "text",
+        SqlValue::Blob(_) => "blob",
+        _ => "integer",
+    }
+}
+
+/// The expressions directly inside another, not counting subqueries.
+fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Exists(_) | Expr::Subquery(_) => vec![],
+        Expr::Unary { expr, .. }
+        | Expr::IsNull { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::Collate { expr, .. } => vec![expr],
+        Expr::Binary { left, right, .. } => vec![left, right],
+        Expr::Between {
+            expr, low, high, ..
+        } => vec![expr, low, high],
+        Expr::In { expr, list, .. } => {
+            let mut children = vec![expr.as_ref()];
+            if let InList::Values(items) = list {
+                children.extend(items);
+            }
+            children
+        }
+        Expr::Like {
+            expr,
+            pattern,
+            escape,
+            ..
+        } => {
+            let mut children = vec![expr.as_ref(), pattern];
+            children.extend(escape.as_deref());
+            children
+        }
+        Expr::Function { args, .. } => match args {
+            FunctionArgs::Star => vec![],
+            FunctionArgs::List { args, .. } => args.iter().collect(),
+        },
+        Expr::Case {
+            operand,
+            branches,
+            else_,
+        } => {
+            let mut children = Vec::new();
+            children.extend(operand.as_deref());
+            for (when, then) in branches {
+                children.push(when);
+                children.push(then);
+            }
+            children.extend(else_.as_deref());
+            children
+        }
+    }
+}
+
+/// The aggregate calls in an expression, each listed once, not counting any nested in another.
+pub fn aggregate_calls(expr: &Expr, calls: &mut Vec<Expr>) {
+    match expr {
+        Expr::Function { name, args } if is_aggregate_call(name, args) => {
+            if !calls.contains(expr) {
+                calls.push(expr.clone());
+            }
+        }
+        _ => children(expr)
+            .into_iter()
+            .for_each(|child| aggregate_calls(child, calls)),
+    }
+}
+
+fn is_aggregate_call(name: &str, args: &FunctionArgs) -> bool {
+    match args {
+        FunctionArgs::Star => is_aggregate(name, 1),
+        FunctionArgs::List { args, .. } => is_aggregate(name, args.len()),
+    }
+}
+
+/// Whether a call with this many arguments is to one of the aggregate functions.
+pub fn is_aggregate(name: &str, arg_count: usize) -> bool {
+    match name.to_lowercase().as_str() {
+        "count" | "sum" | "total" | "avg" | "group_concat" => true,
+        "min" | "max" => arg_count == 1,
+        _ => false,
+    }
+}
+
+fn misuse_of_aggregate(name: &str) -> Error {
+    Error::InvalidStatement(format!("misuse of aggregate function {}()", name))
+}
+
+fn no_such_function(name: &str) -> Error {
+    Error::InvalidStatement(format!("no such function: {}", name))
+}
+
+fn unsupported_subquery() -> Error {
+    Error::Unsupported("subqueries (IN (SELECT ...), EXISTS and scalar SELECTs)".to_string())
+}
+
+fn boolean(value: bool) -> SqlValue {
+    SqlValue::I64(value as i64)
+}
+
+fn from_truth(value: Option<bool>) -> SqlValue {
+    value.map_or(SqlValue::Null, boolean)
+}
+
+/// Three-valued AND: false wins over NULL, which wins over true.
+fn and(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+fn negate(value: Option<bool>, negated: bool) -> SqlValue {
+    from_truth(value.map(|b| b != negated))
+}
+
+enum Number {
+    Integer(i64),
+    Real(f64),
+}
+
+/// A value as a number for arithmetic, or None for NULL.
+fn number(value: &SqlValue) -> Option<Number> {
+    match value {
+        SqlValue::Null => None,
+        SqlValue::F64(n) => Some(Number::Real(*n)),
+        SqlValue::Text(text) => number(&numeric_prefix(text)),
+        SqlValue::Blob(blob) => number(&numeric_prefix(&String::from_utf8_lossy(blob))),
+        int => Some(Number::Integer(int.as_i64().unwrap_or_default())),
+    }
+}
+
+/// A value as an integer, the way CAST and the bitwise operators see it: reals are truncated
+/// and text only counts up to the end of its leading digits.
+fn integer(value: &SqlValue) -> Option<i64> {
+    match value {
+        SqlValue::Null => None,
+        SqlValue::F64(n) => Some(*n as i64),
+        SqlValue::Text(text) => Some(integer_prefix(text)),
+        SqlValue::Blob(blob) => Some(integer_prefix(&String::from_utf8_lossy(blob))),
+        int => int.as_i64(),
+    }
+}
+
+fn real(value: &SqlValue) -> Option<f64> {
+    match number(value)? {
+        Number::Integer(n) => Some(n as f64),
+        Number::Real(n) => Some(n),
+    }
+}
+
+/// `n` as an integer, if it has no fractional part and is inside the range of one.
+fn exact_integer(n: f64) -> Option<i64> {
+    let int = n as i64;
+    (int as f64 == n && int > i64::MIN && int < i64::MAX).then_some(int)
+}
+
+fn number_text(value: &SqlValue) -> String {
+    match value {
+        SqlValue::F64(n) => format_real(*n),
+        other => other.as_i64().unwrap_or_default().to_string(),
+    }
+}
+
+/// Length of the number at the start of `text`, if any, and whether it is written as an
+/// integer: an optional sign, digits with an optional decimal point, and an optional
+/// exponent.
+fn scan_number(text: &str) -> (usize, bool) {
+    let bytes = text.as_bytes();
+    let digits_from = |start: usize| {
+        bytes[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let mut end = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let mut digits = digits_from(end);
+    end += digits;
+    let mut is_integer = true;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction = digits_from(end + 1);
+        if digits + fraction > 0 {
+            end += 1 + fraction;
+            digits += fraction;
+            is_integer = false;
+        }
+    }
+    if digits == 0 {
+        return (0, true);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exponent = end + 1;
+        if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+            exponent += 1;
+        }
+        let exponent_digits = digits_from(exponent);
+        if exponent_digits > 0 {
+            end = exponent + exponent_digits;
+            is_integer = false;
+        }
+    }
+    (end, is_integer)
+}
+
+fn number_from(text: &str, is_integer: bool) -> SqlValue {
+    if is_integer {
+        if let Ok(n) = text.parse::<i64>() {
+            return SqlValue::I64(n);
+        }
+    }
+    SqlValue::F64(text.parse().unwrap_or_default())
+}
+
+/// The number text spells if it is nothing else but surrounding spaces. Integers too large
+/// for 64 bits become reals.
+fn parse_numeric(text: &str) -> Option<SqlValue> {
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (len, is_integer) = scan_number(text);
+    (len > 0 && len == text.len()).then(|| number_from(text, is_integer))
+}
+
+/// The number text starts with after any spaces, or 0.
+fn numeric_prefix(text: &str) -> SqlValue {
+    let text = text.trim_start_matches(|c: char| c.is_ascii_whitespace());
+    match scan_number(text) {
+        (0, _) => SqlValue::I64(0),
+        (len, is_integer) => number_from(&text[..len], is_integer),
+    }
+}
+
+/// The integer text starts with after any spaces, clamped to the range of one, or 0.
+fn integer_prefix(text: &str) -> i64 {
+    let text = text.trim_start_matches(|c: char| c.is_ascii_whitespace());
+    let sign = usize::from(matches!(text.as_bytes().first(), Some(b'+' | b'-')));
+    let digits = text[sign..].bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 {
+        return 0;
+    }
+    match text[..sign + digits].parse() {
+        Ok(n) => n,
+        Err(_) if text.starts_with('-') => i64::MIN,
+        Err(_) => i64::MAX,
+    }
+}
+
+/// Integer arithmetic that overflows moves to reals, and division or remainder by zero is
+/// NULL.
+fn arithmetic(op: BinaryOp, a: Number, b: Number) -> SqlValue {
+    let result = match (a, b) {
+        (Number::Integer(a), Number::Integer(b)) => {
+            let exact = match op {
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Subtract => a.checked_sub(b),
+                BinaryOp::Multiply => a.checked_mul(b),
+                BinaryOp::Divide if b == 0 => return SqlValue::Null,
+                BinaryOp::Divide => a.checked_div(b),
+                BinaryOp::Modulo if b == 0 => return SqlValue::Null,
+                _ => Some(a.checked_rem(b).unwrap_or(0)),
+            };
+            match exact {
+                Some(n) => return SqlValue::I64(n),
+                None => real_arithmetic(op, a as f64, b as f64),
+            }
+        }
+        (a, b) => {
+            let as_real = |n| match n {
+                Number::Integer(n) => n as f64,
+                Number::Real(n) => n,
+            };
+            real_arithmetic(op, as_real(a), as_real(b))
+        }
+    };
+    match result {
+        Some(n) if !n.is_nan() => SqlValue::F64(n),
+        _ => SqlValue::Null,
+    }
+}
+
+fn real_arithmetic(op: BinaryOp, a: f64, b: f64) -> Option<f64> {
+    Some(match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Subtract => a - b,
+        BinaryOp::Multiply => a * b,
+        BinaryOp::Divide if b == 0.0 => return None,
+        BinaryOp::Divide => a / b,
+        // The remainder of reals is that of their integer parts.
+        _ => {
+            let (a, b) = (a as i64, b as i64);
+            if b == 0 {
+                return None;
+            }
+            a.checked_rem(b).unwrap_or(0) as f64
+        }
+    })
+}
+
+/// Rounds to a number of decimal places, with ties going away from zero as in SQLite, where
+/// Rust would pick the even neighbour.
+fn round(n: f64, digits: i64) -> f64 {
+    // From 2^52 on a real has no fractional part to round.
+    if n.is_nan() || n.abs() >= 4503599627370496.0 {
+        return n;
+    }
+    let digits = digits.clamp(0, 20) as usize;
+    // The digits past the ones kept tell a tie from a value just below one.
+    let exact = format!("{:.*}", digits + 20, n.abs());
+    let (kept, rest) = exact.split_at(exact.len() - 20);
+    let mut units: u128 = kept.replace('.', "").parse().unwrap_or_default();
+    if rest.as_bytes()[0] >= b'5' {
+        units += 1;
+    }
+    let rounded: f64 = format!("{}e-{}", units, digits).parse().unwrap_or(n);
+    if n < 0.0 {
+        -rounded
+    } else {
+        rounded
+    }
+}
+
+/// Shifts by a negative amount go the other way, and shifting everything out leaves 0, or -1
+/// for a negative number shifted right.
+fn bitwise(op: BinaryOp, a: i64, b: i64) -> i64 {
+    let shift = |a: i64, left: bool, by: i64| {
+        let (left, by) = if by < 0 {
+            (!left, by.unsigned_abs())
+        } else {
+            (left, by as u64)
+        };
+        match (left, by) {
+            (true, 64..) => 0,
+            (true, by) => a << by,
+            (false, 64..) => a >> 63,
+            (false, by) => a >> by,
+        }
+    };
+    match op {
+        BinaryOp::BitAnd => a & b,
+        BinaryOp::BitOr => a | b,
+        BinaryOp::ShiftLeft => shift(a, true, b),
+        _ => shift(a, false, b),
+    }
+}
+
+/// The part of a string of `len` characters that `substr(x, start, length)` picks, with
+/// SQLite's handling of a start from the end and of negative lengths.
+fn substr_range(len: usize, start: i64, length: Option<i64>) -> (usize, usize) {
+    let len = len as i64;
+    let mut start = start;
+    let mut length = length.unwrap_or(len.saturating_add(1));
+    let negative_length = length < 0;
+    if negative_length {
+        length = length.saturating_neg();
+    }
+    if start < 0 {
+        start += len;
+        if start < 0 {
+            length = (length + start).max(0);
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if length > 0 {
+        length -= 1;
+    }
+    if negative_length {
+        start -= length;
+        if start < 0 {
+            length += start;
+            start = 0;
+        }
+    }
+    let from = start.clamp(0, len);
+    let to = start.saturating_add(length).clamp(from, len);
+    (from as usize, to as usize)
+}
+
+fn find_position<T: PartialEq>(haystack: &[T], needle: &[T]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// One element of a LIKE or GLOB pattern.
+enum PatternElement {
+    /// `%` or `*`
+    AnySequence,
+    /// `_` or `?`
+    AnyChar,
+    Char(char),
+    /// A GLOB `[...]` class of characters and ranges.
+    Set {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl PatternElement {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        match self {
+            PatternElement::AnySequence | PatternElement::AnyChar => true,
+            PatternElement::Char(p) if ignore_case => p.eq_ignore_ascii_case(&c),
+            PatternElement::Char(p) => *p == c,
+            PatternElement::Set { negated, ranges } => {
+                ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+/// Matches text against a pattern, where only an AnySequence can stand for more or fewer
+/// than one character. A mismatch goes back to the last AnySequence and lets it take one
+/// more character.
+fn match_pattern(pattern: &[PatternElement], text: &[char], ignore_case: bool) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(PatternElement::AnySequence) => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(element) if element.matches(text[t], ignore_case) => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((sequence, taken)) => {
+                p = sequence + 1;
+                t = taken + 1;
+                backtrack = Some((sequence, taken + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..]
+        .iter()
+        .all(|element| matches!(element, PatternElement::AnySequence))
+}
+
+/// LIKE: `%` matches any run of characters and `_` any one, ASCII letters match either case,
+/// and the escape character makes the one after it literal.
+fn like(pattern: &str, text: &str, escape: Option<&str>) -> Result<bool> {
+    let escape = match escape {
+        Some(escape) => {
+            let mut chars = escape.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => {
+                    return Err(Error::InvalidStatement(
+                        "ESCAPE expression must be a single character".to_string(),
+                    ))
+                }
+            }
+        }
+        None => None,
+    };
+    let mut elements = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        elements.push(match c {
+            c if Some(c) == escape => match chars.next() {
+                Some(c) => PatternElement::Char(c),
+                None => return Ok(false),
+            },
+            '%' => PatternElement::AnySequence,
+            '_' => PatternElement::AnyChar,
+            c => PatternElement::Char(c),
+        });
+    }
+    Ok(match_pattern(
+        &elements,
+        &text.chars().collect::<Vec<_>>(),
+        true,
+    ))
+}
+
+/// GLOB: `*` matches any run of characters, `?` any one, and `[...]` one from a set, which
+/// `^` negates and where `]` first is literal. Case matters.
+fn glob(pattern: &str, text: &str) -> bool {
+    let mut elements = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        elements.push(match c {
+            '*' => PatternElement::AnySequence,
+            '?' => PatternElement::AnyChar,
+            '[' => {
+                let negated = chars.next_if_eq(&'^').is_some();
+                let mut ranges = Vec::new();
+                let mut first = true;
+                loop {
+                    match chars.next() {
+                        // An unterminated set matches nothing.
+                        None => return false,
+                        Some(']') if !first => break,
+                        Some(low) => {
+                            let mut ahead = chars.clone();
+                            match (ahead.next(), ahead.next()) {
+                                (Some('-'), Some(high)) if high != ']' => {
+                                    chars = ahead;
+                                    ranges.push((low, high));
+                                }
+                                _ => ranges.push((low, low)),
+                            }
+                        }
+                    }
+                    first = false;
+                }
+                PatternElement::Set { negated, ranges }
+            }
+            c => PatternElement::Char(c),
+        });
+    }
+    match_pattern(&elements, &text.chars().collect::<Vec<_>>(), false)
+}
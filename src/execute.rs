@@ -0,0 +1,827 @@
+use crate::btree::{
+    copy_btree, create_btree, delete_index_entry, delete_row, insert_index_entry, insert_row,
+    max_rowid,
+};
+use crate::database::{CreateOptions, Database};
+use crate::error::{Error, Result};
+use crate::eval::{literal, table_row, truth, Affinity, Scope};
+use crate::page_io::{
+    full_table_scan, key_lookup, row_lookup, BTreeCursor, IndexColumn, IndexSchema, SqliteSchema,
+    TableSchema,
+};
+use crate::pager::{JournalMode, Pager};
+use crate::syntax;
+use crate::syntax::create_index::CreateIndexStmt;
+use crate::syntax::create_table::CreateTableStmt;
+use crate::syntax::delete::DeleteStmt;
+use crate::syntax::expr::Expr;
+use crate::syntax::insert::InsertStmt;
+use crate::syntax::pragma::PragmaStmt;
+use crate::syntax::statement::Statement;
+use crate::syntax::update::UpdateStmt;
+use crate::typecodes::{encode_record, SqlValue};
+use crate::wal::CheckpointMode;
+use std::path::Path;
+
+/// Runs a statement that changes the database or controls a transaction, and returns the
+/// number of rows changed. Outside a transaction each change is flushed as soon as it is made.
+pub fn execute(pager: &Pager, stmt: &Statement) -> Result<usize> {
+    autocommit(pager, || run(pager, stmt))
+}
+
+/// Like [`execute`], but also given the statement's text, which CREATE statements keep in the
+/// schema.
+pub fn execute_sql(pager: &Pager, stmt: &Statement, sql: &str) -> Result<usize> {
+    autocommit(pager, || match stmt {
+        Statement::CreateTable(create) => create_table(pager, create, sql).map(|()| 0),
+        Statement::CreateIndex { stmt, unique } => {
+            create_index(pager, stmt, *unique, sql).map(|()| 0)
+        }
+        _ => run(pager, stmt),
+    })
+}
+
+/// Runs a statement that is not part of a transaction as a transaction of its own: flushed
+/// once it succeeds, and rolled back entirely, letting go of any lock it took, if anything
+/// fails along the way.
+fn autocommit(pager: &Pager, statement: impl FnOnce() -> Result<usize>) -> Result<usize> {
+    if pager.in_transaction() {
+        return statement();
+    }
+    let result = statement().and_then(|changed| {
+        // BEGIN and SAVEPOINT start holding changes back instead.
+        if !pager.in_transaction() {
+            pager.flush()?;
+        }
+        Ok(changed)
+    });
+    if result.is_err() {
+        pager.rollback();
+    }
+    result
+}
+
+fn run(pager: &Pager, stmt: &Statement) -> Result<usize> {
+    let changed = match stmt {
+        Statement::Insert(stmt) => insert(pager, stmt)?,
+        Statement::Update(stmt) => update(pager, stmt)?,
+        Statement::Delete(stmt) => delete(pager, stmt)?,
+        Statement::Begin => {
+            pager.begin()?;
+            0
+        }
+        Statement::Commit => {
+            pager.commit()?;
+            0
+        }
+        Statement::Rollback(None) => {
+            if !pager.in_transaction() {
+                return Err(Error::InvalidStatement(
+                    "cannot rollback - no transaction is active".to_string(),
+                ));
+            }
+            pager.rollback();
+            0
+        }
+        Statement::Rollback(Some(name)) => {
+            pager.rollback_to(name)?;
+            0
+        }
+        Statement::Savepoint(name) => {
+            pager.savepoint(name);
+            0
+        }
+        Statement::Release(name) => {
+            pager.release(name)?;
+            0
+        }
+        Statement::Pragma(stmt) => {
+            pragma(pager, stmt)?;
+            0
+        }
+        Statement::Vacuum(into) => {
+            vacuum(pager, into.as_deref())?;
+            0
+        }
+        _ => {
+            return Err(Error::Unsupported(
+                "executing this kind of statement".to_string(),
+            ))
+        }
+    };
+    Ok(changed)
+}
+
+/// Runs a PRAGMA and returns the rows it produces.
+pub fn pragma(pager: &Pager, stmt: &PragmaStmt) -> Result<Vec<Vec<SqlValue>>> {
+    if let Some(schema) = &stmt.schema_name {
+        if !schema.eq_ignore_ascii_case("main") {
+            return Err(Error::Unsupported(format!("schema {}", schema)));
+        }
+    }
+    let value = stmt.value.as_ref().map(|value| value.to_lowercase());
+    match stmt.name.to_lowercase().as_str() {
+        "journal_mode" => {
+            let mode = match value.as_deref() {
+                None => pager.journal_mode(),
+                Some("wal") => pager.set_journal_mode(JournalMode::Wal)?,
+                Some("delete") => pager.set_journal_mode(JournalMode::Delete)?,
+                Some(mode) => return Err(Error::Unsupported(format!("journal mode {}", mode))),
+            };
+            Ok(vec![vec![SqlValue::Text(mode.name().to_string())]])
+        }
+        "wal_checkpoint" => {
+            // Like SQLite, anything that is not another mode's name means PASSIVE.
+            let mode = match value.as_deref() {
+                Some("full") => CheckpointMode::Full,
+                Some("restart") => CheckpointMode::Restart,
+                Some("truncate") => CheckpointMode::Truncate,
+                _ => CheckpointMode::Passive,
+            };
+            // The row is whether the checkpoint was blocked, the number of frames in the log
+            // and how many of them have been checkpointed, which are -1 outside WAL mode.
+            let (busy, log, checkpointed) = match pager.checkpoint(mode)? {
+                Some(checkpoint) => (
+                    checkpoint.busy as i64,
+                    checkpoint.log_frames,
+                    checkpoint.checkpointed,
+                ),
+                None => (0, -1, -1),
+            };
+            Ok(vec![vec![
+                SqlValue::I64(busy),
+                SqlValue::I64(log),
+                SqlValue::I64(checkpointed),
+            ]])
+        }
+        name => Err(Error::Unsupported(format!("PRAGMA {}", name))),
+    }
+}
+
+/// Runs an INSERT and returns the number of rows added. Nothing is written to the file until
+/// the pager is flushed, and on error every change made by the statement is undone.
+pub fn insert(pager: &Pager, stmt: &InsertStmt) -> Result<usize> {
+    atomically(pager, || insert_rows(pager, stmt))
+}
+
+/// Runs a DELETE and returns the number of rows removed, with the same all-or-nothing
+/// behaviour as [`insert`].
+pub fn delete(pager: &Pager, stmt: &DeleteStmt) -> Result<usize> {
+    atomically(pager, || delete_rows(pager, stmt))
+}
+
+/// Runs an UPDATE and returns the number of rows changed, with the same all-or-nothing
+/// behaviour as [`insert`].
+pub fn update(pager: &Pager, stmt: &UpdateStmt) -> Result<usize> {
+    atomically(pager, || update_rows(pager, stmt))
+}
+
+/// Runs a CREATE TABLE whose text is `sql`: allocates the table's root page and one for each
+/// index its UNIQUE and PRIMARY KEY constraints need, and adds them to the schema.
+pub fn create_table(pager: &Pager, stmt: &CreateTableStmt, sql: &str) -> Result<()> {
+    atomically(pager, || add_table(pager, stmt, sql))
+}
+
+/// Runs a CREATE INDEX whose text is `sql`, filling the new index from the rows already in
+/// its table.
+pub fn create_index(pager: &Pager, stmt: &CreateIndexStmt, unique: bool, sql: &str) -> Result<()> {
+    atomically(pager, || add_index(pager, stmt, unique, sql))
+}
+
+/// Rebuilds the database with every b-tree packed into consecutive pages and nothing left on
+/// the freelist. With `into`, the rebuilt copy goes to a new file and this one is left alone;
+/// otherwise it replaces this one's contents in a single flush.
+pub fn vacuum(pager: &Pager, into: Option<&str>) -> Result<()> {
+    if pager.in_transaction() {
+        return Err(Error::InvalidStatement(
+            "cannot VACUUM from within a transaction".to_string(),
+        ));
+    }
+    let options = CreateOptions::from_header(&pager.header());
+    let Some(path) = into else {
+        return atomically(pager, || {
+            let copy = Pager::from_bytes(options.empty_database()?)?;
+            copy_database(pager, &copy)?;
+            pager.replace_with(&copy)
+        });
+    };
+    if Path::new(path).exists() {
+        return Err(Error::InvalidStatement(
+            "output file already exists".to_string(),
+        ));
+    }
+    let copied = Database::create(path, options).and_then(|database| {
+        copy_database(pager, database.pager())?;
+        database.pager().flush()
+    });
+    if copied.is_err() {
+        // Leave nothing half written behind.
+        let _ = std::fs::remove_file(path);
+    }
+    copied
+}
+
+/// Copies each b-tree of `source` into `target`, which starts out empty, along with the schema
+/// rows pointing at them.
+fn copy_database(source: &Pager, target: &Pager) -> Result<()> {
+    let header = target.header();
+    for row in full_table_scan(source, 1)? {
+        let rowid = row.rowid().unwrap_or_default() as i64;
+        let mut values = row.values;
+        if let Some(rootpage) = values.get(3).and_then(SqlValue::as_i64) {
+            if rootpage > 0 {
+                let copied = copy_btree(source, rootpage as u64, target)?;
+                values[3] = SqlValue::I64(copied as i64);
+            }
+        }
+        let payload = encode_record(&values, header.text_encoding, header.schema_format);
+        insert_row(target, 1, rowid, &payload)?;
+    }
+    Ok(())
+}
+
+/// Runs a statement, undoing whatever it changed if it fails but leaving earlier changes in
+/// the same transaction alone.
+fn atomically<T>(pager: &Pager, statement: impl FnOnce() -> Result<T>) -> Result<T> {
+    pager.begin_write()?;
+    let snapshot = pager.snapshot();
+    let result = statement();
+    if result.is_err() {
+        pager.restore(snapshot);
+    }
+    result
+}
+
+/// A rowid table being written to, along with the indexes that have to be kept in step.
+struct Target {
+    name: String,
+    rootpage: u64,
+    schema: TableSchema,
+    /// Each index with the positions of the table columns it covers.
+    indexes: Vec<(IndexSchema, Vec<usize>)>,
+    /// The table's columns, for evaluating conditions against its rows.
+    scope: Scope,
+}
+
+impl Target {
+    fn load(pager: &Pager, table_name: &str, action: &str) -> Result<Target> {
+        let schema = SqliteSchema::from_pager(pager)?;
+        let table = schema.find_table(table_name)?;
+        let table_schema = match syntax::parse(&table.sql)? {
+            Statement::CreateTable(create) => TableSchema::from_ast(&create)?,
+            _ => {
+                return Err(Error::Corrupt(format!(
+                    "bad schema for table {}",
+                    table.name
+                )))
+            }
+        };
+        if table_schema.without_rowid {
+            return Err(Error::Unsupported(format!(
+                "{} a WITHOUT ROWID table",
+                action
+            )));
+        }
+        let mut scope = Scope::new(pager.header().text_encoding);
+        scope.add_table(&table.name, &table_schema);
+        let mut indexes = Vec::new();
+        for element in &schema.schema_elements {
+            if element.element_type == "index" && element.tbl_name == table.name {
+                let index = IndexSchema::from_element(element, &table_schema)?;
+                let columns = index
+                    .columns
+                    .iter()
+                    .map(|column| match &column.name {
+                        Some(name) => table_schema.column_index(name),
+                        None => Err(Error::Unsupported(format!(
+                            "{} a table with an index on an expression",
+                            action
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if let Some(condition) = &index.where_clause {
+                    scope.check(condition)?;
+                }
+                indexes.push((index, columns));
+            }
+        }
+        Ok(Target {
+            name: table.name.clone(),
+            rootpage: table.rootpage,
+            schema: table_schema,
+            indexes,
+            scope,
+        })
+    }
+
+    /// Position of a named column, or None for one of the names SQLite gives the rowid.
+    fn resolve(&self, name: &str) -> Result<Option<usize>> {
+        match self.schema.column_index(name) {
+            Ok(column) if Some(column) == self.schema.rowid_alias() => Ok(None),
+            Ok(column) => Ok(Some(column)),
+            Err(_) if ["rowid", "oid", "_rowid_"].contains(&name.to_lowercase().as_str()) => {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Rows meeting the condition, as rowid and record values.
+    fn matching_rows(
+        &self,
+        pager: &Pager,
+        where_clause: Option<&Expr>,
+    ) -> Result<Vec<(i64, Vec<SqlValue>)>> {
+        if let Some(condition) = where_clause {
+            self.scope.check(condition)?;
+        }
+        let mut rows = Vec::new();
+        let mut cursor = BTreeCursor::new(pager, self.rootpage);
+        let mut found = cursor.first()?;
+        while found {
+            let record = cursor.record()?;
+            found = cursor.next()?;
+            let rowid = record
+                .rowid()
+                .ok_or_else(|| Error::corrupt_page(self.rootpage, "row has no rowid"))?
+                as i64;
+            let mut values = record.values;
+            // Rows written before a column was added have no value for it.
+            values.resize(self.schema.columns.len(), SqlValue::Null);
+            let matches = match where_clause {
+                Some(condition) => {
+                    let row = table_row(&self.schema, values.clone(), Some(rowid));
+                    self.scope.is_true(condition, &row)?
+                }
+                None => true,
+            };
+            if matches {
+                rows.push((rowid, values));
+            }
+        }
+        Ok(rows)
+    }
+
+    fn check_not_null(&self, values: &[SqlValue]) -> Result<()> {
+        let rowid_alias = self.schema.rowid_alias();
+        for (i, column) in self.schema.columns.iter().enumerate() {
+            if column.not_null && Some(i) != rowid_alias && matches!(values[i], SqlValue::Null) {
+                return Err(Error::Constraint(format!(
+                    "NOT NULL constraint failed: {}.{}",
+                    self.name, column.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if the row makes any of the table's CHECK constraints false; NULL passes.
+    fn check_checks(&self, values: &[SqlValue], rowid: i64) -> Result<()> {
+        let row = table_row(&self.schema, values.to_vec(), Some(rowid));
+        for check in &self.schema.checks {
+            if truth(&self.scope.evaluate(check, &row)?) == Some(false) {
+                return Err(Error::Constraint(format!(
+                    "CHECK constraint failed: {}",
+                    self.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The value of each column of a new row that an INSERT names no value for: its DEFAULT
+    /// with the column's affinity applied, or NULL.
+    fn default_values(&self) -> Result<Vec<SqlValue>> {
+        // A default cannot refer to any column.
+        let scope = Scope::new(self.scope.encoding());
+        self.schema
+            .columns
+            .iter()
+            .map(|column| match &column.default {
+                Some(default) => Ok(column.affinity.apply(scope.evaluate(default, &[])?)),
+                None => Ok(SqlValue::Null),
+            })
+            .collect()
+    }
+
+    fn check_rowid_free(&self, pager: &Pager, rowid: i64) -> Result<()> {
+        if row_lookup(pager, self.rootpage, rowid)?.is_none() {
+            return Ok(());
+        }
+        let column = match self.schema.rowid_alias() {
+            Some(alias) => &self.schema.columns[alias].name,
+            None => "rowid",
+        };
+        Err(Error::Constraint(format!(
+            "UNIQUE constraint failed: {}.{}",
+            self.name, column
+        )))
+    }
+
+    /// The key each index holds for a row, not counting the rowid at the end, or None for a
+    /// partial index the row is not in. Index keys see the rowid alias as the rowid, not the
+    /// NULL stored in the record.
+    fn index_keys(&self, values: &[SqlValue], rowid: i64) -> Result<Vec<Option<Vec<SqlValue>>>> {
+        let rowid_alias = self.schema.rowid_alias();
+        let row = table_row(&self.schema, values.to_vec(), Some(rowid));
+        let mut keys = Vec::new();
+        for (index, columns) in &self.indexes {
+            if let Some(condition) = &index.where_clause {
+                // As in a WHERE clause, a NULL condition leaves the row out.
+                if !self.scope.is_true(condition, &row)? {
+                    keys.push(None);
+                    continue;
+                }
+            }
+            keys.push(Some(
+                columns
+                    .iter()
+                    .map(|&i| match rowid_alias {
+                        Some(alias) if alias == i => SqlValue::I64(rowid),
+                        _ => values[i].clone(),
+                    })
+                    .collect(),
+            ));
+        }
+        Ok(keys)
+    }
+
+    fn check_unique(&self, pager: &Pager, keys: &[Option<Vec<SqlValue>>]) -> Result<()> {
+        for ((index, columns), key) in self.indexes.iter().zip(keys) {
+            let Some(key) = key else { continue };
+            // NULLs are distinct from each other, so a key containing one never conflicts.
+            if !index.unique || key.iter().any(|v| matches!(v, SqlValue::Null)) {
+                continue;
+            }
+            if !key_lookup(pager, index.rootpage, key, &index.key_order())?.is_empty() {
+                let names: Vec<String> = columns
+                    .iter()
+                    .map(|&i| format!("{}.{}", self.name, self.schema.columns[i].name))
+                    .collect();
+                return Err(Error::Constraint(format!(
+                    "UNIQUE constraint failed: {}",
+                    names.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a row and its index entries, once the constraints have been checked.
+    fn write_row(
+        &self,
+        pager: &Pager,
+        rowid: i64,
+        values: &[SqlValue],
+        keys: Vec<Option<Vec<SqlValue>>>,
+    ) -> Result<()> {
+        let header = pager.header();
+        let stored = |value: &SqlValue, column: usize| {
+            self.schema.columns[column].affinity.stored(value.clone())
+        };
+        let values: Vec<SqlValue> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| stored(v, i))
+            .collect();
+        let payload = encode_record(&values, header.text_encoding, header.schema_format);
+        insert_row(pager, self.rootpage, rowid, &payload)?;
+        for ((index, columns), key) in self.indexes.iter().zip(keys) {
+            let Some(key) = key else { continue };
+            let mut key: Vec<SqlValue> = key
+                .iter()
+                .zip(columns)
+                .map(|(v, &i)| stored(v, i))
+                .collect();
+            key.push(SqlValue::I64(rowid));
+            insert_index_entry(pager, index.rootpage, &key, &index.key_order())?;
+        }
+        Ok(())
+    }
+
+    fn remove_row(
+        &self,
+        pager: &Pager,
+        rowid: i64,
+        keys: Vec<Option<Vec<SqlValue>>>,
+    ) -> Result<()> {
+        for ((index, _), key) in self.indexes.iter().zip(keys) {
+            let Some(mut key) = key else { continue };
+            key.push(SqlValue::I64(rowid));
+            if !delete_index_entry(pager, index.rootpage, &key, &index.key_order())? {
+                return Err(Error::Corrupt(format!(
+                    "index {} has no entry for row {}",
+                    index.name, rowid
+                )));
+            }
+        }
+        if !delete_row(pager, self.rootpage, rowid)? {
+            return Err(Error::Corrupt(format!(
+                "row {} is missing from {}",
+                rowid, self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn insert_rows(pager: &Pager, stmt: &InsertStmt) -> Result<usize> {
+    let target = Target::load(pager, &stmt.table_name, "INSERT into")?;
+    let table_schema = &target.schema;
+    let targets = if stmt.columns.is_empty() {
+        (0..table_schema.columns.len()).collect()
+    } else {
+        stmt.columns
+            .iter()
+            .map(|name| table_schema.column_index(name))
+            .collect::<Result<Vec<_>>>()?
+    };
+    let rowid_alias = table_schema.rowid_alias();
+    let defaults = target.default_values()?;
+    for row in &stmt.rows {
+        if row.len() != targets.len() {
+            return Err(Error::InvalidStatement(if stmt.columns.is_empty() {
+                format!(
+                    "table {} has {} columns but {} values were supplied",
+                    target.name,
+                    targets.len(),
+                    row.len()
+                )
+            } else {
+                format!("{} values for {} columns", row.len(), targets.len())
+            }));
+        }
+        let mut values = defaults.clone();
+        for (value, column) in row.iter().zip(&targets) {
+            values[*column] = table_schema.columns[*column].affinity.apply(literal(value));
+        }
+
+        // An INTEGER PRIMARY KEY column is the rowid; the record keeps NULL in its place.
+        let given_rowid = match rowid_alias {
+            Some(alias) => match std::mem::replace(&mut values[alias], SqlValue::Null) {
+                SqlValue::Null => None,
+                value => Some(value.as_i64().ok_or_else(datatype_mismatch)?),
+            },
+            None => None,
+        };
+        target.check_not_null(&values)?;
+        let rowid = match given_rowid {
+            Some(rowid) => {
+                target.check_rowid_free(pager, rowid)?;
+                rowid
+            }
+            None => match max_rowid(pager, target.rootpage)? {
+                Some(i64::MAX) => {
+                    return Err(Error::Unsupported(
+                        "choosing a rowid once the largest one is taken".to_string(),
+                    ))
+                }
+                Some(max) => max + 1,
+                None => 1,
+            },
+        };
+        target.check_checks(&values, rowid)?;
+        let keys = target.index_keys(&values, rowid)?;
+        target.check_unique(pager, &keys)?;
+        target.write_row(pager, rowid, &values, keys)?;
+    }
+    Ok(stmt.rows.len())
+}
+
+fn delete_rows(pager: &Pager, stmt: &DeleteStmt) -> Result<usize> {
+    let target = Target::load(pager, &stmt.table_name, "DELETE from")?;
+    let rows = target.matching_rows(pager, stmt.where_clause.as_ref())?;
+    for (rowid, values) in &rows {
+        target.remove_row(pager, *rowid, target.index_keys(values, *rowid)?)?;
+    }
+    Ok(rows.len())
+}
+
+fn update_rows(pager: &Pager, stmt: &UpdateStmt) -> Result<usize> {
+    let target = Target::load(pager, &stmt.table_name, "UPDATE of")?;
+    let assignments = stmt
+        .assignments
+        .iter()
+        .map(|(name, value)| {
+            target.scope.check(value)?;
+            Ok((target.resolve(name)?, value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let rows = target.matching_rows(pager, stmt.where_clause.as_ref())?;
+    for (rowid, old_values) in &rows {
+        // Every new value is worked out from the row as it was before the update.
+        let old_row = table_row(&target.schema, old_values.clone(), Some(*rowid));
+        let mut values = old_values.clone();
+        let mut new_rowid = *rowid;
+        for (column, value) in &assignments {
+            let value = target.scope.evaluate(value, &old_row)?;
+            match column {
+                Some(column) => {
+                    let affinity = target.schema.columns[*column].affinity;
+                    values[*column] = affinity.apply(value);
+                }
+                None => {
+                    new_rowid = Affinity::Integer
+                        .apply(value)
+                        .as_i64()
+                        .ok_or_else(datatype_mismatch)?;
+                }
+            }
+        }
+        target.check_not_null(&values)?;
+        target.check_checks(&values, new_rowid)?;
+        // The old row is taken out first so it does not conflict with its own new version; a
+        // constraint failure after that rolls the whole statement back.
+        target.remove_row(pager, *rowid, target.index_keys(old_values, *rowid)?)?;
+        if new_rowid != *rowid {
+            target.check_rowid_free(pager, new_rowid)?;
+        }
+        let keys = target.index_keys(&values, new_rowid)?;
+        target.check_unique(pager, &keys)?;
+        target.write_row(pager, new_rowid, &values, keys)?;
+    }
+    Ok(rows.len())
+}
+
+fn add_table(pager: &Pager, stmt: &CreateTableStmt, sql: &str) -> Result<()> {
+    if stmt.temp {
+        return Err(Error::Unsupported("temporary tables".to_string()));
+    }
+    check_schema_name(stmt.schema_name.as_deref())?;
+    let schema = SqliteSchema::from_pager(pager)?;
+    if name_taken(&schema, &stmt.table_name, "table", stmt.if_not_exists)? {
+        return Ok(());
+    }
+    let table = TableSchema::from_ast(stmt)?;
+    let rootpage = create_btree(pager, table.without_rowid)?;
+    let sql = format!(
+        "CREATE TABLE {}",
+        schema_sql(sql, stmt.schema_name.is_some())
+    );
+    add_schema_row(
+        pager,
+        "table",
+        &table.name,
+        &table.name,
+        rootpage,
+        Some(sql),
+    )?;
+    for (i, key) in table.autoindexes.iter().enumerate() {
+        // A WITHOUT ROWID table is its own primary key index.
+        if table.without_rowid && *key == table.primary_key {
+            continue;
+        }
+        let name = format!("sqlite_autoindex_{}_{}", table.name, i + 1);
+        let rootpage = create_btree(pager, true)?;
+        add_schema_row(pager, "index", &name, &table.name, rootpage, None)?;
+    }
+    pager.schema_changed();
+    Ok(())
+}
+
+fn add_index(pager: &Pager, stmt: &CreateIndexStmt, unique: bool, sql: &str) -> Result<()> {
+    check_schema_name(stmt.schema_name.as_deref())?;
+    let schema = SqliteSchema::from_pager(pager)?;
+    if name_taken(&schema, &stmt.index_name, "index", stmt.if_not_exists)? {
+        return Ok(());
+    }
+    let mut target = Target::load(pager, &stmt.table_name, "CREATE INDEX on")?;
+    let columns = stmt
+        .columns
+        .iter()
+        .map(|column| match column.name() {
+            Some(name) => target.schema.column_index(name),
+            None => Err(Error::Unsupported("indexes on expressions".to_string())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(condition) = &stmt.where_clause {
+        target.scope.check(condition)?;
+    }
+    let rootpage = create_btree(pager, true)?;
+    let sql = format!(
+        "CREATE {}INDEX {}",
+        if unique { "UNIQUE " } else { "" },
+        schema_sql(sql, stmt.schema_name.is_some())
+    );
+    add_schema_row(
+        pager,
+        "index",
+        &stmt.index_name,
+        &target.name,
+        rootpage,
+        Some(sql),
+    )?;
+    let index = IndexSchema {
+        name: stmt.index_name.clone(),
+        table_name: target.name.clone(),
+        columns: IndexColumn::from_ast(stmt, &target.schema)?,
+        unique,
+        where_clause: stmt.where_clause.clone(),
+        rootpage,
+    };
+    let order = index.key_order();
+    // The new index is the only one the rows need to go into.
+    target.indexes = vec![(index, columns)];
+    for (rowid, values) in target.matching_rows(pager, None)? {
+        let keys = target.index_keys(&values, rowid)?;
+        target.check_unique(pager, &keys)?;
+        for mut key in keys.into_iter().flatten() {
+            key.push(SqlValue::I64(rowid));
+            insert_index_entry(pager, rootpage, &key, &order)?;
+        }
+    }
+    pager.schema_changed();
+    Ok(())
+}
+
+fn check_schema_name(schema_name: Option<&str>) -> Result<()> {
+    match schema_name {
+        Some(schema) if !schema.eq_ignore_ascii_case("main") => {
+            Err(Error::Unsupported(format!("schema {}", schema)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that no table or index already has the name a new `kind` of object is to be given.
+/// Returns true if there is already one of the same kind and IF NOT EXISTS was given, so
+/// there is nothing to do.
+fn name_taken(schema: &SqliteSchema, name: &str, kind: &str, if_not_exists: bool) -> Result<bool> {
+    if name.len() >= 7 && name[..7].eq_ignore_ascii_case("sqlite_") {
+        return Err(Error::InvalidStatement(format!(
+            "object name reserved for internal use: {}",
+            name
+        )));
+    }
+    let existing = schema
+        .schema_elements
+        .iter()
+        .find(|element| element.name.eq_ignore_ascii_case(name));
+    match existing {
+        Some(element) if element.element_type == kind && if_not_exists => Ok(true),
+        Some(element) if element.element_type == kind => Err(Error::InvalidStatement(format!(
+            "{} {} already exists",
+            kind, element.name
+        ))),
+        Some(element) => Err(Error::InvalidStatement(format!(
+            "there is already {} {} named {}",
+            if element.element_type == "index" {
+                "an"
+            } else {
+                "a"
+            },
+            element.element_type,
+            name
+        ))),
+        None => Ok(false),
+    }
+}
+
+/// What SQLite stores of a CREATE statement after its own `CREATE TABLE` or `CREATE INDEX`:
+/// the text from the new object's name on, without the schema name or IF NOT EXISTS.
+fn schema_sql(sql: &str, qualified: bool) -> &str {
+    let mut rest = sql.trim();
+    loop {
+        let word = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .map_or(rest, |end| &rest[..end]);
+        let keyword = ["create", "unique", "table", "index", "if", "not", "exists"]
+            .iter()
+            .any(|keyword| word.eq_ignore_ascii_case(keyword));
+        if word.is_empty() || !keyword {
+            break;
+        }
+        rest = rest[word.len()..].trim_start();
+    }
+    if qualified {
+        if let Some(dot) = rest.find('.') {
+            rest = rest[dot + 1..].trim_start();
+        }
+    }
+    rest
+}
+
+fn add_schema_row(
+    pager: &Pager,
+    type_: &str,
+    name: &str,
+    tbl_name: &str,
+    rootpage: u64,
+    sql: Option<String>,
+) -> Result<()> {
+    let header = pager.header();
+    let values = [
+        SqlValue::Text(type_.to_string()),
+        SqlValue::Text(name.to_string()),
+        SqlValue::Text(tbl_name.to_string()),
+        SqlValue::I64(rootpage as i64),
+        sql.map_or(SqlValue::Null, SqlValue::Text),
+    ];
+    let rowid = max_rowid(pager, 1)?.map_or(1, |max| max + 1);
+    let payload = encode_record(&values, header.text_encoding, header.schema_format);
+    insert_row(pager, 1, rowid, &payload)
+}
+
+fn datatype_mismatch() -> Error {
+    Error::Constraint("datatype mismatch".to_string())
+}
@@ -0,0 +1,618 @@
+use crate::error::{Error, Result};
+use crate::page_io::{
+    read_record, FreelistTrunk, IndexSchema, KeyOrder, Page, Record, SqlSchemaElement, TableSchema,
+    TextEncoding, MAX_BTREE_DEPTH,
+};
+use crate::pager::Pager;
+use crate::syntax;
+use crate::syntax::statement::Statement;
+use crate::typecodes::SqlValue;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A single problem found by `integrity_check`.
+#[derive(Debug)]
+pub struct Finding {
+    pub page: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.page {
+            Some(page) => write!(f, "Page {}: {}", page, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Walks every b-tree reachable from `sqlite_schema` plus the freelist and reports anything that
+/// does not add up, much like `PRAGMA integrity_check`. An empty list means the file is sound.
+pub fn integrity_check(pager: &Pager) -> Result<Vec<Finding>> {
+    let mut checker = Checker {
+        pager,
+        usable_size: pager.header().usable_size(),
+        encoding: pager.header().text_encoding,
+        page_count: pager.page_count()?,
+        key_order: KeyOrder::default(),
+        referenced: HashSet::new(),
+        findings: Vec::new(),
+    };
+    checker.check_header();
+    checker.check_freelist();
+    checker.mark_reserved_pages();
+
+    let mut schema_rows = Vec::new();
+    checker.check_tree(1, true, &mut schema_rows);
+    let mut schema = Vec::new();
+    for row in schema_rows {
+        match SqlSchemaElement::from_row(row) {
+            Ok(element) => schema.push(element),
+            Err(e) => checker.report(Some(1), e.to_string()),
+        }
+    }
+
+    // Every table is read before any index is checked against it, whatever order the schema
+    // lists them in.
+    let mut tables = HashMap::new();
+    let mut indexes = Vec::new();
+    for element in schema.iter().filter(|e| e.rootpage != 0) {
+        // WITHOUT ROWID tables are stored as index b-trees.
+        let is_table = element.element_type == "table"
+            && !matches!(
+                syntax::parse(&element.sql),
+                Ok(Statement::CreateTable(ast)) if ast.without_rowid
+            );
+        let mut records = Vec::new();
+        checker.key_order = key_order(element, &schema);
+        checker.check_tree(element.rootpage, is_table, &mut records);
+        if is_table {
+            tables.insert(element.name.as_str(), records);
+        } else if element.element_type == "index" {
+            indexes.push((element, records));
+        }
+    }
+    for (index, entries) in indexes {
+        checker.check_index(index, &schema, &tables, &entries);
+    }
+
+    checker.check_unreferenced();
+    Ok(checker.findings)
+}
+
+/// How the entries of an index, or of a WITHOUT ROWID table, are ordered by its definition.
+/// One that cannot be read is checked in BINARY order.
+fn key_order(element: &SqlSchemaElement, schema: &[SqlSchemaElement]) -> KeyOrder {
+    let table_schema = |name: &str| {
+        let table = schema
+            .iter()
+            .find(|e| e.element_type == "table" && e.name == name)?;
+        match syntax::parse(&table.sql) {
+            Ok(Statement::CreateTable(ast)) => TableSchema::from_ast(&ast).ok(),
+            _ => None,
+        }
+    };
+    let order = match element.element_type.as_str() {
+        "table" => table_schema(&element.name).map(|table| table.key_order()),
+        "index" => table_schema(&element.tbl_name)
+            .and_then(|table| IndexSchema::from_element(element, &table).ok())
+            .map(|index| index.key_order()),
+        _ => None,
+    };
+    order.unwrap_or_default()
+}
+
+struct Checker<'a> {
+    pager: &'a Pager,
+    usable_size: usize,
+    encoding: TextEncoding,
+    page_count: u64,
+    /// How the index b-tree being walked orders its entries.
+    key_order: KeyOrder,
+    referenced: HashSet<u64>,
+    findings: Vec<Finding>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, page: Option<u64>, message: impl Into<String>) {
+        self.findings.push(Finding {
+            page,
+            message: message.into(),
+        });
+    }
+
+    /// Records that `page` is in use, returning false if it is out of range or already taken.
+    fn mark(&mut self, page: u64, what: &str) -> bool {
+        if page < 1 || page > self.page_count {
+            self.report(None, format!("invalid page number {} in {}", page, what));
+            return false;
+        }
+        if !self.referenced.insert(page) {
+            self.report(
+                None,
+                format!("2nd reference to page {} from {}", page, what),
+            );
+            return false;
+        }
+        true
+    }
+
+    fn check_header(&mut self) {
+        let header = self.pager.header();
+        // The in-header size is only trusted when it was written by a version that maintains it.
+        if header.version_valid_for == header.file_change_counter
+            && header.database_size as u64 != self.page_count
+        {
+            self.report(
+                None,
+                format!(
+                    "database size is {} pages in the header but the file holds {}",
+                    header.database_size, self.page_count
+                ),
+            );
+        }
+    }
+
+    fn check_freelist(&mut self) {
+        let header = self.pager.header();
+        let expected = header.freelist_count as u64;
+        let mut trunk = header.first_freelist_trunk as u64;
+        let mut found = 0;
+        while trunk != 0 && found < expected {
+            if !self.mark(trunk, "freelist") {
+                break;
+            }
+            found += 1;
+            let trunk_page = match FreelistTrunk::from_pager(self.pager, trunk) {
+                Ok(trunk_page) => trunk_page,
+                Err(e) => {
+                    self.report(None, e.to_string());
+                    break;
+                }
+            };
+            for leaf in trunk_page.leaves {
+                if self.mark(leaf as u64, "freelist") {
+                    found += 1;
+                }
+            }
+            trunk = trunk_page.next_trunk as u64;
+        }
+        if found != expected {
+            self.report(
+                None,
+                format!("freelist size is {} but should be {}", found, expected),
+            );
+        }
+    }
+
+    /// Pointer-map pages and the lock-byte page are in use without belonging to any b-tree.
+    fn mark_reserved_pages(&mut self) {
+        let header = self.pager.header();
+        let pending_byte_page = header.pending_byte_page();
+        if pending_byte_page <= self.page_count {
+            self.referenced.insert(pending_byte_page);
+        }
+        self.referenced.extend(header.ptrmap_pages(self.page_count));
+    }
+
+    fn check_tree(&mut self, root: u64, is_table: bool, records: &mut Vec<Record>) {
+        if !self.mark(root, "a b-tree root") {
+            return;
+        }
+        let mut prev_key = None;
+        self.check_page(root, is_table, 0, (None, None), &mut prev_key, records);
+    }
+
+    /// Checks one page and its subtree. Table rowids must fall within `bounds` (exclusive lower,
+    /// inclusive upper); index entries must sort strictly after `prev_key`. Returns the depth at
+    /// which the subtree's leaves sit, or None if the subtree could not be read.
+    fn check_page(
+        &mut self,
+        page_number: u64,
+        is_table: bool,
+        depth: usize,
+        bounds: (Option<i64>, Option<i64>),
+        prev_key: &mut Option<Vec<SqlValue>>,
+        records: &mut Vec<Record>,
+    ) -> Option<usize> {
+        if depth > MAX_BTREE_DEPTH {
+            self.report(Some(page_number), "b-tree is too deep");
+            return None;
+        }
+        let page = match Page::from_pager(self.pager, page_number) {
+            Ok(page) => page,
+            Err(e) => {
+                self.report(None, e.to_string());
+                return None;
+            }
+        };
+        let expected_type = match (is_table, page.header.is_interior()) {
+            (true, true) => 0x05,
+            (true, false) => 0x0d,
+            (false, true) => 0x02,
+            (false, false) => 0x0a,
+        };
+        if page.header.page_type != expected_type {
+            let kind = if is_table { "table" } else { "index" };
+            self.report(
+                Some(page_number),
+                format!("wrong page type for a {} b-tree", kind),
+            );
+            return None;
+        }
+        self.check_cell_layout(&page);
+        if page.header.is_interior() {
+            self.check_interior(&page, is_table, depth, bounds, prev_key, records)
+        } else {
+            self.check_leaf(&page, is_table, bounds, prev_key, records);
+            Some(depth)
+        }
+    }
+
+    fn check_interior(
+        &mut self,
+        page: &Page,
+        is_table: bool,
+        depth: usize,
+        bounds: (Option<i64>, Option<i64>),
+        prev_key: &mut Option<Vec<SqlValue>>,
+        records: &mut Vec<Record>,
+    ) -> Option<usize> {
+        let mut leaf_depth = None;
+        let mut lower = bounds.0;
+        let mut children = Vec::with_capacity(page.pointer_array.len() + 1);
+        for i in 0..page.pointer_array.len() {
+            children.push((Some(i), page.left_child(i) as u64));
+        }
+        match page.right_child() {
+            Ok(right) => children.push((None, right as u64)),
+            Err(e) => self.report(None, e.to_string()),
+        }
+        for (cell_index, child) in children {
+            let upper = match cell_index {
+                Some(i) if is_table => match page.interior_rowid(i) {
+                    Ok(rowid) => Some(rowid as i64),
+                    Err(e) => {
+                        self.report(None, e.to_string());
+                        return None;
+                    }
+                },
+                _ => bounds.1,
+            };
+            if let (Some(i), Some(key)) = (cell_index, upper.filter(|_| is_table)) {
+                if lower.is_some_and(|lo| key <= lo) || bounds.1.is_some_and(|hi| key > hi) {
+                    self.report(
+                        Some(page.offset),
+                        format!("rowid {} in cell {} out of order", key, i),
+                    );
+                }
+            }
+            if self.mark(child, &format!("page {}", page.offset)) {
+                let child_depth = self.check_page(
+                    child,
+                    is_table,
+                    depth + 1,
+                    (lower, upper),
+                    prev_key,
+                    records,
+                );
+                match (leaf_depth, child_depth) {
+                    (None, d) => leaf_depth = d,
+                    (Some(a), Some(b)) if a != b => {
+                        self.report(Some(child), "child page depth differs");
+                    }
+                    _ => {}
+                }
+            }
+            // Index interior cells carry an entry that sorts between their two subtrees.
+            if let Some(i) = cell_index.filter(|_| !is_table) {
+                match read_record(self.pager, page, i, 2) {
+                    Ok(record) => self.check_index_entry(page.offset, record, prev_key, records),
+                    Err(e) => self.report(None, e.to_string()),
+                }
+                self.check_overflow(page, i);
+            }
+            lower = upper;
+        }
+        leaf_depth
+    }
+
+    fn check_leaf(
+        &mut self,
+        page: &Page,
+        is_table: bool,
+        bounds: (Option<i64>, Option<i64>),
+        prev_key: &mut Option<Vec<SqlValue>>,
+        records: &mut Vec<Record>,
+    ) {
+        let mut prev_rowid = bounds.0;
+        for i in 0..page.pointer_array.len() {
+            self.check_overflow(page, i);
+            let record = match read_record(self.pager, page, i, if is_table { 1 } else { 0 }) {
+                Ok(record) => record,
+                Err(e) => {
+                    self.report(None, e.to_string());
+                    continue;
+                }
+            };
+            if !is_table {
+                self.check_index_entry(page.offset, record, prev_key, records);
+                continue;
+            }
+            let rowid = record.rowid().unwrap_or_default() as i64;
+            if prev_rowid.is_some_and(|prev| rowid <= prev)
+                || bounds.1.is_some_and(|upper| rowid > upper)
+            {
+                self.report(Some(page.offset), format!("rowid {} out of order", rowid));
+            }
+            prev_rowid = Some(rowid);
+            records.push(record);
+        }
+    }
+
+    fn check_index_entry(
+        &mut self,
+        page_number: u64,
+        record: Record,
+        prev_key: &mut Option<Vec<SqlValue>>,
+        records: &mut Vec<Record>,
+    ) {
+        if let Some(prev) = prev_key {
+            if self.key_order.compare(prev, &record.values, self.encoding) != Ordering::Less {
+                self.report(Some(page_number), "index keys out of order");
+            }
+        }
+        *prev_key = Some(record.values.to_vec());
+        records.push(record);
+    }
+
+    fn check_overflow(&mut self, page: &Page, cell_index: usize) {
+        let info = match page.cell_info(cell_index, self.usable_size) {
+            Ok(info) => info,
+            Err(e) => {
+                self.report(None, e.to_string());
+                return;
+            }
+        };
+        let Some(first) = info.overflow_page else {
+            return;
+        };
+        let content_size = self.usable_size - 4;
+        let spilled = info.payload_size as usize - info.local_size;
+        let expected = spilled.div_ceil(content_size);
+        let mut next = first as u64;
+        let mut found = 0;
+        while found < expected {
+            if next == 0 || !self.mark(next, &format!("overflow list of page {}", page.offset)) {
+                break;
+            }
+            found += 1;
+            next = match self.pager.get_page(next) {
+                Ok(data) => u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64,
+                Err(e) => {
+                    self.report(None, e.to_string());
+                    break;
+                }
+            };
+        }
+        if found < expected {
+            self.report(
+                Some(page.offset),
+                format!(
+                    "{} of {} pages missing from overflow list starting at {}",
+                    expected - found,
+                    expected,
+                    first
+                ),
+            );
+        } else if next != 0 {
+            self.report(
+                Some(page.offset),
+                format!("overflow list starting at {} is too long", first),
+            );
+        }
+    }
+
+    /// Makes sure the header, cell pointer array, cells and freeblocks tile the page without
+    /// overlapping and that the leftover bytes match the fragment count.
+    fn check_cell_layout(&mut self, page: &Page) {
+        let usable_size = self.usable_size;
+        let header_start = if page.offset == 1 { 100 } else { 0 };
+        let pointer_array_end = header_start + page.header.size() + page.pointer_array.len() * 2;
+        let content_start = match page.header.cell_content_start {
+            0 => 65536,
+            n => n as usize,
+        };
+        if content_start < pointer_array_end || content_start > usable_size {
+            self.report(Some(page.offset), "cell content area out of range");
+            return;
+        }
+        let mut regions = Vec::with_capacity(page.pointer_array.len());
+        for (i, pointer) in page.pointer_array.iter().enumerate() {
+            let start = *pointer as usize;
+            let size = match page.cell_info(i, usable_size) {
+                Ok(info) => info.size,
+                Err(e) => {
+                    self.report(None, e.to_string());
+                    return;
+                }
+            };
+            if start < content_start || start + size > usable_size {
+                self.report(
+                    Some(page.offset),
+                    format!("cell {} extends outside the cell content area", i),
+                );
+                return;
+            }
+            regions.push((start, start + size));
+        }
+        let mut freeblock = page.header.first_freeblock as usize;
+        let mut last_freeblock_end = 0;
+        while freeblock != 0 {
+            if freeblock < content_start.max(last_freeblock_end) || freeblock + 4 > usable_size {
+                self.report(Some(page.offset), "freeblock list is corrupt");
+                return;
+            }
+            let be_u16 = |offset: usize| {
+                u16::from_be_bytes([page.data[offset], page.data[offset + 1]]) as usize
+            };
+            let size = be_u16(freeblock + 2);
+            if size < 4 || freeblock + size > usable_size {
+                self.report(Some(page.offset), "freeblock list is corrupt");
+                return;
+            }
+            regions.push((freeblock, freeblock + size));
+            last_freeblock_end = freeblock + size;
+            freeblock = be_u16(freeblock);
+        }
+        regions.sort_unstable();
+        let mut used = 0;
+        for pair in regions.windows(2) {
+            if pair[1].0 < pair[0].1 {
+                self.report(
+                    Some(page.offset),
+                    format!("multiple uses for byte {}", pair[1].0),
+                );
+                return;
+            }
+        }
+        for (start, end) in &regions {
+            used += end - start;
+        }
+        let fragmented = usable_size - content_start - used;
+        if fragmented != page.header.num_fragments as usize {
+            self.report(
+                Some(page.offset),
+                format!(
+                    "fragmentation of {} bytes reported as {}",
+                    fragmented, page.header.num_fragments
+                ),
+            );
+        }
+    }
+
+    /// Cross-checks an index against its table: every entry must point at an existing row with
+    /// the same column values, and every row must have exactly one entry.
+    fn check_index(
+        &mut self,
+        index: &SqlSchemaElement,
+        schema: &[SqlSchemaElement],
+        tables: &HashMap<&str, Vec<Record>>,
+        entries: &[Record],
+    ) {
+        // Indexes created for UNIQUE and PRIMARY KEY constraints have no SQL of their own, and
+        // statements the parser does not understand cannot be checked any further.
+        let Ok(Statement::CreateIndex { stmt, .. }) = syntax::parse(&index.sql) else {
+            return;
+        };
+        let Some(rows) = tables.get(index.tbl_name.as_str()) else {
+            // A WITHOUT ROWID table is in the schema but not among the tables of rows.
+            if !schema
+                .iter()
+                .any(|e| e.element_type == "table" && e.name == index.tbl_name)
+            {
+                self.report(
+                    None,
+                    format!(
+                        "index {} is on table {} which is not in the schema",
+                        index.name, index.tbl_name
+                    ),
+                );
+            }
+            return;
+        };
+        let table_schema = schema
+            .iter()
+            .find(|e| e.element_type == "table" && e.name == index.tbl_name)
+            .and_then(|e| match syntax::parse(&e.sql) {
+                Ok(Statement::CreateTable(ast)) => TableSchema::from_ast(&ast).ok(),
+                _ => None,
+            });
+        let Some(table_schema) = table_schema else {
+            return;
+        };
+        let Ok(columns) = stmt
+            .columns
+            .iter()
+            .map(|c| match c.name() {
+                Some(name) => table_schema.column_index(name),
+                // Expressions are not evaluated here.
+                None => Err(Error::Unsupported("indexes on expressions".to_string())),
+            })
+            .collect::<Result<Vec<_>>>()
+        else {
+            return;
+        };
+        let rowid_alias = table_schema.primary_key_index().ok().flatten();
+        let rows_by_id: HashMap<u64, &Record> = rows
+            .iter()
+            .filter_map(|r| r.rowid().map(|rowid| (rowid, r)))
+            .collect();
+
+        let mut indexed = HashSet::new();
+        for entry in entries {
+            let Some(rowid) = entry.values.last().and_then(SqlValue::as_i64) else {
+                self.report(
+                    None,
+                    format!("index {} has an entry without a rowid", index.name),
+                );
+                continue;
+            };
+            let rowid = rowid as u64;
+            indexed.insert(rowid);
+            let Some(row) = rows_by_id.get(&rowid) else {
+                self.report(
+                    None,
+                    format!("index {} references missing row {}", index.name, rowid),
+                );
+                continue;
+            };
+            let matches = columns.iter().enumerate().all(|(i, column)| {
+                let stored = match row.values.get(*column) {
+                    Some(SqlValue::Null) | None if Some(*column) == rowid_alias => {
+                        SqlValue::I64(rowid as i64)
+                    }
+                    Some(value) => value.clone(),
+                    None => SqlValue::Null,
+                };
+                entry
+                    .values
+                    .get(i)
+                    .is_some_and(|v| v.cmp_storage(&stored, self.encoding) == Ordering::Equal)
+            });
+            if !matches {
+                self.report(
+                    None,
+                    format!(
+                        "row {} does not match its entry in index {}",
+                        rowid, index.name
+                    ),
+                );
+            }
+        }
+        // A partial index only covers the rows matching its WHERE clause.
+        if stmt.where_clause.is_some() {
+            return;
+        }
+        for rowid in rows_by_id.keys() {
+            if !indexed.contains(rowid) {
+                self.report(
+                    None,
+                    format!("row {} missing from index {}", rowid, index.name),
+                );
+            }
+        }
+        if entries.len() != rows.len() {
+            self.report(None, format!("wrong # of entries in index {}", index.name));
+        }
+    }
+
+    fn check_unreferenced(&mut self) {
+        for page in 1..=self.page_count {
+            if !self.referenced.contains(&page) {
+                self.report(Some(page), "never used");
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::error::Result;
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{prelude::*, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+/// Each journal header fills a sector. SQLite assumes 512-byte sectors unless the file system
+/// says otherwise, and records the size it used in the header.
+const SECTOR_SIZE: u32 = 512;
+
+/// Path of the rollback journal that belongs to a database file.
+pub fn journal_path<P: AsRef<Path>>(db_path: P) -> PathBuf {
+    let mut path = OsString::from(db_path.as_ref());
+    path.push("-journal");
+    PathBuf::from(path)
+}
+
+#[derive(Debug, Clone)]
+pub struct JournalHeader {
+    /// Number of page records that follow the header, or u32::MAX to work it out from the file
+    /// size.
+    pub record_count: u32,
+    /// Starting value for each record's checksum.
+    pub nonce: u32,
+    /// Size of the database in pages before the transaction began.
+    pub db_size: u32,
+    pub sector_size: u32,
+    pub page_size: u32,
+}
+
+impl JournalHeader {
+    /// Parses a header, returning None for anything that does not start with the magic number.
+    pub fn from_bytes(data: &[u8]) -> Option<JournalHeader> {
+        if data.get(..8)? != JOURNAL_MAGIC {
+            return None;
+        }
+        let be_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        let header = data.get(..28).map(|_| JournalHeader {
+            record_count: be_u32(8),
+            nonce: be_u32(12),
+            db_size: be_u32(16),
+            sector_size: be_u32(20),
+            page_size: be_u32(24),
+        })?;
+        let valid_size = |size: u32, range: std::ops::RangeInclusive<u32>| {
+            range.contains(&size) && size.is_power_of_two()
+        };
+        (valid_size(header.sector_size, 32..=65536) && valid_size(header.page_size, 512..=65536))
+            .then_some(header)
+    }
+
+    /// The header as it is stored, padded out to fill its sector.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0; self.sector_size as usize];
+        data[..8].copy_from_slice(&JOURNAL_MAGIC);
+        for (i, field) in [
+            self.record_count,
+            self.nonce,
+            self.db_size,
+            self.sector_size,
+            self.page_size,
+        ]
+        .iter()
+        .enumerate()
+        {
+            data[8 + i * 4..12 + i * 4].copy_from_slice(&field.to_be_bytes());
+        }
+        data
+    }
+}
+
+/// SQLite's journal record checksum: the nonce plus every 200th byte of the page, counting back
+/// from the end (but never the first byte).
+pub fn record_checksum(nonce: u32, page: &[u8]) -> u32 {
+    let mut checksum = nonce;
+    let mut i = page.len() as isize - 200;
+    while i > 0 {
+        checksum = checksum.wrapping_add(page[i as usize] as u32);
+        i -= 200;
+    }
+    checksum
+}
+
+/// Writes a journal holding the original contents of the pages a commit is about to overwrite,
+/// and makes it durable. The record count is only filled in once the records are on disk, so a
+/// journal cut short by a crash is never played back over the database.
+pub fn write_journal(
+    path: &Path,
+    page_size: u32,
+    db_size: u32,
+    originals: &[(u64, Bytes)],
+) -> Result<()> {
+    let mut header = JournalHeader {
+        record_count: 0,
+        nonce: RandomState::new().build_hasher().finish() as u32,
+        db_size,
+        sector_size: SECTOR_SIZE,
+        page_size,
+    };
+    let mut data = header.to_bytes();
+    for (page_number, page) in originals {
+        data.extend_from_slice(&(*page_number as u32).to_be_bytes());
+        data.extend_from_slice(page);
+        data.extend_from_slice(&record_checksum(header.nonce, page).to_be_bytes());
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    header.record_count = originals.len() as u32;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header.to_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Whether a journal left next to the database needs to be played back: it exists, is not
+/// empty, and has not been zeroed out.
+pub fn is_hot(path: &Path) -> Result<bool> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let mut first = [0];
+    Ok(file.read(&mut first)? == 1 && first[0] != 0)
+}
+
+/// Rolls the database back to the state the journal at `path` saved: every page record with a
+/// valid checksum is written back, the file is cut to its original size, and the journal is
+/// deleted once the database has been synced. Returns the number of pages restored.
+pub fn play_back(db: &mut File, path: &Path) -> Result<usize> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    let mut restored = 0;
+    // The database's original size in pages and bytes, from the first header.
+    let mut original_size = None;
+    let mut offset = 0;
+    // A journal is a series of segments, each a header followed by its page records.
+    while let Some(header) = data.get(offset..).and_then(JournalHeader::from_bytes) {
+        let sector_size = header.sector_size as usize;
+        let record_size = header.page_size as usize + 8;
+        let records_start = offset + sector_size;
+        let record_count = match header.record_count {
+            u32::MAX => data.len().saturating_sub(records_start) / record_size,
+            n => n as usize,
+        };
+        let (db_size, _) = *original_size.get_or_insert((
+            header.db_size,
+            header.db_size as u64 * header.page_size as u64,
+        ));
+        let mut complete = true;
+        for i in 0..record_count {
+            let start = records_start + i * record_size;
+            let Some(record) = data.get(start..start + record_size) else {
+                complete = false;
+                break;
+            };
+            let page_number = u32::from_be_bytes(record[..4].try_into().unwrap());
+            let page = &record[4..record_size - 4];
+            let checksum = u32::from_be_bytes(record[record_size - 4..].try_into().unwrap());
+            if page_number == 0 || checksum != record_checksum(header.nonce, page) {
+                complete = false;
+                break;
+            }
+            // Pages past the original end are about to be cut off anyway.
+            if page_number <= db_size {
+                let start = (page_number as u64 - 1) * header.page_size as u64;
+                db.seek(SeekFrom::Start(start))?;
+                db.write_all(page)?;
+                restored += 1;
+            }
+        }
+        if !complete || record_count == 0 {
+            break;
+        }
+        // The next segment starts on a sector boundary.
+        offset = (records_start + record_count * record_size).next_multiple_of(sector_size);
+    }
+    if let Some((_, len)) = original_size {
+        db.set_len(len)?;
+    }
+    db.sync_all()?;
+    std::fs::remove_file(path)?;
+    Ok(restored)
+}
@@ -0,0 +1,16 @@
+pub mod analyzer;
+pub mod btree;
+pub mod database;
+pub mod error;
+pub mod eval;
+pub mod execute;
+pub mod integrity;
+pub mod journal;
+pub mod lock;
+pub mod page_io;
+pub mod pager;
+pub mod query;
+pub mod syntax;
+pub mod typecodes;
+pub mod utils;
+pub mod wal;
@@ -0,0 +1,169 @@
+use crate::error::{Error, Result};
+use std::fs::File;
+use std::time::{Duration, Instant};
+
+/// SQLite locks a database file by taking POSIX advisory locks on bytes in the 1GiB page, which
+/// the format leaves unused so the locks never cover data. Readers hold read locks on a byte
+/// of the shared range, a writer about to commit takes a write lock on the pending byte so no
+/// new readers start, and the reserved byte marks the one connection that may write.
+pub const PENDING_BYTE: u64 = 0x40000000;
+pub const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+pub const SHARED_FIRST: u64 = PENDING_BYTE + 2;
+pub const SHARED_SIZE: u64 = 510;
+
+/// How much of the database a connection has locked, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    Unlocked,
+    /// Reading; any number of connections can share this.
+    Shared,
+    /// Reading, and intending to write. Only one connection can hold this, but readers can
+    /// still come and go.
+    Reserved,
+    /// Writing to the file. No one else holds any lock.
+    Exclusive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Read,
+    Write,
+    Unlock,
+}
+
+/// Tries to lock (or unlock) `len` bytes of `file` at `start` without waiting. Returns false
+/// if another process holds a conflicting lock.
+#[cfg(unix)]
+pub fn try_lock(file: &File, kind: LockKind, start: u64, len: u64) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: flock is plain data, and a zeroed one is valid before its fields are set.
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+    flock.l_type = match kind {
+        LockKind::Read => libc::F_RDLCK,
+        LockKind::Write => libc::F_WRLCK,
+        LockKind::Unlock => libc::F_UNLCK,
+    } as libc::c_short;
+    flock.l_whence = libc::SEEK_SET as libc::c_short;
+    flock.l_start = start as libc::off_t;
+    flock.l_len = len as libc::off_t;
+    // SAFETY: the descriptor is open for as long as `file` is borrowed, and F_SETLK only reads
+    // the flock it is given.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &flock) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EAGAIN | libc::EACCES) => Ok(false),
+        _ => Err(error.into()),
+    }
+}
+
+/// Whether another process holds a lock on any of the given bytes that would stop us taking
+/// a lock of `kind` on them.
+#[cfg(unix)]
+pub fn held_by_other(file: &File, kind: LockKind, start: u64, len: u64) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: as in try_lock.
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+    flock.l_type = match kind {
+        LockKind::Read => libc::F_RDLCK,
+        LockKind::Write | LockKind::Unlock => libc::F_WRLCK,
+    } as libc::c_short;
+    flock.l_whence = libc::SEEK_SET as libc::c_short;
+    flock.l_start = start as libc::off_t;
+    flock.l_len = len as libc::off_t;
+    // SAFETY: F_GETLK overwrites the flock it is given with the first conflicting lock, if any.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut flock) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(flock.l_type != libc::F_UNLCK as libc::c_short)
+}
+
+/// Without POSIX locks there is nothing to coordinate with, and every lock is granted.
+#[cfg(not(unix))]
+pub fn try_lock(_file: &File, _kind: LockKind, _start: u64, _len: u64) -> Result<bool> {
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn held_by_other(_file: &File, _kind: LockKind, _start: u64, _len: u64) -> Result<bool> {
+    Ok(false)
+}
+
+/// Calls `attempt` until it succeeds, sleeping a little longer between tries each time, and
+/// fails with [`Error::Busy`] once `timeout` has passed.
+pub fn retry(timeout: Duration, mut attempt: impl FnMut() -> Result<bool>) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(1);
+    loop {
+        if attempt()? {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::Busy);
+        }
+        std::thread::sleep(delay.min(deadline - now));
+        delay = (delay * 2).min(Duration::from_millis(50));
+    }
+}
+
+/// Like [`try_lock`], but waits up to `timeout` for a conflicting lock to go away.
+pub fn lock(file: &File, kind: LockKind, start: u64, len: u64, timeout: Duration) -> Result<()> {
+    retry(timeout, || try_lock(file, kind, start, len))
+}
+
+/// Raises a database file's lock from `from` to `to`, waiting up to `timeout` for other
+/// connections to get out of the way.
+pub fn upgrade(file: &File, from: LockLevel, to: LockLevel, timeout: Duration) -> Result<()> {
+    if from >= to {
+        return Ok(());
+    }
+    if from == LockLevel::Unlocked {
+        // A read lock on the pending byte is only granted while no writer is waiting to
+        // commit, and is dropped as soon as the shared lock is held.
+        retry(timeout, || {
+            if !try_lock(file, LockKind::Read, PENDING_BYTE, 1)? {
+                return Ok(false);
+            }
+            let shared = try_lock(file, LockKind::Read, SHARED_FIRST, SHARED_SIZE)?;
+            try_lock(file, LockKind::Unlock, PENDING_BYTE, 1)?;
+            Ok(shared)
+        })?;
+    }
+    if to >= LockLevel::Reserved && from < LockLevel::Reserved {
+        lock(file, LockKind::Write, RESERVED_BYTE, 1, timeout)?;
+    }
+    if to == LockLevel::Exclusive {
+        // Holding the pending byte keeps new readers out while the current ones finish.
+        if let Err(e) = lock(file, LockKind::Write, PENDING_BYTE, 1, timeout) {
+            if from < LockLevel::Reserved {
+                try_lock(file, LockKind::Unlock, RESERVED_BYTE, 1)?;
+            }
+            return Err(e);
+        }
+        if let Err(e) = lock(file, LockKind::Write, SHARED_FIRST, SHARED_SIZE, timeout) {
+            // Go back to where we started rather than blocking readers.
+            let taken = if from < LockLevel::Reserved { 2 } else { 1 };
+            try_lock(file, LockKind::Unlock, PENDING_BYTE, taken)?;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Lowers a database file's lock to `to`, which must be Shared or Unlocked.
+pub fn downgrade(file: &File, to: LockLevel) -> Result<()> {
+    match to {
+        LockLevel::Shared => {
+            try_lock(file, LockKind::Read, SHARED_FIRST, SHARED_SIZE)?;
+            try_lock(file, LockKind::Unlock, PENDING_BYTE, 2)?;
+        }
+        _ => {
+            try_lock(file, LockKind::Unlock, PENDING_BYTE, 2 + SHARED_SIZE)?;
+        }
+    }
+    Ok(())
+}
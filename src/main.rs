@@ -1,19 +1,32 @@
 use anyhow::{bail, Result};
-use page_io::{full_table_scan, index_scan, row_lookup, DbHeader, IndexSchema, Page, SqliteSchema, TableSchema};
-use std::fs::File;
-use syntax::statement::Statement;
-
-mod typecodes;
-mod utils;
-use typecodes::SqlValue;
-mod syntax;
-use syntax::tokenizer::tokenize;
-
-mod page_io;
+use codecrafters_sqlite::analyzer::{page_usage, PageKind};
+use codecrafters_sqlite::database::{CreateOptions, Database};
+use codecrafters_sqlite::error::Error;
+use codecrafters_sqlite::execute;
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::{Page, SqliteSchema};
+use codecrafters_sqlite::pager::{Pager, PagerOptions};
+use codecrafters_sqlite::query;
+use codecrafters_sqlite::syntax;
+use codecrafters_sqlite::syntax::statement::Statement;
+use codecrafters_sqlite::syntax::tokenizer::tokenize;
+use codecrafters_sqlite::typecodes::SqlValue;
+use std::path::Path;
+use std::process::ExitCode;
 
 const TABLESCHEMA_PAGE: u64 = 1;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let args = std::env::args().collect::<Vec<_>>();
     match args.len() {
         0 | 1 => bail!("Missing <database path> and <command>"),
@@ -24,25 +37,110 @@ fn main() -> Result<()> {
     let command = &args[2];
     match command.as_str() {
         ".dbinfo" => {
-            let mut file = File::open(&args[1])?;
-            let dbheader = DbHeader::from_file(&mut file)?;
-            let page = Page::from_file(&mut file, TABLESCHEMA_PAGE, &dbheader)?;
-            let page_header = &page.header;
+            let pager = Pager::open(&args[1])?;
+            let page = Page::from_pager(&pager, TABLESCHEMA_PAGE)?;
+            let schema = SqliteSchema::from_page(&pager, &page)?;
+            let dbheader = pager.header();
+            let count_of = |element_type: &str| {
+                schema
+                    .schema_elements
+                    .iter()
+                    .filter(|e| e.element_type == element_type)
+                    .count()
+            };
+            let schema_size: usize = schema
+                .schema_elements
+                .iter()
+                .map(|e| e.sql.chars().count())
+                .sum();
 
-            println!("database page size: {}", dbheader.page_size);
-            println!("number of tables: {}", page_header.num_cells);
+            println!("database page size:  {}", dbheader.page_size);
+            println!("write format:        {}", dbheader.write_version);
+            println!("read format:         {}", dbheader.read_version);
+            println!("reserved bytes:      {}", dbheader.reserved_bytes);
+            println!("file change counter: {}", dbheader.file_change_counter);
+            println!("database page count: {}", dbheader.database_size);
+            println!("freelist page count: {}", dbheader.freelist_count);
+            println!("schema cookie:       {}", dbheader.schema_cookie);
+            println!("schema format:       {}", dbheader.schema_format);
+            println!("default cache size:  {}", dbheader.default_cache_size);
+            println!("autovacuum top root: {}", dbheader.autovacuum_top_root);
+            println!(
+                "incremental vacuum:  {}",
+                dbheader.incremental_vacuum as u32
+            );
+            println!(
+                "text encoding:       {} ({})",
+                dbheader.text_encoding.as_u32(),
+                dbheader.text_encoding.name()
+            );
+            println!("user version:        {}", dbheader.user_version);
+            println!("application id:      {}", dbheader.application_id);
+            println!("software version:    {}", dbheader.sqlite_version_number);
+            println!("number of tables:    {}", count_of("table"));
+            println!("number of indexes:   {}", count_of("index"));
+            println!("number of triggers:  {}", count_of("trigger"));
+            println!("number of views:     {}", count_of("view"));
+            println!("schema size:         {}", schema_size);
+            println!("data version         1");
         }
         ".tables" => {
-            let mut file = File::open(&args[1])?;
-            let dbheader = DbHeader::from_file(&mut file)?;
-            let page = Page::from_file(&mut file, TABLESCHEMA_PAGE, &dbheader)?;
-            let schema = SqliteSchema::from_page(&mut file, &dbheader, &page)?;
+            let pager = Pager::open(&args[1])?;
+            let page = Page::from_pager(&pager, TABLESCHEMA_PAGE)?;
+            let schema = SqliteSchema::from_page(&pager, &page)?;
             for table in schema.schema_elements {
                 println!("{}", table.name);
             }
         }
+        ".integrity_check" => {
+            let pager = Pager::open(&args[1])?;
+            let findings = integrity_check(&pager)?;
+            if findings.is_empty() {
+                println!("ok");
+            }
+            for finding in findings {
+                println!("{}", finding);
+            }
+        }
+        ".page_usage" => {
+            let pager = Pager::open(&args[1])?;
+            let usage = page_usage(&pager)?;
+            println!("total pages:          {}", usage.pages.len());
+            for kind in [
+                PageKind::TableInterior,
+                PageKind::TableLeaf,
+                PageKind::IndexInterior,
+                PageKind::IndexLeaf,
+                PageKind::Overflow,
+                PageKind::FreelistTrunk,
+                PageKind::FreelistLeaf,
+                PageKind::PointerMap,
+                PageKind::LockByte,
+                PageKind::Orphan,
+            ] {
+                let label = format!("{} pages:", kind.name());
+                println!("{:<21} {}", label, usage.count(kind));
+            }
+            println!("free space:           {:.1}%", usage.free_percent());
+            println!();
+            println!("name|type|pages|interior|leaf|overflow|entries|payload|unused|fill");
+            for object in &usage.objects {
+                println!(
+                    "{}|{}|{}|{}|{}|{}|{}|{}|{}|{:.1}%",
+                    object.name,
+                    if object.is_index { "index" } else { "table" },
+                    object.pages(),
+                    object.interior_pages,
+                    object.leaf_pages,
+                    object.overflow_pages,
+                    object.entries,
+                    object.payload_bytes,
+                    object.unused_bytes,
+                    object.fill_factor(usage.usable_size)
+                );
+            }
+        }
         ".tokenize" => {
-            let mut file = File::open(&args[1])?;
             let input = std::fs::read_to_string(&args[1])?;
             let tokenized = tokenize(&input);
             for token in tokenized {
@@ -50,167 +148,78 @@ fn main() -> Result<()> {
             }
         }
         ".parse" => {
-            let mut file = File::open(&args[1])?;
             let input = std::fs::read_to_string(&args[1])?;
-            let stmt = syntax::parse(&input);
+            let stmt = syntax::parse(&input)?;
             println!("{:?}", stmt);
         }
-        select_count_from
-            if select_count_from
-                .to_uppercase()
-                .starts_with("SELECT COUNT(*) FROM") =>
-        {
-            let mut file = File::open(&args[1])?;
-            let dbheader = DbHeader::from_file(&mut file)?;
-            let page = Page::from_file(&mut file, TABLESCHEMA_PAGE, &dbheader)?;
-            let schema = SqliteSchema::from_page(&mut file, &dbheader, &page)?;
-            let tablename = *select_count_from
-                .split(" ")
-                .collect::<Vec<_>>()
-                .last()
-                .unwrap();
-            let table = schema.schema_elements.iter().find(|t| t.name == tablename);
-            let page = Page::from_file(&mut file, table.unwrap().rootpage, &dbheader)?;
-            println!("{}", page.header.num_cells);
-        }
-        select_rows if select_rows.to_uppercase().starts_with("SELECT") => {
-            let mut file = File::open(&args[1])?;
-            let dbheader = DbHeader::from_file(&mut file)?;
-            let page = Page::from_file(&mut file, TABLESCHEMA_PAGE, &dbheader)?;
-            let schema = SqliteSchema::from_page(&mut file, &dbheader, &page)?;
-            let parsed_select_stmt = syntax::parse(&select_rows);
-            let (cols, tablename) = match parsed_select_stmt {
-                Statement::Select(ref stmt) => (stmt.columns.clone(), stmt.table.clone()),
-                _ => panic!("Expected Select statement"),
+        pragma if pragma.to_uppercase().starts_with("PRAGMA") => {
+            let stmt = match syntax::parse(pragma)? {
+                Statement::Pragma(stmt) => stmt,
+                _ => bail!("Expected Pragma statement"),
             };
-            let table = schema.schema_elements.iter().find(|t| t.name == tablename);
-            let parsed_table_sql = syntax::parse(&table.unwrap().sql);
-            let table_schema = match parsed_table_sql {
-                Statement::CreateTable(stmt) => TableSchema::from_ast(&stmt),
-                _ => panic!("Expected CreateTable statement"),
+            let options = PagerOptions {
+                writable: stmt.writes(),
+                ..Default::default()
             };
-            let mut indices_of_selected_cols = Vec::new();
-            for selected_col in &cols {
-                let index_of_selected_col = table_schema
-                    .columns
+            let pager = Pager::open_with(&args[1], options)?;
+            for row in execute::pragma(&pager, &stmt)? {
+                let values = row
                     .iter()
-                    .position(|c| c.name == *selected_col);
-                match index_of_selected_col {
-                    Some(ix) => {
-                        indices_of_selected_cols.push(ix);
-                    }
-                    None => panic!("Column not found: {}", selected_col),
-                }
-            }
-            let mut indices_of_where_cols = Vec::new();
-            let mut where_clause = Vec::new();
-            match parsed_select_stmt {
-                Statement::Select(ref stmt) => {
-                    for clause in stmt.where_clause.iter() {
-                        let index_of_where_col = table_schema
-                            .columns
-                            .iter()
-                            .position(|c| c.name == clause.column);
-                        match index_of_where_col {
-                            Some(ix) => {
-                                indices_of_where_cols.push(ix);
-                                where_clause.push(clause);
-                            }
-                            None => panic!("Column not found: {}", clause.column),
-                        }
-                    }
-                }
-                _ => panic!("Expected Select statement"),
-            }
-            // get all of the schema elements in the sqlschema, and see if any have type index and name tablename
-            let index_elements = schema
-                .schema_elements
-                .iter()
-                .filter(|e| e.element_type == "index");
-            let mut usable_indexes = Vec::new(); // super advanced query optimition
-            for index in index_elements {
-                let parsed_index_sql = syntax::parse(&index.sql);
-                let index_schema = match parsed_index_sql {
-                    Statement::CreateIndex { unique: _, stmt } => IndexSchema {
-                        name: stmt.index_name,
-                        table_name: stmt.table_name,
-                        columns: stmt.columns,
-                    },
-                    _ => panic!("Expected CreateIndex statement"),
-                };
-                if index_schema.table_name == tablename {
-                    for clause in where_clause.iter() {
-                        if index_schema.columns.contains(&clause.column) {
-                            usable_indexes.push(index.rootpage);
-                        }
-                    }
-                }
+                    .map(|value| match value {
+                        SqlValue::Text(text) => text.clone(),
+                        SqlValue::I64(n) => n.to_string(),
+                        other => format!("{:?}", other),
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", values.join("|"));
             }
-            let mut records;
-            if usable_indexes.len() > 0 {
-                let use_index_page = usable_indexes[0].clone();
-                let mut rowids = Vec::new();
-                for clause in &where_clause {
-                    let rows_for_where =
-                        index_scan(&mut file, &dbheader, use_index_page, &clause.value);
-                    rowids.push(rows_for_where);
-                }
-                let mut rowids_satisfying_all_wheres =
-                    rowids.iter().fold(rowids[0].clone(), |acc, rowids| {
-                        acc.iter()
-                            .filter(|r| rowids.contains(r))
-                            .map(|r| *r)
-                            .collect()
-                    });
-                records = Vec::new();
-                for rowid in rowids_satisfying_all_wheres {
-                    records.extend(row_lookup(
-                        &mut file,
-                        &dbheader,
-                        table.unwrap().rootpage,
-                        rowid,
-                    ));
-                }
+        }
+        script
+            if [
+                "CREATE",
+                "INSERT",
+                "UPDATE",
+                "DELETE",
+                "BEGIN",
+                "COMMIT",
+                "END",
+                "ROLLBACK",
+                "SAVEPOINT",
+                "RELEASE",
+                "VACUUM",
+            ]
+            .iter()
+            .any(|verb| script.to_uppercase().starts_with(verb)) =>
+        {
+            // Nothing runs unless the whole script parses.
+            let statements = syntax::split_script(script)
+                .into_iter()
+                .map(|sql| Ok((syntax::parse(sql)?, sql)))
+                .collect::<std::result::Result<Vec<_>, Error>>()?;
+            // Like sqlite3, writing to a database that does not exist yet creates it.
+            let database = if Path::new(&args[1]).exists() {
+                Database::open(&args[1])?
             } else {
-                records = full_table_scan(&mut file, &dbheader, table.unwrap().rootpage);
+                Database::create(&args[1], CreateOptions::default())?
+            };
+            for (stmt, sql) in &statements {
+                execute::execute_sql(database.pager(), stmt, sql)?;
             }
-
-            'outer: for record in records {
-                for (i, where_idx) in indices_of_where_cols.iter().enumerate() {
-                    let where_col_val = &record.values[*where_idx];
-                    let where_clause_val = &where_clause
-                        .iter()
-                        .find(|c| c.column == where_clause[i].column)
-                        .unwrap()
-                        .value;
-                    match where_col_val {
-                        SqlValue::Text(ref val) => {
-                            if val != where_clause_val {
-                                continue 'outer;
-                            }
-                        }
-                        SqlValue::Null => continue 'outer,
-                        _ => panic!("Only text values are supported for now"),
-                    }
-                }
-                let mut selected_record_cols = Vec::new();
-                if cols.contains(&"id".to_string()) {
-                    selected_record_cols.push(record.rowid().unwrap().to_string());
-                }
-                for ix in &indices_of_selected_cols {
-                    match record.values[*ix] {
-                        SqlValue::Text(ref val) => {
-                            selected_record_cols.push(val.clone());
-                        }
-                        SqlValue::Null => continue,
-                        _ => panic!("Only text values are supported for now"),
-                    }
-                }
-                let cols_as_joined_str = selected_record_cols.join("|");
-                println!("{}", cols_as_joined_str);
+            // Like closing a connection, leaving a transaction open abandons it.
+            database.pager().rollback();
+        }
+        select_rows if select_rows.to_uppercase().starts_with("SELECT") => {
+            let pager = Pager::open(&args[1])?;
+            let select_stmt = match syntax::parse(select_rows)? {
+                Statement::Select(stmt) => stmt,
+                _ => bail!("Expected Select statement"),
+            };
+            for row in query::plan(&pager, &select_stmt)? {
+                let values = row?.iter().map(SqlValue::to_string).collect::<Vec<_>>();
+                println!("{}", values.join("|"));
             }
         }
-        _ => panic!("Unknown command: {}", command),
+        _ => bail!("Unknown command: {}", command),
     }
 
     Ok(())
@@ -1,80 +1,486 @@
+use crate::error::{Error, Result};
+use crate::eval::{Affinity, Collation};
+use crate::pager::Pager;
 use crate::syntax;
-use crate::typecodes::{decode_serial_types, SqlValue};
-use crate::utils::decode_varint;
-use anyhow::Result;
+use crate::syntax::create_index::CreateIndexStmt;
+use crate::syntax::create_table::{ColumnConstraint, TableConstraint};
+use crate::syntax::expr::Expr;
+use crate::typecodes::{decode_record, SqlValue};
+use crate::utils::decode_varint_checked;
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::{prelude::*, SeekFrom};
+use std::io::prelude::*;
 
+const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+/// SQLite's default SQLITE_MAX_LENGTH; no record can be larger.
+const MAX_PAYLOAD_SIZE: u64 = 1_000_000_000;
+const PENDING_BYTE: u64 = 0x4000_0000;
+/// Deepest b-tree we are willing to descend, matching SQLite's BTCURSOR_MAX_DEPTH.
+pub const MAX_BTREE_DEPTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16le,
+    Utf16be,
+}
+
+impl TextEncoding {
+    pub fn from_u32(value: u32) -> Result<TextEncoding> {
+        match value {
+            1 => Ok(TextEncoding::Utf8),
+            2 => Ok(TextEncoding::Utf16le),
+            3 => Ok(TextEncoding::Utf16be),
+            _ => Err(Error::NotADatabase(format!(
+                "unknown text encoding {}",
+                value
+            ))),
+        }
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            TextEncoding::Utf8 => 1,
+            TextEncoding::Utf16le => 2,
+            TextEncoding::Utf16be => 3,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "utf8",
+            TextEncoding::Utf16le => "utf16le",
+            TextEncoding::Utf16be => "utf16be",
+        }
+    }
+
+    /// Decodes stored TEXT, replacing invalid sequences. A trailing odd byte in UTF-16 is dropped.
+    pub fn decode(&self, data: &[u8]) -> String {
+        let units = data.chunks_exact(2);
+        match self {
+            TextEncoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            TextEncoding::Utf16le => String::from_utf16_lossy(
+                &units
+                    .map(|u| u16::from_le_bytes([u[0], u[1]]))
+                    .collect::<Vec<_>>(),
+            ),
+            TextEncoding::Utf16be => String::from_utf16_lossy(
+                &units
+                    .map(|u| u16::from_be_bytes([u[0], u[1]]))
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            TextEncoding::Utf8 => text.as_bytes().to_vec(),
+            TextEncoding::Utf16le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            TextEncoding::Utf16be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        }
+    }
+
+    /// Orders text the way the BINARY collation does, which compares the stored bytes.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            TextEncoding::Utf8 => a.as_bytes().cmp(b.as_bytes()),
+            // Big-endian code units compare the same as their bytes.
+            TextEncoding::Utf16be => a.encode_utf16().cmp(b.encode_utf16()),
+            TextEncoding::Utf16le => self.encode(a).cmp(&self.encode(b)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct DbHeader {
-    pub page_size: u16,
+    pub page_size: u32,
+    pub write_version: u8,
+    pub read_version: u8,
+    pub reserved_bytes: u8,
+    pub max_payload_fraction: u8,
+    pub min_payload_fraction: u8,
+    pub leaf_payload_fraction: u8,
+    pub file_change_counter: u32,
+    pub database_size: u32,
+    pub first_freelist_trunk: u32,
+    pub freelist_count: u32,
+    pub schema_cookie: u32,
+    pub schema_format: u32,
+    pub default_cache_size: u32,
+    pub autovacuum_top_root: u32,
+    pub text_encoding: TextEncoding,
+    pub user_version: u32,
+    pub incremental_vacuum: bool,
+    pub application_id: u32,
+    pub version_valid_for: u32,
+    pub sqlite_version_number: u32,
 }
 
 impl DbHeader {
     pub fn from_file(file: &mut File) -> Result<DbHeader> {
         let mut header = [0; Self::len() as usize];
         file.read_exact(&mut header)?;
-        let page_size = u16::from_be_bytes([header[16], header[17]]);
-        Ok(DbHeader { page_size })
+        Self::from_bytes(&header)
+    }
+
+    pub fn from_bytes(header: &[u8; Self::len() as usize]) -> Result<DbHeader> {
+        if &header[0..16] != MAGIC {
+            return Err(Error::NotADatabase("bad magic string".to_string()));
+        }
+        let be_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            ])
+        };
+        // A page size of 65536 does not fit in two bytes and is stored as 1.
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            n => n as u32,
+        };
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            return Err(Error::NotADatabase(format!(
+                "invalid page size {}",
+                page_size
+            )));
+        }
+        Ok(DbHeader {
+            page_size,
+            write_version: header[18],
+            read_version: header[19],
+            reserved_bytes: header[20],
+            max_payload_fraction: header[21],
+            min_payload_fraction: header[22],
+            leaf_payload_fraction: header[23],
+            file_change_counter: be_u32(24),
+            database_size: be_u32(28),
+            first_freelist_trunk: be_u32(32),
+            freelist_count: be_u32(36),
+            schema_cookie: be_u32(40),
+            schema_format: be_u32(44),
+            default_cache_size: be_u32(48),
+            autovacuum_top_root: be_u32(52),
+            text_encoding: TextEncoding::from_u32(be_u32(56))?,
+            user_version: be_u32(60),
+            incremental_vacuum: be_u32(64) != 0,
+            application_id: be_u32(68),
+            version_valid_for: be_u32(92),
+            sqlite_version_number: be_u32(96),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::len() as usize] {
+        let mut header = [0; Self::len() as usize];
+        let mut put_u32 = |offset: usize, value: u32| {
+            header[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        };
+        put_u32(24, self.file_change_counter);
+        put_u32(28, self.database_size);
+        put_u32(32, self.first_freelist_trunk);
+        put_u32(36, self.freelist_count);
+        put_u32(40, self.schema_cookie);
+        put_u32(44, self.schema_format);
+        put_u32(48, self.default_cache_size);
+        put_u32(52, self.autovacuum_top_root);
+        put_u32(56, self.text_encoding.as_u32());
+        put_u32(60, self.user_version);
+        put_u32(64, self.incremental_vacuum as u32);
+        put_u32(68, self.application_id);
+        put_u32(92, self.version_valid_for);
+        put_u32(96, self.sqlite_version_number);
+        header[0..16].copy_from_slice(MAGIC);
+        let page_size = match self.page_size {
+            65536 => 1,
+            n => n as u16,
+        };
+        header[16..18].copy_from_slice(&page_size.to_be_bytes());
+        header[18] = self.write_version;
+        header[19] = self.read_version;
+        header[20] = self.reserved_bytes;
+        header[21] = self.max_payload_fraction;
+        header[22] = self.min_payload_fraction;
+        header[23] = self.leaf_payload_fraction;
+        header
     }
 
     pub const fn len() -> u64 {
         100
     }
+
+    /// Usable bytes per page, i.e. the page size minus the reserved region at the end of each page.
+    pub fn usable_size(&self) -> usize {
+        self.page_size as usize - self.reserved_bytes as usize
+    }
+
+    /// The page holding the byte at offset 2^30 is never used, so that file locks can live there.
+    pub fn pending_byte_page(&self) -> u64 {
+        PENDING_BYTE / self.page_size as u64 + 1
+    }
+
+    /// Pointer-map pages only exist in auto-vacuum databases. The first one is page 2 and each
+    /// holds a five byte entry for every page that follows it until the next one.
+    pub fn ptrmap_pages(&self, page_count: u64) -> Vec<u64> {
+        let mut pages = Vec::new();
+        if self.autovacuum_top_root == 0 {
+            return pages;
+        }
+        let entries_per_page = self.usable_size() as u64 / 5;
+        let mut page = 2;
+        while page <= page_count {
+            if page == self.pending_byte_page() {
+                page += 1;
+            }
+            pages.push(page);
+            page += entries_per_page + 1;
+        }
+        pages
+    }
 }
 
 #[derive(Debug)]
 pub struct Page {
-    offset: u64,
+    pub offset: u64,
     pub header: PageHeader,
-    pointer_array: Vec<u16>,
-    data: Vec<u8>,
+    pub pointer_array: Vec<u16>,
+    pub data: Bytes,
 }
 
 #[derive(Debug)]
 pub struct PageHeader {
-    page_type: u8,
-    first_freeblock: u16,
+    pub page_type: u8,
+    pub first_freeblock: u16,
     pub num_cells: u16,
-    cell_content_start: u16,
-    num_fragments: u8,
-    rightmost_pointer: Option<u32>,
+    pub cell_content_start: u16,
+    pub num_fragments: u8,
+    pub rightmost_pointer: Option<u32>,
 }
 
 impl Page {
-    pub fn from_file(file: &mut File, page_offset: u64, dbheader: &DbHeader) -> Result<Page> {
-        let mut data = vec![0; dbheader.page_size as usize];
-        let start = (page_offset - 1) * (dbheader.page_size as u64);
-        file.seek(SeekFrom::Start(start))?;
-        file.read_exact(&mut data)?;
+    pub fn from_pager(pager: &Pager, page_offset: u64) -> Result<Page> {
+        let data = pager.get_page(page_offset)?;
+        Page::from_data(page_offset, data)
+    }
+
+    pub fn from_data(page_offset: u64, data: Bytes) -> Result<Page> {
+        let corrupt = |reason: &str| Error::corrupt_page(page_offset, reason);
         let dbheader_offset = if page_offset == 1 { 100 } else { 0 };
-        let (header, headerlen) =
-            PageHeader::from_data(&data[dbheader_offset..dbheader_offset + 12])?;
-        let mut pointer_array = Vec::new();
-        for i in 0..header.num_cells {
-            let offset = dbheader_offset + headerlen + (i * 2) as usize;
-            pointer_array.push(u16::from_be_bytes([data[offset], data[offset + 1]]));
+        let header_bytes = data
+            .get(dbheader_offset..(dbheader_offset + 12).min(data.len()))
+            .filter(|h| h.len() >= 8)
+            .ok_or_else(|| corrupt("page is too short for its header"))?;
+        if !matches!(header_bytes[0], 0x02 | 0x05 | 0x0a | 0x0d) {
+            return Err(corrupt("unknown page type"));
+        }
+        if header_bytes[0] & 0x08 == 0 && header_bytes.len() < 12 {
+            return Err(corrupt("page is too short for its header"));
+        }
+        let (header, headerlen) = PageHeader::from_data(header_bytes)?;
+        let pointer_array_start = dbheader_offset + headerlen;
+        let pointer_array_end = pointer_array_start + header.num_cells as usize * 2;
+        if pointer_array_end > data.len() {
+            return Err(corrupt("cell pointer array runs past the end of the page"));
+        }
+        // Interior cells start with a four byte child pointer; every other cell needs at least
+        // one byte for its payload size.
+        let min_cell_size = if header.is_interior() { 4 } else { 1 };
+        let mut pointer_array = Vec::with_capacity(header.num_cells as usize);
+        for offset in (pointer_array_start..pointer_array_end).step_by(2) {
+            let pointer = u16::from_be_bytes([data[offset], data[offset + 1]]);
+            if (pointer as usize) < pointer_array_end
+                || pointer as usize + min_cell_size > data.len()
+            {
+                return Err(corrupt("cell pointer out of range"));
+            }
+            pointer_array.push(pointer);
         }
         Ok(Page {
             offset: page_offset,
             header,
             pointer_array,
-            data: data,
+            data,
+        })
+    }
+
+    /// Child page number stored in the first four bytes of an interior cell.
+    pub fn left_child(&self, cell_index: usize) -> u32 {
+        let offset = self.pointer_array[cell_index] as usize;
+        u32::from_be_bytes([
+            self.data[offset],
+            self.data[offset + 1],
+            self.data[offset + 2],
+            self.data[offset + 3],
+        ])
+    }
+
+    pub fn right_child(&self) -> Result<u32> {
+        self.header
+            .rightmost_pointer
+            .ok_or_else(|| Error::corrupt_page(self.offset, "leaf page has no right child"))
+    }
+
+    /// Rowid stored in a table interior cell after the child pointer.
+    pub fn interior_rowid(&self, cell_index: usize) -> Result<u64> {
+        let offset = self.pointer_array[cell_index] as usize + 4;
+        self.data
+            .get(offset..)
+            .and_then(decode_varint_checked)
+            .map(|(rowid, _)| rowid)
+            .ok_or_else(|| Error::corrupt_page(self.offset, "truncated varint in cell"))
+    }
+
+    /// Works out how much of the page a cell occupies and where its payload spills over to.
+    pub fn cell_info(&self, cell_index: usize, usable_size: usize) -> Result<CellInfo> {
+        let corrupt = |reason: &str| Error::corrupt_page(self.offset, reason);
+        let varint_at = |offset: usize| {
+            self.data
+                .get(offset..)
+                .and_then(decode_varint_checked)
+                .ok_or_else(|| corrupt("truncated varint in cell"))
+        };
+        let cell_offset = *self
+            .pointer_array
+            .get(cell_index)
+            .ok_or_else(|| corrupt("cell index out of range"))? as usize;
+        let (payload_size, payload_start) = match self.header.page_type {
+            0x05 => {
+                let (_, rowid_len) = varint_at(cell_offset + 4)?;
+                return Ok(CellInfo {
+                    payload_size: 0,
+                    local_size: 0,
+                    overflow_page: None,
+                    size: 4 + rowid_len,
+                });
+            }
+            0x0d => {
+                let (payload_size, payload_size_len) = varint_at(cell_offset)?;
+                let (_, rowid_len) = varint_at(cell_offset + payload_size_len)?;
+                (payload_size, cell_offset + payload_size_len + rowid_len)
+            }
+            0x0a => {
+                let (payload_size, payload_size_len) = varint_at(cell_offset)?;
+                (payload_size, cell_offset + payload_size_len)
+            }
+            _ => {
+                let (payload_size, payload_size_len) = varint_at(cell_offset + 4)?;
+                (payload_size, cell_offset + 4 + payload_size_len)
+            }
+        };
+        if payload_size > MAX_PAYLOAD_SIZE {
+            return Err(corrupt("payload size is larger than any record can be"));
+        }
+        let local_size = local_payload_size(
+            usable_size,
+            payload_size as usize,
+            self.header.page_type == 0x0d,
+        );
+        let mut end = payload_start + local_size;
+        let mut overflow_page = None;
+        if local_size < payload_size as usize {
+            let pointer = self
+                .data
+                .get(end..end + 4)
+                .ok_or_else(|| corrupt("overflow pointer runs past the end of the page"))?;
+            overflow_page = Some(u32::from_be_bytes(pointer.try_into().unwrap()));
+            end += 4;
+        }
+        Ok(CellInfo {
+            payload_size,
+            local_size,
+            overflow_page,
+            // SQLite never allocates less than four bytes for a cell.
+            size: (end - cell_offset).max(4),
         })
     }
 }
 
+#[derive(Debug)]
+pub struct CellInfo {
+    pub payload_size: u64,
+    pub local_size: usize,
+    pub overflow_page: Option<u32>,
+    pub size: usize,
+}
+
 pub struct OverflowPage {
-    data: Vec<u8>,
+    data: Bytes,
 }
 
 impl OverflowPage {
-    pub fn from_file(file: &mut File, page_offset: u64, dbheader: &DbHeader) -> Result<OverflowPage> {
-        let mut data = vec![0; dbheader.page_size as usize];
-        let start = (page_offset - 1) * (dbheader.page_size as u64);
-        file.seek(SeekFrom::Start(start))?;
-        file.read_to_end(&mut data).unwrap();
+    pub fn from_pager(pager: &Pager, page_offset: u64) -> Result<OverflowPage> {
+        let data = pager.get_page(page_offset)?;
         Ok(OverflowPage { data })
     }
+
+    /// Page number of the next page in the chain, or 0 for the last one.
+    pub fn next_page(&self) -> u32 {
+        u32::from_be_bytes([self.data[0], self.data[1], self.data[2], self.data[3]])
+    }
+}
+
+/// A freelist trunk page: a pointer to the next trunk, then the free leaf pages it tracks.
+#[derive(Debug)]
+pub struct FreelistTrunk {
+    pub page_number: u64,
+    pub next_trunk: u32,
+    pub leaves: Vec<u32>,
+}
+
+impl FreelistTrunk {
+    pub fn from_pager(pager: &Pager, page_number: u64) -> Result<FreelistTrunk> {
+        let data = pager.get_page(page_number)?;
+        let be_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        let leaf_count = be_u32(4) as usize;
+        if leaf_count > pager.header().usable_size() / 4 - 2 {
+            return Err(Error::corrupt_page(
+                page_number,
+                "freelist leaf count too big",
+            ));
+        }
+        Ok(FreelistTrunk {
+            page_number,
+            next_trunk: be_u32(0),
+            leaves: (0..leaf_count).map(|i| be_u32(8 + i * 4)).collect(),
+        })
+    }
+}
+
+/// Follows the freelist from the header, checking it against the header's page count.
+pub fn read_freelist(pager: &Pager) -> Result<Vec<FreelistTrunk>> {
+    let expected = pager.header().freelist_count as u64;
+    let mut trunks = Vec::new();
+    let mut visited = HashSet::new();
+    let mut found = 0;
+    let mut page_number = pager.header().first_freelist_trunk as u64;
+    while page_number != 0 {
+        if !visited.insert(page_number) || found >= expected {
+            return Err(Error::corrupt_page(
+                page_number,
+                "freelist is longer than its count",
+            ));
+        }
+        let trunk = FreelistTrunk::from_pager(pager, page_number)?;
+        found += 1 + trunk.leaves.len() as u64;
+        page_number = trunk.next_trunk as u64;
+        trunks.push(trunk);
+    }
+    if found != expected {
+        return Err(Error::Corrupt(format!(
+            "freelist size is {} but should be {}",
+            found, expected
+        )));
+    }
+    Ok(trunks)
 }
 
 impl PageHeader {
@@ -104,7 +510,7 @@ impl PageHeader {
         ))
     }
 
-    pub fn len(&self) -> usize {
+    pub fn size(&self) -> usize {
         8 + match self.rightmost_pointer {
             Some(_) => 4,
             None => 0,
@@ -122,50 +528,321 @@ pub struct SqliteSchema {
 }
 
 impl SqliteSchema {
-    pub fn from_page(file: &mut File, dbheader: &DbHeader, page: &Page) -> Result<SqliteSchema> {
+    pub fn from_page(pager: &Pager, page: &Page) -> Result<SqliteSchema> {
         let mut schema_elements = Vec::new();
         for i in 0..page.header.num_cells {
-            let record = read_record(file, dbheader, page, i as usize, 1)?;
+            let record = read_record(pager, page, i as usize, 1)?;
             schema_elements.push(SqlSchemaElement::from_row(record)?);
         }
         Ok(SqliteSchema { schema_elements })
     }
+
+    /// Reads every row of `sqlite_schema`, following the b-tree past page 1.
+    pub fn from_pager(pager: &Pager) -> Result<SqliteSchema> {
+        let schema_elements = full_table_scan(pager, 1)?
+            .into_iter()
+            .map(SqlSchemaElement::from_row)
+            .collect::<Result<_>>()?;
+        Ok(SqliteSchema { schema_elements })
+    }
+
+    pub fn find_table(&self, name: &str) -> Result<&SqlSchemaElement> {
+        self.schema_elements
+            .iter()
+            .find(|e| e.element_type == "table" && e.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::UnknownTable(name.to_string()))
+    }
 }
 
 #[derive(Debug)]
 pub struct IndexSchema {
     pub name: String,
     pub table_name: String,
-    pub columns: Vec<String>,
+    pub columns: Vec<IndexColumn>,
+    pub unique: bool,
+    /// The condition a row must meet to be in a partial index.
+    pub where_clause: Option<Expr>,
+    pub rootpage: u64,
+}
+
+/// A key of an index and how the index orders it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexColumn {
+    /// The table column the key holds, or None when it is an expression.
+    pub name: Option<String>,
+    pub collation: Collation,
+    pub descending: bool,
+}
+
+impl IndexSchema {
+    /// Describes an index from its `sqlite_schema` row. Indexes SQLite creates for UNIQUE and
+    /// PRIMARY KEY constraints have no SQL, so their columns come from the table definition.
+    pub fn from_element(element: &SqlSchemaElement, table: &TableSchema) -> Result<IndexSchema> {
+        if element.sql.is_empty() {
+            let columns = element
+                .name
+                .strip_prefix(&format!("sqlite_autoindex_{}_", table.name))
+                .and_then(|n| n.parse::<usize>().ok())
+                .and_then(|n| table.autoindexes.get(n.checked_sub(1)?))
+                .ok_or_else(|| {
+                    Error::Corrupt(format!("no constraint for index {}", element.name))
+                })?;
+            return Ok(IndexSchema {
+                name: element.name.clone(),
+                table_name: table.name.clone(),
+                columns: columns
+                    .iter()
+                    .map(|i| IndexColumn {
+                        name: Some(table.columns[*i].name.clone()),
+                        collation: table.columns[*i].collation,
+                        descending: false,
+                    })
+                    .collect(),
+                unique: true,
+                where_clause: None,
+                rootpage: element.rootpage,
+            });
+        }
+        match syntax::parse(&element.sql)? {
+            syntax::statement::Statement::CreateIndex { stmt, unique } => Ok(IndexSchema {
+                name: stmt.index_name.clone(),
+                table_name: stmt.table_name.clone(),
+                columns: IndexColumn::from_ast(&stmt, table)?,
+                unique,
+                where_clause: stmt.where_clause,
+                rootpage: element.rootpage,
+            }),
+            _ => Err(Error::Corrupt(format!(
+                "bad schema for index {}",
+                element.name
+            ))),
+        }
+    }
+
+    /// How the entries of the index are ordered.
+    pub fn key_order(&self) -> KeyOrder {
+        KeyOrder::new(
+            self.columns
+                .iter()
+                .map(|column| (column.collation, column.descending))
+                .collect(),
+        )
+    }
+}
+
+impl IndexColumn {
+    /// The keys of a CREATE INDEX. One without a COLLATE of its own takes the collation of
+    /// the table column it holds.
+    pub fn from_ast(stmt: &CreateIndexStmt, table: &TableSchema) -> Result<Vec<IndexColumn>> {
+        stmt.columns
+            .iter()
+            .map(|column| {
+                let table_column = column.name().and_then(|name| {
+                    table
+                        .columns
+                        .iter()
+                        .find(|c| c.name.eq_ignore_ascii_case(name))
+                });
+                let collation = match (&column.collation, table_column) {
+                    (Some(name), _) => Collation::from_name(name)?,
+                    (None, Some(table_column)) => table_column.collation,
+                    (None, None) => Collation::Binary,
+                };
+                Ok(IndexColumn {
+                    name: column.name().map(str::to_string),
+                    collation,
+                    descending: column.descending,
+                })
+            })
+            .collect()
+    }
+}
+
+/// How the entries of an index b-tree are ordered: each of the first values under a
+/// collation, ascending or descending. Any values after those, like the rowid at the end of
+/// an index entry, compare as BINARY and ascending.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyOrder {
+    columns: Vec<(Collation, bool)>,
+}
+
+impl KeyOrder {
+    /// `columns` gives the collation of each value and whether it is in descending order.
+    pub fn new(columns: Vec<(Collation, bool)>) -> KeyOrder {
+        KeyOrder { columns }
+    }
+
+    /// Compares two entries, or prefixes of them, value by value. When one is a prefix of the
+    /// other, the shorter sorts first.
+    pub fn compare(&self, a: &[SqlValue], b: &[SqlValue], encoding: TextEncoding) -> Ordering {
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            let (collation, descending) = self.columns.get(i).copied().unwrap_or_default();
+            let ordering = collation.compare(a, b, encoding);
+            match ordering {
+                Ordering::Equal => continue,
+                _ if descending => return ordering.reverse(),
+                _ => return ordering,
+            }
+        }
+        a.len().cmp(&b.len())
+    }
 }
 
 #[derive(Debug)]
 pub struct TableSchema {
     pub name: String,
     pub columns: Vec<Column>,
+    /// Primary key columns in key order.
+    pub primary_key: Vec<usize>,
+    pub without_rowid: bool,
+    /// Columns of each index SQLite creates automatically for a UNIQUE or PRIMARY KEY
+    /// constraint, in creation order; the Nth is named `sqlite_autoindex_<table>_N`. A
+    /// WITHOUT ROWID table's primary key takes a number too, though the table is its index.
+    pub autoindexes: Vec<Vec<usize>>,
+    /// The CHECK constraints of the columns and the table, which every row must not make
+    /// false.
+    pub checks: Vec<Expr>,
 }
 
 impl TableSchema {
-    pub fn from_ast(ast: &syntax::create_table::CreateTableStmt) -> TableSchema {
+    pub fn from_ast(ast: &syntax::create_table::CreateTableStmt) -> Result<TableSchema> {
         let name = ast.table_name.clone();
-        let columns = ast.cols().iter().map(Column::from_ast).collect();
-        TableSchema { name, columns }
+        let column_defs = ast.cols()?;
+        let mut columns = column_defs
+            .iter()
+            .map(Column::from_ast)
+            .collect::<Result<Vec<_>>>()?;
+        let mut primary_key: Vec<usize> = (0..columns.len())
+            .filter(|i| columns[*i].is_primary_key)
+            .collect();
+        // Keys in the order SQLite meets them while parsing, flagged when they are the primary
+        // key.
+        let mut keys = Vec::new();
+        let mut checks = Vec::new();
+        for (i, column) in column_defs.iter().enumerate() {
+            for constraint in &column.constraints {
+                match constraint {
+                    ColumnConstraint::PrimaryKey { .. } => keys.push((vec![i], true)),
+                    ColumnConstraint::Unique => keys.push((vec![i], false)),
+                    ColumnConstraint::Check(check) => checks.push(check.clone()),
+                    _ => {}
+                }
+            }
+        }
+        let position = |name: &String| {
+            column_defs
+                .iter()
+                .position(|c| c.name == *name)
+                .ok_or_else(|| Error::UnknownColumn(name.clone()))
+        };
+        for constraint in ast.table_constraints() {
+            match constraint {
+                TableConstraint::PrimaryKey { columns: names, .. } => {
+                    if !primary_key.is_empty() {
+                        return Err(Error::Unsupported("multiple primary keys".to_string()));
+                    }
+                    for name in names {
+                        let i = position(name)?;
+                        columns[i].is_primary_key = true;
+                        primary_key.push(i);
+                    }
+                    keys.push((primary_key.clone(), true));
+                }
+                TableConstraint::Unique { columns: names, .. } => {
+                    let key = names.iter().map(position).collect::<Result<_>>()?;
+                    keys.push((key, false));
+                }
+                TableConstraint::Check(check) => checks.push(check.clone()),
+            }
+        }
+        if ast.without_rowid && primary_key.is_empty() {
+            return Err(Error::Corrupt(format!(
+                "PRIMARY KEY missing on table {}",
+                name
+            )));
+        }
+        let mut table = TableSchema {
+            name,
+            columns,
+            primary_key,
+            without_rowid: ast.without_rowid,
+            autoindexes: Vec::new(),
+            checks,
+        };
+        // An INTEGER PRIMARY KEY is the rowid, which needs no index, and a constraint on the
+        // same columns as an earlier one shares its index.
+        let rowid_alias = table.rowid_alias();
+        for (key, is_primary_key) in keys {
+            if (is_primary_key && rowid_alias.is_some()) || table.autoindexes.contains(&key) {
+                continue;
+            }
+            table.autoindexes.push(key);
+        }
+        Ok(table)
     }
 
-    pub fn primary_key_index(&self) -> Option<usize> {
-        let mut i = 0;
-        let mut pkeys = Vec::new();
-        for col in &self.columns {
-            if col.is_primary_key {
-                pkeys.push(i);
+    pub fn column_index(&self, name: &str) -> Result<usize> {
+        self.columns
+            .iter()
+            .position(|c| c.name == name)
+            .ok_or_else(|| Error::UnknownColumn(name.to_string()))
+    }
+
+    pub fn primary_key_index(&self) -> Result<Option<usize>> {
+        match self.primary_key.len() {
+            0 => Ok(None),
+            1 => Ok(Some(self.primary_key[0])),
+            _ => Err(Error::Unsupported("multiple primary keys".to_string())),
+        }
+    }
+
+    /// The column that stores the rowid itself: a lone INTEGER PRIMARY KEY on a rowid table.
+    pub fn rowid_alias(&self) -> Option<usize> {
+        match self.primary_key.as_slice() {
+            [pk] if !self.without_rowid
+                && self.columns[*pk]
+                    .type_name
+                    .as_ref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case("INTEGER")) =>
+            {
+                Some(*pk)
             }
-            i += 1;
+            _ => None,
         }
-        return match pkeys.len() {
-            0 => None,
-            1 => Some(pkeys[0]),
-            _ => panic!("Multiple primary keys not supported"),
-        };
+    }
+
+    /// How a WITHOUT ROWID table's b-tree orders its rows: by the primary key columns, each
+    /// under its collation.
+    pub fn key_order(&self) -> KeyOrder {
+        KeyOrder::new(
+            self.primary_key
+                .iter()
+                .map(|&i| (self.columns[i].collation, false))
+                .collect(),
+        )
+    }
+
+    /// Column index for each value of a stored record. WITHOUT ROWID tables store the primary
+    /// key columns first, followed by the rest in declaration order.
+    pub fn storage_order(&self) -> Vec<usize> {
+        if !self.without_rowid {
+            return (0..self.columns.len()).collect();
+        }
+        let mut order = self.primary_key.clone();
+        order.extend((0..self.columns.len()).filter(|i| !self.primary_key.contains(i)));
+        order
+    }
+
+    /// Puts the values of a stored record back into declaration order.
+    pub fn row_from_record(&self, record: Record) -> Record {
+        if !self.without_rowid {
+            return record;
+        }
+        let mut values = vec![SqlValue::Null; self.columns.len()];
+        for (value, column) in record.values.into_iter().zip(self.storage_order()) {
+            values[column] = value;
+        }
+        Record { values, ..record }
     }
 }
 
@@ -173,7 +850,7 @@ impl TableSchema {
 pub struct SqlSchemaElement {
     pub element_type: String,
     pub name: String,
-    tbl_name: String,
+    pub tbl_name: String,
     pub rootpage: u64,
     pub sql: String,
 }
@@ -181,45 +858,76 @@ pub struct SqlSchemaElement {
 #[derive(Debug)]
 pub struct Column {
     pub name: String,
-    is_primary_key: bool,
+    pub type_name: Option<String>,
+    pub affinity: Affinity,
+    /// How the column's text compares, BINARY unless declared with COLLATE.
+    pub collation: Collation,
+    pub is_primary_key: bool,
+    pub not_null: bool,
+    /// The value an INSERT gives the column when it names no value for it.
+    pub default: Option<Expr>,
 }
 
 impl Column {
-    pub fn from_ast(ast: &syntax::create_table::ColumnDef) -> Column {
+    pub fn from_ast(ast: &syntax::create_table::ColumnDef) -> Result<Column> {
         let is_primary_key = ast.constraints.iter().any(|c| c.is_primary_key());
-        Column {
+        let not_null = ast
+            .constraints
+            .iter()
+            .any(|c| matches!(c, ColumnConstraint::NotNull { .. }));
+        let collation = ast
+            .constraints
+            .iter()
+            .rev()
+            .find_map(|c| match c {
+                ColumnConstraint::Collate(name) => Some(Collation::from_name(name)),
+                _ => None,
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let default = ast.constraints.iter().rev().find_map(|c| match c {
+            ColumnConstraint::Default(default) => Some(default.clone()),
+            _ => None,
+        });
+        Ok(Column {
             name: ast.name.clone(),
+            type_name: ast.type_name.clone(),
+            affinity: Affinity::from_type_name(ast.type_name.as_deref()),
+            collation,
             is_primary_key,
-        }
+            not_null,
+            default,
+        })
     }
 }
 
 impl SqlSchemaElement {
-    pub fn from_row(mut row: Record) -> Result<SqlSchemaElement> {
-        let sql = match row.values.pop().unwrap() {
-            SqlValue::Text(strval) => strval,
-            _ => panic!("Expected a string value, got"),
-        };
-        let rootpage = match row.values.pop().unwrap() {
-            SqlValue::I8(val) => val as u64,
-            SqlValue::I16(val) => val as u64,
-            SqlValue::I24(val) => val as u64,
-            SqlValue::I32(val) => val as u64,
-            SqlValue::I48(val) => val as u64,
-            SqlValue::I64(val) => val as u64,
-            _ => panic!("Expected an integer value, got"),
+    pub fn from_row(row: Record) -> Result<SqlSchemaElement> {
+        let malformed = |column: &str| Error::Corrupt(format!("bad {} in sqlite_schema", column));
+        let mut values = row.values.into_iter();
+        let mut next_text = |column: &str| match values.next() {
+            Some(SqlValue::Text(strval)) => Ok(strval),
+            _ => Err(malformed(column)),
         };
-        let tbl_name = match row.values.pop().unwrap() {
-            SqlValue::Text(strval) => strval,
-            _ => panic!("Expected a string value, got"),
-        };
-        let name = match row.values.pop().unwrap() {
-            SqlValue::Text(strval) => strval,
-            _ => panic!("Expected a string value, got"),
-        };
-        let tabletype = match row.values.pop().unwrap() {
-            SqlValue::Text(strval) => strval,
-            _ => panic!("Expected a string value, got"),
+        let tabletype = next_text("type")?;
+        let name = next_text("name")?;
+        let tbl_name = next_text("tbl_name")?;
+        // Triggers and views have no b-tree, so their rootpage is 0 (or NULL) and sql may be NULL
+        // for automatically created indexes.
+        let (rootpage, sql) = match (values.next(), values.next()) {
+            (Some(rootpage), Some(sql)) => {
+                let rootpage = match rootpage {
+                    SqlValue::Null => 0,
+                    other => other.as_i64().ok_or_else(|| malformed("rootpage"))? as u64,
+                };
+                let sql = match sql {
+                    SqlValue::Text(strval) => strval,
+                    SqlValue::Null => String::new(),
+                    _ => return Err(malformed("sql")),
+                };
+                (rootpage, sql)
+            }
+            _ => return Err(malformed("row")),
         };
         Ok(SqlSchemaElement {
             element_type: tabletype,
@@ -240,8 +948,8 @@ pub enum RecordStart {
 
 #[derive(Debug)]
 pub struct Record {
-    record_start: RecordStart,
-    payload_size: u64,
+    pub record_start: RecordStart,
+    pub payload_size: u64,
     pub values: Vec<SqlValue>,
 }
 
@@ -261,170 +969,457 @@ impl Record {
     }
 }
 
-pub fn full_table_scan(file: &mut File, dbheader: &DbHeader, page_number: u64) -> Vec<Record> {
-    let mut records = Vec::new();
-    let page = Page::from_file(file, page_number, dbheader).unwrap();
-    if page.header.is_interior() {
-        for i in 0..page.pointer_array.len() {
-            let bytes = &page.data[page.pointer_array[i] as usize..];
-            let left_page = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
-            records.append(&mut full_table_scan(file, dbheader, left_page as u64));
-        }
-        let right_page = page.header.rightmost_pointer.unwrap();
-        records.append(&mut full_table_scan(file, dbheader, right_page as u64));
-    } else {
-        for i in 0..page.header.num_cells {
-            let record = read_record(file, dbheader, &page, i as usize, 1).unwrap();
-            records.push(record);
+/// Where a seek leaves a [`BTreeCursor`], relative to the key it is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekOp {
+    /// The first entry at or after the key.
+    GreaterEqual,
+    /// The first entry after the key.
+    Greater,
+    /// The last entry at or before the key.
+    LessEqual,
+    /// The last entry before the key.
+    Less,
+}
+
+/// Walks the entries of a table or index b-tree in order, one at a time, in either direction.
+/// Only the pages on the path from the root to the current entry are held, so a scan can stop
+/// early and costs no more memory for a large table than for a small one, and moving to the
+/// next entry rarely has to read more than one page.
+pub struct BTreeCursor<'a> {
+    pager: &'a Pager,
+    root: u64,
+    /// Each page from the root down to the current entry, with a cell index. On a leaf that is
+    /// the current cell. On an interior page it is the child being visited, or, when the page
+    /// is on top of the stack, the index interior cell that is the current entry.
+    stack: Vec<(Page, usize)>,
+    /// Pages read since the cursor was last positioned or turned around. Moving one way
+    /// through a sound b-tree reads each page at most once, so this catches pages that are
+    /// children of more than one parent.
+    pages_read: u64,
+    page_count: u64,
+    forward: bool,
+}
+
+impl<'a> BTreeCursor<'a> {
+    pub fn new(pager: &'a Pager, root: u64) -> BTreeCursor<'a> {
+        BTreeCursor {
+            pager,
+            root,
+            stack: Vec::new(),
+            pages_read: 0,
+            page_count: 0,
+            forward: true,
+        }
+    }
+
+    /// Moves to the first entry, returning false if the b-tree is empty.
+    pub fn first(&mut self) -> Result<bool> {
+        self.reset(true)?;
+        self.descend(self.root, false)?;
+        self.settle_forward()
+    }
+
+    /// Moves to the last entry, returning false if the b-tree is empty.
+    pub fn last(&mut self) -> Result<bool> {
+        self.reset(false)?;
+        self.descend(self.root, true)?;
+        self.settle_backward()
+    }
+
+    /// Moves to the entry after the current one, returning false once there are no more.
+    // Not an Iterator: moving can fail, and reading the entry is a separate step.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<bool> {
+        self.turn(true);
+        let Some((page, cell)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        *cell += 1;
+        if page.header.is_interior() {
+            // What follows an index interior cell is in the subtree to its right.
+            let child = child_page(page, *cell)?;
+            self.descend(child, false)?;
+        }
+        self.settle_forward()
+    }
+
+    /// Moves to the entry before the current one, returning false once there are no more.
+    pub fn prev(&mut self) -> Result<bool> {
+        self.turn(false);
+        let Some((page, cell)) = self.stack.last() else {
+            return Ok(false);
+        };
+        if page.header.is_interior() {
+            // What precedes an index interior cell is in the subtree to its left.
+            let child = child_page(page, *cell)?;
+            self.descend(child, true)?;
+        }
+        self.settle_backward()
+    }
+
+    /// Moves to the row of a table b-tree nearest `rowid` in the direction `op` gives,
+    /// returning false if there is none.
+    pub fn seek_rowid(&mut self, rowid: i64, op: SeekOp) -> Result<bool> {
+        // Each child of an interior page holds rowids up to the key of its cell.
+        self.seek(op, |page, cell| {
+            let key = match page.header.page_type {
+                0x05 => page.interior_rowid(cell)?,
+                0x0d => cell_rowid(page, cell)?,
+                _ => return Err(Error::corrupt_page(page.offset, "expected a table page")),
+            };
+            Ok((key as i64).cmp(&rowid))
+        })
+    }
+
+    /// Moves to the entry of an index b-tree nearest `key` in the direction `op` gives, with
+    /// entries in `order`, returning false if there is none. Entries are compared on as many
+    /// leading values as `key` has, so a partial key finds the first or last of the entries
+    /// starting with it.
+    pub fn seek_key(&mut self, key: &[SqlValue], order: &KeyOrder, op: SeekOp) -> Result<bool> {
+        let pager = self.pager;
+        let encoding = pager.header().text_encoding;
+        self.seek(op, |page, cell| {
+            let record = match page.header.page_type {
+                0x02 => read_record(pager, page, cell, 2)?,
+                0x0a => read_record(pager, page, cell, 0)?,
+                _ => return Err(Error::corrupt_page(page.offset, "expected an index page")),
+            };
+            let prefix = &record.values[..key.len().min(record.values.len())];
+            Ok(order.compare(prefix, key, encoding))
+        })
+    }
+
+    /// Reads the entry the cursor is at.
+    pub fn record(&self) -> Result<Record> {
+        let (page, cell) = self.current()?;
+        let record_start_kind = match page.header.page_type {
+            0x0d => 1,
+            0x02 => 2,
+            _ => 0,
+        };
+        read_record(self.pager, page, cell, record_start_kind)
+    }
+
+    /// The rowid of the table row the cursor is at, without reading the row.
+    pub fn rowid(&self) -> Result<i64> {
+        let (page, cell) = self.current()?;
+        Ok(cell_rowid(page, cell)? as i64)
+    }
+
+    fn current(&self) -> Result<(&Page, usize)> {
+        match self.stack.last() {
+            Some((page, cell)) if *cell < page.pointer_array.len() => Ok((page, *cell)),
+            _ => Err(Error::corrupt_page(
+                self.root,
+                "cursor read while not at an entry",
+            )),
         }
     }
-    records
-}
 
-pub fn index_scan(file: &mut File, dbheader: &DbHeader, page_number: u64, key: &str) -> Vec<u64> {
-    let mut row_ids = Vec::new();
-    let page = Page::from_file(file, page_number, dbheader).unwrap();
-    if page.header.is_interior() {
-        let mut found_conclusive_subtree = false;
-        for i in 0..page.pointer_array.len() {
-            let record = read_record(file, dbheader, &page, i as usize, 2).unwrap();
-            let found_key = match record.values[0] {
-                SqlValue::Text(ref val) => val,
-                SqlValue::Null => continue,
-                _ => panic!("Only text values are supported for now"),
+    /// Descends to where `op` puts the cursor relative to a key, given how the key of each
+    /// cell compares with it. In an index the interior cells are entries too, coming between
+    /// the subtrees either side of them.
+    fn seek(
+        &mut self,
+        op: SeekOp,
+        mut compare: impl FnMut(&Page, usize) -> Result<Ordering>,
+    ) -> Result<bool> {
+        let forward = matches!(op, SeekOp::GreaterEqual | SeekOp::Greater);
+        self.reset(forward)?;
+        // Cells before the returned index come before the entry sought, or when seeking
+        // backward, are the entry itself or come before it.
+        let before = |ordering: Ordering| match op {
+            SeekOp::GreaterEqual | SeekOp::Less => ordering == Ordering::Less,
+            SeekOp::Greater | SeekOp::LessEqual => ordering != Ordering::Greater,
+        };
+        let mut page_number = self.root;
+        loop {
+            let page = self.read_page(page_number)?;
+            let (mut low, mut high) = (0, page.pointer_array.len());
+            while low < high {
+                let middle = (low + high) / 2;
+                if before(compare(&page, middle)?) {
+                    low = middle + 1;
+                } else {
+                    high = middle;
+                }
+            }
+            let child = match page.header.is_interior() {
+                true => Some(child_page(&page, low)?),
+                false => None,
             };
-            if *found_key == key {
-                let left_page = record.left_page().unwrap();
-                row_ids.append(&mut index_scan(file, dbheader, left_page as u64, key));
-            } else if *found_key > key.to_string() {
-                row_ids.append(&mut index_scan(
-                    file,
-                    dbheader,
-                    record.left_page().unwrap() as u64,
-                    key,
-                ));
-                found_conclusive_subtree = true;
-                break;
+            self.stack.push((page, low));
+            match child {
+                Some(child) => page_number = child,
+                None if forward => return self.settle_forward(),
+                None => return self.settle_backward(),
             }
         }
-        if !found_conclusive_subtree {
-            let right_page = page.header.rightmost_pointer.unwrap();
-            row_ids.append(&mut index_scan(file, dbheader, right_page as u64, key));
+    }
+
+    fn reset(&mut self, forward: bool) -> Result<()> {
+        self.stack.clear();
+        self.pages_read = 0;
+        self.page_count = self.pager.page_count()?;
+        self.forward = forward;
+        Ok(())
+    }
+
+    fn turn(&mut self, forward: bool) {
+        if self.forward != forward {
+            self.forward = forward;
+            self.pages_read = 0;
         }
-    } else {
-        for i in 0..page.header.num_cells {
-            let record = read_record(file, dbheader, &page, i as usize, 0).unwrap();
-            let found_key = match record.values[0] {
-                SqlValue::Text(ref val) => val,
-                _ => panic!("Only text values are supported for now"),
+    }
+
+    fn read_page(&mut self, page_number: u64) -> Result<Page> {
+        if self.stack.len() > MAX_BTREE_DEPTH {
+            return Err(Error::corrupt_page(page_number, "b-tree is too deep"));
+        }
+        self.pages_read += 1;
+        if self.pages_read > self.page_count {
+            return Err(Error::corrupt_page(
+                page_number,
+                "page is referenced more than once",
+            ));
+        }
+        let page = Page::from_pager(self.pager, page_number)?;
+        // Only the leaf bit (0x08) may differ between pages of one b-tree.
+        if let Some((root, _)) = self.stack.first() {
+            if (page.header.page_type ^ root.header.page_type) & 0x07 != 0 {
+                return Err(Error::corrupt_page(page_number, "child of the wrong kind"));
+            }
+        }
+        Ok(page)
+    }
+
+    /// Pushes the pages from `page_number` down its leftmost edge to a leaf, or its rightmost
+    /// edge to just past the last cell of a leaf.
+    fn descend(&mut self, mut page_number: u64, rightmost: bool) -> Result<()> {
+        loop {
+            let page = self.read_page(page_number)?;
+            let cell = match rightmost {
+                true => page.pointer_array.len(),
+                false => 0,
             };
-            if *found_key == key {
-                let rowid = match record.values[1] {
-                    SqlValue::I8(val) => row_ids.push(val as u64),
-                    SqlValue::I16(val) => row_ids.push(val as u64),
-                    SqlValue::I24(val) => row_ids.push(val as u64),
-                    SqlValue::I32(val) => row_ids.push(val as u64),
-                    SqlValue::I48(val) => row_ids.push(val as u64),
-                    SqlValue::I64(val) => row_ids.push(val as u64),
-                    _ => panic!("Found a noninteger rowid."),
-                };
+            let child = match page.header.is_interior() {
+                true => Some(child_page(&page, cell)?),
+                false => None,
+            };
+            self.stack.push((page, cell));
+            match child {
+                Some(child) => page_number = child,
+                None => return Ok(()),
             }
         }
     }
-    row_ids
-}
 
-pub fn row_lookup(file: &mut File, dbheader: &DbHeader, page_number: u64, rowid: u64) -> Vec<Record> {
-    let mut records = Vec::new();
-    let page = Page::from_file(file, page_number, dbheader).unwrap();
-    if page.header.is_interior() {
-        for i in 0..page.pointer_array.len() {
-            let bytes = &page.data[page.pointer_array[i] as usize..];
-            let left_page = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
-            let (cmpr_rowid, _) = decode_varint(&bytes[4..]);
-            if rowid <= cmpr_rowid as u64 {
-                records.append(&mut row_lookup(file, dbheader, left_page as u64, rowid));
-                break;
+    /// Climbs out of pages whose cells have all been visited until the cursor is at an entry,
+    /// returning false if it runs off the end of the b-tree. An interior page on top of the
+    /// stack here has just had the subtree left of its current cell visited.
+    fn settle_forward(&mut self) -> Result<bool> {
+        loop {
+            let Some((page, cell)) = self.stack.last_mut() else {
+                return Ok(false);
+            };
+            let cells = page.pointer_array.len();
+            if !page.header.is_interior() {
+                if *cell < cells {
+                    return Ok(true);
+                }
+                self.stack.pop();
+            } else if page.header.page_type == 0x02 && *cell < cells {
+                return Ok(true);
+            } else if *cell < cells {
+                *cell += 1;
+                let child = child_page(page, *cell)?;
+                self.descend(child, false)?;
+            } else {
+                self.stack.pop();
             }
         }
-        let right_page = page.header.rightmost_pointer.unwrap();
-        records.append(&mut row_lookup(file, dbheader, right_page as u64, rowid));
-    } else {
-        for i in 0..page.header.num_cells {
-            let record = read_record(file, dbheader, &page, i as usize, 1).unwrap();
-            if record.rowid().unwrap() == rowid {
-                records.push(record);
+    }
+
+    /// Steps back from just past an entry to the entry itself, climbing out of pages whose
+    /// cells have all been visited, and returns false if it runs off the start of the b-tree.
+    /// An interior page on top of the stack here has just had the subtree left of its current
+    /// cell visited.
+    fn settle_backward(&mut self) -> Result<bool> {
+        loop {
+            let Some((page, cell)) = self.stack.last_mut() else {
+                return Ok(false);
+            };
+            if *cell == 0 {
+                self.stack.pop();
+                continue;
             }
+            *cell -= 1;
+            if !page.header.is_interior() || page.header.page_type == 0x02 {
+                return Ok(true);
+            }
+            let child = child_page(page, *cell)?;
+            self.descend(child, true)?;
         }
     }
-    records
+}
+
+/// The child of an interior page to the left of a cell, or its right child past the last cell.
+fn child_page(page: &Page, cell: usize) -> Result<u64> {
+    if cell < page.pointer_array.len() {
+        Ok(page.left_child(cell) as u64)
+    } else {
+        Ok(page.right_child()? as u64)
+    }
+}
+
+/// The rowid of a cell on a table leaf page, which follows the payload size.
+fn cell_rowid(page: &Page, cell: usize) -> Result<u64> {
+    let truncated = || Error::corrupt_page(page.offset, "truncated varint in cell");
+    let offset = page.pointer_array[cell] as usize;
+    let (_, size_len) = page
+        .data
+        .get(offset..)
+        .and_then(decode_varint_checked)
+        .ok_or_else(truncated)?;
+    page.data
+        .get(offset + size_len..)
+        .and_then(decode_varint_checked)
+        .map(|(rowid, _)| rowid)
+        .ok_or_else(truncated)
+}
+
+/// Reads every entry of a b-tree in order: the rows of a table, or the entries of an index.
+pub fn full_table_scan(pager: &Pager, page_number: u64) -> Result<Vec<Record>> {
+    let mut cursor = BTreeCursor::new(pager, page_number);
+    let mut records = Vec::new();
+    let mut found = cursor.first()?;
+    while found {
+        records.push(cursor.record()?);
+        found = cursor.next()?;
+    }
+    Ok(records)
+}
+
+/// Finds the entries of an index-format b-tree in `order` whose leading values equal `key`,
+/// e.g. a row of a WITHOUT ROWID table by its primary key.
+pub fn key_lookup(
+    pager: &Pager,
+    page_number: u64,
+    key: &[SqlValue],
+    order: &KeyOrder,
+) -> Result<Vec<Record>> {
+    let encoding = pager.header().text_encoding;
+    let mut cursor = BTreeCursor::new(pager, page_number);
+    let mut records = Vec::new();
+    let mut found = cursor.seek_key(key, order, SeekOp::GreaterEqual)?;
+    while found {
+        let record = cursor.record()?;
+        let prefix = &record.values[..key.len().min(record.values.len())];
+        if order.compare(prefix, key, encoding) != Ordering::Equal {
+            break;
+        }
+        records.push(record);
+        found = cursor.next()?;
+    }
+    Ok(records)
+}
+
+/// Counts the entries of a b-tree without reading any of them.
+pub fn count_entries(pager: &Pager, page_number: u64) -> Result<u64> {
+    let mut cursor = BTreeCursor::new(pager, page_number);
+    let mut count = 0;
+    let mut found = cursor.first()?;
+    while found {
+        count += 1;
+        found = cursor.next()?;
+    }
+    Ok(count)
+}
+
+/// Finds the row of a table b-tree with the given rowid.
+pub fn row_lookup(pager: &Pager, page_number: u64, rowid: i64) -> Result<Option<Record>> {
+    let mut cursor = BTreeCursor::new(pager, page_number);
+    if cursor.seek_rowid(rowid, SeekOp::GreaterEqual)? && cursor.rowid()? == rowid {
+        return Ok(Some(cursor.record()?));
+    }
+    Ok(None)
 }
 
 pub fn read_record(
-    file: &mut File,
-    dbheader: &DbHeader,
+    pager: &Pager,
     page: &Page,
     cell_index: usize,
     record_start_kind: u8,
 ) -> Result<Record> {
-    let cell_offset = page.pointer_array[cell_index] as usize;
+    let corrupt = |reason: &str| Error::corrupt_page(page.offset, reason);
+    let varint_at = |offset: usize| {
+        page.data
+            .get(offset..)
+            .and_then(decode_varint_checked)
+            .ok_or_else(|| corrupt("truncated varint in cell"))
+    };
+    let cell_offset = *page
+        .pointer_array
+        .get(cell_index)
+        .ok_or_else(|| corrupt("cell index out of range"))? as usize;
     let left_page: Option<u32>;
     let rowid: Option<u64>;
     let payload_size;
-    let mut payload_start;
+    let payload_start;
     if record_start_kind == 0 {
         left_page = None;
         rowid = None;
-        let (_payload_size, payload_size_len) = decode_varint(&page.data[cell_offset..]);
+        let (_payload_size, payload_size_len) = varint_at(cell_offset)?;
         payload_size = _payload_size;
         payload_start = cell_offset + payload_size_len;
     } else if record_start_kind == 1 {
-        let (_payload_size, payload_size_len) = decode_varint(&page.data[cell_offset..]);
-        let (_rowid, rowid_len) = decode_varint(&page.data[cell_offset + payload_size_len..]);
+        let (_payload_size, payload_size_len) = varint_at(cell_offset)?;
+        let (_rowid, rowid_len) = varint_at(cell_offset + payload_size_len)?;
         payload_size = _payload_size;
         payload_start = cell_offset + payload_size_len + rowid_len;
         rowid = Some(_rowid);
         left_page = None;
     } else if record_start_kind == 2 {
         rowid = None;
-        left_page = Some(u32::from_be_bytes(
-            page.data[cell_offset..cell_offset + 4].try_into().unwrap(),
-        ));
-        let (_payload_size, payload_size_len) = decode_varint(&page.data[cell_offset + 4..]);
+        left_page = Some(page.left_child(cell_index));
+        let (_payload_size, payload_size_len) = varint_at(cell_offset + 4)?;
         payload_size = _payload_size;
         payload_start = cell_offset + payload_size_len + 4;
     } else {
-        panic!("Invalid record start kind");
-    }
-    let overflow = page.data.len() - payload_start - payload_size as usize;
-    let overflow_page = u32::from_be_bytes(
-        page.data[cell_offset + payload_size as usize - 4..cell_offset + payload_size as usize]
-            .try_into()
-            .unwrap(),
-    );
-    let mut overflow_data = Vec::new();
-    if overflow > 0 {
-        overflow_data = read_overflow(file, dbheader, overflow_page as u64, overflow);
-    }
-    let mut payload = page.data[payload_start..].to_vec();
-    payload.append(&mut overflow_data);
-    let (record_headerlen, bytes_for_headerlen) = decode_varint(&payload[0..]);
-    let serial_types =
-        decode_serial_types(&payload[bytes_for_headerlen..record_headerlen as usize]);
-    let mut offset = record_headerlen as usize;
-    let mut values: Vec<SqlValue> = Vec::with_capacity(serial_types.len());
-
-    for t in serial_types {
-        let size = t.size();
-        let data = &payload[offset..offset + size];
-        offset += size;
-        values.push(t.decode(data));
+        return Err(Error::Unsupported(format!(
+            "record start kind {}",
+            record_start_kind
+        )));
+    }
+    if payload_size > MAX_PAYLOAD_SIZE {
+        return Err(corrupt("payload size is larger than any record can be"));
     }
+    let usable_size = pager.header().usable_size();
+    let local_size = local_payload_size(usable_size, payload_size as usize, record_start_kind == 1);
+    if payload_start + local_size > usable_size.min(page.data.len()) {
+        return Err(corrupt("cell runs past the end of the page"));
+    }
+    let mut payload = page.data[payload_start..payload_start + local_size].to_vec();
+    if local_size < payload_size as usize {
+        let overflow_pointer = payload_start + local_size;
+        let overflow_page = page
+            .data
+            .get(overflow_pointer..overflow_pointer + 4)
+            .ok_or_else(|| corrupt("overflow pointer runs past the end of the page"))?;
+        let overflow_page = u32::from_be_bytes(overflow_page.try_into().unwrap());
+        let overflow = payload_size as usize - local_size;
+        payload.append(&mut read_overflow(pager, overflow_page as u64, overflow)?);
+    }
+    let values = decode_record(&payload, pager.header().text_encoding).map_err(|e| match e {
+        Error::Corrupt(reason) => corrupt(&reason),
+        e => e,
+    })?;
     Ok(Record {
-        record_start: if rowid.is_some() {
-            RecordStart::RowId(rowid.unwrap())
-        } else if left_page.is_some() {
-            RecordStart::LeftPage(left_page.unwrap())
+        record_start: if let Some(rowid) = rowid {
+            RecordStart::RowId(rowid)
+        } else if let Some(left_page) = left_page {
+            RecordStart::LeftPage(left_page)
         } else {
             RecordStart::None
         },
@@ -433,14 +1428,51 @@ pub fn read_record(
     })
 }
 
+/// Number of payload bytes stored in the cell itself; the rest spills onto overflow pages.
+/// Table leaf cells and index cells use different maximums (X) but share the minimum (M).
+pub fn local_payload_size(usable_size: usize, payload_size: usize, is_table_leaf: bool) -> usize {
+    let max_local = if is_table_leaf {
+        usable_size - 35
+    } else {
+        ((usable_size - 12) * 64 / 255) - 23
+    };
+    if payload_size <= max_local {
+        return payload_size;
+    }
+    let min_local = ((usable_size - 12) * 32 / 255) - 23;
+    let k = min_local + ((payload_size - min_local) % (usable_size - 4));
+    if k <= max_local {
+        k
+    } else {
+        min_local
+    }
+}
+
+/// Reads `num_overflow_bytes` by following the linked list of overflow pages from `page_number`.
 pub fn read_overflow(
-    file: &mut File,
-    dbheader: &DbHeader,
+    pager: &Pager,
     page_number: u64,
     num_overflow_bytes: usize,
-) -> Vec<u8> {
+) -> Result<Vec<u8>> {
+    let content_size = pager.header().usable_size() - 4;
     let mut overflow = Vec::new();
-    let page = OverflowPage::from_file(file, page_number, dbheader).unwrap();
-    overflow.append(&mut page.data[4..4 + num_overflow_bytes].to_vec());
-    overflow
+    let mut visited = HashSet::new();
+    let mut page_number = page_number;
+    while overflow.len() < num_overflow_bytes {
+        if !visited.insert(page_number) {
+            return Err(Error::corrupt_page(page_number, "overflow chain loops"));
+        }
+        if page_number == 0 {
+            return Err(Error::Corrupt(format!(
+                "overflow chain ended after {} of {} bytes",
+                overflow.len(),
+                num_overflow_bytes
+            )));
+        }
+        let page = OverflowPage::from_pager(pager, page_number)?;
+        let take = content_size.min(num_overflow_bytes - overflow.len());
+        overflow.extend_from_slice(&page.data[4..4 + take]);
+        page_number = page.next_page() as u64;
+    }
+    Ok(overflow)
 }
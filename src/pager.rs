@@ -0,0 +1,1118 @@
+use crate::error::{Error, Result};
+use crate::journal::{self, journal_path};
+use crate::lock::{self, LockKind, LockLevel, RESERVED_BYTE};
+use crate::page_io::{DbHeader, FreelistTrunk};
+use crate::wal::{shm_path, wal_path, Checkpoint, CheckpointMode, Wal};
+use bytes::Bytes;
+use memmap2::Mmap;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{prelude::*, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_CACHE_SIZE: usize = 2000;
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Read each page from the file into its own buffer.
+    #[default]
+    Read,
+    /// Map the whole file read-only and hand out slices of the mapping without copying.
+    Mmap,
+    /// Read the whole file into memory once at open time.
+    Memory,
+}
+
+#[derive(Debug, Clone)]
+pub struct PagerOptions {
+    /// Maximum number of pages kept in the cache.
+    pub cache_size: usize,
+    pub backend: Backend,
+    /// Open the file for writing as well as reading.
+    pub writable: bool,
+    /// How long to keep retrying when another connection holds a lock we need, before giving
+    /// up with [`Error::Busy`].
+    pub busy_timeout: Duration,
+}
+
+impl Default for PagerOptions {
+    fn default() -> Self {
+        PagerOptions {
+            cache_size: DEFAULT_CACHE_SIZE,
+            backend: Backend::default(),
+            writable: false,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    /// Commits go through a rollback journal that is deleted once they finish.
+    Delete,
+    /// Commits are appended to a write-ahead log and checkpointed into the file later.
+    Wal,
+}
+
+impl JournalMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Wal => "wal",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub cached_pages: usize,
+}
+
+enum Storage {
+    Read(File),
+    Mmap { file: File, mapping: Bytes },
+    Memory(Bytes),
+}
+
+/// Pending changes as they stood at some point, to go back to with [`Pager::restore`].
+#[derive(Clone)]
+pub struct Snapshot {
+    dirty: BTreeMap<u64, Bytes>,
+    header: DbHeader,
+    truncate_to: Option<u64>,
+}
+
+struct Savepoint {
+    name: String,
+    snapshot: Snapshot,
+    /// Whether opening it started the transaction, so that releasing it commits.
+    begins_transaction: bool,
+}
+
+/// Owns the database file and hands out shared, cached page buffers by page number.
+///
+/// Writes are buffered: `write_page` and `allocate_page` only touch pages held in memory until
+/// `flush` stores them in the file, and `rollback` throws them away. A flush first saves the
+/// pages it overwrites to a rollback journal, which is played back the next time the database
+/// is opened if the flush never finished. In WAL mode a flush appends the pages to the log
+/// instead, and they only reach the file when it is checkpointed.
+pub struct Pager {
+    storage: RefCell<Storage>,
+    /// The database file's path, or None for an in-memory image.
+    path: Option<PathBuf>,
+    /// The header as of the last flush, and as modified by pending writes.
+    saved_header: Cell<DbHeader>,
+    header: Cell<DbHeader>,
+    cache: RefCell<PageCache>,
+    dirty: RefCell<BTreeMap<u64, Bytes>>,
+    /// The page count the file is cut down to by the next flush, after VACUUM has rewritten it.
+    truncate_to: Cell<Option<u64>>,
+    writable: bool,
+    /// How far the database file is locked. A shared lock is taken for the first read of each
+    /// statement or transaction and given up when it ends, and raised while writing.
+    lock: Cell<LockLevel>,
+    busy_timeout: Duration,
+    /// Committed frames from the database's `-wal` file, which take precedence over the file.
+    wal: RefCell<Option<Wal>>,
+    /// Whether a BEGIN or SAVEPOINT is holding changes back from being flushed.
+    in_transaction: Cell<bool>,
+    savepoints: RefCell<Vec<Savepoint>>,
+}
+
+impl Pager {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Pager> {
+        Self::open_with(path, PagerOptions::default())
+    }
+
+    pub fn open_with<P: AsRef<Path>>(path: P, options: PagerOptions) -> Result<Pager> {
+        let path = path.as_ref();
+        let timeout = options.busy_timeout;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(options.writable)
+            .open(path)?;
+        lock::upgrade(&file, LockLevel::Unlocked, LockLevel::Shared, timeout)?;
+        let journal = journal_path(path);
+        // While a connection is writing its journal it holds the reserved lock, so a journal
+        // without one is left over from a flush that was interrupted. The pages it had begun
+        // to overwrite go back before anything is read.
+        if journal::is_hot(&journal)?
+            && !lock::held_by_other(&file, LockKind::Write, RESERVED_BYTE, 1)?
+        {
+            if !options.writable {
+                // Closing the read-only handle gives up its lock along with it.
+                drop(file);
+                file = OpenOptions::new().read(true).write(true).open(path)?;
+                lock::upgrade(&file, LockLevel::Unlocked, LockLevel::Shared, timeout)?;
+            }
+            lock::upgrade(&file, LockLevel::Shared, LockLevel::Exclusive, timeout)?;
+            // Another connection may have played it back while we waited.
+            if journal::is_hot(&journal)? {
+                journal::play_back(&mut file, &journal)?;
+            }
+            lock::downgrade(&file, LockLevel::Shared)?;
+            file.seek(SeekFrom::Start(0))?;
+        }
+        // The header in the file may be out of date, and is only fully parsed once it is
+        // clear the log does not hold a newer one.
+        let mut raw_header = [0; DbHeader::len() as usize];
+        file.read_exact(&mut raw_header)?;
+        let page_size = match u16::from_be_bytes([raw_header[16], raw_header[17]]) {
+            1 => 65536,
+            n => n as u32,
+        };
+        // A database is in WAL mode if its header says so or it has a log, which SQLite
+        // picks up even when the header does not.
+        let wal = if raw_header[18] == 2 || wal_path(path).exists() {
+            match Wal::attach(path, page_size, timeout) {
+                Ok(wal) => Some(wal),
+                // Without write access to the wal-index, read the log without sharing it.
+                Err(Error::Io(e)) if e.kind() == ErrorKind::PermissionDenied => {
+                    Wal::open(path)?.filter(|wal| wal.header.page_size == page_size)
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        // Page 1 in the log carries a newer copy of the database header, and may be the only
+        // valid one if the database has never been checkpointed.
+        if let Some(page) = wal.as_ref().and_then(|wal| wal.get_page(1)) {
+            raw_header.copy_from_slice(&page[..DbHeader::len() as usize]);
+        }
+        let header = DbHeader::from_bytes(&raw_header)?;
+        if options.writable {
+            if header.autovacuum_top_root != 0 {
+                return Err(Error::Unsupported(
+                    "writing to an auto-vacuum database".to_string(),
+                ));
+            }
+            if options.backend == Backend::Memory {
+                return Err(Error::Unsupported(
+                    "writing through the in-memory backend".to_string(),
+                ));
+            }
+        }
+        let storage = match options.backend {
+            Backend::Read => Storage::Read(file),
+            Backend::Mmap => {
+                let mapping = map_file(&file)?;
+                Storage::Mmap { file, mapping }
+            }
+            Backend::Memory => {
+                let mut data = Vec::new();
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut data)?;
+                Storage::Memory(Bytes::from(data))
+            }
+        };
+        Ok(Pager {
+            storage: RefCell::new(storage),
+            path: Some(path.to_path_buf()),
+            saved_header: Cell::new(header),
+            header: Cell::new(header),
+            cache: RefCell::new(PageCache::new(options.cache_size)),
+            dirty: RefCell::new(BTreeMap::new()),
+            truncate_to: Cell::new(None),
+            writable: options.writable,
+            lock: Cell::new(LockLevel::Shared),
+            busy_timeout: timeout,
+            wal: RefCell::new(wal),
+            in_transaction: Cell::new(false),
+            savepoints: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Serves pages from an in-memory database image, e.g. a file that should not be touched
+    /// on disk or a fuzzer input. Writes are flushed back into the image.
+    pub fn from_bytes(data: impl Into<Bytes>) -> Result<Pager> {
+        let data = data.into();
+        let header = match data.get(..DbHeader::len() as usize) {
+            Some(header) => DbHeader::from_bytes(header.try_into().unwrap())?,
+            None => return Err(Error::NotADatabase("file is too short".to_string())),
+        };
+        Ok(Pager {
+            storage: RefCell::new(Storage::Memory(data)),
+            path: None,
+            saved_header: Cell::new(header),
+            header: Cell::new(header),
+            cache: RefCell::new(PageCache::new(0)),
+            dirty: RefCell::new(BTreeMap::new()),
+            truncate_to: Cell::new(None),
+            writable: true,
+            lock: Cell::new(LockLevel::Unlocked),
+            busy_timeout: Duration::ZERO,
+            wal: RefCell::new(None),
+            in_transaction: Cell::new(false),
+            savepoints: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        match *self.storage.borrow() {
+            Storage::Read(_) => Backend::Read,
+            Storage::Mmap { .. } => Backend::Mmap,
+            Storage::Memory(_) => Backend::Memory,
+        }
+    }
+
+    pub fn header(&self) -> DbHeader {
+        self.header.get()
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.header().page_size as usize
+    }
+
+    pub fn wal(&self) -> Option<Ref<'_, Wal>> {
+        Ref::filter_map(self.wal.borrow(), Option::as_ref).ok()
+    }
+
+    pub fn page_count(&self) -> Result<u64> {
+        let committed = self.wal.borrow().as_ref().and_then(Wal::db_size);
+        let committed = match self.truncate_to.get().or(committed) {
+            Some(page_count) => page_count,
+            None => self.stored_page_count()?,
+        };
+        let last_dirty = self.dirty.borrow().keys().next_back().copied();
+        Ok(committed.max(last_dirty.unwrap_or(0)))
+    }
+
+    /// Number of pages in the file itself, leaving out pending writes.
+    fn stored_page_count(&self) -> Result<u64> {
+        let len = match &*self.storage.borrow() {
+            Storage::Read(file) | Storage::Mmap { file, .. } => file.metadata()?.len(),
+            Storage::Memory(data) => data.len() as u64,
+        };
+        Ok(len / self.page_size() as u64)
+    }
+
+    /// Returns the raw bytes of a page. Page numbers start at 1.
+    pub fn get_page(&self, page_number: u64) -> Result<Bytes> {
+        if page_number == 0 {
+            return Err(Error::corrupt_page(0, "page numbers start at 1"));
+        }
+        if let Some(data) = self.dirty.borrow().get(&page_number) {
+            return Ok(data.clone());
+        }
+        self.begin_read()?;
+        if let Some(data) = self.cache.borrow_mut().get(page_number) {
+            return Ok(data);
+        }
+        let logged = self
+            .wal
+            .borrow()
+            .as_ref()
+            .and_then(|wal| wal.get_page(page_number));
+        let data = match logged {
+            Some(data) => data,
+            None => match self.backend() {
+                Backend::Read => self.read_page(page_number)?,
+                Backend::Mmap => self.map_page(page_number)?,
+                Backend::Memory => self.slice_page(page_number)?,
+            },
+        };
+        self.cache.borrow_mut().insert(page_number, data.clone());
+        Ok(data)
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+
+    /// Replaces the contents of a page. The change is visible to `get_page` straight away but
+    /// only reaches the file on `flush`.
+    pub fn write_page(&self, page_number: u64, data: Vec<u8>) -> Result<()> {
+        if !self.writable {
+            return Err(Error::ReadOnly);
+        }
+        if page_number == 0 || data.len() != self.page_size() {
+            return Err(Error::corrupt_page(page_number, "bad page write"));
+        }
+        // In WAL mode writers take turns at commit time instead.
+        if self.wal.borrow().is_none() {
+            self.lock_file(LockLevel::Reserved)?;
+        }
+        self.dirty
+            .borrow_mut()
+            .insert(page_number, Bytes::from(data));
+        Ok(())
+    }
+
+    /// Hands out a page for new content, reusing a freelist page if there is one and growing
+    /// the file otherwise. The caller is expected to overwrite the whole page.
+    pub fn allocate_page(&self) -> Result<u64> {
+        let mut header = self.header();
+        let page_number = if header.first_freelist_trunk != 0 {
+            header.freelist_count = header.freelist_count.saturating_sub(1);
+            let trunk = FreelistTrunk::from_pager(self, header.first_freelist_trunk as u64)?;
+            match trunk.leaves.last() {
+                Some(&leaf) => {
+                    let mut data = self.get_page(trunk.page_number)?.to_vec();
+                    let leaf_count = trunk.leaves.len() as u32 - 1;
+                    data[4..8].copy_from_slice(&leaf_count.to_be_bytes());
+                    self.write_page(trunk.page_number, data)?;
+                    leaf as u64
+                }
+                None => {
+                    header.first_freelist_trunk = trunk.next_trunk;
+                    trunk.page_number
+                }
+            }
+        } else {
+            let mut page_number = self.page_count()? + 1;
+            if page_number == header.pending_byte_page() {
+                page_number += 1;
+            }
+            page_number
+        };
+        self.write_page(page_number, vec![0; self.page_size()])?;
+        self.header.set(header);
+        Ok(page_number)
+    }
+
+    /// Overwrites the whole database with the pages of `source`, cutting off any pages past
+    /// its end. The header comes from `source` too, except for what tracks this file's history:
+    /// the change counter, the journal mode and the schema cookie, which is bumped.
+    pub fn replace_with(&self, source: &Pager) -> Result<()> {
+        let page_count = source.page_count()?;
+        let pending_byte_page = source.header().pending_byte_page();
+        for page_number in 1..=page_count {
+            if page_number != pending_byte_page {
+                self.write_page(page_number, source.get_page(page_number)?.to_vec())?;
+            }
+        }
+        self.dirty.borrow_mut().split_off(&(page_count + 1));
+        let old = self.header();
+        let mut header = source.header();
+        header.file_change_counter = old.file_change_counter;
+        header.write_version = old.write_version;
+        header.read_version = old.read_version;
+        header.schema_cookie = old.schema_cookie.wrapping_add(1);
+        self.header.set(header);
+        self.truncate_to.set(Some(page_count));
+        Ok(())
+    }
+
+    /// Bumps the schema cookie, telling other connections that the schema they read is out of
+    /// date.
+    pub fn schema_changed(&self) {
+        let mut header = self.header();
+        header.schema_cookie = header.schema_cookie.wrapping_add(1);
+        self.header.set(header);
+    }
+
+    /// Puts a page that is no longer used on the freelist, as a leaf of the first trunk if it
+    /// has room and as a new trunk otherwise.
+    pub fn free_page(&self, page_number: u64) -> Result<()> {
+        let mut header = self.header();
+        // SQLite fills trunks to six entries short of the format's limit, for the sake of
+        // versions that got the limit wrong.
+        let max_leaves = header.usable_size() / 4 - 8;
+        if header.first_freelist_trunk != 0 {
+            let trunk = FreelistTrunk::from_pager(self, header.first_freelist_trunk as u64)?;
+            if trunk.leaves.len() < max_leaves {
+                let mut data = self.get_page(trunk.page_number)?.to_vec();
+                let leaf_count = trunk.leaves.len() as u32;
+                let entry = 8 + leaf_count as usize * 4;
+                data[4..8].copy_from_slice(&(leaf_count + 1).to_be_bytes());
+                data[entry..entry + 4].copy_from_slice(&(page_number as u32).to_be_bytes());
+                self.write_page(trunk.page_number, data)?;
+                header.freelist_count += 1;
+                self.header.set(header);
+                return Ok(());
+            }
+        }
+        let mut data = vec![0; self.page_size()];
+        data[..4].copy_from_slice(&header.first_freelist_trunk.to_be_bytes());
+        self.write_page(page_number, data)?;
+        header.first_freelist_trunk = page_number as u32;
+        header.freelist_count += 1;
+        self.header.set(header);
+        Ok(())
+    }
+
+    /// Updates the header fields kept on page 1 and writes every modified page to the file. If
+    /// that fails, whatever reached the file is undone and the pending writes are rolled back.
+    pub fn flush(&self) -> Result<()> {
+        if self.dirty.borrow().is_empty() {
+            self.end_read();
+            return Ok(());
+        }
+        let flushed = self.write_dirty();
+        if flushed.is_err() {
+            self.rollback();
+        }
+        flushed
+    }
+
+    fn write_dirty(&self) -> Result<()> {
+        let mut header = self.header();
+        // Only rollback-mode commits count as changes to the file; readers of a WAL database
+        // notice commits through the wal-index instead.
+        if self.wal.borrow().is_none() {
+            header.file_change_counter = header.file_change_counter.wrapping_add(1);
+            header.version_valid_for = header.file_change_counter;
+        }
+        header.database_size = self.page_count()? as u32;
+        let mut page_one = self.get_page(1)?.to_vec();
+        page_one[..DbHeader::len() as usize].copy_from_slice(&header.to_bytes());
+        self.write_page(1, page_one)?;
+
+        // The pending writes stay pending until they are safely stored.
+        let dirty = self.dirty.borrow().clone();
+        let truncate_to = self.truncate_to.get();
+        match self.wal.borrow_mut().as_mut() {
+            Some(wal) => wal.commit(&dirty, header.database_size)?,
+            None => {
+                // Everyone else has to be out of the way while the file changes under them.
+                let written = self
+                    .lock_file(LockLevel::Exclusive)
+                    .and_then(|()| self.write_through_journal(&dirty, truncate_to));
+                self.unlock_file();
+                written?;
+            }
+        }
+        self.dirty.borrow_mut().clear();
+        self.truncate_to.set(None);
+        let mut cache = self.cache.borrow_mut();
+        for (page_number, data) in dirty {
+            cache.insert(page_number, data);
+        }
+        self.header.set(header);
+        self.saved_header.set(header);
+        self.end_read();
+        Ok(())
+    }
+
+    /// Writes pages to the file and cuts it down to `truncate_to` pages, first saving the pages
+    /// that are overwritten or cut off to a rollback journal.
+    fn write_through_journal(
+        &self,
+        dirty: &BTreeMap<u64, Bytes>,
+        truncate_to: Option<u64>,
+    ) -> Result<()> {
+        let journal = match &self.path {
+            Some(path) => {
+                let stored_pages = self.stored_page_count()?;
+                let cut_off = truncate_to.unwrap_or(stored_pages);
+                let originals = dirty
+                    .keys()
+                    .copied()
+                    .filter(|&page_number| page_number <= stored_pages)
+                    .chain(cut_off + 1..=stored_pages)
+                    .map(|page_number| Ok((page_number, self.read_page(page_number)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                let journal = journal_path(path);
+                journal::write_journal(
+                    &journal,
+                    self.page_size() as u32,
+                    stored_pages as u32,
+                    &originals,
+                )?;
+                Some(journal)
+            }
+            None => None,
+        };
+        let written = self
+            .write_to_storage(dirty)
+            .and_then(|()| match truncate_to {
+                Some(page_count) => self.truncate_storage(page_count),
+                None => Ok(()),
+            });
+        if let Err(e) = written {
+            // Put back the pages that made it into the file before giving up.
+            if let Some(journal) = &journal {
+                self.play_back(journal)?;
+            }
+            return Err(e);
+        }
+        // Once the database itself is durable the journal is no longer needed, and deleting
+        // it is what commits the change.
+        if let Some(journal) = journal {
+            std::fs::remove_file(journal)?;
+        }
+        Ok(())
+    }
+
+    pub fn journal_mode(&self) -> JournalMode {
+        if self.wal.borrow().is_some() || self.header().write_version == 2 {
+            JournalMode::Wal
+        } else {
+            JournalMode::Delete
+        }
+    }
+
+    /// Switches the database into or out of WAL mode, which is recorded in the file format
+    /// versions in its header. Leaving WAL mode checkpoints the log and deletes it.
+    pub fn set_journal_mode(&self, mode: JournalMode) -> Result<JournalMode> {
+        if mode == self.journal_mode() {
+            return Ok(mode);
+        }
+        if !self.writable {
+            return Err(Error::ReadOnly);
+        }
+        let Some(path) = &self.path else {
+            return Err(Error::Unsupported(
+                "WAL mode for an in-memory image".to_string(),
+            ));
+        };
+        if self.in_transaction() {
+            return Err(Error::InvalidStatement(format!(
+                "cannot change {} wal mode from within a transaction",
+                if mode == JournalMode::Wal {
+                    "into"
+                } else {
+                    "out of"
+                }
+            )));
+        }
+        match mode {
+            JournalMode::Wal => {
+                self.set_file_format(2)?;
+                let wal = Wal::attach(path, self.header().page_size, self.busy_timeout)?;
+                *self.wal.borrow_mut() = Some(wal);
+            }
+            JournalMode::Delete => {
+                // The log can only go once no other connection is using it.
+                self.lock_file(LockLevel::Exclusive)?;
+                let removed = self.remove_wal(path);
+                self.unlock_file();
+                removed?;
+                self.set_file_format(1)?;
+            }
+        }
+        Ok(mode)
+    }
+
+    /// Checkpoints the whole log into the database file, then deletes the log and wal-index.
+    fn remove_wal(&self, path: &Path) -> Result<()> {
+        if self
+            .checkpoint(CheckpointMode::Truncate)?
+            .is_some_and(|checkpoint| checkpoint.busy)
+        {
+            return Err(Error::Busy);
+        }
+        *self.wal.borrow_mut() = None;
+        for file in [wal_path(path), shm_path(path)] {
+            match std::fs::remove_file(file) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the header's read and write format versions, which are 2 in WAL mode and 1
+    /// otherwise, and flushes the change.
+    fn set_file_format(&self, version: u8) -> Result<()> {
+        let mut header = self.header();
+        header.write_version = version;
+        header.read_version = version;
+        self.header.set(header);
+        // Page 1 has to be dirty for the flush to write the new header.
+        self.write_page(1, self.get_page(1)?.to_vec())?;
+        self.flush()
+    }
+
+    /// Copies the latest committed version of every page in the log into the database file.
+    /// Returns None if the database is not in WAL mode.
+    pub fn checkpoint(&self, mode: CheckpointMode) -> Result<Option<Checkpoint>> {
+        if !self.writable {
+            return Err(Error::ReadOnly);
+        }
+        if self.in_transaction() {
+            return Err(Error::InvalidStatement(
+                "database table is locked".to_string(),
+            ));
+        }
+        // Only the latest snapshot can be checkpointed.
+        self.begin_read()?;
+        let checkpoint = self.wal.borrow_mut().as_mut().map(|wal| {
+            wal.checkpoint(mode, |pages, page_count| {
+                self.write_to_storage(pages)?;
+                self.truncate_storage(page_count)
+            })
+        });
+        self.end_read();
+        checkpoint.transpose()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            dirty: self.dirty.borrow().clone(),
+            header: self.header(),
+            truncate_to: self.truncate_to.get(),
+        }
+    }
+
+    /// Undoes every write made since `snapshot` was taken.
+    pub fn restore(&self, snapshot: Snapshot) {
+        *self.dirty.borrow_mut() = snapshot.dirty;
+        self.header.set(snapshot.header);
+        self.truncate_to.set(snapshot.truncate_to);
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction.get()
+    }
+
+    /// Claims the right to write before a statement reads anything, so that it does not end
+    /// up holding a read lock that another writer is waiting on while it waits for that writer.
+    /// Inside a transaction that has already read, all it can do is wait.
+    pub fn begin_write(&self) -> Result<()> {
+        if !self.writable {
+            return Err(Error::ReadOnly);
+        }
+        // In WAL mode writers take turns at commit time instead.
+        if self.wal.borrow().is_some() || self.lock.get() >= LockLevel::Reserved {
+            return Ok(());
+        }
+        if self.in_transaction() || !self.dirty.borrow().is_empty() {
+            return self.lock_file(LockLevel::Reserved);
+        }
+        lock::retry(self.busy_timeout, || {
+            self.end_read();
+            self.begin_read()?;
+            let reserved = match &*self.storage.borrow() {
+                Storage::Read(file) | Storage::Mmap { file, .. } => {
+                    lock::try_lock(file, LockKind::Write, RESERVED_BYTE, 1)?
+                }
+                Storage::Memory(_) => true,
+            };
+            if reserved {
+                self.lock.set(LockLevel::Reserved);
+            }
+            Ok(reserved)
+        })
+    }
+
+    /// Starts holding writes back until `commit`.
+    pub fn begin(&self) -> Result<()> {
+        if self.in_transaction() {
+            return Err(Error::InvalidStatement(
+                "cannot start a transaction within a transaction".to_string(),
+            ));
+        }
+        self.in_transaction.set(true);
+        Ok(())
+    }
+
+    /// Flushes the open transaction's writes and ends it.
+    pub fn commit(&self) -> Result<()> {
+        if !self.in_transaction() {
+            return Err(Error::InvalidStatement(
+                "cannot commit - no transaction is active".to_string(),
+            ));
+        }
+        self.flush()?;
+        self.end_transaction();
+        self.end_read();
+        Ok(())
+    }
+
+    /// Drops every write since the last flush and ends the open transaction, if there is one.
+    pub fn rollback(&self) {
+        self.dirty.borrow_mut().clear();
+        self.header.set(self.saved_header.get());
+        self.truncate_to.set(None);
+        self.end_transaction();
+        self.unlock_file();
+        self.end_read();
+    }
+
+    /// Marks a point that `rollback_to` can return to, starting a transaction if none is open.
+    pub fn savepoint(&self, name: &str) {
+        self.savepoints.borrow_mut().push(Savepoint {
+            name: name.to_string(),
+            snapshot: self.snapshot(),
+            begins_transaction: !self.in_transaction(),
+        });
+        self.in_transaction.set(true);
+    }
+
+    /// Forgets the most recent savepoint called `name` and every one opened after it. If that
+    /// savepoint started the transaction, the transaction is committed.
+    pub fn release(&self, name: &str) -> Result<()> {
+        let position = self.find_savepoint(name)?;
+        let released = self.savepoints.borrow_mut().drain(position..).next();
+        if released.is_some_and(|savepoint| savepoint.begins_transaction) {
+            self.flush()?;
+            self.end_transaction();
+        }
+        Ok(())
+    }
+
+    /// Undoes every write made since the most recent savepoint called `name`, which stays open
+    /// while those opened after it are forgotten.
+    pub fn rollback_to(&self, name: &str) -> Result<()> {
+        let position = self.find_savepoint(name)?;
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.truncate(position + 1);
+        self.restore(savepoints[position].snapshot.clone());
+        Ok(())
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .borrow()
+            .iter()
+            .rposition(|savepoint| savepoint.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::InvalidStatement(format!("no such savepoint: {}", name)))
+    }
+
+    fn end_transaction(&self) {
+        self.in_transaction.set(false);
+        self.savepoints.borrow_mut().clear();
+    }
+
+    /// Takes a shared lock for reading, if this connection does not hold one already. Anything
+    /// another connection has committed since the last read transaction ended invalidates the
+    /// cache.
+    fn begin_read(&self) -> Result<()> {
+        if self.lock.get() != LockLevel::Unlocked {
+            return Ok(());
+        }
+        // An in-memory image has no one else to share it with.
+        if let Storage::Memory(_) = &*self.storage.borrow() {
+            return Ok(());
+        }
+        self.lock_file(LockLevel::Shared)?;
+        let changed = match self.wal.borrow_mut().as_mut() {
+            Some(wal) => wal.begin_read(),
+            None => self.file_changed(),
+        };
+        let changed = match changed {
+            Ok(changed) => changed,
+            Err(e) => {
+                self.end_read();
+                return Err(e);
+            }
+        };
+        if changed {
+            self.cache.borrow_mut().clear();
+            let page = match self.wal.borrow().as_ref().and_then(|wal| wal.get_page(1)) {
+                Some(page) => page,
+                None => self.read_page(1)?,
+            };
+            let header =
+                DbHeader::from_bytes(page[..DbHeader::len() as usize].try_into().unwrap())?;
+            self.header.set(header);
+            self.saved_header.set(header);
+        }
+        Ok(())
+    }
+
+    /// Whether another connection has committed to the file since it was last read. Every
+    /// commit in rollback mode bumps the file change counter.
+    fn file_changed(&self) -> Result<bool> {
+        if self.stored_page_count()? == 0 {
+            return Ok(false);
+        }
+        let page = self.read_page(1)?;
+        Ok(page[24..28] != self.saved_header.get().file_change_counter.to_be_bytes())
+    }
+
+    /// Ends the read transaction once there is no write transaction left to keep it open,
+    /// letting other connections write.
+    fn end_read(&self) {
+        if self.in_transaction() || !self.dirty.borrow().is_empty() {
+            return;
+        }
+        if self.lock.get() == LockLevel::Unlocked {
+            return;
+        }
+        if let Some(wal) = self.wal.borrow_mut().as_mut() {
+            let _ = wal.end_read();
+        }
+        if let Storage::Read(file) | Storage::Mmap { file, .. } = &*self.storage.borrow() {
+            let _ = lock::downgrade(file, LockLevel::Unlocked);
+        }
+        self.lock.set(LockLevel::Unlocked);
+    }
+
+    /// Raises the lock on the database file to `level`, if it is not that high already.
+    fn lock_file(&self, level: LockLevel) -> Result<()> {
+        let current = self.lock.get();
+        if current >= level {
+            return Ok(());
+        }
+        if let Storage::Read(file) | Storage::Mmap { file, .. } = &*self.storage.borrow() {
+            lock::upgrade(file, current, level, self.busy_timeout)?;
+        }
+        self.lock.set(level);
+        Ok(())
+    }
+
+    /// Drops back to the shared lock held between writes.
+    fn unlock_file(&self) {
+        if self.lock.get() <= LockLevel::Shared {
+            return;
+        }
+        if let Storage::Read(file) | Storage::Mmap { file, .. } = &*self.storage.borrow() {
+            // Giving up a lock never conflicts with anyone.
+            let _ = lock::downgrade(file, LockLevel::Shared);
+        }
+        self.lock.set(LockLevel::Shared);
+    }
+
+    fn write_to_storage(&self, pages: &BTreeMap<u64, Bytes>) -> Result<()> {
+        let mut storage = self.storage.borrow_mut();
+        match &mut *storage {
+            Storage::Read(file) | Storage::Mmap { file, .. } => {
+                for (page_number, data) in pages {
+                    let (start, _) = self.page_range(*page_number);
+                    file.seek(SeekFrom::Start(start))?;
+                    file.write_all(data)?;
+                }
+                file.sync_all()?;
+            }
+            Storage::Memory(image) => {
+                let mut bytes = image.to_vec();
+                for (page_number, data) in pages {
+                    let (start, end) = self.page_range(*page_number);
+                    if bytes.len() < end as usize {
+                        bytes.resize(end as usize, 0);
+                    }
+                    bytes[start as usize..end as usize].copy_from_slice(data);
+                }
+                *image = Bytes::from(bytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Cuts the file down to `page_count` pages, once the database has shrunk.
+    fn truncate_storage(&self, page_count: u64) -> Result<()> {
+        let len = page_count * self.page_size() as u64;
+        match &mut *self.storage.borrow_mut() {
+            Storage::Read(file) | Storage::Mmap { file, .. } => {
+                if file.metadata()?.len() > len {
+                    file.set_len(len)?;
+                    file.sync_all()?;
+                }
+            }
+            Storage::Memory(image) => image.truncate(len as usize),
+        }
+        Ok(())
+    }
+
+    /// Restores the file from the journal written for a flush that failed part way.
+    fn play_back(&self, journal: &Path) -> Result<()> {
+        if let Storage::Read(file) | Storage::Mmap { file, .. } = &mut *self.storage.borrow_mut() {
+            journal::play_back(file, journal)?;
+        }
+        Ok(())
+    }
+
+    fn page_range(&self, page_number: u64) -> (u64, u64) {
+        let page_size = self.page_size() as u64;
+        let start = (page_number - 1) * page_size;
+        (start, start + page_size)
+    }
+
+    fn read_page(&self, page_number: u64) -> Result<Bytes> {
+        let mut data = vec![0; self.page_size()];
+        let (start, _) = self.page_range(page_number);
+        let storage = self.storage.borrow();
+        let mut file = match &*storage {
+            Storage::Read(file) | Storage::Mmap { file, .. } => file,
+            Storage::Memory(_) => unreachable!("in-memory pages are sliced, not read"),
+        };
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut data).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => {
+                Error::corrupt_page(page_number, "past the end of the file")
+            }
+            _ => Error::Io(e),
+        })?;
+        Ok(Bytes::from(data))
+    }
+
+    fn slice_page(&self, page_number: u64) -> Result<Bytes> {
+        let (start, end) = self.page_range(page_number);
+        match &*self.storage.borrow() {
+            Storage::Memory(data) if end <= data.len() as u64 => {
+                Ok(data.slice(start as usize..end as usize))
+            }
+            _ => Err(Error::corrupt_page(page_number, "past the end of the file")),
+        }
+    }
+
+    /// Slices a page out of the mapping. The file length is checked on every cache miss: a
+    /// file that has grown is remapped, and a file that has shrunk below the mapping drops
+    /// back to the read backend, since touching a mapped page past the end of the file
+    /// raises SIGBUS.
+    fn map_page(&self, page_number: u64) -> Result<Bytes> {
+        let (start, end) = self.page_range(page_number);
+        let mut storage = self.storage.borrow_mut();
+        let Storage::Mmap { file, mapping } = &mut *storage else {
+            unreachable!("map_page is only called for the mmap backend");
+        };
+        let file_len = file.metadata()?.len();
+        let mapped_len = mapping.len() as u64;
+        if file_len < mapped_len {
+            if let Storage::Mmap { file, .. } =
+                std::mem::replace(&mut *storage, Storage::Memory(Bytes::new()))
+            {
+                *storage = Storage::Read(file);
+            }
+            drop(storage);
+            self.cache.borrow_mut().clear();
+            return self.read_page(page_number);
+        }
+        if file_len > mapped_len {
+            *mapping = map_file(file)?;
+        }
+        if end > mapping.len() as u64 {
+            return Err(Error::corrupt_page(page_number, "past the end of the file"));
+        }
+        Ok(mapping.slice(start as usize..end as usize))
+    }
+}
+
+fn map_file(file: &File) -> Result<Bytes> {
+    if file.metadata()?.len() == 0 {
+        return Ok(Bytes::new());
+    }
+    // SAFETY: the mapping is only ever read, but its pages are not safe from the file
+    // changing underneath them. Slices handed out keep pointing into the mapping, and reading
+    // one after another process has cut the file short raises SIGBUS; the length check in
+    // `map_page` only catches truncation that happens before a page is sliced. This relies on
+    // other connections following the locking protocol: a writer only shrinks the file under
+    // an exclusive lock, which it cannot get while this connection holds its shared lock, and
+    // pages from before a change are dropped from the cache when the next read begins.
+    let map = unsafe { Mmap::map(file)? };
+    Ok(Bytes::from_owner(map))
+}
+
+const NIL: usize = usize::MAX;
+
+struct CacheEntry {
+    page_number: u64,
+    data: Bytes,
+    prev: usize,
+    next: usize,
+}
+
+/// Fixed-capacity LRU map from page number to page bytes. Entries live in a slab and are
+/// chained into a doubly linked list ordered from most to least recently used.
+struct PageCache {
+    capacity: usize,
+    index: HashMap<u64, usize>,
+    entries: Vec<CacheEntry>,
+    head: usize,
+    tail: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl PageCache {
+    fn new(capacity: usize) -> PageCache {
+        PageCache {
+            capacity,
+            index: HashMap::new(),
+            entries: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    fn get(&mut self, page_number: u64) -> Option<Bytes> {
+        match self.index.get(&page_number) {
+            Some(&slot) => {
+                self.hits += 1;
+                self.unlink(slot);
+                self.push_front(slot);
+                Some(self.entries[slot].data.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, page_number: u64, data: Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(&slot) = self.index.get(&page_number) {
+            self.entries[slot].data = data;
+            self.unlink(slot);
+            self.push_front(slot);
+            return;
+        }
+        let slot = if self.entries.len() < self.capacity {
+            self.entries.push(CacheEntry {
+                page_number,
+                data,
+                prev: NIL,
+                next: NIL,
+            });
+            self.entries.len() - 1
+        } else {
+            let slot = self.tail;
+            self.unlink(slot);
+            self.index.remove(&self.entries[slot].page_number);
+            self.evictions += 1;
+            self.entries[slot].page_number = page_number;
+            self.entries[slot].data = data;
+            slot
+        };
+        self.index.insert(page_number, slot);
+        self.push_front(slot);
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = (self.entries[slot].prev, self.entries[slot].next);
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.entries[prev].next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.entries[next].prev = prev;
+        }
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        self.entries[slot].prev = NIL;
+        self.entries[slot].next = self.head;
+        if self.head != NIL {
+            self.entries[self.head].prev = slot;
+        }
+        self.head = slot;
+        if self.tail == NIL {
+            self.tail = slot;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.index.clear();
+        self.entries.clear();
+        self.head = NIL;
+        self.tail = NIL;
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            cached_pages: self.index.len(),
+        }
+    }
+}
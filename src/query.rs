@@ -0,0 +1,1191 @@
+use crate::error::{Error, Result};
+use crate::eval::{aggregate_calls, table_row, Accumulator, Affinity, Collation, Scope};
+use crate::page_io::{
+    count_entries, row_lookup, BTreeCursor, IndexSchema, KeyOrder, Record, SeekOp, SqliteSchema,
+    TableSchema, TextEncoding,
+};
+use crate::pager::Pager;
+use crate::syntax;
+use crate::syntax::expr::{BinaryOp, Expr, FunctionArgs};
+use crate::syntax::insert::Value;
+use crate::syntax::select::{JoinConstraint, JoinKind, ResultColumn, SelectStmt};
+use crate::syntax::statement::Statement;
+use crate::typecodes::SqlValue;
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// The table every database describes its schema in, under either of its names.
+const SCHEMA_TABLE_SQL: &str =
+    "CREATE TABLE sqlite_schema(type text, name text, tbl_name text, rootpage int, sql text)";
+
+/// A step of a query plan, which hands out its rows one at a time as it is asked for them, so
+/// nothing is read before it is needed.
+pub trait Operator {
+    /// The next row, or None once there are no more.
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>>;
+}
+
+type BoxedOperator<'a> = Box<dyn Operator + 'a>;
+
+/// The rows of a SELECT, read as they are iterated over.
+pub struct Query<'a> {
+    root: BoxedOperator<'a>,
+}
+
+impl Iterator for Query<'_> {
+    type Item = Result<Vec<SqlValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.root.next().transpose()
+    }
+}
+
+/// Every row of a table, in rowid order, or key order for a WITHOUT ROWID table.
+pub struct TableScan<'a> {
+    cursor: BTreeCursor<'a>,
+    schema: Rc<TableSchema>,
+    started: bool,
+}
+
+impl<'a> TableScan<'a> {
+    pub fn new(pager: &'a Pager, root: u64, schema: Rc<TableSchema>) -> TableScan<'a> {
+        TableScan {
+            cursor: BTreeCursor::new(pager, root),
+            schema,
+            started: false,
+        }
+    }
+}
+
+impl Operator for TableScan<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        let found = if self.started {
+            self.cursor.next()?
+        } else {
+            self.started = true;
+            self.cursor.first()?
+        };
+        if !found {
+            return Ok(None);
+        }
+        Ok(Some(stored_row(&self.schema, self.cursor.record()?)))
+    }
+}
+
+/// The row of a table with a given rowid, if there is one.
+pub struct RowidSeek<'a> {
+    pager: &'a Pager,
+    root: u64,
+    schema: Rc<TableSchema>,
+    rowid: Option<i64>,
+}
+
+impl<'a> RowidSeek<'a> {
+    pub fn new(
+        pager: &'a Pager,
+        root: u64,
+        schema: Rc<TableSchema>,
+        rowid: Option<i64>,
+    ) -> RowidSeek<'a> {
+        RowidSeek {
+            pager,
+            root,
+            schema,
+            rowid,
+        }
+    }
+}
+
+impl Operator for RowidSeek<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        let Some(rowid) = self.rowid.take() else {
+            return Ok(None);
+        };
+        let record = row_lookup(self.pager, self.root, rowid)?;
+        Ok(record.map(|record| stored_row(&self.schema, record)))
+    }
+}
+
+/// The rows whose key starts with a given value, found through an index of a rowid table, or
+/// directly in a WITHOUT ROWID table by its primary key.
+pub struct IndexSeek<'a> {
+    pager: &'a Pager,
+    cursor: BTreeCursor<'a>,
+    /// The table to look each index entry's rowid up in, or None when the entries are rows.
+    table_root: Option<u64>,
+    schema: Rc<TableSchema>,
+    key: SqlValue,
+    /// How the b-tree orders its entries, which decides which of them equal the key.
+    order: KeyOrder,
+    started: bool,
+}
+
+impl<'a> IndexSeek<'a> {
+    pub fn new(
+        pager: &'a Pager,
+        index_root: u64,
+        table_root: Option<u64>,
+        schema: Rc<TableSchema>,
+        key: SqlValue,
+        order: KeyOrder,
+    ) -> IndexSeek<'a> {
+        IndexSeek {
+            pager,
+            cursor: BTreeCursor::new(pager, index_root),
+            table_root,
+            schema,
+            key,
+            order,
+            started: false,
+        }
+    }
+
+    /// Moves to the next entry with the key, returning false once there are no more.
+    fn advance(&mut self) -> Result<bool> {
+        let found = if self.started {
+            self.cursor.next()?
+        } else {
+            self.started = true;
+            // No entry equals NULL.
+            !matches!(self.key, SqlValue::Null)
+                && self.cursor.seek_key(
+                    std::slice::from_ref(&self.key),
+                    &self.order,
+                    SeekOp::GreaterEqual,
+                )?
+        };
+        Ok(found)
+    }
+}
+
+impl Operator for IndexSeek<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        let encoding = self.pager.header().text_encoding;
+        while self.advance()? {
+            let entry = self.cursor.record()?;
+            let first = &entry.values[..entry.values.len().min(1)];
+            let key = std::slice::from_ref(&self.key);
+            if self.order.compare(first, key, encoding) != Ordering::Equal {
+                // Past the last entry with the key; make sure nothing more is read.
+                self.key = SqlValue::Null;
+                self.started = false;
+                return Ok(None);
+            }
+            let Some(root) = self.table_root else {
+                return Ok(Some(stored_row(&self.schema, entry)));
+            };
+            let rowid = entry.values.last().and_then(SqlValue::as_i64);
+            let rowid =
+                rowid.ok_or_else(|| Error::corrupt_page(root, "index entry has no rowid"))?;
+            if let Some(record) = row_lookup(self.pager, root, rowid)? {
+                return Ok(Some(stored_row(&self.schema, record)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The one empty row a SELECT without FROM computes its columns from.
+#[derive(Default)]
+pub struct SingleRow {
+    done: bool,
+}
+
+impl Operator for SingleRow {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        Ok((!std::mem::replace(&mut self.done, true)).then(Vec::new))
+    }
+}
+
+/// The rows of its input that meet a condition.
+pub struct Filter<'a> {
+    input: BoxedOperator<'a>,
+    scope: Rc<Scope>,
+    condition: Expr,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(input: BoxedOperator<'a>, scope: Rc<Scope>, condition: Expr) -> Filter<'a> {
+        Filter {
+            input,
+            scope,
+            condition,
+        }
+    }
+}
+
+impl Operator for Filter<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        while let Some(row) = self.input.next()? {
+            if self.scope.is_true(&self.condition, &row)? {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The values of some expressions for each row of its input.
+pub struct Project<'a> {
+    input: BoxedOperator<'a>,
+    scope: Rc<Scope>,
+    exprs: Vec<Expr>,
+}
+
+impl<'a> Project<'a> {
+    pub fn new(input: BoxedOperator<'a>, scope: Rc<Scope>, exprs: Vec<Expr>) -> Project<'a> {
+        Project {
+            input,
+            scope,
+            exprs,
+        }
+    }
+}
+
+impl Operator for Project<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        let Some(row) = self.input.next()? else {
+            return Ok(None);
+        };
+        let values = self
+            .exprs
+            .iter()
+            .map(|expr| self.scope.evaluate(expr, &row))
+            .collect::<Result<_>>()?;
+        Ok(Some(values))
+    }
+}
+
+/// At most `limit` rows of its input after skipping `offset`. Its input is not asked for
+/// anything once the limit is reached.
+pub struct Limit<'a> {
+    input: BoxedOperator<'a>,
+    limit: Option<u64>,
+    offset: u64,
+}
+
+impl<'a> Limit<'a> {
+    pub fn new(input: BoxedOperator<'a>, limit: Option<u64>, offset: u64) -> Limit<'a> {
+        Limit {
+            input,
+            limit,
+            offset,
+        }
+    }
+}
+
+impl Operator for Limit<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        if self.limit == Some(0) {
+            return Ok(None);
+        }
+        while self.offset > 0 {
+            if self.input.next()?.is_none() {
+                return Ok(None);
+            }
+            self.offset -= 1;
+        }
+        let row = self.input.next()?;
+        if row.is_some() {
+            self.limit = self.limit.map(|limit| limit - 1);
+        }
+        Ok(row)
+    }
+}
+
+/// A value of a row to sort by, and how.
+#[derive(Debug, Clone, Copy)]
+pub struct SortKey {
+    pub position: usize,
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub collation: Collation,
+}
+
+/// Its input in order. The rows carry their sort keys after the first `width` values, and
+/// are handed out without them.
+pub struct Sort<'a> {
+    input: Option<BoxedOperator<'a>>,
+    keys: Vec<SortKey>,
+    width: usize,
+    encoding: TextEncoding,
+    sorted: std::vec::IntoIter<Vec<SqlValue>>,
+}
+
+impl<'a> Sort<'a> {
+    pub fn new(
+        input: BoxedOperator<'a>,
+        keys: Vec<SortKey>,
+        width: usize,
+        encoding: TextEncoding,
+    ) -> Sort<'a> {
+        Sort {
+            input: Some(input),
+            keys,
+            width,
+            encoding,
+            sorted: Vec::new().into_iter(),
+        }
+    }
+
+    fn compare(&self, a: &[SqlValue], b: &[SqlValue]) -> Ordering {
+        for key in &self.keys {
+            let (a, b) = (&a[key.position], &b[key.position]);
+            let ordering = match (a, b) {
+                (SqlValue::Null, SqlValue::Null) => Ordering::Equal,
+                (SqlValue::Null, _) if key.nulls_first => Ordering::Less,
+                (SqlValue::Null, _) => Ordering::Greater,
+                (_, SqlValue::Null) if key.nulls_first => Ordering::Greater,
+                (_, SqlValue::Null) => Ordering::Less,
+                _ if key.descending => key.collation.compare(b, a, self.encoding),
+                _ => key.collation.compare(a, b, self.encoding),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl Operator for Sort<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        if let Some(mut input) = self.input.take() {
+            let mut rows = Vec::new();
+            while let Some(row) = input.next()? {
+                rows.push(row);
+            }
+            rows.sort_by(|a, b| self.compare(a, b));
+            self.sorted = rows.into_iter();
+        }
+        Ok(self.sorted.next().map(|mut row| {
+            row.truncate(self.width);
+            row
+        }))
+    }
+}
+
+/// Its input without repeated rows, comparing the first values of each row under the given
+/// collations.
+pub struct Distinct<'a> {
+    input: BoxedOperator<'a>,
+    collations: Vec<Collation>,
+    encoding: TextEncoding,
+    /// The rows handed out so far, sorted.
+    seen: Vec<Vec<SqlValue>>,
+}
+
+impl<'a> Distinct<'a> {
+    pub fn new(
+        input: BoxedOperator<'a>,
+        collations: Vec<Collation>,
+        encoding: TextEncoding,
+    ) -> Distinct<'a> {
+        Distinct {
+            input,
+            collations,
+            encoding,
+            seen: Vec::new(),
+        }
+    }
+}
+
+impl Operator for Distinct<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        while let Some(row) = self.input.next()? {
+            let distinct = &row[..self.collations.len()];
+            let found = self.seen.binary_search_by(|seen| {
+                compare_values(seen, distinct, &self.collations, self.encoding)
+            });
+            if let Err(i) = found {
+                self.seen.insert(i, distinct.to_vec());
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// One row per group of its input rows with equal GROUP BY values, in order of those values,
+/// or a single row for all of them when there is no GROUP BY. A row holds the values of one
+/// of the group's input rows, followed by the result of each aggregate call.
+pub struct Aggregate<'a> {
+    input: Option<BoxedOperator<'a>>,
+    scope: Rc<Scope>,
+    group_by: Vec<Expr>,
+    collations: Vec<Collation>,
+    accumulators: Vec<Accumulator>,
+    groups: std::vec::IntoIter<Vec<SqlValue>>,
+}
+
+impl<'a> Aggregate<'a> {
+    /// `accumulators` hold the state each group starts its calls with.
+    pub fn new(
+        input: BoxedOperator<'a>,
+        scope: Rc<Scope>,
+        group_by: Vec<Expr>,
+        accumulators: Vec<Accumulator>,
+    ) -> Aggregate<'a> {
+        let collations = group_by
+            .iter()
+            .map(|expr| collation_of(&scope, expr))
+            .collect();
+        Aggregate {
+            input: Some(input),
+            scope,
+            group_by,
+            collations,
+            accumulators,
+            groups: Vec::new().into_iter(),
+        }
+    }
+
+    fn run(&self, mut input: BoxedOperator) -> Result<Vec<Vec<SqlValue>>> {
+        let encoding = self.scope.encoding();
+        // The other columns come from the row min() or max() found, if that is the only one.
+        let mut min_max = self
+            .accumulators
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| a.is_min_max());
+        let chooser = match (min_max.next(), min_max.next()) {
+            (Some((i, _)), None) => Some(i),
+            _ => None,
+        };
+        let mut groups: Vec<(Vec<SqlValue>, Vec<SqlValue>, Vec<Accumulator>)> = Vec::new();
+        while let Some(row) = input.next()? {
+            let key = self
+                .group_by
+                .iter()
+                .map(|expr| self.scope.evaluate(expr, &row))
+                .collect::<Result<Vec<_>>>()?;
+            let found = groups.binary_search_by(|(group_key, _, _)| {
+                compare_values(group_key, &key, &self.collations, encoding)
+            });
+            let i = match found {
+                Ok(i) => i,
+                Err(i) => {
+                    groups.insert(i, (key, row.clone(), self.accumulators.clone()));
+                    i
+                }
+            };
+            let (_, chosen, accumulators) = &mut groups[i];
+            let mut found_min_max = false;
+            for (j, accumulator) in accumulators.iter_mut().enumerate() {
+                let improved = accumulator.step(&self.scope, &row)?;
+                found_min_max |= improved && chooser == Some(j);
+            }
+            if chooser.is_none() || found_min_max {
+                *chosen = row;
+            }
+        }
+        if groups.is_empty() && self.group_by.is_empty() {
+            let row = vec![SqlValue::Null; self.scope.columns.len()];
+            groups.push((Vec::new(), row, self.accumulators.clone()));
+        }
+        groups
+            .into_iter()
+            .map(|(_, mut row, accumulators)| {
+                for accumulator in &accumulators {
+                    row.push(accumulator.finish()?);
+                }
+                Ok(row)
+            })
+            .collect()
+    }
+}
+
+impl Operator for Aggregate<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        if let Some(input) = self.input.take() {
+            self.groups = self.run(input)?.into_iter();
+        }
+        Ok(self.groups.next())
+    }
+}
+
+/// The row [`Aggregate`] gives for a whole table when its only calls are to count(*), found by
+/// counting the entries of a b-tree that has one per row.
+pub struct CountEntries<'a> {
+    pager: &'a Pager,
+    btree: Option<u64>,
+    width: usize,
+    calls: usize,
+}
+
+impl<'a> CountEntries<'a> {
+    /// `width` is the number of columns of the table, which are all NULL in the row.
+    pub fn new(pager: &'a Pager, btree: u64, width: usize, calls: usize) -> CountEntries<'a> {
+        CountEntries {
+            pager,
+            btree: Some(btree),
+            width,
+            calls,
+        }
+    }
+}
+
+impl Operator for CountEntries<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        let Some(btree) = self.btree.take() else {
+            return Ok(None);
+        };
+        let count = count_entries(self.pager, btree)? as i64;
+        let mut row = vec![SqlValue::Null; self.width];
+        row.resize(self.width + self.calls, SqlValue::I64(count));
+        Ok(Some(row))
+    }
+}
+
+/// Builds the operator for the right side of a join, given the left row it is joined to.
+pub type JoinSide<'a> = Box<dyn FnMut(&[SqlValue]) -> Result<BoxedOperator<'a>> + 'a>;
+
+/// Each row of its left input followed by each row of the right side built for it. A LEFT
+/// join keeps a left row that no right row meets the condition with, followed by NULLs.
+pub struct NestedLoopJoin<'a> {
+    left: BoxedOperator<'a>,
+    right: JoinSide<'a>,
+    kind: JoinKind,
+    /// The ON condition of a LEFT join, with the scope of the joined rows.
+    condition: Option<(Rc<Scope>, Expr)>,
+    right_width: usize,
+    current: Option<(Vec<SqlValue>, BoxedOperator<'a>, bool)>,
+}
+
+impl<'a> NestedLoopJoin<'a> {
+    pub fn new(
+        left: BoxedOperator<'a>,
+        right: JoinSide<'a>,
+        kind: JoinKind,
+        condition: Option<(Rc<Scope>, Expr)>,
+        right_width: usize,
+    ) -> NestedLoopJoin<'a> {
+        NestedLoopJoin {
+            left,
+            right,
+            kind,
+            condition,
+            right_width,
+            current: None,
+        }
+    }
+}
+
+impl Operator for NestedLoopJoin<'_> {
+    fn next(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        loop {
+            let (left_row, right, matched) = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let Some(left_row) = self.left.next()? else {
+                        return Ok(None);
+                    };
+                    let right = (self.right)(&left_row)?;
+                    self.current.insert((left_row, right, false))
+                }
+            };
+            match right.next()? {
+                Some(right_row) => {
+                    let mut row = left_row.clone();
+                    row.extend(right_row);
+                    if let Some((scope, condition)) = &self.condition {
+                        if !scope.is_true(condition, &row)? {
+                            continue;
+                        }
+                    }
+                    *matched = true;
+                    return Ok(Some(row));
+                }
+                None => {
+                    let null_extend = self.kind == JoinKind::Left && !*matched;
+                    if let Some((mut row, _, _)) = self.current.take() {
+                        if null_extend {
+                            row.resize(row.len() + self.right_width, SqlValue::Null);
+                            return Ok(Some(row));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A table of the FROM clause, as the plan reads it.
+struct PlanTable {
+    /// The name queries know the table by: its alias, if it has one.
+    name: String,
+    root: u64,
+    schema: Rc<TableSchema>,
+    indexes: Vec<IndexSchema>,
+    /// Where the table's values are in a joined row.
+    columns: Range<usize>,
+}
+
+/// How the rows of a table are found for each row of the tables before it.
+#[derive(Clone)]
+enum Access {
+    Scan,
+    /// The row whose rowid equals an expression.
+    Rowid(Expr),
+    /// The rows whose key in an index-format b-tree starts with an expression's value, after
+    /// converting it as the comparison would.
+    Key {
+        root: u64,
+        /// The table to look up the rowids of an index in, or None for a WITHOUT ROWID
+        /// table's own b-tree.
+        table_root: Option<u64>,
+        key: Expr,
+        affinity: Option<Affinity>,
+        order: KeyOrder,
+    },
+}
+
+/// Plans a SELECT. Its rows are read from the tables as the query is iterated over.
+pub fn plan<'a>(pager: &'a Pager, select: &SelectStmt) -> Result<Query<'a>> {
+    let encoding = pager.header().text_encoding;
+    let schema = match select.from.is_empty() {
+        true => None,
+        false => Some(SqliteSchema::from_pager(pager)?),
+    };
+    let mut scope = Scope::new(encoding);
+    let mut tables = Vec::new();
+    // The terms of the WHERE clause, and of the ON clauses of inner joins, which mean the same.
+    let mut conditions = Vec::new();
+    split_and(select.where_clause.as_ref(), &mut conditions);
+    // The ON terms of each LEFT join, which decide which rows of the table are joined.
+    let mut join_conditions = Vec::new();
+    for from in &select.from {
+        let (root, table_schema, indexes) = open_table(schema.as_ref(), &from.name)?;
+        let name = from.alias.clone().unwrap_or_else(|| from.name.clone());
+        let mut on = Vec::new();
+        let using = match (&from.constraint, from.natural) {
+            (Some(_), true) => {
+                return Err(Error::InvalidStatement(
+                    "a NATURAL join may not have an ON or USING clause".to_string(),
+                ))
+            }
+            (Some(JoinConstraint::On(expr)), false) => {
+                split_and(Some(expr), &mut on);
+                Vec::new()
+            }
+            (Some(JoinConstraint::Using(columns)), false) => columns.clone(),
+            (None, true) => table_schema
+                .columns
+                .iter()
+                .filter(|column| scope.resolve(None, &column.name).is_ok())
+                .map(|column| column.name.clone())
+                .collect(),
+            (None, false) => Vec::new(),
+        };
+        let start = scope.columns.len();
+        let before = scope.clone();
+        scope.add_table(&name, &table_schema);
+        // Each USING column is the same as the earlier table's, which is the one unqualified
+        // references see.
+        for column in &using {
+            let not_in_both = || {
+                Error::InvalidStatement(format!(
+                    "cannot join using column {} - column not present in both tables",
+                    column
+                ))
+            };
+            let left = before.resolve(None, column).map_err(|_| not_in_both())?;
+            let right = table_schema
+                .columns
+                .iter()
+                .position(|c| c.name.eq_ignore_ascii_case(column))
+                .ok_or_else(not_in_both)?;
+            scope.columns[start + right].merged = true;
+            on.push(Expr::Binary {
+                op: BinaryOp::Equal,
+                left: Box::new(Expr::Column {
+                    table: before.columns[left].table.clone(),
+                    name: column.clone(),
+                }),
+                right: Box::new(Expr::Column {
+                    table: Some(name.clone()),
+                    name: column.clone(),
+                }),
+            });
+        }
+        match from.join {
+            JoinKind::Inner => {
+                conditions.append(&mut on);
+                join_conditions.push(Vec::new());
+            }
+            JoinKind::Left => join_conditions.push(on),
+        }
+        tables.push(PlanTable {
+            name,
+            root,
+            schema: Rc::new(table_schema),
+            indexes,
+            columns: start..scope.columns.len(),
+        });
+    }
+    for condition in conditions.iter().chain(join_conditions.iter().flatten()) {
+        scope.check(condition)?;
+    }
+    let scope = Rc::new(scope);
+
+    let mut outputs = Vec::new();
+    for column in &select.columns {
+        let (table_name, skip_merged) = match column {
+            ResultColumn::Expr { expr, alias } => {
+                outputs.push((expr.clone(), alias.clone()));
+                continue;
+            }
+            ResultColumn::All if tables.is_empty() => {
+                return Err(Error::InvalidStatement("no tables specified".to_string()))
+            }
+            ResultColumn::All => (None, true),
+            ResultColumn::AllFrom(name) => (Some(name), false),
+        };
+        let mut found = false;
+        for table in &tables {
+            if table_name.is_some_and(|name| !name.eq_ignore_ascii_case(&table.name)) {
+                continue;
+            }
+            found = true;
+            for column in &scope.columns[table.columns.clone()] {
+                let hidden = column.is_rowid || (skip_merged && column.merged);
+                if !hidden {
+                    let expr = Expr::Column {
+                        table: Some(table.name.clone()),
+                        name: column.name.clone(),
+                    };
+                    outputs.push((expr, None));
+                }
+            }
+        }
+        if let (Some(name), false) = (table_name, found) {
+            return Err(Error::UnknownTable(name.clone()));
+        }
+    }
+
+    let group_by = select
+        .group_by
+        .iter()
+        .enumerate()
+        .map(|(i, term)| result_column(term, i, &outputs, "GROUP", Some(&scope)))
+        .collect::<Result<Vec<_>>>()?;
+    let order_by = select
+        .order_by
+        .iter()
+        .enumerate()
+        .map(|(i, term)| result_column(&term.expr, i, &outputs, "ORDER", None))
+        .collect::<Result<Vec<_>>>()?;
+    let mut calls = Vec::new();
+    for term in &group_by {
+        aggregate_calls(term, &mut calls);
+        if !calls.is_empty() {
+            return Err(Error::InvalidStatement(
+                "aggregate functions are not allowed in the GROUP BY clause".to_string(),
+            ));
+        }
+        scope.check(term)?;
+    }
+    let results = outputs.iter().map(|(expr, _)| expr);
+    for expr in results.chain(&select.having).chain(&order_by) {
+        aggregate_calls(expr, &mut calls);
+    }
+
+    let mut root: BoxedOperator = Box::<SingleRow>::default();
+    for (i, table) in tables.iter().enumerate() {
+        let level = |expr: &Expr| {
+            let columns = scope.columns_read(expr);
+            let table_of = |column: usize| tables.iter().position(|t| t.columns.contains(&column));
+            columns.into_iter().filter_map(table_of).max().unwrap_or(0)
+        };
+        let kind = select.from[i].join;
+        let candidates = match kind {
+            JoinKind::Inner => conditions.iter().filter(|c| level(c) == i).collect(),
+            JoinKind::Left => join_conditions[i].iter().collect::<Vec<_>>(),
+        };
+        // An equality between a column of this table and anything the tables before it
+        // settle may find the matching rows without reading the whole table.
+        let mut keys = Vec::new();
+        for condition in candidates {
+            if let Expr::Binary {
+                op: BinaryOp::Equal,
+                left,
+                right,
+            } = condition
+            {
+                for (column, other) in [(left, right), (right, left)] {
+                    let Expr::Column { table: t, name } = column.as_ref() else {
+                        continue;
+                    };
+                    let position = scope.resolve(t.as_deref(), name)?;
+                    let settled = scope
+                        .columns_read(other)
+                        .iter()
+                        .all(|c| c < &table.columns.start);
+                    if table.columns.contains(&position) && settled {
+                        let collation = scope.comparison_collation(left, right);
+                        keys.push((position - table.columns.start, other.as_ref(), collation));
+                    }
+                }
+            }
+        }
+        let access = choose_access(table, &scope, &keys);
+        let (pager, root_page, schema, key_scope) =
+            (pager, table.root, table.schema.clone(), scope.clone());
+        let right: JoinSide = Box::new(move |row| -> Result<BoxedOperator> {
+            Ok(match &access {
+                Access::Scan => Box::new(TableScan::new(pager, root_page, schema.clone())),
+                Access::Rowid(key) => {
+                    let rowid = Affinity::Numeric
+                        .apply(key_scope.evaluate(key, row)?)
+                        .as_i64();
+                    Box::new(RowidSeek::new(pager, root_page, schema.clone(), rowid))
+                }
+                Access::Key {
+                    root,
+                    table_root,
+                    key,
+                    affinity,
+                    order,
+                } => {
+                    let mut key = key_scope.evaluate(key, row)?;
+                    if let Some(affinity) = affinity {
+                        key = affinity.apply(key);
+                    }
+                    Box::new(IndexSeek::new(
+                        pager,
+                        *root,
+                        *table_root,
+                        schema.clone(),
+                        key,
+                        order.clone(),
+                    ))
+                }
+            })
+        });
+        let on = join_conditions[i]
+            .iter()
+            .cloned()
+            .reduce(|a, b| Expr::Binary {
+                op: BinaryOp::And,
+                left: Box::new(a),
+                right: Box::new(b),
+            })
+            .map(|condition| (scope.clone(), condition));
+        let width = table.columns.len();
+        root = Box::new(NestedLoopJoin::new(root, right, kind, on, width));
+        for condition in conditions.iter().filter(|c| level(c) == i) {
+            root = Box::new(Filter::new(root, scope.clone(), condition.clone()));
+        }
+    }
+    // A condition on no table at all still has to hold.
+    if tables.is_empty() {
+        for condition in &conditions {
+            root = Box::new(Filter::new(root, scope.clone(), condition.clone()));
+        }
+    }
+
+    let aggregated = !calls.is_empty() || !group_by.is_empty() || select.having.is_some();
+    let output_scope = if aggregated {
+        let accumulators = calls
+            .iter()
+            .map(|call| Accumulator::new(&scope, call))
+            .collect::<Result<Vec<_>>>()?;
+        // Counting the rows of a single table needs none of their values, just how many
+        // entries its smallest b-tree has.
+        let counts_rows = calls.iter().all(|call| match call {
+            Expr::Function {
+                name,
+                args: FunctionArgs::Star,
+            } => name.eq_ignore_ascii_case("count"),
+            _ => false,
+        });
+        let reads_columns = outputs
+            .iter()
+            .map(|(expr, _)| expr)
+            .chain(&select.having)
+            .chain(&order_by)
+            .any(|expr| !scope.columns_read(expr).is_empty());
+        root = match tables.as_slice() {
+            [table]
+                if counts_rows
+                    && !reads_columns
+                    && !calls.is_empty()
+                    && conditions.is_empty()
+                    && group_by.is_empty() =>
+            {
+                let width = scope.columns.len();
+                let btree = smallest_btree(table);
+                Box::new(CountEntries::new(pager, btree, width, calls.len()))
+            }
+            _ => Box::new(Aggregate::new(root, scope.clone(), group_by, accumulators)),
+        };
+        let mut output_scope = Scope::clone(&scope);
+        calls
+            .iter()
+            .for_each(|call| output_scope.add_aggregate(call));
+        Rc::new(output_scope)
+    } else {
+        scope
+    };
+    let results = outputs.iter().map(|(expr, _)| expr);
+    for expr in results.chain(&select.having).chain(&order_by) {
+        output_scope.check(expr)?;
+    }
+    if let Some(having) = &select.having {
+        root = Box::new(Filter::new(root, output_scope.clone(), having.clone()));
+    }
+    let width = outputs.len();
+    let collations = outputs
+        .iter()
+        .map(|(expr, _)| collation_of(&output_scope, expr))
+        .collect();
+    let keys = select
+        .order_by
+        .iter()
+        .zip(&order_by)
+        .enumerate()
+        .map(|(i, (term, expr))| SortKey {
+            position: width + i,
+            descending: term.descending,
+            nulls_first: term.nulls_first.unwrap_or(!term.descending),
+            collation: collation_of(&output_scope, expr),
+        })
+        .collect::<Vec<_>>();
+    let exprs = outputs.into_iter().map(|(expr, _)| expr).chain(order_by);
+    root = Box::new(Project::new(root, output_scope, exprs.collect()));
+    if select.distinct {
+        root = Box::new(Distinct::new(root, collations, encoding));
+    }
+    if !keys.is_empty() {
+        root = Box::new(Sort::new(root, keys, width, encoding));
+    }
+    if let Some(limit) = &select.limit {
+        let count = u64::try_from(limit_value(&limit.limit, encoding)?).ok();
+        let offset = match &limit.offset {
+            Some(offset) => limit_value(offset, encoding)?.max(0) as u64,
+            None => 0,
+        };
+        root = Box::new(Limit::new(root, count, offset));
+    }
+    Ok(Query { root })
+}
+
+/// The root page, schema and indexes of a table, by the name a query gives it.
+fn open_table(
+    schema: Option<&SqliteSchema>,
+    name: &str,
+) -> Result<(u64, TableSchema, Vec<IndexSchema>)> {
+    let is_schema_table = ["sqlite_schema", "sqlite_master"]
+        .iter()
+        .any(|n| n.eq_ignore_ascii_case(name));
+    let (root, sql, element) = match schema {
+        _ if is_schema_table => (1, SCHEMA_TABLE_SQL, None),
+        Some(schema) => {
+            let element = schema.find_table(name)?;
+            (
+                element.rootpage,
+                element.sql.as_str(),
+                Some((schema, element)),
+            )
+        }
+        None => return Err(Error::UnknownTable(name.to_string())),
+    };
+    let table_schema = match syntax::parse(sql)? {
+        Statement::CreateTable(stmt) => TableSchema::from_ast(&stmt)?,
+        _ => return Err(Error::Corrupt(format!("bad schema for table {}", name))),
+    };
+    let indexes = match element {
+        Some((schema, element)) => schema
+            .schema_elements
+            .iter()
+            .filter(|e| e.element_type == "index" && e.tbl_name.eq_ignore_ascii_case(&element.name))
+            .map(|e| IndexSchema::from_element(e, &table_schema))
+            .collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+    Ok((root, table_schema, indexes))
+}
+
+/// The cheapest way to the rows of a table meeting one of `keys`, each a column of the table
+/// that has to equal an expression under a collation.
+fn choose_access(table: &PlanTable, scope: &Scope, keys: &[(usize, &Expr, Collation)]) -> Access {
+    let schema = &table.schema;
+    let rowid = |column: usize| {
+        !schema.without_rowid
+            && (column == schema.columns.len() || schema.rowid_alias() == Some(column))
+    };
+    if let Some((_, key, _)) = keys.iter().find(|(column, _, _)| rowid(*column)) {
+        return Access::Rowid((*key).clone());
+    }
+    // A b-tree ordered by a column can only be searched by a key compared the same way, and
+    // any conversion has to be of the key rather than the column's values.
+    let usable = |column: usize, key: &Expr| {
+        let column = schema.columns.get(column)?;
+        let key_affinity = scope.affinity(key);
+        let key_numeric = key_affinity.is_some_and(Affinity::is_numeric);
+        if key_numeric && !column.affinity.is_numeric() {
+            return None;
+        }
+        Some(if column.affinity.is_numeric() && !key_numeric {
+            Some(Affinity::Numeric)
+        } else if column.affinity == Affinity::Text && key_affinity.is_none() {
+            Some(Affinity::Text)
+        } else {
+            None
+        })
+    };
+    for &(column, key, collation) in keys {
+        let Some(affinity) = usable(column, key) else {
+            continue;
+        };
+        if schema.without_rowid
+            && schema.primary_key.as_slice() == [column]
+            && schema.columns[column].collation == collation
+        {
+            return Access::Key {
+                root: table.root,
+                table_root: None,
+                key: key.clone(),
+                affinity,
+                order: schema.key_order(),
+            };
+        }
+        let name = &schema.columns[column].name;
+        let index = table.indexes.iter().find(|index| {
+            index.where_clause.is_none()
+                && index.columns.first().is_some_and(|first| {
+                    first.collation == collation
+                        && first
+                            .name
+                            .as_ref()
+                            .is_some_and(|c| c.eq_ignore_ascii_case(name))
+                })
+        });
+        if let (Some(index), false) = (index, schema.without_rowid) {
+            return Access::Key {
+                root: index.rootpage,
+                table_root: Some(table.root),
+                key: key.clone(),
+                affinity,
+                order: index.key_order(),
+            };
+        }
+    }
+    Access::Scan
+}
+
+/// The root of the b-tree with an entry for each row of a table that is likely the smallest:
+/// the full index with the fewest columns, or else the table itself.
+fn smallest_btree(table: &PlanTable) -> u64 {
+    table
+        .indexes
+        .iter()
+        .filter(|index| index.where_clause.is_none())
+        .min_by_key(|index| index.columns.len())
+        .map_or(table.root, |index| index.rootpage)
+}
+
+/// What a GROUP BY or ORDER BY term stands for: a result column when it is the column's
+/// number or alias, else the term itself. A GROUP BY term only means an alias when it names no
+/// column of the tables.
+fn result_column(
+    term: &Expr,
+    i: usize,
+    outputs: &[(Expr, Option<String>)],
+    clause: &str,
+    tables: Option<&Scope>,
+) -> Result<Expr> {
+    match term {
+        Expr::Literal(Value::Integer(n)) => match outputs.get((*n as usize).wrapping_sub(1)) {
+            Some((expr, _)) if *n > 0 => Ok(expr.clone()),
+            _ => Err(Error::InvalidStatement(format!(
+                "{} {} BY term out of range - should be between 1 and {}",
+                ordinal(i + 1),
+                clause,
+                outputs.len()
+            ))),
+        },
+        Expr::Column { table: None, name }
+            if tables.map_or(true, |scope| scope.resolve(None, name).is_err()) =>
+        {
+            let alias = outputs.iter().find(|(_, alias)| {
+                alias
+                    .as_ref()
+                    .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+            });
+            Ok(alias.map_or_else(|| term.clone(), |(expr, _)| expr.clone()))
+        }
+        Expr::Collate { expr, collation } => Ok(Expr::Collate {
+            expr: Box::new(result_column(expr, i, outputs, clause, tables)?),
+            collation: collation.clone(),
+        }),
+        _ => Ok(term.clone()),
+    }
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// The value of a LIMIT or OFFSET expression, which has to be an integer.
+fn limit_value(expr: &Expr, encoding: TextEncoding) -> Result<i64> {
+    let scope = Scope::new(encoding);
+    scope.check(expr)?;
+    Affinity::Numeric
+        .apply(scope.evaluate(expr, &[])?)
+        .as_i64()
+        .ok_or_else(|| Error::InvalidStatement("datatype mismatch".to_string()))
+}
+
+/// Adds the terms of a condition joined by AND at its top.
+fn split_and(expr: Option<&Expr>, terms: &mut Vec<Expr>) {
+    match expr {
+        Some(Expr::Binary {
+            op: BinaryOp::And,
+            left,
+            right,
+        }) => {
+            split_and(Some(left), terms);
+            split_and(Some(right), terms);
+        }
+        Some(expr) => terms.push(expr.clone()),
+        None => {}
+    }
+}
+
+/// The row [`Scope::add_table`] describes for a record read from a table's b-tree.
+fn stored_row(schema: &TableSchema, record: Record) -> Vec<SqlValue> {
+    if schema.without_rowid {
+        return table_row(schema, schema.row_from_record(record).values, None);
+    }
+    let rowid = record.rowid().map(|rowid| rowid as i64);
+    table_row(schema, record.values, rowid)
+}
+
+fn collation_of(scope: &Scope, expr: &Expr) -> Collation {
+    scope
+        .collation(expr)
+        .map_or(Collation::Binary, |(collation, _)| collation)
+}
+
+fn compare_values(
+    a: &[SqlValue],
+    b: &[SqlValue],
+    collations: &[Collation],
+    encoding: TextEncoding,
+) -> Ordering {
+    a.iter()
+        .zip(b)
+        .zip(collations)
+        .map(|((a, b), collation)| collation.compare(a, b, encoding))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
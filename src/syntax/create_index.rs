@@ -1,8 +1,8 @@
-use std::{iter::Peekable, mem::uninitialized, slice::Iter};
+use std::{iter::Peekable, slice::Iter};
 
-use crate::page_io::Column;
+use crate::error::Result;
 
-use super::{create_table::ColumnDef, select::WhereClause, tokenizer::Token, Parse};
+use super::{expect, expr::Expr, next_token, tokenizer::Token, unexpected, Parse};
 
 #[derive(Debug)]
 pub struct CreateIndexStmt {
@@ -10,12 +10,61 @@ pub struct CreateIndexStmt {
     pub schema_name: Option<String>,
     pub index_name: String,
     pub table_name: String,
-    pub columns: Vec<String>,
-    pub where_clause: Option<WhereClause>,
+    pub columns: Vec<IndexedColumn>,
+    /// The condition rows must meet to be in a partial index.
+    pub where_clause: Option<Expr>,
+}
+
+/// A key of an index: the column or expression it orders by, with the collation and direction
+/// given for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub expr: Expr,
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+impl IndexedColumn {
+    /// The name of the table column the key is, or None for any other expression.
+    pub fn name(&self) -> Option<&str> {
+        match &self.expr {
+            Expr::Column { table: None, name } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl Parse for IndexedColumn {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let start = input.len();
+        let (expr, _) = Expr::parse(input)?;
+        // The expression parser takes a trailing COLLATE as part of the expression.
+        let (expr, collation) = match expr {
+            Expr::Collate { expr, collation } => (*expr, Some(collation)),
+            expr => (expr, None),
+        };
+        let descending = match input.peek() {
+            Some(Token::Asc) => {
+                input.next();
+                false
+            }
+            Some(Token::Desc) => {
+                input.next();
+                true
+            }
+            _ => false,
+        };
+        let column = IndexedColumn {
+            expr,
+            collation,
+            descending,
+        };
+        Ok((column, start - input.len()))
+    }
 }
 
 impl Parse for CreateIndexStmt {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
         let mut stmt_mut = CreateIndexStmt {
             if_not_exists: false,
             schema_name: None,
@@ -25,88 +74,98 @@ impl Parse for CreateIndexStmt {
             where_clause: None,
         };
         let mut consumed_tokens = 0;
-        let token = input.next().unwrap();
+        if let Some(Token::If) = input.peek() {
+            input.next();
+            expect(input, &Token::Not, "after IF in Create Index Statement")?;
+            expect(
+                input,
+                &Token::Exists,
+                "after IF NOT in Create Index Statement",
+            )?;
+            consumed_tokens += 3;
+            stmt_mut.if_not_exists = true;
+        }
+        // The name, which may be qualified by the schema it goes in.
+        stmt_mut.index_name = index_name(input)?;
         consumed_tokens += 1;
-        match token {
-            Token::If => {
-                assert_eq!(
-                    *input.next().unwrap(),
-                    Token::Not,
-                    "expected NOT after If in Create Index Statment"
-                );
-                consumed_tokens += 1;
-                assert_eq!(
-                    *input.next().unwrap(),
-                    Token::Exists,
-                    "expected EXISTS after IF NOT in Create Index Statement"
-                );
-                consumed_tokens += 1;
-                stmt_mut.if_not_exists = true;
-            }
-            Token::Identifier(iden) => {
-                let split_at_period = iden.split(".").collect::<Vec<&str>>();
-                if split_at_period.len() == 1 {
-                    stmt_mut.index_name = split_at_period.first().unwrap().to_string();
-                } else if split_at_period.len() == 2 {
-                    stmt_mut.schema_name = Some(split_at_period.first().unwrap().to_string());
-                    stmt_mut.index_name = split_at_period.iter().nth(2).unwrap().to_string();
-                } else {
-                    panic!("Too many periods in a schema/table name at Create Index")
-                }
-            }
-            _ => {
-                panic!("Cannot parse Create Index Statement - unknown token immediately after Index keyword");
+        if let Some(Token::Operator(op)) = input.peek() {
+            if op == "." {
+                input.next();
+                stmt_mut.schema_name = Some(std::mem::take(&mut stmt_mut.index_name));
+                stmt_mut.index_name = index_name(input)?;
+                consumed_tokens += 2;
             }
         }
+        expect(
+            input,
+            &Token::On,
+            "after index name in Create Index Statement",
+        )?;
         consumed_tokens += 1;
-        assert_eq!(
-            *input.next().unwrap(),
-            Token::On,
-            "Expected ON after index name in Create Index Statement"
-        );
-        consumed_tokens += 1;
-        match input.next().unwrap() {
+        match next_token(input)? {
             Token::Identifier(iden) => {
                 stmt_mut.table_name = iden.clone();
             }
             _ => {
-                panic!("Create Index Statement contains no name");
+                return Err(unexpected(input, "Create Index Statement contains no name"));
             }
         }
         consumed_tokens += 1;
-        match input.next().unwrap() {
+        match next_token(input)? {
             Token::Operator(op) if op == "(" => {
                 consumed_tokens += 1;
-                while let Token::Identifier(column_name) = input.next().unwrap() {
-                    stmt_mut.columns.push(column_name.to_string());
-                    consumed_tokens += 1;
+                loop {
+                    let (column, n) = IndexedColumn::parse(input)?;
+                    stmt_mut.columns.push(column);
+                    consumed_tokens += n;
                     match input.peek() {
                         Some(Token::Operator(op)) if op == "," => {
                             input.next();
                             consumed_tokens += 1;
                         }
                         Some(Token::Operator(op)) if op == ")" => break,
-                        _ => panic!("Expected ',' or ')' in column list of Create Index Statement"),
+                        _ => {
+                            input.next();
+                            return Err(unexpected(
+                                input,
+                                "expected ',' or ')' in column list of Create Index Statement",
+                            ));
+                        }
                     }
                 }
-                assert_eq!(
-                    *input.next().unwrap(),
-                    Token::Operator(")".to_string()),
-                    "Expected ')' at the end of column list in Create Index Statement"
-                );
+                expect(
+                    input,
+                    &Token::Operator(")".to_string()),
+                    "at the end of column list in Create Index Statement",
+                )?;
                 consumed_tokens += 1;
             }
-            _ => panic!("Expected '(' after table name in Create Index Statement"),
+            _ => {
+                return Err(unexpected(
+                    input,
+                    "expected '(' after table name in Create Index Statement",
+                ))
+            }
         }
 
         if let Some(Token::Where) = input.peek() {
             input.next();
             consumed_tokens += 1;
-            let (where_clause, where_consumed) = WhereClause::parse(input);
+            let (where_clause, where_consumed) = Expr::parse(input)?;
             stmt_mut.where_clause = Some(where_clause);
             consumed_tokens += where_consumed;
         }
 
-        (stmt_mut, consumed_tokens)
+        Ok((stmt_mut, consumed_tokens))
+    }
+}
+
+fn index_name(input: &mut Peekable<Iter<Token>>) -> Result<String> {
+    match next_token(input)? {
+        Token::Identifier(name) => Ok(name.to_string()),
+        _ => Err(unexpected(
+            input,
+            "expected index name in Create Index Statement",
+        )),
     }
 }
@@ -1,31 +1,51 @@
 use std::{iter::Peekable, slice::Iter};
 
-use crate::typecodes::TypeCode;
+use crate::error::{Error, Result};
 
-use super::{select::SelectStmt, tokenizer::Token, Parse};
+use super::{
+    expect,
+    expr::{parse_operand, parse_type_name, Expr},
+    next_token, peek_token,
+    select::SelectStmt,
+    tokenizer::Token,
+    unexpected, Parse,
+};
 
 #[derive(Debug, Clone)]
 pub struct CreateTableStmt {
-    temp: bool,
-    if_not_exists: bool,
-    schema_name: Option<String>,
+    pub temp: bool,
+    pub if_not_exists: bool,
+    pub schema_name: Option<String>,
     pub table_name: String,
-    schema_constructor: SchemaConstructor,
+    pub schema_constructor: SchemaConstructor,
+    /// The table is stored as an index b-tree keyed on its primary key.
+    pub without_rowid: bool,
 }
 
 impl CreateTableStmt {
-    pub fn cols(&self) -> Vec<ColumnDef> {
+    pub fn cols(&self) -> Result<Vec<ColumnDef>> {
         match &self.schema_constructor {
-            SchemaConstructor::FromColumns { columns, .. } => columns.clone(),
-            _ => panic!("Cannot Extract Columns from this schema constructor"),
+            SchemaConstructor::FromColumns { columns, .. } => Ok(columns.clone()),
+            _ => Err(Error::Unsupported(
+                "extracting columns from CREATE TABLE ... AS SELECT".to_string(),
+            )),
+        }
+    }
+
+    pub fn table_constraints(&self) -> &[TableConstraint] {
+        match &self.schema_constructor {
+            SchemaConstructor::FromColumns {
+                table_constraints, ..
+            } => table_constraints,
+            _ => &[],
         }
     }
 }
 
 impl Parse for CreateTableStmt {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
         let mut consumed = 0;
-        let temp = match input.peek().unwrap() {
+        let temp = match peek_token(input)? {
             Token::Temp => {
                 input.next();
                 consumed += 1;
@@ -33,102 +53,150 @@ impl Parse for CreateTableStmt {
             }
             _ => false,
         };
-        let if_not_exists = match input.peek().unwrap() {
+        let if_not_exists = match peek_token(input)? {
             Token::If => {
                 input.next();
-                assert_eq!(input.next().unwrap(), &Token::Not);
-                assert_eq!(input.next().unwrap(), &Token::Exists);
+                expect(input, &Token::Not, "after IF")?;
+                expect(input, &Token::Exists, "after IF NOT")?;
                 consumed += 3;
                 true
             }
             _ => false,
         };
-        let (schema_name, table_name) = match input.peek().unwrap() {
+        let (schema_name, table_name) = match peek_token(input)? {
             Token::Identifier(s) => {
                 input.next();
                 consumed += 1;
-                match input.peek().unwrap() {
-                    Token::Operator(t) if *t == ".".to_string() => {
+                match peek_token(input)? {
+                    Token::Operator(t) if t == "." => {
                         input.next();
                         consumed += 1;
-                        let table_name = match input.next().unwrap() {
+                        let table_name = match next_token(input)? {
                             Token::Identifier(t) => t.to_string(),
-                            _ => panic!("expected table name"),
+                            _ => return Err(unexpected(input, "expected table name")),
                         };
                         (Some(s.to_string()), table_name)
                     }
                     _ => (None, s.to_string()),
                 }
             }
-            _ => panic!("expected schema name or table name"),
+            _ => {
+                input.next();
+                return Err(unexpected(input, "expected schema name or table name"));
+            }
         };
 
-        let (schema_constructor, n) = SchemaConstructor::parse(input);
+        let (schema_constructor, n) = SchemaConstructor::parse(input)?;
         consumed += n;
-        (
+        let mut without_rowid = false;
+        if let SchemaConstructor::FromColumns { .. } = schema_constructor {
+            while let Some(Token::Without) = input.peek() {
+                input.next();
+                match next_token(input)? {
+                    Token::Identifier(t) if t.eq_ignore_ascii_case("rowid") => {}
+                    _ => return Err(unexpected(input, "expected ROWID after WITHOUT")),
+                }
+                consumed += 2;
+                without_rowid = true;
+                match input.peek() {
+                    Some(Token::Operator(t)) if t == "," => {
+                        input.next();
+                        consumed += 1;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        if let Some(Token::Operator(t)) = input.peek() {
+            if t == ";" {
+                input.next();
+                consumed += 1;
+            }
+        }
+        Ok((
             CreateTableStmt {
                 temp,
                 if_not_exists,
                 schema_name,
                 table_name,
                 schema_constructor,
+                without_rowid,
             },
             consumed,
-        )
+        ))
     }
 }
 
 #[derive(Debug, Clone)]
-enum SchemaConstructor {
+pub enum SchemaConstructor {
     FromColumns {
         columns: Vec<ColumnDef>,
         table_constraints: Vec<TableConstraint>,
     },
-    AsSelect(SelectStmt),
+    AsSelect(Box<SelectStmt>),
 }
 
 impl Parse for SchemaConstructor {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
         let mut consumed = 0;
-        match input.peek().unwrap() {
-            Token::Operator(t) if *t == "(".to_string() => {
+        match peek_token(input)? {
+            Token::Operator(t) if t == "(" => {
                 let mut columns = Vec::new();
                 let mut table_constraints = Vec::new();
                 input.next();
                 consumed += 1;
                 loop {
-                    if input.peek().is_none() {
-                        break;
-                    }
-                    match input.peek().unwrap() {
-                        Token::Identifier(_) => {
-                            let (column_def, n) = ColumnDef::parse(input);
+                    match peek_token(input)? {
+                        Token::Identifier(_) if table_constraints.is_empty() => {
+                            let (column_def, n) = ColumnDef::parse(input)?;
                             columns.push(column_def);
                             consumed += n;
                         }
-                        Token::Operator(t) if *t == ";".to_string() => {
+                        Token::Constraint
+                        | Token::Primary
+                        | Token::Unique
+                        | Token::Check
+                        | Token::Foreign => {
+                            let (constraint, n) = TableConstraint::parse(input)?;
+                            table_constraints.push(constraint);
+                            consumed += n;
+                        }
+                        _ => {
                             input.next();
+                            return Err(unexpected(input, "unexpected token in column list"));
+                        }
+                    }
+                    match next_token(input)? {
+                        Token::Operator(t) if t == "," => consumed += 1,
+                        Token::Operator(t) if t == ")" => {
                             consumed += 1;
                             break;
                         }
-                        _ => panic!("unexpected token"),
+                        _ => return Err(unexpected(input, "expected ',' or ')' in column list")),
                     }
                 }
-                (
+                Ok((
                     SchemaConstructor::FromColumns {
                         columns,
                         table_constraints,
                     },
                     consumed,
-                )
+                ))
             }
             Token::As => {
                 input.next();
-                consumed += 1;
-                let (select_stmt, n) = SelectStmt::parse(input);
-                (SchemaConstructor::AsSelect(select_stmt), consumed + n)
+                expect(input, &Token::Select, "after AS")?;
+                consumed += 2;
+                let (select_stmt, n) = SelectStmt::parse(input)?;
+                Ok((
+                    SchemaConstructor::AsSelect(Box::new(select_stmt)),
+                    consumed + n,
+                ))
+            }
+            _ => {
+                input.next();
+                Err(unexpected(input, "expected '(' or AS after table name"))
             }
-            _ => panic!("unexpected token"),
         }
     }
 }
@@ -136,57 +204,50 @@ impl Parse for SchemaConstructor {
 #[derive(Debug, Clone)]
 pub struct ColumnDef {
     pub name: String,
-    type_: Option<TypeCode>,
+    /// The declared type as written, e.g. `VARCHAR(10)`, which the column's affinity comes
+    /// from.
+    pub type_name: Option<String>,
     pub constraints: Vec<ColumnConstraint>,
 }
 
 impl Parse for ColumnDef {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
         let mut consumed = 0;
-        let name = match input.next().unwrap() {
+        let name = match next_token(input)? {
             Token::Identifier(name) => {
                 consumed += 1;
                 name.to_string()
             }
-            _ => panic!("expected column name"),
+            _ => return Err(unexpected(input, "expected column name")),
         };
-        let type_ = match input.peek().unwrap() {
-            Token::Identifier(s) => {
-                let type_code = TypeCode::parse_str(s);
-                input.next();
-                consumed += 1;
-                Some(type_code)
+        let type_name = match peek_token(input)? {
+            Token::Identifier(_) => {
+                let before = input.len();
+                let type_name = parse_type_name(input)?;
+                consumed += before - input.len();
+                Some(type_name)
             }
             _ => None,
         };
         let mut constraints = Vec::new();
         loop {
-            match input.peek().unwrap() {
-                Token::Operator(t) if *t == ",".to_string() => {
-                    input.next();
-                    consumed += 1;
-                    break;
-                }
-                Token::Operator(t) if *t == ")".to_string() => {
-                    input.next();
-                    consumed += 1;
-                    break;
-                }
+            match peek_token(input)? {
+                Token::Operator(t) if t == "," || t == ")" => break,
                 _ => {
-                    let new_constraint = ColumnConstraint::parse(input);
+                    let new_constraint = ColumnConstraint::parse(input)?;
                     consumed += new_constraint.1;
                     constraints.push(new_constraint.0);
                 }
             }
         }
-        (
+        Ok((
             ColumnDef {
                 name,
-                type_,
+                type_name,
                 constraints,
             },
             consumed,
-        )
+        ))
     }
 }
 
@@ -202,31 +263,28 @@ pub enum ColumnConstraint {
         conflict_clause: Option<ConflictClause>,
     },
     Unique,
-    Check,
-    Default,
-    Collate,
+    Check(Expr),
+    Default(Expr),
+    Collate(String),
     ForeignKey(ForeignKeyClause),
 }
 
 impl ColumnConstraint {
     pub fn is_primary_key(&self) -> bool {
-        match self {
-            ColumnConstraint::PrimaryKey { .. } => true,
-            _ => false,
-        }
+        matches!(self, ColumnConstraint::PrimaryKey { .. })
     }
 }
 
 impl Parse for ColumnConstraint {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
         let mut consumed = 0;
-        let constraint = match input.peek().unwrap() {
+        let constraint = match peek_token(input)? {
             Token::Primary => {
                 input.next();
                 consumed += 1;
-                assert_eq!(input.next().unwrap(), &Token::Key);
+                expect(input, &Token::Key, "in column constraint")?;
                 consumed += 1;
-                let ord_ = match input.peek().unwrap() {
+                let ord_ = match peek_token(input)? {
                     Token::Asc => {
                         input.next();
                         consumed += 1;
@@ -239,19 +297,19 @@ impl Parse for ColumnConstraint {
                     }
                     _ => None,
                 };
-                let conflict_clause = match input.peek().unwrap() {
+                let conflict_clause = match peek_token(input)? {
                     Token::On => {
                         input.next();
                         consumed += 1;
-                        assert_eq!(input.next().unwrap(), &Token::Conflict);
+                        expect(input, &Token::Conflict, "in column constraint")?;
                         consumed += 2;
-                        let (clause, n) = ConflictClause::parse(input);
+                        let (clause, n) = ConflictClause::parse(input)?;
                         consumed += n;
                         Some(clause)
                     }
                     _ => None,
                 };
-                let autoincrement = match input.peek().unwrap() {
+                let autoincrement = match peek_token(input)? {
                     Token::Autoincrement => {
                         input.next();
                         consumed += 1;
@@ -268,15 +326,15 @@ impl Parse for ColumnConstraint {
             Token::Not => {
                 input.next();
                 consumed += 1;
-                assert_eq!(input.next().unwrap(), &Token::Null);
+                expect(input, &Token::Null, "in column constraint")?;
                 consumed += 1;
-                let conflict_clause = match input.peek().unwrap() {
+                let conflict_clause = match peek_token(input)? {
                     Token::On => {
                         input.next();
                         consumed += 1;
-                        assert_eq!(input.next().unwrap(), &Token::Conflict);
+                        expect(input, &Token::Conflict, "in column constraint")?;
                         consumed += 2;
-                        let (clause, n) = ConflictClause::parse(input);
+                        let (clause, n) = ConflictClause::parse(input)?;
                         consumed += n;
                         Some(clause)
                     }
@@ -291,48 +349,73 @@ impl Parse for ColumnConstraint {
             }
             Token::Check => {
                 input.next();
-                consumed += 1;
-                ColumnConstraint::Check
+                let (check, n) = parse_check(input)?;
+                consumed += 1 + n;
+                ColumnConstraint::Check(check)
             }
             Token::Default => {
                 input.next();
-                consumed += 1;
-                ColumnConstraint::Default
+                let (default, n) = parse_operand(input)?;
+                consumed += 1 + n;
+                ColumnConstraint::Default(default)
             }
             Token::Collate => {
                 input.next();
-                consumed += 1;
-                ColumnConstraint::Collate
+                let collation = match next_token(input)? {
+                    Token::Identifier(name) => name.clone(),
+                    _ => return Err(unexpected(input, "expected collation name")),
+                };
+                consumed += 2;
+                ColumnConstraint::Collate(collation)
             }
             Token::Foreign => {
                 input.next();
                 consumed += 1;
-                assert_eq!(input.next().unwrap(), &Token::Key);
+                expect(input, &Token::Key, "in column constraint")?;
                 consumed += 1;
-                let (foreign_key, n) = ForeignKeyClause::parse(input);
+                let (foreign_key, n) = ForeignKeyClause::parse(input)?;
                 consumed += n;
                 ColumnConstraint::ForeignKey(foreign_key)
             }
-            _ => panic!("unexpected token"),
+            _ => {
+                input.next();
+                return Err(unexpected(input, "unexpected token in column constraint"));
+            }
         };
-        (constraint, consumed)
+        Ok((constraint, consumed))
     }
 }
 
+/// Parses the parenthesized condition after CHECK.
+fn parse_check(input: &mut Peekable<Iter<Token>>) -> Result<(Expr, usize)> {
+    expect(input, &Token::Operator("(".to_string()), "after CHECK")?;
+    let (check, n) = Expr::parse(input)?;
+    expect(
+        input,
+        &Token::Operator(")".to_string()),
+        "after CHECK condition",
+    )?;
+    Ok((check, n + 2))
+}
+
 #[derive(Debug, Clone)]
-enum SortOrder {
+pub enum SortOrder {
     Asc,
     Desc,
 }
 
 impl Parse for SortOrder {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
-        unimplemented!()
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        match next_token(input)? {
+            Token::Asc => Ok((SortOrder::Asc, 1)),
+            Token::Desc => Ok((SortOrder::Desc, 1)),
+            _ => Err(unexpected(input, "expected ASC or DESC")),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
-enum ConflictClause {
+pub enum ConflictClause {
     Rollback,
     Abort,
     Fail,
@@ -341,68 +424,177 @@ enum ConflictClause {
 }
 
 impl Parse for ConflictClause {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
-        unimplemented!()
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let clause = match next_token(input)? {
+            Token::Rollback => ConflictClause::Rollback,
+            Token::Abort => ConflictClause::Abort,
+            Token::Fail => ConflictClause::Fail,
+            Token::Ignore => ConflictClause::Ignore,
+            Token::Replace => ConflictClause::Replace,
+            _ => {
+                return Err(unexpected(
+                    input,
+                    "expected conflict resolution after ON CONFLICT",
+                ))
+            }
+        };
+        Ok((clause, 1))
     }
 }
 
 #[derive(Debug, Clone)]
-struct ForeignKeyClause {
-    foreign_table: ForeignKeyTable,
+pub struct ForeignKeyClause {
+    pub foreign_table: ForeignKeyTable,
 }
 
 impl Parse for ForeignKeyClause {
-    fn parse(mut input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
         let mut consumed = 0;
-        assert_eq!(input.next().unwrap(), &Token::Operator("(".to_string()));
+        expect(
+            input,
+            &Token::Operator("(".to_string()),
+            "after FOREIGN KEY",
+        )?;
         consumed += 1;
         let mut column_names = Vec::new();
         loop {
-            match input.next().unwrap() {
+            match next_token(input)? {
                 Token::Identifier(column_name) => {
                     column_names.push(column_name.to_string());
                     consumed += 1;
                 }
-                Token::Operator(t) if *t == ",".to_string() => {
+                Token::Operator(t) if t == "," => {
                     consumed += 1;
                 }
-                Token::Operator(t) if *t == "(".to_string() => {
+                Token::Operator(t) if t == "(" => {
                     consumed += 1;
                     break;
                 }
-                _ => panic!("unexpected token"),
+                _ => return Err(unexpected(input, "unexpected token in foreign key")),
             }
         }
         let foreign_table = ForeignKeyTable {
             schema_name: None,
-            table_name: match input.next().unwrap() {
+            table_name: match next_token(input)? {
                 Token::Identifier(table_name) => table_name.to_string(),
-                _ => panic!("expected table name"),
+                _ => return Err(unexpected(input, "expected table name")),
             },
             column_names,
         };
-        (ForeignKeyClause { foreign_table }, consumed)
+        Ok((ForeignKeyClause { foreign_table }, consumed))
     }
 }
 
 #[derive(Debug, Clone)]
-struct ForeignKeyTable {
-    schema_name: Option<String>,
-    table_name: String,
-    column_names: Vec<String>,
+pub struct ForeignKeyTable {
+    pub schema_name: Option<String>,
+    pub table_name: String,
+    pub column_names: Vec<String>,
 }
 
 impl Parse for ForeignKeyTable {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
-        unimplemented!()
+    fn parse(_input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        Err(Error::Unsupported("foreign key table clause".to_string()))
     }
 }
 
 #[derive(Debug, Clone)]
-struct TableConstraint;
+pub enum TableConstraint {
+    PrimaryKey {
+        columns: Vec<String>,
+        conflict_clause: Option<ConflictClause>,
+    },
+    Unique {
+        columns: Vec<String>,
+        conflict_clause: Option<ConflictClause>,
+    },
+    Check(Expr),
+}
 
 impl Parse for TableConstraint {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
-        unimplemented!()
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let mut consumed = 0;
+        if let Token::Constraint = peek_token(input)? {
+            input.next();
+            match next_token(input)? {
+                Token::Identifier(_) => consumed += 2,
+                _ => return Err(unexpected(input, "expected constraint name")),
+            }
+        }
+        let primary_key = match next_token(input)? {
+            Token::Primary => {
+                expect(input, &Token::Key, "in table constraint")?;
+                consumed += 2;
+                true
+            }
+            Token::Unique => {
+                consumed += 1;
+                false
+            }
+            Token::Check => {
+                let (check, n) = parse_check(input)?;
+                return Ok((TableConstraint::Check(check), consumed + 1 + n));
+            }
+            Token::Foreign => {
+                return Err(Error::Unsupported(
+                    "FOREIGN KEY table constraints".to_string(),
+                ))
+            }
+            _ => return Err(unexpected(input, "expected table constraint")),
+        };
+        expect(
+            input,
+            &Token::Operator("(".to_string()),
+            "before constraint columns",
+        )?;
+        consumed += 1;
+        let mut columns = Vec::new();
+        loop {
+            match next_token(input)? {
+                Token::Identifier(column_name) => columns.push(column_name.to_string()),
+                _ => {
+                    return Err(unexpected(
+                        input,
+                        "expected column name in table constraint",
+                    ))
+                }
+            }
+            consumed += 1;
+            if let Token::Asc | Token::Desc = peek_token(input)? {
+                input.next();
+                consumed += 1;
+            }
+            match next_token(input)? {
+                Token::Operator(t) if t == "," => consumed += 1,
+                Token::Operator(t) if t == ")" => {
+                    consumed += 1;
+                    break;
+                }
+                _ => return Err(unexpected(input, "expected ',' or ')' in table constraint")),
+            }
+        }
+        let conflict_clause = match input.peek() {
+            Some(Token::On) => {
+                input.next();
+                expect(input, &Token::Conflict, "in table constraint")?;
+                consumed += 2;
+                let (clause, n) = ConflictClause::parse(input)?;
+                consumed += n;
+                Some(clause)
+            }
+            _ => None,
+        };
+        let constraint = if primary_key {
+            TableConstraint::PrimaryKey {
+                columns,
+                conflict_clause,
+            }
+        } else {
+            TableConstraint::Unique {
+                columns,
+                conflict_clause,
+            }
+        };
+        Ok((constraint, consumed))
     }
 }
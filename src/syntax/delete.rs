@@ -0,0 +1,36 @@
+use std::{iter::Peekable, slice::Iter};
+
+use crate::error::Result;
+
+use super::{
+    expect, expr::Expr, next_token, select::parse_where, tokenizer::Token, unexpected, Parse,
+};
+
+#[derive(Debug, Clone)]
+pub struct DeleteStmt {
+    pub table_name: String,
+    /// The condition a row must meet to be deleted; with none, every row goes.
+    pub where_clause: Option<Expr>,
+}
+
+impl Parse for DeleteStmt {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let mut consumed = 0;
+        expect(input, &Token::From, "after DELETE")?;
+        consumed += 1;
+        let table_name = match next_token(input)? {
+            Token::Identifier(name) => name.to_string(),
+            _ => return Err(unexpected(input, "expected table name")),
+        };
+        consumed += 1;
+        let (where_clause, where_consumed) = parse_where(input)?;
+        consumed += where_consumed;
+        Ok((
+            DeleteStmt {
+                table_name,
+                where_clause,
+            },
+            consumed,
+        ))
+    }
+}
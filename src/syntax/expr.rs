@@ -0,0 +1,581 @@
+use std::{iter::Peekable, slice::Iter};
+
+use crate::error::Result;
+
+use super::{
+    expect,
+    insert::{decode_hex, Value},
+    next_token, peek_token,
+    select::SelectStmt,
+    tokenizer::{LiteralKind, Token},
+    unexpected, Parse,
+};
+
+/// An expression as written, before any names are resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    /// A column, optionally qualified with the name of its table.
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `expr ISNULL`, or with `negated`, `expr NOTNULL` and `expr NOT NULL`.
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    In {
+        expr: Box<Expr>,
+        list: InList,
+        negated: bool,
+    },
+    /// LIKE, GLOB, REGEXP or MATCH, with the ESCAPE character LIKE can be given.
+    Like {
+        op: LikeOp,
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        escape: Option<Box<Expr>>,
+        negated: bool,
+    },
+    Function {
+        name: String,
+        args: FunctionArgs,
+    },
+    /// With an operand, each WHEN value is compared to it; without one, each is a condition.
+    Case {
+        operand: Option<Box<Expr>>,
+        branches: Vec<(Expr, Expr)>,
+        else_: Option<Box<Expr>>,
+    },
+    Cast {
+        expr: Box<Expr>,
+        type_name: String,
+    },
+    Collate {
+        expr: Box<Expr>,
+        collation: String,
+    },
+    Exists(Box<SelectStmt>),
+    /// A SELECT in parentheses, standing for the first column of its first row.
+    Subquery(Box<SelectStmt>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Plus,
+    BitNot,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Concat,
+    Multiply,
+    Divide,
+    Modulo,
+    Add,
+    Subtract,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    Is,
+    IsNot,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LikeOp {
+    Like,
+    Glob,
+    Regexp,
+    Match,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InList {
+    Values(Vec<Expr>),
+    Select(Box<SelectStmt>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionArgs {
+    /// `f(*)`, as in `count(*)`.
+    Star,
+    List {
+        distinct: bool,
+        args: Vec<Expr>,
+    },
+}
+
+// How tightly each operator binds, from loosest to tightest, as in SQLite.
+const OR: u8 = 1;
+const AND: u8 = 2;
+const NOT: u8 = 3;
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const BITWISE: u8 = 6;
+const ADDITIVE: u8 = 7;
+const MULTIPLICATIVE: u8 = 8;
+const CONCAT: u8 = 9;
+const UNARY: u8 = 10;
+const COLLATE: u8 = 11;
+
+impl Parse for Expr {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let remaining = input.len();
+        let expr = parse_expr(input, 0)?;
+        Ok((expr, remaining - input.len()))
+    }
+}
+
+/// Parses a single operand with no binary operator after it, such as a literal, a signed
+/// number or an expression in parentheses. Column DEFAULT values are written this way.
+pub(super) fn parse_operand(input: &mut Peekable<Iter<Token>>) -> Result<(Expr, usize)> {
+    let remaining = input.len();
+    let expr = parse_expr(input, UNARY)?;
+    Ok((expr, remaining - input.len()))
+}
+
+/// Parses an expression, stopping before any operator that binds no tighter than
+/// `min_power`.
+fn parse_expr(input: &mut Peekable<Iter<Token>>, min_power: u8) -> Result<Expr> {
+    let mut left = parse_prefix(input)?;
+    loop {
+        let Some(&token) = input.peek() else {
+            return Ok(left);
+        };
+        let mut ahead = input.clone();
+        ahead.next();
+        let power = match binding_power(token, ahead.peek().copied()) {
+            Some(power) if power > min_power => power,
+            _ => return Ok(left),
+        };
+        input.next();
+        let negated = token == &Token::Not;
+        let token = if negated { next_token(input)? } else { token };
+        left = match token {
+            Token::Operator(op) => {
+                let right = parse_expr(input, power)?;
+                binary(binary_op(op).unwrap(), left, right)
+            }
+            Token::And => binary(BinaryOp::And, left, parse_expr(input, power)?),
+            Token::Or => binary(BinaryOp::Or, left, parse_expr(input, power)?),
+            Token::Is => {
+                let op = match input.next_if_eq(&&Token::Not) {
+                    Some(_) => BinaryOp::IsNot,
+                    None => BinaryOp::Is,
+                };
+                binary(op, left, parse_expr(input, power)?)
+            }
+            Token::IsNull | Token::NotNull | Token::Null => Expr::IsNull {
+                expr: Box::new(left),
+                negated: negated || token == &Token::NotNull,
+            },
+            Token::Between => {
+                // The AND between the bounds belongs to BETWEEN, so the bounds stop short of
+                // it.
+                let low = parse_expr(input, EQUALITY)?;
+                expect(input, &Token::And, "in BETWEEN")?;
+                let high = parse_expr(input, EQUALITY)?;
+                Expr::Between {
+                    expr: Box::new(left),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                }
+            }
+            Token::In => Expr::In {
+                expr: Box::new(left),
+                list: parse_in_list(input)?,
+                negated,
+            },
+            Token::Like | Token::Glob | Token::Regexp | Token::Match => {
+                let op = match token {
+                    Token::Like => LikeOp::Like,
+                    Token::Glob => LikeOp::Glob,
+                    Token::Regexp => LikeOp::Regexp,
+                    _ => LikeOp::Match,
+                };
+                let pattern = parse_expr(input, power)?;
+                let escape = match input.next_if_eq(&&Token::Escape) {
+                    Some(_) => Some(Box::new(parse_expr(input, power)?)),
+                    None => None,
+                };
+                Expr::Like {
+                    op,
+                    expr: Box::new(left),
+                    pattern: Box::new(pattern),
+                    escape,
+                    negated,
+                }
+            }
+            Token::Collate => Expr::Collate {
+                expr: Box::new(left),
+                collation: identifier(input, "expected collation name")?,
+            },
+            _ => unreachable!("token without a binding power"),
+        };
+    }
+}
+
+/// How tightly `token` binds as an operator after an operand, or None if it cannot follow
+/// one. NOT only does before IN, LIKE and the rest, so it needs the token after it.
+fn binding_power(token: &Token, next: Option<&Token>) -> Option<u8> {
+    let power = match token {
+        Token::Or => OR,
+        Token::And => AND,
+        Token::Operator(op) => match binary_op(op)? {
+            BinaryOp::Concat => CONCAT,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => MULTIPLICATIVE,
+            BinaryOp::Add | BinaryOp::Subtract => ADDITIVE,
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+                BITWISE
+            }
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+                COMPARISON
+            }
+            _ => EQUALITY,
+        },
+        Token::Is
+        | Token::IsNull
+        | Token::NotNull
+        | Token::Between
+        | Token::In
+        | Token::Like
+        | Token::Glob
+        | Token::Regexp
+        | Token::Match => EQUALITY,
+        Token::Not => match next? {
+            Token::Null
+            | Token::Between
+            | Token::In
+            | Token::Like
+            | Token::Glob
+            | Token::Regexp
+            | Token::Match => EQUALITY,
+            _ => return None,
+        },
+        Token::Collate => COLLATE,
+        _ => return None,
+    };
+    Some(power)
+}
+
+fn binary_op(op: &str) -> Option<BinaryOp> {
+    let op = match op {
+        "||" => BinaryOp::Concat,
+        "*" => BinaryOp::Multiply,
+        "/" => BinaryOp::Divide,
+        "%" => BinaryOp::Modulo,
+        "+" => BinaryOp::Add,
+        "-" => BinaryOp::Subtract,
+        "&" => BinaryOp::BitAnd,
+        "|" => BinaryOp::BitOr,
+        "<<" => BinaryOp::ShiftLeft,
+        ">>" => BinaryOp::ShiftRight,
+        "<" => BinaryOp::Less,
+        "<=" => BinaryOp::LessEqual,
+        ">" => BinaryOp::Greater,
+        ">=" => BinaryOp::GreaterEqual,
+        "=" | "==" => BinaryOp::Equal,
+        "!=" | "<>" => BinaryOp::NotEqual,
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+/// Parses an operand, along with any prefix operators in front of it.
+fn parse_prefix(input: &mut Peekable<Iter<Token>>) -> Result<Expr> {
+    let mut ahead = input.clone();
+    ahead.next();
+    let next = ahead.peek().copied();
+    let token = peek_token(input)?;
+    let expr = match token {
+        // A sign directly in front of a number is part of the literal, which is the only way
+        // to write the smallest integer.
+        Token::Operator(op)
+            if (op == "-" || op == "+")
+                && matches!(
+                    next,
+                    Some(Token::Literal(
+                        LiteralKind::Integer(_) | LiteralKind::Real(_)
+                    ))
+                ) =>
+        {
+            Expr::Literal(Value::parse(input)?.0)
+        }
+        Token::Literal(LiteralKind::Integer(_) | LiteralKind::Real(_)) | Token::Null => {
+            Expr::Literal(Value::parse(input)?.0)
+        }
+        Token::Literal(LiteralKind::Str(text)) => {
+            input.next();
+            Expr::Literal(Value::Text(text.clone()))
+        }
+        Token::Literal(LiteralKind::Blob(hex)) => {
+            input.next();
+            match decode_hex(hex) {
+                Some(blob) => Expr::Literal(Value::Blob(blob)),
+                None => return Err(unexpected(input, "malformed blob literal")),
+            }
+        }
+        Token::Operator(op) if op == "-" || op == "+" || op == "~" => {
+            input.next();
+            let op = match op.as_str() {
+                "-" => UnaryOp::Negate,
+                "+" => UnaryOp::Plus,
+                _ => UnaryOp::BitNot,
+            };
+            Expr::Unary {
+                op,
+                expr: Box::new(parse_expr(input, UNARY)?),
+            }
+        }
+        Token::Not => {
+            input.next();
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(parse_expr(input, NOT)?),
+            }
+        }
+        Token::Operator(op) if op == "(" => {
+            input.next();
+            let expr = if next == Some(&Token::Select) {
+                Expr::Subquery(Box::new(parse_select(input)?))
+            } else {
+                parse_expr(input, 0)?
+            };
+            expect(input, &Token::Operator(")".to_string()), "to close '('")?;
+            expr
+        }
+        Token::Exists => {
+            input.next();
+            expect(input, &Token::Operator("(".to_string()), "after EXISTS")?;
+            let select = parse_select(input)?;
+            expect(
+                input,
+                &Token::Operator(")".to_string()),
+                "after EXISTS subquery",
+            )?;
+            Expr::Exists(Box::new(select))
+        }
+        Token::Case => {
+            input.next();
+            parse_case(input)?
+        }
+        Token::Cast => {
+            input.next();
+            expect(input, &Token::Operator("(".to_string()), "after CAST")?;
+            let expr = parse_expr(input, 0)?;
+            expect(input, &Token::As, "in CAST")?;
+            let type_name = parse_type_name(input)?;
+            expect(input, &Token::Operator(")".to_string()), "after CAST type")?;
+            Expr::Cast {
+                expr: Box::new(expr),
+                type_name,
+            }
+        }
+        Token::CurrentDate | Token::CurrentTime | Token::CurrentTimestamp => {
+            input.next();
+            let name = match token {
+                Token::CurrentDate => "current_date",
+                Token::CurrentTime => "current_time",
+                _ => "current_timestamp",
+            };
+            Expr::Function {
+                name: name.to_string(),
+                args: FunctionArgs::List {
+                    distinct: false,
+                    args: Vec::new(),
+                },
+            }
+        }
+        // A few keywords are also the names of functions.
+        Token::Replace | Token::Like | Token::Glob
+            if next == Some(&Token::Operator("(".to_string())) =>
+        {
+            input.next();
+            let name = format!("{:?}", token).to_lowercase();
+            parse_function(input, name)?
+        }
+        Token::Identifier(name) => {
+            input.next();
+            match next {
+                Some(Token::Operator(op)) if op == "(" => parse_function(input, name.clone())?,
+                Some(Token::Operator(op)) if op == "." => {
+                    input.next();
+                    Expr::Column {
+                        table: Some(name.clone()),
+                        name: identifier(input, "expected column name")?,
+                    }
+                }
+                _ => Expr::Column {
+                    table: None,
+                    name: name.clone(),
+                },
+            }
+        }
+        _ => {
+            input.next();
+            return Err(unexpected(input, "expected an expression"));
+        }
+    };
+    Ok(expr)
+}
+
+fn identifier(input: &mut Peekable<Iter<Token>>, message: &str) -> Result<String> {
+    match next_token(input)? {
+        Token::Identifier(name) => Ok(name.clone()),
+        _ => Err(unexpected(input, message)),
+    }
+}
+
+/// Parses a SELECT whose keyword is next.
+fn parse_select(input: &mut Peekable<Iter<Token>>) -> Result<SelectStmt> {
+    expect(input, &Token::Select, "to start a subquery")?;
+    Ok(SelectStmt::parse(input)?.0)
+}
+
+/// Parses the arguments of a function call, from the opening parenthesis.
+fn parse_function(input: &mut Peekable<Iter<Token>>, name: String) -> Result<Expr> {
+    expect(
+        input,
+        &Token::Operator("(".to_string()),
+        "after function name",
+    )?;
+    let close = Token::Operator(")".to_string());
+    let args = if input
+        .next_if_eq(&&Token::Operator("*".to_string()))
+        .is_some()
+    {
+        FunctionArgs::Star
+    } else {
+        let distinct = input.next_if_eq(&&Token::Distinct).is_some();
+        let args = if !distinct && input.peek() == Some(&&close) {
+            Vec::new()
+        } else {
+            parse_list(input)?
+        };
+        FunctionArgs::List { distinct, args }
+    };
+    expect(input, &close, "after function arguments")?;
+    Ok(Expr::Function { name, args })
+}
+
+/// Parses expressions separated by commas.
+fn parse_list(input: &mut Peekable<Iter<Token>>) -> Result<Vec<Expr>> {
+    let mut list = vec![parse_expr(input, 0)?];
+    while input
+        .next_if_eq(&&Token::Operator(",".to_string()))
+        .is_some()
+    {
+        list.push(parse_expr(input, 0)?);
+    }
+    Ok(list)
+}
+
+/// Parses what follows IN: a parenthesized list of values, which may be empty, or a SELECT.
+fn parse_in_list(input: &mut Peekable<Iter<Token>>) -> Result<InList> {
+    expect(input, &Token::Operator("(".to_string()), "after IN")?;
+    let close = Token::Operator(")".to_string());
+    let list = match peek_token(input)? {
+        Token::Select => InList::Select(Box::new(parse_select(input)?)),
+        token if token == &close => InList::Values(Vec::new()),
+        _ => InList::Values(parse_list(input)?),
+    };
+    expect(input, &close, "after IN list")?;
+    Ok(list)
+}
+
+/// Parses a CASE expression after the CASE keyword, up to and including END.
+fn parse_case(input: &mut Peekable<Iter<Token>>) -> Result<Expr> {
+    let operand = match peek_token(input)? {
+        Token::When => None,
+        _ => Some(Box::new(parse_expr(input, 0)?)),
+    };
+    let mut branches = Vec::new();
+    while input.next_if_eq(&&Token::When).is_some() {
+        let condition = parse_expr(input, 0)?;
+        expect(input, &Token::Then, "after WHEN condition")?;
+        branches.push((condition, parse_expr(input, 0)?));
+    }
+    if branches.is_empty() {
+        input.next();
+        return Err(unexpected(input, "expected WHEN in CASE"));
+    }
+    let else_ = match input.next_if_eq(&&Token::Else) {
+        Some(_) => Some(Box::new(parse_expr(input, 0)?)),
+        None => None,
+    };
+    // END is not one of the tokenizer's keywords.
+    match next_token(input)? {
+        Token::Identifier(end) if end.eq_ignore_ascii_case("end") => {}
+        _ => return Err(unexpected(input, "expected END after CASE")),
+    }
+    Ok(Expr::Case {
+        operand,
+        branches,
+        else_,
+    })
+}
+
+/// Parses a type name such as `INTEGER`, `VARCHAR(10)` or `DOUBLE PRECISION`, as written.
+pub(super) fn parse_type_name(input: &mut Peekable<Iter<Token>>) -> Result<String> {
+    let mut words = vec![identifier(input, "expected type name")?];
+    while let Some(Token::Identifier(word)) = input.peek() {
+        words.push(word.clone());
+        input.next();
+    }
+    let mut type_name = words.join(" ");
+    if input
+        .next_if_eq(&&Token::Operator("(".to_string()))
+        .is_some()
+    {
+        let mut sizes = Vec::new();
+        loop {
+            match Value::parse(input)?.0 {
+                size @ (Value::Integer(_) | Value::Real(_)) => sizes.push(size.to_string()),
+                _ => return Err(unexpected(input, "expected a number in type name")),
+            }
+            match next_token(input)? {
+                Token::Operator(op) if op == "," => {}
+                Token::Operator(op) if op == ")" => break,
+                _ => return Err(unexpected(input, "expected ',' or ')' in type name")),
+            }
+        }
+        type_name = format!("{}({})", type_name, sizes.join(","));
+    }
+    Ok(type_name)
+}
@@ -0,0 +1,185 @@
+use std::{fmt, iter::Peekable, slice::Iter};
+
+use crate::error::Result;
+
+use super::{
+    expect, next_token, peek_token,
+    tokenizer::{LiteralKind, Token},
+    unexpected, Parse,
+};
+
+#[derive(Debug, Clone)]
+pub struct InsertStmt {
+    pub schema_name: Option<String>,
+    pub table_name: String,
+    /// Columns named after the table, or empty to fill every column in order.
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Shows the value the way the sqlite3 shell prints it.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Real(n) => write!(f, "{:?}", n),
+            Value::Text(text) => f.write_str(text),
+            Value::Blob(blob) => f.write_str(&String::from_utf8_lossy(blob)),
+        }
+    }
+}
+
+impl Parse for InsertStmt {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let mut consumed = 0;
+        expect(input, &Token::Into, "after INSERT")?;
+        consumed += 1;
+        let first_name = match next_token(input)? {
+            Token::Identifier(name) => name.to_string(),
+            _ => return Err(unexpected(input, "expected table name")),
+        };
+        consumed += 1;
+        let (schema_name, table_name) = match input.peek() {
+            Some(Token::Operator(t)) if t == "." => {
+                input.next();
+                let table_name = match next_token(input)? {
+                    Token::Identifier(name) => name.to_string(),
+                    _ => return Err(unexpected(input, "expected table name")),
+                };
+                consumed += 2;
+                (Some(first_name), table_name)
+            }
+            _ => (None, first_name),
+        };
+
+        let mut columns = Vec::new();
+        if let Token::Operator(t) = peek_token(input)? {
+            if t == "(" {
+                input.next();
+                consumed += 1;
+                loop {
+                    match next_token(input)? {
+                        Token::Identifier(name) => columns.push(name.to_string()),
+                        _ => return Err(unexpected(input, "expected column name")),
+                    }
+                    match next_token(input)? {
+                        Token::Operator(t) if t == "," => {}
+                        Token::Operator(t) if t == ")" => {
+                            consumed += 2;
+                            break;
+                        }
+                        _ => return Err(unexpected(input, "expected ',' or ')' in column list")),
+                    }
+                    consumed += 2;
+                }
+            }
+        }
+
+        expect(input, &Token::Values, "before the rows to insert")?;
+        consumed += 1;
+        let mut rows = Vec::new();
+        loop {
+            expect(input, &Token::Operator("(".to_string()), "before a row")?;
+            consumed += 1;
+            let mut row = Vec::new();
+            loop {
+                let (value, n) = Value::parse(input)?;
+                row.push(value);
+                consumed += n;
+                match next_token(input)? {
+                    Token::Operator(t) if t == "," => {}
+                    Token::Operator(t) if t == ")" => {
+                        consumed += 1;
+                        break;
+                    }
+                    _ => return Err(unexpected(input, "expected ',' or ')' in row")),
+                }
+                consumed += 1;
+            }
+            rows.push(row);
+            match input.peek() {
+                Some(Token::Operator(t)) if t == "," => {
+                    input.next();
+                    consumed += 1;
+                }
+                _ => break,
+            }
+        }
+        Ok((
+            InsertStmt {
+                schema_name,
+                table_name,
+                columns,
+                rows,
+            },
+            consumed,
+        ))
+    }
+}
+
+impl Parse for Value {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let mut consumed = 0;
+        let mut negative = false;
+        while let Token::Operator(t) = peek_token(input)? {
+            match t.as_str() {
+                "-" => negative = !negative,
+                "+" => {}
+                _ => break,
+            }
+            input.next();
+            consumed += 1;
+        }
+        let value = match next_token(input)? {
+            Token::Null if consumed == 0 => Value::Null,
+            Token::Literal(LiteralKind::Str(s)) if consumed == 0 => Value::Text(s.clone()),
+            Token::Literal(LiteralKind::Blob(hex)) if consumed == 0 => match decode_hex(hex) {
+                Some(blob) => Value::Blob(blob),
+                None => return Err(unexpected(input, "malformed blob literal")),
+            },
+            // Integers too large for 64 bits are read as reals, as SQLite does.
+            Token::Literal(LiteralKind::Integer(digits)) => match digits.parse::<i64>() {
+                Ok(n) if negative => Value::Integer(-n),
+                Ok(n) => Value::Integer(n),
+                Err(_) if negative && digits.trim_start_matches('0') == "9223372036854775808" => {
+                    Value::Integer(i64::MIN)
+                }
+                Err(_) => Value::Real(signed(digits.parse().unwrap_or(f64::INFINITY), negative)),
+            },
+            Token::Literal(LiteralKind::Real(digits)) => match digits.parse::<f64>() {
+                Ok(n) => Value::Real(signed(n, negative)),
+                Err(_) => return Err(unexpected(input, "malformed real literal")),
+            },
+            _ => return Err(unexpected(input, "expected a literal value")),
+        };
+        Ok((value, consumed + 1))
+    }
+}
+
+fn signed(n: f64, negative: bool) -> f64 {
+    if negative {
+        -n
+    } else {
+        n
+    }
+}
+
+pub(super) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
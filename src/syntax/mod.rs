@@ -1,21 +1,148 @@
 use std::{iter::Peekable, slice::Iter};
 use tokenizer::Token;
 
+use crate::error::{Error, Result};
+
+pub mod create_index;
 pub mod create_table;
+pub mod delete;
+pub mod expr;
+pub mod insert;
+pub mod pragma;
 pub mod select;
 pub mod statement;
 pub mod tokenizer;
-pub mod create_index;
+pub mod update;
 
 pub trait Parse {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize)
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)>
     where
         Self: Sized;
 }
 
-pub fn parse(input: &str) -> statement::Statement {
+pub fn parse(input: &str) -> Result<statement::Statement> {
+    let tokens = tokenizer::tokenize(input);
+    let mut iter = tokens.iter().peekable();
+    match statement::Statement::parse(&mut iter) {
+        Ok((stmt, _)) => Ok(stmt),
+        // Parsers record how many tokens were left, counting the offending one; turn that into
+        // an index from the start of the statement.
+        Err(Error::Parse { position, message }) => Err(Error::Parse {
+            position: tokens.len() - position,
+            message,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Parses a series of statements separated by semicolons.
+pub fn parse_script(input: &str) -> Result<Vec<statement::Statement>> {
     let tokens = tokenizer::tokenize(input);
     let mut iter = tokens.iter().peekable();
-    let stmt = statement::Statement::parse(&mut iter);
-    stmt.0
+    let mut statements = Vec::new();
+    loop {
+        while at_semicolon(&mut iter) {
+            iter.next();
+        }
+        if iter.peek().is_none() {
+            return Ok(statements);
+        }
+        let parsed = statement::Statement::parse(&mut iter).and_then(|(stmt, _)| {
+            if iter.peek().is_some() && !at_semicolon(&mut iter) {
+                iter.next();
+                return Err(unexpected(&iter, "expected ';' after statement"));
+            }
+            Ok(stmt)
+        });
+        match parsed {
+            Ok(stmt) => statements.push(stmt),
+            Err(Error::Parse { position, message }) => {
+                return Err(Error::Parse {
+                    position: tokens.len() - position,
+                    message,
+                })
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Splits a script into the text of each statement, without the semicolons between them.
+/// Semicolons inside quotes and comments do not count, and statements with nothing but
+/// comments in them are dropped.
+pub fn split_script(input: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let close = match c {
+            '\'' | '"' | '`' => c,
+            '[' => ']',
+            '-' if chars.peek().map(|&(_, c)| c) == Some('-') => '\n',
+            '/' if chars.peek().map(|&(_, c)| c) == Some('*') => {
+                chars.next();
+                let mut star = false;
+                for (_, c) in chars.by_ref() {
+                    if star && c == '/' {
+                        break;
+                    }
+                    star = c == '*';
+                }
+                continue;
+            }
+            ';' => {
+                statements.push(input[start..i].trim());
+                start = i + 1;
+                continue;
+            }
+            _ => continue,
+        };
+        // A doubled quote inside a string is just a quote, which this skips as the end of one
+        // string and the start of the next.
+        for (_, c) in chars.by_ref() {
+            if c == close {
+                break;
+            }
+        }
+    }
+    statements.push(input[start..].trim());
+    statements.retain(|statement| !tokenizer::tokenize(statement).is_empty());
+    statements
+}
+
+fn at_semicolon(input: &mut Peekable<Iter<Token>>) -> bool {
+    matches!(input.peek(), Some(Token::Operator(t)) if t == ";")
+}
+
+/// Error for the token most recently taken from `input`.
+fn unexpected(input: &Peekable<Iter<Token>>, message: impl Into<String>) -> Error {
+    Error::Parse {
+        position: input.len() + 1,
+        message: message.into(),
+    }
+}
+
+fn next_token<'a>(input: &mut Peekable<Iter<'a, Token>>) -> Result<&'a Token> {
+    input.next().ok_or_else(|| Error::Parse {
+        position: 0,
+        message: "unexpected end of input".to_string(),
+    })
+}
+
+fn peek_token<'a>(input: &mut Peekable<Iter<'a, Token>>) -> Result<&'a Token> {
+    input.peek().copied().ok_or_else(|| Error::Parse {
+        position: 0,
+        message: "unexpected end of input".to_string(),
+    })
+}
+
+fn expect(input: &mut Peekable<Iter<Token>>, expected: &Token, context: &str) -> Result<()> {
+    let token = next_token(input)?;
+    if token != expected {
+        return Err(unexpected(
+            input,
+            format!("expected {:?} {}, found {:?}", expected, context, token),
+        ));
+    }
+    Ok(())
 }
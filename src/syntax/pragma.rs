@@ -0,0 +1,101 @@
+use std::{iter::Peekable, slice::Iter};
+
+use crate::error::Result;
+
+use super::{
+    next_token,
+    tokenizer::{LiteralKind, Token},
+    unexpected, Parse,
+};
+
+#[derive(Debug, Clone)]
+pub struct PragmaStmt {
+    pub schema_name: Option<String>,
+    pub name: String,
+    /// The argument given after `=` or in parentheses, as written.
+    pub value: Option<String>,
+}
+
+impl PragmaStmt {
+    /// Whether running the pragma can change the database, so that it has to be opened for
+    /// writing. Reading the journal mode does not; setting it or checkpointing does.
+    pub fn writes(&self) -> bool {
+        match self.name.to_lowercase().as_str() {
+            "journal_mode" => self.value.is_some(),
+            "wal_checkpoint" => true,
+            _ => false,
+        }
+    }
+}
+
+impl Parse for PragmaStmt {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let mut consumed = 1;
+        let mut schema_name = None;
+        let mut name = pragma_name(input)?;
+        if let Some(Token::Operator(op)) = input.peek() {
+            if op == "." {
+                input.next();
+                schema_name = Some(name);
+                name = pragma_name(input)?;
+                consumed += 2;
+            }
+        }
+        let value = match input.peek() {
+            Some(Token::Operator(op)) if op == "=" => {
+                input.next();
+                let (value, n) = pragma_value(input)?;
+                consumed += 1 + n;
+                Some(value)
+            }
+            Some(Token::Operator(op)) if op == "(" => {
+                input.next();
+                let (value, n) = pragma_value(input)?;
+                match next_token(input)? {
+                    Token::Operator(op) if op == ")" => {}
+                    _ => return Err(unexpected(input, "expected ')'")),
+                }
+                consumed += 2 + n;
+                Some(value)
+            }
+            _ => None,
+        };
+        Ok((
+            PragmaStmt {
+                schema_name,
+                name,
+                value,
+            },
+            consumed,
+        ))
+    }
+}
+
+fn pragma_name(input: &mut Peekable<Iter<Token>>) -> Result<String> {
+    match next_token(input)? {
+        Token::Identifier(name) => Ok(name.to_string()),
+        _ => Err(unexpected(input, "expected pragma name")),
+    }
+}
+
+/// Parses a pragma argument: a name, a string, or a possibly signed number. Keywords such as
+/// FULL or DELETE are arguments to some pragmas, and are taken by name.
+fn pragma_value(input: &mut Peekable<Iter<Token>>) -> Result<(String, usize)> {
+    let (sign, consumed) = match input.peek() {
+        Some(Token::Operator(op)) if op == "-" || op == "+" => {
+            input.next();
+            (op.as_str(), 2)
+        }
+        _ => ("", 1),
+    };
+    let value = match next_token(input)? {
+        Token::Literal(LiteralKind::Integer(n) | LiteralKind::Real(n)) => format!("{}{}", sign, n),
+        _ if !sign.is_empty() => return Err(unexpected(input, "expected a number")),
+        Token::Identifier(name) | Token::Literal(LiteralKind::Str(name)) => name.to_string(),
+        Token::Whitespace | Token::Literal(_) | Token::Variable(_) | Token::Operator(_) => {
+            return Err(unexpected(input, "expected pragma value"))
+        }
+        keyword => format!("{:?}", keyword).to_lowercase(),
+    };
+    Ok((value, consumed))
+}
@@ -1,112 +1,351 @@
 use std::{iter::Peekable, slice::Iter};
 
+use crate::error::{Error, Result};
+
 use super::{
+    expect,
+    expr::Expr,
+    next_token,
     tokenizer::{LiteralKind, Token},
-    Parse,
+    unexpected, Parse,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SelectStmt {
-    pub columns: Vec<String>,
-    pub table: String,
-    pub where_clause: Vec<WhereClause>,
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    /// The tables after FROM in the order they are joined, empty without a FROM clause.
+    pub from: Vec<FromTable>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+}
+
+/// One entry of the list between SELECT and FROM.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    /// `*`: every column of every table.
+    All,
+    /// `table.*`
+    AllFrom(String),
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+    },
+}
+
+/// A table of the FROM clause, with how it is joined to the tables before it. The first
+/// table is given as an inner join.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromTable {
+    pub name: String,
+    pub alias: Option<String>,
+    pub join: JoinKind,
+    /// NATURAL joins on every column name the tables share.
+    pub natural: bool,
+    pub constraint: Option<JoinConstraint>,
+}
+
+/// A comma, plain JOIN and CROSS JOIN are all inner joins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    On(Expr),
+    Using(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+    /// Where NULLs go when given with NULLS FIRST or NULLS LAST. By default they sort as the
+    /// smallest value.
+    pub nulls_first: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub limit: Expr,
+    pub offset: Option<Expr>,
 }
 
 impl Parse for SelectStmt {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
-        let mut consumed = 0;
-        let mut columns = Vec::new();
-        loop {
-            match input.peek().unwrap() {
-                Token::Identifier(ident) => {
-                    columns.push(ident.clone());
-                    consumed += 1;
-                    input.next();
-                }
-                Token::From => {
-                    consumed += 1;
-                    input.next();
-                    break;
-                }
-                Token::Operator(t) if t == "," => {
-                    consumed += 1;
-                    input.next();
-                }
-                _ => {
-                    panic!("Expected identifier or FROM");
-                }
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let start = input.len();
+        let distinct = match input.peek() {
+            Some(Token::Distinct) => {
+                input.next();
+                true
             }
-        }
-        let table = match input.next().unwrap() {
-            Token::Identifier(ident) => ident.clone(),
-            _ => panic!("Expected identifier after FROM"),
-        };
-        consumed += 1;
-
-        let mut where_clause = Vec::new();
-        match input.peek() {
-            Some(Token::Where) => {
-                consumed += 1;
+            Some(Token::All) => {
                 input.next();
-                loop {
-                    let (clause, clause_consumed) = WhereClause::parse(input);
-                    where_clause.push(clause);
-                    consumed += clause_consumed;
-                    match input.peek() {
-                        Some(Token::And) => {
-                            consumed += 1;
-                            input.next();
-                        }
-                        _ => {
-                            break;
-                        }
-                    }
-                }
+                false
             }
-            _ => {}
+            _ => false,
+        };
+        let columns = parse_list(input, ResultColumn::parse)?;
+        let from = match input.next_if_eq(&&Token::From) {
+            Some(_) => parse_from(input)?,
+            None => Vec::new(),
+        };
+        let (where_clause, _) = parse_where(input)?;
+        let mut group_by = Vec::new();
+        if input.next_if_eq(&&Token::Group).is_some() {
+            expect(input, &Token::By, "after GROUP")?;
+            group_by = parse_list(input, Expr::parse)?;
         }
-        (
+        let having = match input.next_if_eq(&&Token::Having) {
+            Some(_) => Some(Expr::parse(input)?.0),
+            None => None,
+        };
+        let mut order_by = Vec::new();
+        if input.next_if_eq(&&Token::Order).is_some() {
+            expect(input, &Token::By, "after ORDER")?;
+            order_by = parse_list(input, OrderingTerm::parse)?;
+        }
+        let limit = match input.next_if_eq(&&Token::Limit) {
+            Some(_) => Some(Limit::parse(input)?.0),
+            None => None,
+        };
+        Ok((
             SelectStmt {
+                distinct,
                 columns,
-                table,
+                from,
                 where_clause,
+                group_by,
+                having,
+                order_by,
+                limit,
             },
-            consumed,
-        )
+            start - input.len(),
+        ))
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct WhereClause {
-    pub column: String,
-    pub value: String,
+impl Parse for ResultColumn {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let star = Token::Operator("*".to_string());
+        if input.next_if_eq(&&star).is_some() {
+            return Ok((ResultColumn::All, 1));
+        }
+        let mut ahead = input.clone();
+        if let (Some(Token::Identifier(table)), Some(Token::Operator(dot)), Some(token)) =
+            (ahead.next(), ahead.next(), ahead.next())
+        {
+            if dot == "." && token == &star {
+                *input = ahead;
+                return Ok((ResultColumn::AllFrom(table.clone()), 3));
+            }
+        }
+        let (expr, mut consumed) = Expr::parse(input)?;
+        let alias = match input.peek() {
+            Some(Token::As) => {
+                input.next();
+                match next_token(input)? {
+                    Token::Identifier(alias) | Token::Literal(LiteralKind::Str(alias)) => {
+                        consumed += 2;
+                        Some(alias.clone())
+                    }
+                    _ => return Err(unexpected(input, "expected alias after AS")),
+                }
+            }
+            Some(Token::Identifier(alias)) => {
+                input.next();
+                consumed += 1;
+                Some(alias.clone())
+            }
+            _ => None,
+        };
+        Ok((ResultColumn::Expr { expr, alias }, consumed))
+    }
 }
 
-impl Parse for WhereClause {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
-        let mut consumed = 0;
-        let column = match input.next().unwrap() {
-            Token::Identifier(ident) => ident.clone(),
-            _ => panic!("Expected identifier"),
+impl Parse for OrderingTerm {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let start = input.len();
+        let (expr, _) = Expr::parse(input)?;
+        let descending = match input.peek() {
+            Some(Token::Asc) => {
+                input.next();
+                false
+            }
+            Some(Token::Desc) => {
+                input.next();
+                true
+            }
+            _ => false,
         };
-        let _ = match input.next().unwrap() {
-            Token::Operator(t) if t == "=" => {
-                consumed += 1;
+        let nulls_first = match input.peek() {
+            Some(Token::Identifier(nulls)) if nulls.eq_ignore_ascii_case("nulls") => {
+                input.next();
+                match next_token(input)? {
+                    Token::Identifier(word) if word.eq_ignore_ascii_case("first") => Some(true),
+                    Token::Identifier(word) if word.eq_ignore_ascii_case("last") => Some(false),
+                    _ => return Err(unexpected(input, "expected FIRST or LAST after NULLS")),
+                }
             }
-            _ => panic!("Expected ="),
+            _ => None,
         };
-        consumed += 1;
-        let value = match input.next().unwrap() {
-            Token::Literal(lit) => match lit {
-                LiteralKind::Str(s)
-                | LiteralKind::Blob(s)
-                | LiteralKind::Integer(s)
-                | LiteralKind::Real(s) => s.clone(),
-                _ => panic!("Expected string literal"),
+        Ok((
+            OrderingTerm {
+                expr,
+                descending,
+                nulls_first,
+            },
+            start - input.len(),
+        ))
+    }
+}
+
+impl Parse for Limit {
+    /// Parses what follows LIMIT: `count`, `count OFFSET skip` or `skip, count`.
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let start = input.len();
+        let (first, _) = Expr::parse(input)?;
+        let limit = match input.peek() {
+            Some(Token::Offset) => {
+                input.next();
+                Limit {
+                    limit: first,
+                    offset: Some(Expr::parse(input)?.0),
+                }
+            }
+            Some(Token::Operator(t)) if t == "," => {
+                input.next();
+                Limit {
+                    limit: Expr::parse(input)?.0,
+                    offset: Some(first),
+                }
+            }
+            _ => Limit {
+                limit: first,
+                offset: None,
             },
-            _ => panic!("Expected literal"),
         };
-        consumed += 1;
-        (WhereClause { column, value }, consumed)
+        Ok((limit, start - input.len()))
+    }
+}
+
+/// Parses one or more items separated by commas.
+fn parse_list<T, F>(input: &mut Peekable<Iter<Token>>, mut parse: F) -> Result<Vec<T>>
+where
+    F: FnMut(&mut Peekable<Iter<Token>>) -> Result<(T, usize)>,
+{
+    let mut items = vec![parse(input)?.0];
+    while input
+        .next_if_eq(&&Token::Operator(",".to_string()))
+        .is_some()
+    {
+        items.push(parse(input)?.0);
+    }
+    Ok(items)
+}
+
+/// Parses the tables after FROM and the joins between them.
+fn parse_from(input: &mut Peekable<Iter<Token>>) -> Result<Vec<FromTable>> {
+    let mut tables = vec![parse_table(input, JoinKind::Inner, false)?];
+    loop {
+        if input
+            .next_if_eq(&&Token::Operator(",".to_string()))
+            .is_some()
+        {
+            tables.push(parse_table(input, JoinKind::Inner, false)?);
+            continue;
+        }
+        let natural = input.next_if_eq(&&Token::Natural).is_some();
+        let join = match input.peek() {
+            Some(Token::Join) => JoinKind::Inner,
+            Some(Token::Inner | Token::Cross) => {
+                input.next();
+                JoinKind::Inner
+            }
+            Some(Token::Left) => {
+                input.next();
+                input.next_if_eq(&&Token::Outer);
+                JoinKind::Left
+            }
+            Some(Token::Right | Token::Full) => {
+                return Err(Error::Unsupported("RIGHT and FULL joins".to_string()))
+            }
+            _ if natural => {
+                input.next();
+                return Err(unexpected(input, "expected JOIN after NATURAL"));
+            }
+            _ => return Ok(tables),
+        };
+        expect(input, &Token::Join, "to join a table")?;
+        tables.push(parse_table(input, join, natural)?);
+    }
+}
+
+/// Parses `name [[AS] alias] [ON expr | USING (columns)]`.
+fn parse_table(
+    input: &mut Peekable<Iter<Token>>,
+    join: JoinKind,
+    natural: bool,
+) -> Result<FromTable> {
+    let name = match next_token(input)? {
+        Token::Identifier(name) => name.clone(),
+        Token::Operator(t) if t == "(" => {
+            return Err(Error::Unsupported("subqueries in FROM".to_string()))
+        }
+        _ => return Err(unexpected(input, "expected table name")),
+    };
+    let has_as = input.next_if_eq(&&Token::As).is_some();
+    let alias = match input.peek() {
+        Some(Token::Identifier(alias)) => {
+            input.next();
+            Some(alias.clone())
+        }
+        _ if has_as => {
+            input.next();
+            return Err(unexpected(input, "expected alias after AS"));
+        }
+        _ => None,
+    };
+    let constraint = match input.peek() {
+        Some(Token::On) => {
+            input.next();
+            Some(JoinConstraint::On(Expr::parse(input)?.0))
+        }
+        Some(Token::Using) => {
+            input.next();
+            expect(input, &Token::Operator("(".to_string()), "after USING")?;
+            let columns = parse_list(input, |input| match next_token(input)? {
+                Token::Identifier(column) => Ok((column.clone(), 1)),
+                _ => Err(unexpected(input, "expected column name in USING")),
+            })?;
+            expect(
+                input,
+                &Token::Operator(")".to_string()),
+                "after USING columns",
+            )?;
+            Some(JoinConstraint::Using(columns))
+        }
+        _ => None,
+    };
+    Ok(FromTable {
+        name,
+        alias,
+        join,
+        natural,
+        constraint,
+    })
+}
+
+/// Parses an optional `WHERE expr`.
+pub(super) fn parse_where(input: &mut Peekable<Iter<Token>>) -> Result<(Option<Expr>, usize)> {
+    if input.next_if_eq(&&Token::Where).is_none() {
+        return Ok((None, 0));
     }
+    let (expr, consumed) = Expr::parse(input)?;
+    Ok((Some(expr), consumed + 1))
 }
@@ -1,13 +1,17 @@
 use std::iter::Peekable;
 use std::slice::Iter;
 
+use crate::error::{Error, Result};
 use crate::syntax::create_table::CreateTableStmt;
-use crate::typecodes::TypeCode;
 
-use super::select::SelectStmt;
-use super::tokenizer::Token;
-use super::Parse;
 use super::create_index::CreateIndexStmt;
+use super::delete::DeleteStmt;
+use super::insert::InsertStmt;
+use super::pragma::PragmaStmt;
+use super::select::SelectStmt;
+use super::tokenizer::{LiteralKind, Token};
+use super::update::UpdateStmt;
+use super::{expect, next_token, unexpected, Parse};
 
 #[derive(Debug)]
 pub enum Statement {
@@ -16,57 +20,166 @@ pub enum Statement {
     Attach,
     Begin,
     Commit,
-    CreateIndex{stmt: CreateIndexStmt, unique: bool},
+    CreateIndex {
+        stmt: CreateIndexStmt,
+        unique: bool,
+    },
     CreateTable(CreateTableStmt),
     CreateTrigger,
     CreateView,
     CreateVirtualTable,
-    Delete,
+    Delete(DeleteStmt),
     DeleteFrom,
     Detach,
     DropIndex,
     DropTable,
     DropTrigger,
     DropView,
-    Insert,
-    Pragma,
+    Insert(InsertStmt),
+    Pragma(PragmaStmt),
     Reindex,
-    Release,
-    Rollback,
-    Savepoint,
+    Release(String),
+    /// ROLLBACK, or ROLLBACK TO the named savepoint.
+    Rollback(Option<String>),
+    Savepoint(String),
     Select(SelectStmt),
-    Update,
-    Vacuum,
+    Update(UpdateStmt),
+    /// VACUUM, or VACUUM INTO the named file.
+    Vacuum(Option<String>),
 }
 
 impl Parse for Statement {
-    fn parse(input: &mut Peekable<Iter<Token>>) -> (Self, usize) {
-        let token = input.next().unwrap();
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let token = next_token(input)?;
         match token {
             Token::Create => {
-                let token = input.next().unwrap();
+                let token = next_token(input)?;
                 match token {
                     Token::Table => {
-                        let (stmt, n) = CreateTableStmt::parse(input);
-                        (Statement::CreateTable(stmt), n)
-                    },
+                        let (stmt, n) = CreateTableStmt::parse(input)?;
+                        Ok((Statement::CreateTable(stmt), n))
+                    }
                     Token::Unique => {
-                        input.next();
-                        let (stmt, n) = CreateIndexStmt::parse(input);
-                        (Statement::CreateIndex{stmt, unique: false}, n)
-                    },
+                        expect(input, &Token::Index, "after CREATE UNIQUE")?;
+                        let (stmt, n) = CreateIndexStmt::parse(input)?;
+                        Ok((Statement::CreateIndex { stmt, unique: true }, n + 1))
+                    }
                     Token::Index => {
-                        let (stmt, n) = CreateIndexStmt::parse(input);
-                        (Statement::CreateIndex{stmt, unique: false}, n)
-                    },
-                    _ => unimplemented!(),
+                        let (stmt, n) = CreateIndexStmt::parse(input)?;
+                        Ok((
+                            Statement::CreateIndex {
+                                stmt,
+                                unique: false,
+                            },
+                            n,
+                        ))
+                    }
+                    _ => Err(Error::Unsupported(format!("CREATE {:?}", token))),
+                }
+            }
+            Token::Begin => {
+                let mut consumed = 0;
+                if let Some(Token::Deferred | Token::Immediate | Token::Exclusive) = input.peek() {
+                    input.next();
+                    consumed += 1;
+                }
+                Ok((Statement::Begin, consumed + skip_transaction(input)))
+            }
+            Token::Commit => Ok((Statement::Commit, skip_transaction(input))),
+            // END is a synonym for COMMIT that the tokenizer does not treat as a keyword.
+            Token::Identifier(name) if name.eq_ignore_ascii_case("end") => {
+                Ok((Statement::Commit, skip_transaction(input)))
+            }
+            Token::Pragma => {
+                let (stmt, consumed) = PragmaStmt::parse(input)?;
+                Ok((Statement::Pragma(stmt), consumed))
+            }
+            Token::Rollback => {
+                let mut consumed = skip_transaction(input);
+                if input.peek() != Some(&&Token::To) {
+                    return Ok((Statement::Rollback(None), consumed));
                 }
+                input.next();
+                let (name, n) = savepoint_name(input)?;
+                consumed += 1 + n;
+                Ok((Statement::Rollback(Some(name)), consumed))
+            }
+            Token::Savepoint => {
+                let (name, consumed) = savepoint_name(input)?;
+                Ok((Statement::Savepoint(name), consumed))
+            }
+            Token::Release => {
+                let (name, consumed) = savepoint_name(input)?;
+                Ok((Statement::Release(name), consumed))
+            }
+            Token::Delete => {
+                let (stmt, consumed) = DeleteStmt::parse(input)?;
+                Ok((Statement::Delete(stmt), consumed))
+            }
+            Token::Insert => {
+                let (stmt, consumed) = InsertStmt::parse(input)?;
+                Ok((Statement::Insert(stmt), consumed))
             }
             Token::Select => {
-                let (stmt, consumed) = SelectStmt::parse(input);
-                (Statement::Select(stmt), consumed)
+                let (stmt, consumed) = SelectStmt::parse(input)?;
+                Ok((Statement::Select(stmt), consumed))
+            }
+            Token::Update => {
+                let (stmt, consumed) = UpdateStmt::parse(input)?;
+                Ok((Statement::Update(stmt), consumed))
             }
-            _ => unimplemented!(),
+            Token::Vacuum => {
+                let mut consumed = 0;
+                // Only the main database can be vacuumed, and naming it changes nothing.
+                match input.peek() {
+                    Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("main") => {
+                        input.next();
+                        consumed += 1;
+                    }
+                    Some(Token::Identifier(name)) => {
+                        return Err(Error::Unsupported(format!("VACUUM of schema {}", name)))
+                    }
+                    Some(Token::Temp | Token::Temporary) => {
+                        return Err(Error::Unsupported("VACUUM of schema temp".to_string()))
+                    }
+                    _ => {}
+                }
+                if input.peek() != Some(&&Token::Into) {
+                    return Ok((Statement::Vacuum(None), consumed));
+                }
+                input.next();
+                match next_token(input)? {
+                    Token::Literal(LiteralKind::Str(path)) => {
+                        Ok((Statement::Vacuum(Some(path.to_string())), consumed + 2))
+                    }
+                    _ => Err(unexpected(input, "expected file name")),
+                }
+            }
+            _ => Err(Error::Unsupported(format!("{:?} statements", token))),
         }
     }
 }
+
+/// Skips the optional TRANSACTION keyword after BEGIN, COMMIT or ROLLBACK.
+fn skip_transaction(input: &mut Peekable<Iter<Token>>) -> usize {
+    match input.peek() {
+        Some(Token::Transaction) => {
+            input.next();
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// Parses `[SAVEPOINT] name`; the keyword is optional after RELEASE and ROLLBACK TO.
+fn savepoint_name(input: &mut Peekable<Iter<Token>>) -> Result<(String, usize)> {
+    let mut consumed = 1;
+    if let Some(Token::Savepoint) = input.peek() {
+        input.next();
+        consumed += 1;
+    }
+    match next_token(input)? {
+        Token::Identifier(name) => Ok((name.to_string(), consumed)),
+        _ => Err(unexpected(input, "expected savepoint name")),
+    }
+}
@@ -22,19 +22,22 @@ fn classify(c: &char) -> CharacterClass {
         | '\u{202F}'
         | '\u{205F}'
         | '\u{3000}' => CharacterClass::Whitespace,
-        '\u{0041}'..='\u{005A}' | '\u{0061}'..='\u{007A}' | '\u{000f}' | '\u{007f}' | '_'.. => {
-            CharacterClass::Alphabetic
-        }
+        '\u{0041}'..='\u{005A}'
+        | '\u{0061}'..='\u{007A}'
+        | '\u{000f}'
+        | '\u{007f}'
+        | '_'
+        | '\u{0080}'.. => CharacterClass::Alphabetic,
         '\u{0030}'..='\u{0039}' => CharacterClass::Numeric,
         _ => CharacterClass::Special,
     }
 }
 
 fn is_alphanumeric(c: &char) -> bool {
-    match classify(c) {
-        CharacterClass::Alphabetic | CharacterClass::Numeric => true,
-        _ => false,
-    }
+    matches!(
+        classify(c),
+        CharacterClass::Alphabetic | CharacterClass::Numeric
+    )
 }
 
 #[derive(Debug, PartialEq)]
@@ -199,6 +202,12 @@ pub fn tokenize(input: &str) -> Vec<Token> {
                         break;
                     }
                 }
+                if ident.eq_ignore_ascii_case("x") && iter.peek() == Some(&'\'') {
+                    iter.next();
+                    let hex = read_quoted(&mut iter, '\'');
+                    tokens.push(Token::Literal(LiteralKind::Blob(hex)));
+                    continue;
+                }
                 tokens.push(match ident.to_lowercase().as_str() {
                     "abort" => Token::Abort,
                     "add" => Token::Add,
@@ -328,86 +337,120 @@ pub fn tokenize(input: &str) -> Vec<Token> {
                 });
             }
             CharacterClass::Numeric => {
-                let mut number = String::new();
-                number.push(c);
-                let mut is_float = false;
-                while let Some(&c) = iter.peek() {
-                    if c.is_digit(10) {
-                        number.push(c);
-                        iter.next();
-                    } else if c == '.' {
-                        if is_float {
+                tokens.push(read_number(&mut iter, c));
+            }
+            CharacterClass::Special => {
+                if c == '-' && iter.peek() == Some(&'-') {
+                    iter.next();
+                    while let Some(&c) = iter.peek() {
+                        if c == '\n' {
                             break;
                         }
-                        is_float = true;
-                        number.push(c);
                         iter.next();
-                    } else {
-                        break;
                     }
-                }
-                if is_float {
-                    tokens.push(Token::Literal(LiteralKind::Real(number)));
-                } else {
-                    tokens.push(Token::Literal(LiteralKind::Integer(number)));
-                }
-            }
-            CharacterClass::Special => {
-                if c == '-' {
-                    if let Some(&next_c) = iter.peek() {
-                        if next_c == '-' {
-                            iter.next();
-                            while let Some(&c) = iter.peek() {
-                                if c == '\n' {
+                    continue;
+                } else if c == '/' && iter.peek() == Some(&'*') {
+                    iter.next();
+                    while let Some(c) = iter.next() {
+                        if c == '*' {
+                            if let Some(&next_c) = iter.peek() {
+                                if next_c == '/' {
+                                    iter.next();
                                     break;
                                 }
-                                iter.next();
-                            }
-                            continue;
-                        }
-                    }
-                } else if c == '/' {
-                    if let Some(&next_c) = iter.peek() {
-                        if next_c == '*' {
-                            iter.next();
-                            while let Some(c) = iter.next() {
-                                if c == '*' {
-                                    if let Some(&next_c) = iter.peek() {
-                                        if next_c == '/' {
-                                            iter.next();
-                                            break;
-                                        }
-                                    }
-                                }
                             }
-                            continue;
                         }
                     }
+                    continue;
                 } else if c == '\'' {
-                    let mut string = String::new();
-                    while let Some(c) = iter.next() {
-                        if c == '\'' {
-                            iter.next();
-                            break;
-                        }
-                        string.push(c);
-                    }
+                    let string = read_quoted(&mut iter, '\'');
                     tokens.push(Token::Literal(LiteralKind::Str(string)));
-                } else if c == '"' {
-                    let mut string = String::new();
-                    while let Some(c) = iter.next() {
-                        if c == '"' {
+                } else if c == '"' || c == '`' {
+                    let string = read_quoted(&mut iter, c);
+                    tokens.push(Token::Identifier(string));
+                } else if c == '[' {
+                    let string = iter.by_ref().take_while(|&c| c != ']').collect();
+                    tokens.push(Token::Identifier(string));
+                } else if c == '.' && iter.peek().is_some_and(char::is_ascii_digit) {
+                    tokens.push(read_number(&mut iter, c));
+                } else {
+                    let mut operator = c.to_string();
+                    // The two-character operators; anything else stands alone.
+                    if let Some(&next) = iter.peek() {
+                        if matches!(
+                            (c, next),
+                            ('|', '|')
+                                | ('<', '=')
+                                | ('<', '>')
+                                | ('<', '<')
+                                | ('>', '=')
+                                | ('>', '>')
+                                | ('!', '=')
+                                | ('=', '=')
+                        ) {
+                            operator.push(next);
                             iter.next();
-                            break;
                         }
-                        string.push(c);
                     }
-                    tokens.push(Token::Identifier(string));
-                } else{
-                    tokens.push(Token::Operator(c.to_string()));
+                    tokens.push(Token::Operator(operator));
                 }
             }
         }
     }
     tokens
 }
+
+/// Reads the rest of a numeric literal that starts with `first`, a digit or a decimal point.
+/// It is real if it has a decimal point or an exponent.
+fn read_number(iter: &mut std::iter::Peekable<std::str::Chars>, first: char) -> Token {
+    let mut number = first.to_string();
+    let mut is_float = first == '.';
+    while let Some(&c) = iter.peek() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            iter.next();
+        } else if c == '.' && !is_float {
+            is_float = true;
+            number.push(c);
+            iter.next();
+        } else if c == 'e' || c == 'E' {
+            // Only an exponent if digits follow, possibly after a sign.
+            let mut ahead = iter.clone();
+            ahead.next();
+            let sign = ahead.next_if(|&c| c == '+' || c == '-');
+            if !ahead.peek().is_some_and(char::is_ascii_digit) {
+                break;
+            }
+            number.push(c);
+            number.extend(sign);
+            *iter = ahead;
+            while let Some(c) = iter.next_if(char::is_ascii_digit) {
+                number.push(c);
+            }
+            is_float = true;
+            break;
+        } else {
+            break;
+        }
+    }
+    if is_float {
+        Token::Literal(LiteralKind::Real(number))
+    } else {
+        Token::Literal(LiteralKind::Integer(number))
+    }
+}
+
+/// Reads up to the closing `quote`, which is escaped inside the string by doubling it.
+fn read_quoted(iter: &mut std::iter::Peekable<std::str::Chars>, quote: char) -> String {
+    let mut string = String::new();
+    while let Some(c) = iter.next() {
+        if c == quote {
+            if iter.peek() != Some(&quote) {
+                break;
+            }
+            iter.next();
+        }
+        string.push(c);
+    }
+    string
+}
@@ -0,0 +1,61 @@
+use std::{iter::Peekable, slice::Iter};
+
+use crate::error::Result;
+
+use super::{
+    expect, expr::Expr, next_token, select::parse_where, tokenizer::Token, unexpected, Parse,
+};
+
+#[derive(Debug, Clone)]
+pub struct UpdateStmt {
+    pub table_name: String,
+    /// Columns to change and the expressions giving their new values, in the order written.
+    pub assignments: Vec<(String, Expr)>,
+    /// The condition a row must meet to be updated; with none, every row is.
+    pub where_clause: Option<Expr>,
+}
+
+impl Parse for UpdateStmt {
+    fn parse(input: &mut Peekable<Iter<Token>>) -> Result<(Self, usize)> {
+        let mut consumed = 0;
+        let table_name = match next_token(input)? {
+            Token::Identifier(name) => name.to_string(),
+            _ => return Err(unexpected(input, "expected table name")),
+        };
+        consumed += 1;
+        expect(input, &Token::Set, "after the table name")?;
+        consumed += 1;
+        let mut assignments = Vec::new();
+        loop {
+            let column = match next_token(input)? {
+                Token::Identifier(name) => name.to_string(),
+                _ => return Err(unexpected(input, "expected column name")),
+            };
+            expect(
+                input,
+                &Token::Operator("=".to_string()),
+                "after column name",
+            )?;
+            let (value, n) = Expr::parse(input)?;
+            assignments.push((column, value));
+            consumed += 2 + n;
+            match input.peek() {
+                Some(Token::Operator(t)) if t == "," => {
+                    input.next();
+                    consumed += 1;
+                }
+                _ => break,
+            }
+        }
+        let (where_clause, where_consumed) = parse_where(input)?;
+        consumed += where_consumed;
+        Ok((
+            UpdateStmt {
+                table_name,
+                assignments,
+                where_clause,
+            },
+            consumed,
+        ))
+    }
+}
@@ -1,4 +1,8 @@
-use crate::utils::decode_varint;
+use crate::error::{Error, Result};
+use crate::page_io::TextEncoding;
+use crate::utils::{decode_varint_checked, encode_varint, format_real};
+use std::cmp::Ordering;
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum TypeCode {
@@ -34,8 +38,16 @@ impl TypeCode {
         }
     }
 
-    pub fn decode(&self, data: &[u8]) -> SqlValue {
-        match self {
+    pub fn decode(&self, data: &[u8], encoding: TextEncoding) -> Result<SqlValue> {
+        if data.len() < self.size() {
+            return Err(Error::Corrupt(format!(
+                "{:?} value needs {} bytes, got {}",
+                self,
+                self.size(),
+                data.len()
+            )));
+        }
+        Ok(match self {
             TypeCode::Null => SqlValue::Null,
             TypeCode::I8 => SqlValue::I8(data[0] as i8),
             TypeCode::I16 => {
@@ -43,7 +55,7 @@ impl TypeCode {
                 SqlValue::I16(val)
             }
             TypeCode::I24 => {
-                let val = i32::from_be_bytes([0, data[0], data[1], data[2]]);
+                let val = i32::from_be_bytes([data[0], data[1], data[2], 0]) >> 8;
                 SqlValue::I24(val)
             }
             TypeCode::I32 => {
@@ -51,7 +63,9 @@ impl TypeCode {
                 SqlValue::I32(val)
             }
             TypeCode::I48 => {
-                let val = i64::from_be_bytes([0, 0, 0, 0, data[0], data[1], data[2], data[3]]);
+                let val = i64::from_be_bytes([
+                    data[0], data[1], data[2], data[3], data[4], data[5], 0, 0,
+                ]) >> 16;
                 SqlValue::I48(val)
             }
             TypeCode::I64 => {
@@ -66,41 +80,36 @@ impl TypeCode {
                 ]);
                 SqlValue::F64(val)
             }
-            TypeCode::Zero => {
-                let val = i32::from_be_bytes([0, 0, 0, 0]);
-                SqlValue::Zero
-            }
-            TypeCode::One => {
-                let val = i32::from_be_bytes([0, 0, 0, 1]);
-                SqlValue::One
-            }
+            TypeCode::Zero => SqlValue::Zero,
+            TypeCode::One => SqlValue::One,
             TypeCode::Blob(size) => {
                 let blob = data[0..*size].to_vec();
                 SqlValue::Blob(blob)
             }
-            TypeCode::Text(size) => {
-                let text = String::from_utf8_lossy(&data[0..*size]).into_owned();
-                SqlValue::Text(text)
-            }
-            _ => {
-                panic!("Not implemented");
-            }
-        }
+            TypeCode::Text(size) => SqlValue::Text(encoding.decode(&data[0..*size])),
+        })
     }
 
-    pub fn parse_str(data: &str) -> TypeCode {
-        match data.to_uppercase().as_str() {
-            "NULL" => TypeCode::Null,
-            "INTEGER" => TypeCode::I64,
-            "REAL" => TypeCode::F64,
-            "TEXT" => TypeCode::Text(0),
-            "BLOB" => TypeCode::Blob(0),
-            _ => panic!("Unknown type code: {}", data),
+    /// Number written for this type in a record header.
+    pub fn serial_type(&self) -> u64 {
+        match self {
+            TypeCode::Null => 0,
+            TypeCode::I8 => 1,
+            TypeCode::I16 => 2,
+            TypeCode::I24 => 3,
+            TypeCode::I32 => 4,
+            TypeCode::I48 => 5,
+            TypeCode::I64 => 6,
+            TypeCode::F64 => 7,
+            TypeCode::Zero => 8,
+            TypeCode::One => 9,
+            TypeCode::Blob(size) => *size as u64 * 2 + 12,
+            TypeCode::Text(size) => *size as u64 * 2 + 13,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SqlValue {
     Null,
     I8(i8),
@@ -116,11 +125,163 @@ pub enum SqlValue {
     Text(String),
 }
 
-pub fn decode_serial_types(data: &[u8]) -> Vec<(TypeCode)> {
+impl SqlValue {
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            SqlValue::I8(val) => Some(val as i64),
+            SqlValue::I16(val) => Some(val as i64),
+            SqlValue::I24(val) => Some(val as i64),
+            SqlValue::I32(val) => Some(val as i64),
+            SqlValue::I48(val) => Some(val),
+            SqlValue::I64(val) => Some(val),
+            SqlValue::Zero => Some(0),
+            SqlValue::One => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Smallest serial type that holds the value. The constant 0 and 1 types only exist from
+    /// schema format 4 on.
+    pub fn type_code(&self, encoding: TextEncoding, schema_format: u32) -> TypeCode {
+        match self {
+            SqlValue::Null => TypeCode::Null,
+            // SQLite never stores a NaN; it becomes NULL.
+            SqlValue::F64(val) if val.is_nan() => TypeCode::Null,
+            SqlValue::F64(_) => TypeCode::F64,
+            SqlValue::Blob(blob) => TypeCode::Blob(blob.len()),
+            SqlValue::Text(text) => TypeCode::Text(encoding.encode(text).len()),
+            int => match int.as_i64().unwrap_or_default() {
+                0 if schema_format >= 4 => TypeCode::Zero,
+                1 if schema_format >= 4 => TypeCode::One,
+                -0x80..=0x7f => TypeCode::I8,
+                -0x8000..=0x7fff => TypeCode::I16,
+                -0x80_0000..=0x7f_ffff => TypeCode::I24,
+                -0x8000_0000..=0x7fff_ffff => TypeCode::I32,
+                -0x8000_0000_0000..=0x7fff_ffff_ffff => TypeCode::I48,
+                _ => TypeCode::I64,
+            },
+        }
+    }
+
+    /// Appends the value's bytes as stored under `type_code`.
+    fn encode(&self, type_code: &TypeCode, encoding: TextEncoding, out: &mut Vec<u8>) {
+        match self {
+            _ if type_code.size() == 0 => {}
+            SqlValue::F64(val) => out.extend_from_slice(&val.to_be_bytes()),
+            SqlValue::Blob(blob) => out.extend_from_slice(blob),
+            SqlValue::Text(text) => out.extend_from_slice(&encoding.encode(text)),
+            int => {
+                let bytes = int.as_i64().unwrap_or_default().to_be_bytes();
+                out.extend_from_slice(&bytes[8 - type_code.size()..]);
+            }
+        }
+    }
+
+    /// Orders values the way SQLite sorts index keys with the BINARY collation: NULLs first,
+    /// then numbers, then text (by its bytes in the database encoding), then blobs.
+    pub fn cmp_storage(&self, other: &SqlValue, encoding: TextEncoding) -> Ordering {
+        let class = |value: &SqlValue| match value {
+            SqlValue::Null => 0,
+            SqlValue::Text(_) => 2,
+            SqlValue::Blob(_) => 3,
+            _ => 1,
+        };
+        match (self, other) {
+            (SqlValue::Text(a), SqlValue::Text(b)) => encoding.compare(a, b),
+            (SqlValue::Blob(a), SqlValue::Blob(b)) => a.cmp(b),
+            (SqlValue::F64(a), SqlValue::F64(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (SqlValue::F64(a), b) => match b.as_i64() {
+                Some(b) => a.partial_cmp(&(b as f64)).unwrap_or(Ordering::Equal),
+                None => class(self).cmp(&class(other)),
+            },
+            (a, SqlValue::F64(b)) => match a.as_i64() {
+                Some(a) => (a as f64).partial_cmp(b).unwrap_or(Ordering::Equal),
+                None => class(self).cmp(&class(other)),
+            },
+            (a, b) => match (a.as_i64(), b.as_i64()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => class(self).cmp(&class(other)),
+            },
+        }
+    }
+}
+
+/// Shows the value the way the sqlite3 shell prints it.
+impl fmt::Display for SqlValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SqlValue::Null => Ok(()),
+            SqlValue::F64(n) => f.write_str(&format_real(*n)),
+            SqlValue::Text(text) => f.write_str(text),
+            SqlValue::Blob(blob) => f.write_str(&String::from_utf8_lossy(blob)),
+            int => write!(f, "{}", int.as_i64().unwrap_or_default()),
+        }
+    }
+}
+
+/// Compares two records value by value, as SQLite does for index b-tree keys.
+pub fn compare_records(a: &[SqlValue], b: &[SqlValue], encoding: TextEncoding) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        match a.cmp_storage(b, encoding) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Serializes values into a record: a header of serial types, prefixed by its own length,
+/// followed by the values themselves.
+pub fn encode_record(values: &[SqlValue], encoding: TextEncoding, schema_format: u32) -> Vec<u8> {
+    let type_codes: Vec<TypeCode> = values
+        .iter()
+        .map(|v| v.type_code(encoding, schema_format))
+        .collect();
+    let mut serial_types = Vec::new();
+    for type_code in &type_codes {
+        serial_types.extend(encode_varint(type_code.serial_type()));
+    }
+    // The header length counts the varint that holds it.
+    let mut header_len = serial_types.len() + 1;
+    while encode_varint(header_len as u64).len() + serial_types.len() > header_len {
+        header_len += 1;
+    }
+    let mut record = encode_varint(header_len as u64);
+    record.extend(serial_types);
+    for (value, type_code) in values.iter().zip(&type_codes) {
+        value.encode(type_code, encoding, &mut record);
+    }
+    record
+}
+
+/// Splits a record back into its values; the inverse of [`encode_record`].
+pub fn decode_record(payload: &[u8], encoding: TextEncoding) -> Result<Vec<SqlValue>> {
+    let corrupt = |reason: &str| Error::Corrupt(reason.to_string());
+    let (header_len, header_len_size) =
+        decode_varint_checked(payload).ok_or_else(|| corrupt("truncated record header length"))?;
+    let header = usize::try_from(header_len)
+        .ok()
+        .and_then(|end| payload.get(header_len_size..end))
+        .ok_or_else(|| corrupt("record header runs past the end of the payload"))?;
+    let mut offset = header_len as usize;
+    let mut values = Vec::new();
+    for type_code in decode_serial_types(header)? {
+        let size = type_code.size();
+        let data = payload
+            .get(offset..offset.saturating_add(size))
+            .ok_or_else(|| corrupt("record value runs past the end of the payload"))?;
+        offset += size;
+        values.push(type_code.decode(data, encoding)?);
+    }
+    Ok(values)
+}
+
+pub fn decode_serial_types(data: &[u8]) -> Result<Vec<TypeCode>> {
     let mut i = 0;
     let mut serial_types = Vec::new();
     while i < data.len() {
-        let (serial_type_code, bytes_for_serial_type) = decode_varint(&data[i..]);
+        let (serial_type_code, bytes_for_serial_type) = decode_varint_checked(&data[i..])
+            .ok_or_else(|| Error::Corrupt("truncated serial type in record header".to_string()))?;
         i += bytes_for_serial_type;
         match serial_type_code {
             0x00 => {
@@ -138,19 +299,19 @@ pub fn decode_serial_types(data: &[u8]) -> Vec<(TypeCode)> {
             0x04 => {
                 serial_types.push(TypeCode::I32);
             }
-            0x06 => {
+            0x05 => {
                 serial_types.push(TypeCode::I48);
             }
-            0x07 => {
+            0x06 => {
                 serial_types.push(TypeCode::I64);
             }
-            0x08 => {
+            0x07 => {
                 serial_types.push(TypeCode::F64);
             }
-            0x09 => {
+            0x08 => {
                 serial_types.push(TypeCode::Zero);
             }
-            0x0a => {
+            0x09 => {
                 serial_types.push(TypeCode::One);
             }
             n if n >= 12 && n % 2 == 0 => {
@@ -162,9 +323,9 @@ pub fn decode_serial_types(data: &[u8]) -> Vec<(TypeCode)> {
                 serial_types.push(TypeCode::Text(size as usize));
             }
             _ => {
-                panic!("Unknown serial type code: {}", serial_type_code);
+                return Err(Error::BadSerialType(serial_type_code));
             }
         }
     }
-    serial_types
+    Ok(serial_types)
 }
@@ -7,10 +7,74 @@ pub fn decode_varint(data: &[u8]) -> (u64, usize) {
             result = result << 8 | *byte as i64;
             break;
         }
-        result = (result << 7 | (*byte & 0b0111_1111) as i64);
+        result = result << 7 | (*byte & 0b0111_1111) as i64;
         if *byte < 0b1000_0000 {
             break;
         }
     }
     (result as u64, bytes_read)
 }
+
+/// Like [`decode_varint`], but returns `None` if `data` ends before the varint does.
+pub fn decode_varint_checked(data: &[u8]) -> Option<(u64, usize)> {
+    let (value, bytes_read) = decode_varint(data);
+    let complete = bytes_read == 9 || data.get(bytes_read.wrapping_sub(1))? & 0x80 == 0;
+    complete.then_some((value, bytes_read))
+}
+
+/// Encodes `value` as a SQLite varint: big-endian groups of seven bits with the high bit set on
+/// every byte but the last, except that a ninth byte carries a full eight bits.
+pub fn encode_varint(value: u64) -> Vec<u8> {
+    if value > 0x00ff_ffff_ffff_ffff {
+        let mut bytes = vec![0; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest as u8 & 0x7f) | 0x80;
+            rest >>= 7;
+        }
+        return bytes;
+    }
+    let mut bytes = Vec::with_capacity(9);
+    let mut rest = value;
+    loop {
+        bytes.push((rest as u8 & 0x7f) | 0x80);
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    bytes[0] &= 0x7f;
+    bytes.reverse();
+    bytes
+}
+
+/// Writes a real the way SQLite turns one into text: 15 significant digits, in exponent form
+/// below 1e-4 or from 1e15 on, and always with a decimal point, as in `1.0` or `1.0e+20`.
+pub fn format_real(n: f64) -> String {
+    if n.is_infinite() {
+        return if n > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if n == 0.0 {
+        return "0.0".to_string();
+    }
+    let scientific = format!("{:.14e}", n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    let with_point = |digits: &str| {
+        if !digits.contains('.') {
+            return format!("{}.0", digits);
+        }
+        let digits = digits.trim_end_matches('0');
+        match digits.strip_suffix('.') {
+            Some(whole) => format!("{}.0", whole),
+            None => digits.to_string(),
+        }
+    };
+    if !(-4..15).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", with_point(mantissa), sign, exponent.abs())
+    } else {
+        with_point(&format!("{:.*}", (14 - exponent) as usize, n))
+    }
+}
@@ -0,0 +1,932 @@
+use crate::error::{Error, Result};
+use crate::lock::{self, LockKind};
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{prelude::*, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Magic number for a WAL whose checksums are computed over little-endian words; the big-endian
+/// variant has the low bit set.
+const WAL_MAGIC: u32 = 0x377f0682;
+const WAL_FORMAT_VERSION: u32 = 3007000;
+pub const WAL_HEADER_SIZE: usize = 32;
+pub const WAL_FRAME_HEADER_SIZE: usize = 24;
+
+/// The wal-index is made of 32KiB blocks, each a page-number array with one entry per frame
+/// followed by a hash table over those entries. The first block also holds the index header,
+/// so it has room for fewer frames.
+const WAL_INDEX_BLOCK_SIZE: usize = 32768;
+const WAL_INDEX_HEADER_SIZE: usize = 136;
+const HASH_PAGE_COUNT: usize = 4096;
+const HASH_PAGE_COUNT_FIRST: usize = HASH_PAGE_COUNT - WAL_INDEX_HEADER_SIZE / 4;
+const HASH_SLOT_COUNT: usize = 8192;
+/// Offsets of the checkpoint information that follows the two copies of the index header.
+const BACKFILL_OFFSET: usize = 96;
+const READ_MARK_OFFSET: usize = 100;
+const BACKFILL_ATTEMPTED_OFFSET: usize = 128;
+/// Read marks that no reader has claimed.
+const READ_MARK_UNUSED: u32 = 0xffffffff;
+
+/// The wal-index locks are single bytes of the `-shm` file from this offset on.
+const SHM_LOCK_OFFSET: u64 = 120;
+const WRITE_LOCK: u64 = 0;
+const CHECKPOINT_LOCK: u64 = 1;
+/// Readers hold a read lock on one of five read-mark slots while they use the log. Slot 0
+/// means the reader ignores the log, and the others hold the last frame the reader uses.
+const READ_LOCK_FIRST: u64 = 3;
+const READER_COUNT: usize = 5;
+/// Held shared by every connection using the wal-index. A connection that gets it exclusively
+/// is the only one, and rebuilds the index.
+const DMS_LOCK: u64 = 8;
+
+/// Path of the write-ahead log that belongs to a database file.
+pub fn wal_path<P: AsRef<Path>>(db_path: P) -> PathBuf {
+    let mut path = OsString::from(db_path.as_ref());
+    path.push("-wal");
+    PathBuf::from(path)
+}
+
+/// Path of the shared-memory wal-index that connections to a database in WAL mode use to find
+/// frames in the log without reading all of it.
+pub fn shm_path<P: AsRef<Path>>(db_path: P) -> PathBuf {
+    let mut path = OsString::from(db_path.as_ref());
+    path.push("-shm");
+    PathBuf::from(path)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    Passive,
+    Full,
+    Restart,
+    /// Also empties the log file once everything in it has been copied.
+    Truncate,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalHeader {
+    pub magic: u32,
+    pub format_version: u32,
+    pub page_size: u32,
+    pub checkpoint_sequence: u32,
+    pub salt: [u32; 2],
+    pub checksum: [u32; 2],
+}
+
+impl WalHeader {
+    pub fn from_bytes(data: &[u8; WAL_HEADER_SIZE]) -> Result<WalHeader> {
+        let be_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        let magic = be_u32(0);
+        if magic & !1 != WAL_MAGIC {
+            return Err(Error::Corrupt("bad WAL magic number".to_string()));
+        }
+        let format_version = be_u32(4);
+        if format_version != WAL_FORMAT_VERSION {
+            return Err(Error::Unsupported(format!(
+                "WAL format version {}",
+                format_version
+            )));
+        }
+        let header = WalHeader {
+            magic,
+            format_version,
+            page_size: match be_u32(8) {
+                1 => 65536,
+                n => n,
+            },
+            checkpoint_sequence: be_u32(12),
+            salt: [be_u32(16), be_u32(20)],
+            checksum: [be_u32(24), be_u32(28)],
+        };
+        if !(512..=65536).contains(&header.page_size) || !header.page_size.is_power_of_two() {
+            return Err(Error::Corrupt(format!(
+                "invalid WAL page size {}",
+                header.page_size
+            )));
+        }
+        if wal_checksum(header.big_endian(), [0, 0], &data[..24]) != header.checksum {
+            return Err(Error::Corrupt("WAL header checksum mismatch".to_string()));
+        }
+        Ok(header)
+    }
+
+    /// A header for a new log, checksummed in this machine's byte order as SQLite does.
+    pub fn new(page_size: u32, checkpoint_sequence: u32, salt: [u32; 2]) -> WalHeader {
+        let mut header = WalHeader {
+            magic: WAL_MAGIC | cfg!(target_endian = "big") as u32,
+            format_version: WAL_FORMAT_VERSION,
+            page_size,
+            checkpoint_sequence,
+            salt,
+            checksum: [0, 0],
+        };
+        header.checksum = wal_checksum(header.big_endian(), [0, 0], &header.to_bytes()[..24]);
+        header
+    }
+
+    pub fn to_bytes(&self) -> [u8; WAL_HEADER_SIZE] {
+        let mut data = [0; WAL_HEADER_SIZE];
+        for (i, field) in [
+            self.magic,
+            self.format_version,
+            self.page_size,
+            self.checkpoint_sequence,
+            self.salt[0],
+            self.salt[1],
+            self.checksum[0],
+            self.checksum[1],
+        ]
+        .iter()
+        .enumerate()
+        {
+            data[i * 4..i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+        }
+        data
+    }
+
+    /// Whether checksums are computed over big-endian words.
+    pub fn big_endian(&self) -> bool {
+        self.magic & 1 == 1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalFrameHeader {
+    pub page_number: u32,
+    /// Size of the database in pages after this frame, or 0 if it does not end a transaction.
+    pub db_size: u32,
+    pub salt: [u32; 2],
+    pub checksum: [u32; 2],
+}
+
+impl WalFrameHeader {
+    pub fn from_bytes(data: &[u8]) -> WalFrameHeader {
+        let be_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        WalFrameHeader {
+            page_number: be_u32(0),
+            db_size: be_u32(4),
+            salt: [be_u32(8), be_u32(12)],
+            checksum: [be_u32(16), be_u32(20)],
+        }
+    }
+
+    pub fn is_commit(&self) -> bool {
+        self.db_size != 0
+    }
+}
+
+/// Outcome of a checkpoint, as PRAGMA wal_checkpoint reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Whether another connection stopped the checkpoint from finishing.
+    pub busy: bool,
+    /// Number of frames in the log, or -1 if the checkpoint could not start.
+    pub log_frames: i64,
+    /// Number of those frames copied into the database file, or -1.
+    pub checkpointed: i64,
+}
+
+/// The wal-index of a log in use, held open for as long as the log is: POSIX locks belong to
+/// the process, and closing any handle on a file drops all of them.
+struct Shm {
+    db_path: PathBuf,
+    file: File,
+    busy_timeout: Duration,
+    /// The read-mark slot this connection holds a read lock on.
+    read_slot: Option<usize>,
+}
+
+impl Shm {
+    fn open(db_path: &Path, busy_timeout: Duration) -> Result<Shm> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(shm_path(db_path))?;
+        Ok(Shm {
+            db_path: db_path.to_path_buf(),
+            file,
+            busy_timeout,
+            read_slot: None,
+        })
+    }
+
+    /// Takes `count` of the wal-index locks starting at `first`, waiting up to the busy
+    /// timeout if `wait` is set. Returns false if another connection held on to them.
+    fn lock(&self, kind: LockKind, first: u64, count: u64, wait: bool) -> Result<bool> {
+        let start = SHM_LOCK_OFFSET + first;
+        if !wait {
+            return lock::try_lock(&self.file, kind, start, count);
+        }
+        match lock::lock(&self.file, kind, start, count, self.busy_timeout) {
+            Ok(()) => Ok(true),
+            Err(Error::Busy) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn unlock(&self, first: u64, count: u64) -> Result<()> {
+        lock::try_lock(&self.file, LockKind::Unlock, SHM_LOCK_OFFSET + first, count)?;
+        Ok(())
+    }
+
+    /// The index header, if both copies agree and the checksum matches.
+    fn header(&self) -> Result<Option<[u8; 48]>> {
+        let mut data = [0; 96];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))?;
+        if file.read_exact(&mut data).is_err() {
+            return Ok(None);
+        }
+        let checksum = wal_checksum(cfg!(target_endian = "big"), [0, 0], &data[..40]);
+        let stored = [ne_u32(&data, 40), ne_u32(&data, 44)];
+        let valid = data[..48] == data[48..] && data[12] == 1 && checksum == stored;
+        Ok(valid.then(|| data[..48].try_into().unwrap()))
+    }
+
+    fn read_u32(&self, offset: usize) -> Result<u32> {
+        let mut data = [0; 4];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        Ok(match file.read_exact(&mut data) {
+            Ok(()) => u32::from_ne_bytes(data),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => 0,
+            Err(e) => return Err(e.into()),
+        })
+    }
+
+    fn write(&self, offset: usize, data: &[u8]) -> Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    fn read_marks(&self) -> Result<[u32; READER_COUNT]> {
+        let mut marks = [0; READER_COUNT];
+        for (i, mark) in marks.iter_mut().enumerate() {
+            *mark = self.read_u32(READ_MARK_OFFSET + i * 4)?;
+        }
+        Ok(marks)
+    }
+
+    /// Checkpoint progress and read marks for a log that has just started over.
+    fn reset_checkpoint_info(&self) -> Result<()> {
+        self.write(BACKFILL_OFFSET, &checkpoint_info(0, 0))
+    }
+}
+
+/// The wal-index's checkpoint information: how many frames have been copied into the
+/// database, and the read marks, with slot 1 marking `max_frame` and the rest unused.
+fn checkpoint_info(backfilled: usize, max_frame: usize) -> [u8; 40] {
+    let mut data = [0; 40];
+    data[..4].copy_from_slice(&(backfilled as u32).to_ne_bytes());
+    for i in 1..READER_COUNT {
+        let mark = match i {
+            1 => max_frame as u32,
+            _ => READ_MARK_UNUSED,
+        };
+        data[4 + i * 4..8 + i * 4].copy_from_slice(&mark.to_ne_bytes());
+    }
+    data[32..36].copy_from_slice(&(backfilled as u32).to_ne_bytes());
+    data
+}
+
+fn ne_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// The committed contents of a write-ahead log: for every page, its most recent version from a
+/// transaction that finished. Frames after the last commit, or after the first frame whose salt
+/// or checksum does not match, are ignored just as SQLite ignores them.
+///
+/// A log opened with [`Wal::attach`] is shared with other connections through the `-shm`
+/// wal-index, following SQLite's protocol: commits append frames and update the index under
+/// its write lock, and a read lock on a read mark keeps checkpoints from overwriting pages
+/// this snapshot still reads from the database file.
+pub struct Wal {
+    pub header: WalHeader,
+    /// Number of valid frames up to and including the last commit.
+    pub max_frame: usize,
+    /// Number of frames a checkpoint has copied into the database file.
+    pub backfilled: usize,
+    db_size: u32,
+    pages: HashMap<u32, Bytes>,
+    /// Page number of each committed frame, in log order.
+    frames: Vec<u32>,
+    /// Checksum of the last committed frame, which the next frame's checksum continues from.
+    checksum: [u32; 2],
+    /// Bumped on every change to the wal-index so readers notice it.
+    change_count: u32,
+    shm: Option<Shm>,
+}
+
+impl Wal {
+    /// Reads the WAL next to `db_path`. A missing or empty log, or one that does not start with
+    /// a valid header, holds no committed frames and yields None.
+    pub fn open<P: AsRef<Path>>(db_path: P) -> Result<Option<Wal>> {
+        let mut file = match File::open(wal_path(db_path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Wal::from_bytes(Bytes::from(data)))
+    }
+
+    /// Opens the log next to `db_path` for sharing with other connections. Joins its
+    /// wal-index, rebuilding it if no one else is using it, and takes a read lock on a
+    /// snapshot of the log, waiting up to `busy_timeout` for one to be available.
+    pub fn attach(db_path: &Path, page_size: u32, busy_timeout: Duration) -> Result<Wal> {
+        let mut wal = Wal::new(page_size);
+        wal.shm = Some(Shm::open(db_path, busy_timeout)?);
+        if wal.shm()?.lock(LockKind::Write, DMS_LOCK, 1, false)? {
+            wal.reload()?;
+            wal.write_index(true)?;
+            wal.shm()?.lock(LockKind::Read, DMS_LOCK, 1, false)?;
+        } else if !wal.shm()?.lock(LockKind::Read, DMS_LOCK, 1, true)? {
+            return Err(Error::Busy);
+        }
+        lock::retry(busy_timeout, || {
+            wal.reload()?;
+            wal.lock_snapshot()
+        })?;
+        Ok(wal)
+    }
+
+    pub fn from_bytes(data: Bytes) -> Option<Wal> {
+        let header = data
+            .get(..WAL_HEADER_SIZE)
+            .and_then(|h| WalHeader::from_bytes(h.try_into().unwrap()).ok())?;
+        let page_size = header.page_size;
+        let frame_size = WAL_FRAME_HEADER_SIZE + page_size as usize;
+        let mut wal = Wal::empty(header);
+        let mut checksum = wal.header.checksum;
+        let mut uncommitted = Vec::new();
+        let mut offset = WAL_HEADER_SIZE;
+        while offset + frame_size <= data.len() {
+            let frame = &data[offset..offset + frame_size];
+            let frame_header = WalFrameHeader::from_bytes(frame);
+            if frame_header.salt != wal.header.salt || frame_header.page_number == 0 {
+                break;
+            }
+            // The checksum chains over each frame header's first eight bytes and its page.
+            let big_endian = wal.header.big_endian();
+            checksum = wal_checksum(big_endian, checksum, &frame[..8]);
+            checksum = wal_checksum(big_endian, checksum, &frame[WAL_FRAME_HEADER_SIZE..]);
+            if checksum != frame_header.checksum {
+                break;
+            }
+            let page_start = offset + WAL_FRAME_HEADER_SIZE;
+            uncommitted.push((
+                frame_header.page_number,
+                data.slice(page_start..page_start + page_size as usize),
+            ));
+            offset += frame_size;
+            if frame_header.is_commit() {
+                for (page_number, page) in uncommitted.drain(..) {
+                    wal.frames.push(page_number);
+                    wal.pages.insert(page_number, page);
+                }
+                wal.max_frame = wal.frames.len();
+                wal.db_size = frame_header.db_size;
+                wal.checksum = checksum;
+            }
+        }
+        Some(wal)
+    }
+
+    /// An empty log for a database with the given page size. Nothing is written until the
+    /// first commit.
+    pub fn new(page_size: u32) -> Wal {
+        Wal::empty(WalHeader::new(page_size, 0, [random_u32(), random_u32()]))
+    }
+
+    fn empty(header: WalHeader) -> Wal {
+        Wal {
+            checksum: header.checksum,
+            header,
+            max_frame: 0,
+            backfilled: 0,
+            db_size: 0,
+            pages: HashMap::new(),
+            frames: Vec::new(),
+            change_count: 0,
+            shm: None,
+        }
+    }
+
+    fn shm(&self) -> Result<&Shm> {
+        self.shm
+            .as_ref()
+            .ok_or_else(|| Error::Unsupported("a log without its wal-index".to_string()))
+    }
+
+    /// Starts a read transaction on the latest snapshot, unless one is already open. Returns
+    /// whether the log has changed since this connection last read it.
+    pub fn begin_read(&mut self) -> Result<bool> {
+        let Some(shm) = &self.shm else {
+            return Ok(false);
+        };
+        if shm.read_slot.is_some() {
+            return Ok(false);
+        }
+        let busy_timeout = shm.busy_timeout;
+        let before = (self.header.salt, self.max_frame, self.checksum);
+        lock::retry(busy_timeout, || {
+            // The log only needs reading again if another connection has written to it.
+            if !self
+                .shm()?
+                .header()?
+                .is_some_and(|header| self.is_current(&header))
+            {
+                self.reload()?;
+            }
+            self.lock_snapshot()
+        })?;
+        Ok((self.header.salt, self.max_frame, self.checksum) != before)
+    }
+
+    /// Ends the read transaction, so that checkpoints can go past this snapshot.
+    pub fn end_read(&mut self) -> Result<()> {
+        self.unlock_snapshot()
+    }
+
+    /// Reads the log file again, and how far checkpoints have got from the wal-index.
+    fn reload(&mut self) -> Result<()> {
+        let shm = self.shm.take();
+        let db_path = shm
+            .as_ref()
+            .map(|shm| shm.db_path.clone())
+            .unwrap_or_default();
+        let page_size = self.header.page_size;
+        *self = Wal::open(&db_path)?
+            .filter(|wal| wal.header.page_size == page_size)
+            .unwrap_or_else(|| Wal::new(page_size));
+        self.shm = shm;
+        let Some(header) = self.shm()?.header()? else {
+            return Ok(());
+        };
+        if self.max_frame == 0 && ne_u32(&header, 16) == 0 {
+            // An empty log carries on with the salts the index has for it, which the next
+            // writer puts in the log header.
+            let salt = [
+                u32::from_be_bytes(header[32..36].try_into().unwrap()),
+                u32::from_be_bytes(header[36..40].try_into().unwrap()),
+            ];
+            self.header = WalHeader::new(page_size, self.header.checkpoint_sequence, salt);
+            self.checksum = self.header.checksum;
+        }
+        if self.is_current(&header) {
+            self.change_count = ne_u32(&header, 8);
+            self.backfilled = (self.shm()?.read_u32(BACKFILL_OFFSET)? as usize).min(self.max_frame);
+        }
+        Ok(())
+    }
+
+    /// Whether a wal-index header describes the same log and last commit as this snapshot.
+    fn is_current(&self, header: &[u8; 48]) -> bool {
+        let ours = self.index_header();
+        match self.max_frame {
+            // The frame checksum is left over from before the log last started over.
+            0 => header[16..20] == ours[16..20] && header[32..40] == ours[32..40],
+            _ => header[16..40] == ours[16..40],
+        }
+    }
+
+    /// Takes a read lock on a read mark that covers this snapshot, claiming a slot for it if
+    /// none does, and checks that the snapshot is still usable. Returns false if it is not,
+    /// or no slot could be had, so that the caller can reload and try again.
+    fn lock_snapshot(&mut self) -> Result<bool> {
+        self.unlock_snapshot()?;
+        let shm = self.shm()?;
+        let max_frame = self.max_frame as u32;
+        let slot = if self.backfilled == self.max_frame {
+            // Everything in the log is in the database file too.
+            Some(0)
+        } else {
+            let marks = shm.read_marks()?;
+            let mut slot = (1..READER_COUNT).find(|&i| marks[i] == max_frame);
+            if slot.is_none() {
+                for i in 1..READER_COUNT {
+                    if shm.lock(LockKind::Write, READ_LOCK_FIRST + i as u64, 1, false)? {
+                        shm.write(READ_MARK_OFFSET + i * 4, &max_frame.to_ne_bytes())?;
+                        slot = Some(i);
+                        break;
+                    }
+                }
+            }
+            // Failing that, any mark short of the snapshot keeps checkpoints short of it too.
+            slot.or_else(|| {
+                (1..READER_COUNT)
+                    .filter(|&i| marks[i] <= max_frame)
+                    .max_by_key(|&i| marks[i])
+            })
+        };
+        let Some(slot) = slot else {
+            return Ok(false);
+        };
+        if !shm.lock(LockKind::Read, READ_LOCK_FIRST + slot as u64, 1, false)? {
+            return Ok(false);
+        }
+        // The log may have started over, or a checkpoint gone past this snapshot, before the
+        // lock was granted.
+        let mark = shm.read_u32(READ_MARK_OFFSET + slot * 4)?;
+        let backfilled = shm.read_u32(BACKFILL_OFFSET)?;
+        let salts_match = shm
+            .header()?
+            .is_some_and(|header| header[32..40] == self.header.to_bytes()[16..24]);
+        let usable = salts_match && backfilled <= max_frame && (slot == 0 || mark <= max_frame);
+        if !usable {
+            shm.unlock(READ_LOCK_FIRST + slot as u64, 1)?;
+            return Ok(false);
+        }
+        self.shm.as_mut().unwrap().read_slot = Some(slot);
+        Ok(true)
+    }
+
+    fn unlock_snapshot(&mut self) -> Result<()> {
+        if let Some(shm) = self.shm.as_mut() {
+            if let Some(slot) = shm.read_slot.take() {
+                shm.unlock(READ_LOCK_FIRST + slot as u64, 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a transaction's pages to the log, the last one as the commit frame carrying the
+    /// new database size, syncs the log and updates the wal-index. Once a checkpoint has
+    /// copied the whole log into the database and no reader is using it, the log starts over
+    /// from the beginning instead of growing. Fails with [`Error::Busy`] if another connection
+    /// is writing, or has committed since this snapshot was taken.
+    pub fn commit(&mut self, pages: &BTreeMap<u64, Bytes>, db_size: u32) -> Result<()> {
+        if !self.shm()?.lock(LockKind::Write, WRITE_LOCK, 1, true)? {
+            return Err(Error::Busy);
+        }
+        let result = self.append(pages, db_size);
+        self.shm()?.unlock(WRITE_LOCK, 1)?;
+        result
+    }
+
+    fn append(&mut self, pages: &BTreeMap<u64, Bytes>, db_size: u32) -> Result<()> {
+        match self.shm()?.header()? {
+            Some(header) if self.is_current(&header) => {}
+            _ => return Err(Error::Busy),
+        }
+        self.backfilled = (self.shm()?.read_u32(BACKFILL_OFFSET)? as usize).min(self.max_frame);
+        if self.max_frame > 0 && self.backfilled == self.max_frame {
+            self.unlock_snapshot()?;
+            let readers = READER_COUNT as u64 - 1;
+            if self
+                .shm()?
+                .lock(LockKind::Write, READ_LOCK_FIRST + 1, readers, false)?
+            {
+                self.restart();
+                self.shm()?.reset_checkpoint_info()?;
+                self.shm()?.unlock(READ_LOCK_FIRST + 1, readers)?;
+            }
+        }
+        let big_endian = self.header.big_endian();
+        let mut data = Vec::new();
+        if self.max_frame == 0 {
+            data.extend_from_slice(&self.header.to_bytes());
+        }
+        let mut checksum = self.checksum;
+        for (i, (&page_number, page)) in pages.iter().enumerate() {
+            let commit_size = if i + 1 == pages.len() { db_size } else { 0 };
+            let mut frame_header = [0; WAL_FRAME_HEADER_SIZE];
+            for (j, field) in [
+                page_number as u32,
+                commit_size,
+                self.header.salt[0],
+                self.header.salt[1],
+            ]
+            .iter()
+            .enumerate()
+            {
+                frame_header[j * 4..j * 4 + 4].copy_from_slice(&field.to_be_bytes());
+            }
+            checksum = wal_checksum(big_endian, checksum, &frame_header[..8]);
+            checksum = wal_checksum(big_endian, checksum, page);
+            frame_header[16..20].copy_from_slice(&checksum[0].to_be_bytes());
+            frame_header[20..24].copy_from_slice(&checksum[1].to_be_bytes());
+            data.extend_from_slice(&frame_header);
+            data.extend_from_slice(page);
+        }
+        let offset = match self.max_frame {
+            0 => 0,
+            n => WAL_HEADER_SIZE + n * (WAL_FRAME_HEADER_SIZE + self.header.page_size as usize),
+        };
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(wal_path(&self.shm()?.db_path))?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+
+        for (&page_number, page) in pages {
+            self.frames.push(page_number as u32);
+            self.pages.insert(page_number as u32, page.clone());
+        }
+        self.max_frame = self.frames.len();
+        self.db_size = db_size;
+        self.checksum = checksum;
+        self.write_index(false)?;
+        // This connection now reads what it has just written.
+        self.lock_snapshot()?;
+        Ok(())
+    }
+
+    /// Copies the latest committed version of every page into the database file, by handing
+    /// them and the database size in pages to `write_pages`. The checkpoint only goes ahead if
+    /// this snapshot has the latest commit and no reader needs an older one. PASSIVE gives up
+    /// straight away if another connection is in the way; the other modes wait up to the busy
+    /// timeout, and RESTART and TRUNCATE also wait for readers to finish with the log so that
+    /// the next commit can start it over. TRUNCATE starts it over straight away and empties
+    /// the file.
+    pub fn checkpoint(
+        &mut self,
+        mode: CheckpointMode,
+        write_pages: impl FnOnce(&BTreeMap<u64, Bytes>, u64) -> Result<()>,
+    ) -> Result<Checkpoint> {
+        if !self
+            .shm()?
+            .lock(LockKind::Write, CHECKPOINT_LOCK, 1, false)?
+        {
+            return Ok(Checkpoint {
+                busy: true,
+                log_frames: -1,
+                checkpointed: -1,
+            });
+        }
+        // Only PASSIVE lets writers carry on while it runs, and the others fall back to it if
+        // a writer does not finish in time.
+        let wait = mode != CheckpointMode::Passive;
+        let writing = wait && self.shm()?.lock(LockKind::Write, WRITE_LOCK, 1, true)?;
+        let result = self.backfill(mode, wait, write_pages);
+        if writing {
+            self.shm()?.unlock(WRITE_LOCK, 1)?;
+        }
+        self.shm()?.unlock(CHECKPOINT_LOCK, 1)?;
+        result
+    }
+
+    fn backfill(
+        &mut self,
+        mode: CheckpointMode,
+        wait: bool,
+        write_pages: impl FnOnce(&BTreeMap<u64, Bytes>, u64) -> Result<()>,
+    ) -> Result<Checkpoint> {
+        let busy = |wal: &Wal| Checkpoint {
+            busy: true,
+            log_frames: wal.max_frame as i64,
+            checkpointed: wal.backfilled as i64,
+        };
+        match self.shm()?.header()? {
+            Some(header) if self.is_current(&header) => {}
+            _ => {
+                return Ok(Checkpoint {
+                    busy: true,
+                    log_frames: -1,
+                    checkpointed: -1,
+                })
+            }
+        }
+        self.backfilled = (self.shm()?.read_u32(BACKFILL_OFFSET)? as usize).min(self.max_frame);
+        // Readers of older snapshots hold read locks on marks short of the end of the log.
+        // Marks no one holds are moved up out of the way.
+        let shm = self.shm()?;
+        let marks = shm.read_marks()?;
+        for (i, &mark) in marks.iter().enumerate().skip(1) {
+            if shm.read_slot == Some(i) || mark >= self.max_frame as u32 {
+                continue;
+            }
+            if !shm.lock(LockKind::Write, READ_LOCK_FIRST + i as u64, 1, wait)? {
+                return Ok(busy(self));
+            }
+            let mark = match i {
+                1 => self.max_frame as u32,
+                _ => READ_MARK_UNUSED,
+            };
+            shm.write(READ_MARK_OFFSET + i * 4, &mark.to_ne_bytes())?;
+            shm.unlock(READ_LOCK_FIRST + i as u64, 1)?;
+        }
+        if self.backfilled < self.max_frame {
+            // Readers that ignore the log hold slot 0, and would see the copied pages.
+            if !shm.lock(LockKind::Write, READ_LOCK_FIRST, 1, wait)? {
+                return Ok(busy(self));
+            }
+            let pages = self
+                .pages
+                .iter()
+                .filter(|(&page_number, _)| page_number <= self.db_size)
+                .map(|(&page_number, page)| (page_number as u64, page.clone()))
+                .collect();
+            let written = write_pages(&pages, self.db_size as u64).and_then(|()| {
+                let backfilled = (self.max_frame as u32).to_ne_bytes();
+                shm.write(BACKFILL_OFFSET, &backfilled)?;
+                shm.write(BACKFILL_ATTEMPTED_OFFSET, &backfilled)
+            });
+            shm.unlock(READ_LOCK_FIRST, 1)?;
+            written?;
+            self.backfilled = self.max_frame;
+        }
+        if mode == CheckpointMode::Restart || mode == CheckpointMode::Truncate {
+            self.unlock_snapshot()?;
+            let readers = READER_COUNT as u64 - 1;
+            if !self
+                .shm()?
+                .lock(LockKind::Write, READ_LOCK_FIRST + 1, readers, true)?
+            {
+                self.lock_snapshot()?;
+                return Ok(busy(self));
+            }
+            let truncated = if mode == CheckpointMode::Truncate {
+                self.truncate()
+            } else {
+                Ok(())
+            };
+            self.shm()?.unlock(READ_LOCK_FIRST + 1, readers)?;
+            truncated?;
+            self.lock_snapshot()?;
+        }
+        Ok(Checkpoint {
+            busy: false,
+            log_frames: self.max_frame as i64,
+            checkpointed: self.backfilled as i64,
+        })
+    }
+
+    /// Starts the log over and empties its file.
+    fn truncate(&mut self) -> Result<()> {
+        self.restart();
+        self.shm()?.reset_checkpoint_info()?;
+        self.write_index(false)?;
+        match OpenOptions::new()
+            .write(true)
+            .open(wal_path(&self.shm()?.db_path))
+        {
+            Ok(file) => {
+                file.set_len(0)?;
+                file.sync_all()?;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Empties the log so the next commit overwrites it from the start. The new salts make
+    /// whatever frames are left in the file invalid.
+    fn restart(&mut self) {
+        let salt = [self.header.salt[0].wrapping_add(1), random_u32()];
+        let sequence = self.header.checkpoint_sequence.wrapping_add(1);
+        let change_count = self.change_count;
+        let shm = self.shm.take();
+        *self = Wal::empty(WalHeader::new(self.header.page_size, sequence, salt));
+        self.change_count = change_count;
+        self.shm = shm;
+    }
+
+    /// The wal-index header describing the log's current state.
+    fn index_header(&self) -> [u8; 48] {
+        let mut header = [0; 48];
+        let mut put = |offset: usize, value: u32| {
+            header[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+        };
+        put(0, WAL_FORMAT_VERSION);
+        put(8, self.change_count);
+        put(16, self.max_frame as u32);
+        put(20, self.db_size);
+        put(24, self.checksum[0]);
+        put(28, self.checksum[1]);
+        header[12] = 1;
+        header[13] = self.header.big_endian() as u8;
+        // A 64KiB page size does not fit in 16 bits and is stored as 1.
+        let page_size = self.header.page_size;
+        header[14..16]
+            .copy_from_slice(&((page_size & 0xff00 | page_size >> 16) as u16).to_ne_bytes());
+        // The salts are copied byte for byte from the log header.
+        header[32..40].copy_from_slice(&self.header.to_bytes()[16..24]);
+        let checksum = wal_checksum(cfg!(target_endian = "big"), [0, 0], &header[..40]);
+        header[40..44].copy_from_slice(&checksum[0].to_ne_bytes());
+        header[44..48].copy_from_slice(&checksum[1].to_ne_bytes());
+        header
+    }
+
+    /// Writes the wal-index for the log's current state to the `-shm` file. The index is kept
+    /// in this machine's byte order, and holds two copies of its header (which readers compare
+    /// to detect a torn write), the checkpoint information, and the frame lookup tables. The
+    /// tables go first so that readers never see a header for frames they cannot find. The
+    /// checkpoint information belongs to every connection, and is only written when the index
+    /// is rebuilt (`fresh`).
+    fn write_index(&mut self, fresh: bool) -> Result<()> {
+        self.change_count = self.change_count.wrapping_add(1);
+        let blocks = match self.max_frame {
+            0 => 1,
+            n => 1 + (n + HASH_PAGE_COUNT - HASH_PAGE_COUNT_FIRST - 1) / HASH_PAGE_COUNT,
+        };
+        let mut data = vec![0; blocks * WAL_INDEX_BLOCK_SIZE];
+        for (i, &page_number) in self.frames.iter().enumerate() {
+            let frame = i + 1;
+            // Where this frame's block starts, and the entry it takes within the block,
+            // counting from 1.
+            let (block_start, page_array, entry) = if frame <= HASH_PAGE_COUNT_FIRST {
+                (0, WAL_INDEX_HEADER_SIZE, frame)
+            } else {
+                let frame = frame - HASH_PAGE_COUNT_FIRST;
+                let block = 1 + (frame - 1) / HASH_PAGE_COUNT;
+                let block_start = block * WAL_INDEX_BLOCK_SIZE;
+                (
+                    block_start,
+                    block_start,
+                    frame - (block - 1) * HASH_PAGE_COUNT,
+                )
+            };
+            let slot = page_array + (entry - 1) * 4;
+            data[slot..slot + 4].copy_from_slice(&page_number.to_ne_bytes());
+            let hash_table = block_start + HASH_PAGE_COUNT * 4;
+            let mut key = (page_number as usize * 383) & (HASH_SLOT_COUNT - 1);
+            while data[hash_table + key * 2..hash_table + key * 2 + 2] != [0, 0] {
+                key = (key + 1) & (HASH_SLOT_COUNT - 1);
+            }
+            data[hash_table + key * 2..hash_table + key * 2 + 2]
+                .copy_from_slice(&(entry as u16).to_ne_bytes());
+        }
+        let shm = self.shm()?;
+        // The file is never shortened, since readers may have it mapped.
+        shm.write(WAL_INDEX_HEADER_SIZE, &data[WAL_INDEX_HEADER_SIZE..])?;
+        if fresh {
+            shm.write(
+                BACKFILL_OFFSET,
+                &checkpoint_info(self.backfilled, self.max_frame),
+            )?;
+        }
+        let header = self.index_header();
+        shm.write(48, &header)?;
+        shm.write(0, &header)?;
+        Ok(())
+    }
+
+    /// Latest committed version of a page, if the log has one.
+    pub fn get_page(&self, page_number: u64) -> Option<Bytes> {
+        u32::try_from(page_number)
+            .ok()
+            .and_then(|n| self.pages.get(&n))
+            .cloned()
+    }
+
+    /// Database size in pages as of the last commit, if anything has been committed.
+    pub fn db_size(&self) -> Option<u64> {
+        match self.db_size {
+            0 => None,
+            n => Some(n as u64),
+        }
+    }
+}
+
+fn random_u32() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+/// SQLite's WAL checksum: a Fibonacci-weighted sum over pairs of 32-bit words, continuing from
+/// `initial`. `data` must be a multiple of eight bytes long.
+pub fn wal_checksum(big_endian: bool, initial: [u32; 2], data: &[u8]) -> [u32; 2] {
+    let [mut s0, mut s1] = initial;
+    let word = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    for pair in data.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    [s0, s1]
+}
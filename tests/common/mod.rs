@@ -0,0 +1,167 @@
+#![allow(dead_code)]
+
+use codecrafters_sqlite::database::{CreateOptions, Database};
+use codecrafters_sqlite::error::Result;
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::{full_table_scan, SqliteSchema};
+use codecrafters_sqlite::pager::{Pager, PagerOptions};
+use codecrafters_sqlite::typecodes::SqlValue;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// A path in the temp directory that nothing else uses, with any file left at it by an
+/// earlier run removed.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "codecrafters-sqlite-{}-{}.db",
+        std::process::id(),
+        name
+    ));
+    for suffix in ["", "-journal", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    path
+}
+
+/// A copy of sample.db at a fresh temp path. Its `apples` table holds four rows.
+pub fn sample_copy(name: &str) -> PathBuf {
+    let path = temp_path(name);
+    std::fs::copy("sample.db", &path).unwrap();
+    path
+}
+
+/// A new, empty database at a fresh temp path, with the script run against it.
+pub fn create(name: &str, script: &str) -> (Database, PathBuf) {
+    let path = temp_path(name);
+    let database = Database::create(&path, CreateOptions::default()).unwrap();
+    database.execute(script).unwrap();
+    (database, path)
+}
+
+/// A connection that may write.
+pub fn writer(path: &PathBuf) -> Pager {
+    let options = PagerOptions {
+        writable: true,
+        ..Default::default()
+    };
+    Pager::open_with(path, options).unwrap()
+}
+
+/// A writable connection that gives up on a lock after a moment instead of the usual wait.
+pub fn impatient_writer(path: &PathBuf) -> Pager {
+    let options = PagerOptions {
+        writable: true,
+        busy_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+    Pager::open_with(path, options).unwrap()
+}
+
+/// Runs one statement on a connection opened without a [`Database`].
+pub fn execute(pager: &Pager, sql: &str) -> Result<usize> {
+    codecrafters_sqlite::execute::execute_sql(pager, &codecrafters_sqlite::syntax::parse(sql)?, sql)
+}
+
+/// One column of every row in a table, in rowid order, as the connection sees it. Text comes
+/// back as it is; anything else in its debug form.
+pub fn column(pager: &Pager, table: &str, index: usize) -> Vec<String> {
+    let schema = SqliteSchema::from_pager(pager).unwrap();
+    let root = schema.find_table(table).unwrap().rootpage;
+    full_table_scan(pager, root)
+        .unwrap()
+        .iter()
+        .map(|record| match &record.values[index] {
+            SqlValue::Text(text) => text.clone(),
+            other => format!("{:?}", other),
+        })
+        .collect()
+}
+
+/// Output of the sqlite3 shell run on a database, or None when it is not installed, in which
+/// case the checks that need it are skipped.
+pub fn sqlite3(path: &PathBuf, sql: &str) -> Option<String> {
+    let output = match Command::new("sqlite3").arg(path).arg(sql).output() {
+        Ok(output) => output,
+        Err(_) => {
+            eprintln!("sqlite3 not found; skipping the check against it");
+            return None;
+        }
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "sqlite3 failed on {}: {}",
+        sql,
+        stderr
+    );
+    Some(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim_end()
+            .to_string(),
+    )
+}
+
+/// Checks a database with both our integrity check and, if it is installed, SQLite's.
+pub fn assert_intact(database: &Database, path: &PathBuf) {
+    let findings = integrity_check(database.pager()).unwrap();
+    assert!(findings.is_empty(), "{:?}", findings);
+    if let Some(result) = sqlite3(path, "PRAGMA integrity_check") {
+        assert_eq!(result, "ok");
+    }
+}
+
+/// The rows a query gives through the query planner, each as its values joined by "|".
+pub fn query(database: &Database, sql: &str) -> Vec<String> {
+    query_pager(database.pager(), sql)
+}
+
+/// Like [`query`], for a connection opened without a [`Database`].
+pub fn query_pager(pager: &Pager, sql: &str) -> Vec<String> {
+    let stmt = match codecrafters_sqlite::syntax::parse(sql).unwrap() {
+        codecrafters_sqlite::syntax::statement::Statement::Select(stmt) => stmt,
+        other => panic!("{} is not a query: {:?}", sql, other),
+    };
+    codecrafters_sqlite::query::plan(pager, &stmt)
+        .unwrap()
+        .map(|row| {
+            let values: Vec<String> = row.unwrap().iter().map(|v| v.to_string()).collect();
+            values.join("|")
+        })
+        .collect()
+}
+
+/// A sqlite3 shell that has started a transaction with `begin` and read from the database, so
+/// that it holds its locks until the shell is closed; None when sqlite3 is not installed.
+pub fn sqlite3_transaction(path: &PathBuf, begin: &str) -> Option<Child> {
+    let mut shell = match Command::new("sqlite3")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(shell) => shell,
+        Err(_) => {
+            eprintln!("sqlite3 not found; skipping the check against it");
+            return None;
+        }
+    };
+    let stdin = shell.stdin.as_mut().unwrap();
+    let sql = format!("{}; SELECT count(*) FROM sqlite_schema;\n", begin);
+    stdin.write_all(sql.as_bytes()).unwrap();
+    stdin.flush().unwrap();
+    // Once the count is out, the lock is held.
+    let mut line = String::new();
+    BufReader::new(shell.stdout.as_mut().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    Some(shell)
+}
+
+/// Ends the transaction of a [`sqlite3_transaction`], rolling it back.
+pub fn close_transaction(mut shell: Child) {
+    drop(shell.stdin.take());
+    shell.wait().unwrap();
+}
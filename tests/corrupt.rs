@@ -0,0 +1,131 @@
+mod common;
+
+use codecrafters_sqlite::database::{CreateOptions, Database};
+use codecrafters_sqlite::error::Error;
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::{full_table_scan, Page, SqliteSchema};
+use codecrafters_sqlite::pager::Pager;
+use std::sync::OnceLock;
+
+const PAGE_SIZE: usize = 512;
+
+/// A database of 512-byte pages whose table `t` is three levels deep, with a last row big
+/// enough to spill onto a chain of overflow pages. Returns the file and the table's root page.
+fn deep_table() -> (Vec<u8>, u64) {
+    static FILE: OnceLock<(Vec<u8>, u64)> = OnceLock::new();
+    FILE.get_or_init(write_deep_table).clone()
+}
+
+fn write_deep_table() -> (Vec<u8>, u64) {
+    let path = common::temp_path("corrupt");
+    let options = CreateOptions {
+        page_size: PAGE_SIZE as u32,
+        ..CreateOptions::default()
+    };
+    let database = Database::create(&path, options).unwrap();
+    database
+        .execute("CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT)")
+        .unwrap();
+    database.execute("BEGIN").unwrap();
+    for i in 0..3000 {
+        let sql = format!("INSERT INTO t(v) VALUES ('{:0>100}')", i);
+        database.execute(&sql).unwrap();
+    }
+    let sql = format!("INSERT INTO t(v) VALUES ('{}')", "x".repeat(3000));
+    database.execute(&sql).unwrap();
+    database.execute("COMMIT").unwrap();
+    let root = SqliteSchema::from_pager(database.pager())
+        .unwrap()
+        .find_table("t")
+        .unwrap()
+        .rootpage;
+    drop(database);
+    (std::fs::read(&path).unwrap(), root)
+}
+
+fn page(data: &mut [u8], page_number: u64) -> &mut [u8] {
+    let start = (page_number as usize - 1) * PAGE_SIZE;
+    &mut data[start..start + PAGE_SIZE]
+}
+
+fn read_page(data: &[u8], page_number: u64) -> Page {
+    let pager = Pager::from_bytes(data.to_vec()).unwrap();
+    Page::from_pager(&pager, page_number).unwrap()
+}
+
+/// Reading the table, or checking the file, gives an error rather than a panic or a hang.
+fn assert_corrupt(data: Vec<u8>, root: u64) {
+    let pager = Pager::from_bytes(data).unwrap();
+    let scanned = full_table_scan(&pager, root);
+    assert!(
+        matches!(scanned, Err(Error::Corrupt(_) | Error::CorruptPage { .. })),
+        "{:?}",
+        scanned.map(|records| records.len())
+    );
+    if let Ok(findings) = integrity_check(&pager) {
+        assert!(!findings.is_empty());
+    }
+}
+
+#[test]
+fn the_fixture_is_sound() {
+    let (data, root) = deep_table();
+    let pager = Pager::from_bytes(data.clone()).unwrap();
+    assert_eq!(full_table_scan(&pager, root).unwrap().len(), 3001);
+    assert!(integrity_check(&pager).unwrap().is_empty());
+    let child = read_page(&data, root).left_child(0) as u64;
+    assert_eq!(read_page(&data, child).header.page_type, 0x05);
+}
+
+#[test]
+fn a_truncated_page_is_an_error() {
+    let (mut data, root) = deep_table();
+    // The file ends half way through its last page.
+    data.truncate(data.len() - PAGE_SIZE / 2);
+    assert_corrupt(data, root);
+
+    // A b-tree page cut off in the middle of its cell pointers.
+    let (mut data, _) = deep_table();
+    let child = read_page(&data, root).left_child(0) as u64;
+    let leaf = read_page(&data, child).left_child(0) as u64;
+    let cut = page(&mut data, leaf)[..9].to_vec();
+    let result = Page::from_data(leaf, cut.into());
+    assert!(matches!(result, Err(Error::CorruptPage { .. })));
+}
+
+#[test]
+fn a_child_pointing_back_at_an_ancestor_is_an_error() {
+    let (mut data, root) = deep_table();
+    let child = read_page(&data, root).left_child(0) as u64;
+    // The child's right-most pointer leads back up to the root.
+    page(&mut data, child)[8..12].copy_from_slice(&(root as u32).to_be_bytes());
+    assert_corrupt(data, root);
+}
+
+#[test]
+fn a_cell_pointer_past_the_end_of_the_page_is_an_error() {
+    let (mut data, root) = deep_table();
+    let child = read_page(&data, root).left_child(0) as u64;
+    let leaf = read_page(&data, child).left_child(0) as u64;
+    assert_eq!(read_page(&data, leaf).header.page_type, 0x0d);
+    page(&mut data, leaf)[8..10].copy_from_slice(&(PAGE_SIZE as u16 + 100).to_be_bytes());
+    assert_corrupt(data, root);
+}
+
+#[test]
+fn an_overflow_chain_that_never_ends_is_an_error() {
+    let (mut data, root) = deep_table();
+    // The big row is the last cell of the right-most leaf.
+    let mut page_number = root;
+    let mut found = read_page(&data, page_number);
+    while let Some(right) = found.header.rightmost_pointer {
+        page_number = right as u64;
+        found = read_page(&data, page_number);
+    }
+    let last = found.pointer_array.len() - 1;
+    let info = found.cell_info(last, PAGE_SIZE).unwrap();
+    let overflow = info.overflow_page.unwrap();
+    // The first overflow page names itself as the next one.
+    page(&mut data, overflow as u64)[..4].copy_from_slice(&overflow.to_be_bytes());
+    assert_corrupt(data, root);
+}
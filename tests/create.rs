@@ -0,0 +1,92 @@
+mod common;
+
+use codecrafters_sqlite::database::{CreateOptions, Database};
+use codecrafters_sqlite::page_io::TextEncoding;
+use common::{assert_intact, column, create, sqlite3, temp_path};
+
+#[test]
+fn created_files_are_what_sqlite_expects() {
+    let path = temp_path("create_options");
+    let options = CreateOptions {
+        page_size: 1024,
+        reserved_bytes: 8,
+        text_encoding: TextEncoding::Utf16le,
+        user_version: 7,
+        application_id: 0x1234,
+    };
+    let database = Database::create(&path, options).unwrap();
+    database
+        .execute("CREATE TABLE t(v TEXT); INSERT INTO t(v) VALUES ('héllo')")
+        .unwrap();
+    assert_intact(&database, &path);
+    let Some(settings) = sqlite3(
+        &path,
+        "PRAGMA page_size; PRAGMA encoding; PRAGMA user_version; PRAGMA application_id;
+         SELECT v FROM t",
+    ) else {
+        return;
+    };
+    assert_eq!(settings, "1024\nUTF-16le\n7\n4660\nhéllo");
+    assert!(Database::create(&path, CreateOptions::default()).is_err());
+    for page_size in [0, 100, 3000, 131072] {
+        let options = CreateOptions {
+            page_size,
+            ..Default::default()
+        };
+        let path = temp_path(&format!("page_size_{}", page_size));
+        assert!(Database::create(&path, options).is_err(), "{}", page_size);
+    }
+}
+
+#[test]
+fn vacuum_packs_the_file_and_keeps_every_row() {
+    let (database, path) = create(
+        "vacuum",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT);
+         CREATE INDEX iv ON t(v);
+         CREATE TABLE u(x);",
+    );
+    database.execute("BEGIN").unwrap();
+    for i in 0..1000 {
+        let sql = format!("INSERT INTO t(v) VALUES ('{:0>150}')", i);
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+    database.execute("BEGIN").unwrap();
+    for id in (1..=1000).filter(|id| id % 10 != 0) {
+        let sql = format!("DELETE FROM t WHERE id = {}", id);
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+    let before = std::fs::metadata(&path).unwrap().len();
+    assert!(database.pager().header().freelist_count > 0);
+    let rows = column(database.pager(), "t", 1);
+    assert_eq!(rows.len(), 100);
+
+    database.execute("VACUUM").unwrap();
+    assert_eq!(database.pager().header().freelist_count, 0);
+    assert!(std::fs::metadata(&path).unwrap().len() * 4 < before);
+    assert_eq!(column(database.pager(), "t", 1), rows);
+    assert_intact(&database, &path);
+    if let Some(found) = sqlite3(
+        &path,
+        &format!("SELECT id FROM t WHERE v = '{:0>150}'", 989),
+    ) {
+        assert_eq!(found, "990");
+    }
+
+    let copy = temp_path("vacuum_into");
+    database
+        .execute(&format!("VACUUM INTO '{}'", copy.display()))
+        .unwrap();
+    let copied = Database::open(&copy).unwrap();
+    assert_eq!(column(copied.pager(), "t", 1), rows);
+    assert_intact(&copied, &copy);
+    // It never overwrites a file, and cannot run inside a transaction.
+    assert!(database
+        .execute(&format!("VACUUM INTO '{}'", copy.display()))
+        .is_err());
+    database.execute("BEGIN").unwrap();
+    assert!(database.execute("VACUUM").is_err());
+    database.execute("ROLLBACK").unwrap();
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use codecrafters_sqlite::database::Database;
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::TextEncoding;
+use codecrafters_sqlite::pager::Pager;
+use common::{query, query_pager, sqlite3};
+
+/// Written by sqlite3 with `PRAGMA encoding` set before anything else:
+///
+/// ```sql
+/// CREATE TABLE fruits(id INTEGER PRIMARY KEY, name TEXT, origin TEXT);
+/// CREATE INDEX fruits_name ON fruits(name);
+/// INSERT INTO fruits(name, origin) VALUES ('apple', 'Kazakhstan'), ('Äpfel', 'Deutschland'),
+///     ('çilek', 'Türkiye'), ('りんご', '日本'), ('ｚｅｓｔ', 'fullwidth'), ('🍎 emoji', 'Unicode'),
+///     ('ﬁg', 'ligature'), ('banana', 'Ecuador');
+/// ```
+const FIXTURES: [(&str, TextEncoding); 2] = [
+    ("tests/fixtures/utf16le.db", TextEncoding::Utf16le),
+    ("tests/fixtures/utf16be.db", TextEncoding::Utf16be),
+];
+
+const NAMES: [&str; 8] = [
+    "apple",
+    "Äpfel",
+    "çilek",
+    "りんご",
+    "ｚｅｓｔ",
+    "🍎 emoji",
+    "ﬁg",
+    "banana",
+];
+
+/// BINARY collation compares the bytes of text as stored, so each encoding sorts it its own way.
+fn sorted_names(encoding: TextEncoding) -> Vec<String> {
+    let order: [usize; 8] = match encoding {
+        TextEncoding::Utf16le => [6, 5, 4, 0, 7, 3, 1, 2],
+        _ => [0, 7, 1, 2, 3, 5, 6, 4],
+    };
+    order.iter().map(|&i| NAMES[i].to_string()).collect()
+}
+
+#[test]
+fn utf16_databases_written_by_sqlite_read_back() {
+    for (fixture, encoding) in FIXTURES {
+        let pager = Pager::from_bytes(std::fs::read(fixture).unwrap()).unwrap();
+        assert_eq!(pager.header().text_encoding, encoding);
+        assert_eq!(
+            query_pager(&pager, "SELECT name FROM fruits ORDER BY id"),
+            NAMES
+        );
+        assert_eq!(
+            query_pager(
+                &pager,
+                "SELECT id, origin FROM fruits WHERE name = 'りんご'"
+            ),
+            ["4|日本"]
+        );
+        assert_eq!(
+            query_pager(&pager, "SELECT origin FROM fruits WHERE name = '🍎 emoji'"),
+            ["Unicode"]
+        );
+        // Walking the index gives the same order as sorting.
+        let sorted = sorted_names(encoding);
+        assert_eq!(
+            query_pager(
+                &pager,
+                "SELECT name FROM fruits WHERE name > '' ORDER BY name"
+            ),
+            sorted
+        );
+        assert_eq!(
+            query_pager(&pager, "SELECT name FROM fruits ORDER BY +name"),
+            sorted
+        );
+        assert!(integrity_check(&pager).unwrap().is_empty(), "{}", fixture);
+    }
+}
+
+#[test]
+fn rows_inserted_into_utf16_databases_round_trip() {
+    for (fixture, encoding) in FIXTURES {
+        let path = common::temp_path(&format!("{:?}", encoding));
+        std::fs::copy(fixture, &path).unwrap();
+        let database = Database::open(&path).unwrap();
+        database
+            .execute("INSERT INTO fruits(name, origin) VALUES ('ябълка', 'България')")
+            .unwrap();
+        database
+            .execute("UPDATE fruits SET origin = 'Ελλάδα' WHERE name = 'ﬁg'")
+            .unwrap();
+        assert_eq!(
+            query(
+                &database,
+                "SELECT id, origin FROM fruits WHERE name = 'ябълка'"
+            ),
+            ["9|България"]
+        );
+        assert!(integrity_check(database.pager()).unwrap().is_empty());
+        drop(database);
+
+        let Some(rows) = sqlite3(
+            &path,
+            "PRAGMA integrity_check; SELECT origin FROM fruits WHERE name IN ('ябълка', 'ﬁg') \
+             ORDER BY id",
+        ) else {
+            continue;
+        };
+        assert_eq!(rows, "ok\nΕλλάδα\nБългария");
+    }
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{create, query};
+
+#[test]
+fn expressions_evaluate_as_in_sqlite() {
+    let (database, _) = create("expressions", "");
+    // Each expression with what the sqlite3 shell prints for it.
+    for (expr, expected) in [
+        ("NULL + 1", ""),
+        ("NULL = NULL", ""),
+        ("NULL IS NULL", "1"),
+        ("1 IS NOT NULL", "1"),
+        ("NULL AND 0", "0"),
+        ("NULL AND 1", ""),
+        ("NULL OR 1", "1"),
+        ("NULL OR 0", ""),
+        ("NOT NULL", ""),
+        ("5 / 2", "2"),
+        ("5 / 2.0", "2.5"),
+        ("5 % 3", "2"),
+        ("-5 % 3", "-2"),
+        ("1 / 0", ""),
+        ("'3' + 4", "7"),
+        ("'3.5abc' * 2", "7.0"),
+        ("'abc' + 1", "1"),
+        ("9223372036854775807 + 1", "9.22337203685478e+18"),
+        ("'10' = 10", "0"),
+        ("'10' < 9", "0"),
+        ("x'41' < 'B'", "0"),
+        ("1 < 'a'", "1"),
+        ("'a' < x'00'", "1"),
+        ("typeof(1 + 1.0)", "real"),
+        ("typeof('1' || 2)", "text"),
+        ("CAST('12abc' AS INTEGER)", "12"),
+        ("CAST(3.9 AS INTEGER)", "3"),
+        ("CAST(-3.9 AS INTEGER)", "-3"),
+        ("CAST('1e3' AS REAL)", "1000.0"),
+        ("CAST(12 AS TEXT) || 'x'", "12x"),
+        ("'abc' LIKE 'A_C'", "1"),
+        ("'abc' GLOB 'A*'", "0"),
+        ("'a%c' LIKE 'a!%c' ESCAPE '!'", "1"),
+        ("2 BETWEEN 1 AND 3", "1"),
+        ("2 NOT BETWEEN NULL AND 1", "1"),
+        ("3 IN (1, 2, NULL)", ""),
+        ("1 IN (1, NULL)", "1"),
+        ("3 NOT IN (1, 2)", "1"),
+        ("CASE WHEN NULL THEN 'a' WHEN 1 THEN 'b' END", "b"),
+        (
+            "CASE 2 WHEN 1 THEN 'one' WHEN 2 THEN 'two' ELSE 'many' END",
+            "two",
+        ),
+        ("CASE NULL WHEN NULL THEN 'null' ELSE 'else' END", "else"),
+        ("coalesce(NULL, NULL, 3)", "3"),
+        ("nullif(2, 2)", ""),
+        ("ifnull(NULL, 'x')", "x"),
+        ("length('héllo')", "5"),
+        ("substr('hello', 2, 3)", "ell"),
+        ("substr('hello', -3)", "llo"),
+        ("upper('abc') || lower('DEF')", "ABCdef"),
+        ("abs(-7)", "7"),
+        ("round(2.5)", "3.0"),
+        ("round(-2.567, 2)", "-2.57"),
+        ("max(1, 'a', 2)", "a"),
+        ("min(3, NULL, 1)", ""),
+        ("instr('hello', 'll')", "3"),
+        ("replace('aaa', 'a', 'b')", "bbb"),
+        ("trim('  x  ') || '|'", "x|"),
+        ("hex('AB')", "4142"),
+        ("'A' = 'a' COLLATE NOCASE", "1"),
+        ("'a ' = 'a' COLLATE RTRIM", "1"),
+        ("6 & 3 << 1 | 1", "5"),
+        ("~5", "-6"),
+        ("-(-9223372036854775807 - 1)", "9.22337203685478e+18"),
+        ("1.0 / 3", "0.333333333333333"),
+        ("1e308 * 10", "Inf"),
+    ] {
+        let rows = query(&database, &format!("SELECT {}", expr));
+        assert_eq!(rows, [expected], "{}", expr);
+    }
+}
+
+#[test]
+fn comparisons_use_column_affinity_and_collation() {
+    let (database, _) = create(
+        "affinity",
+        "CREATE TABLE t(i INTEGER, r REAL, s TEXT COLLATE NOCASE, b BLOB);
+         INSERT INTO t VALUES ('12', '1.5', 'Abc', '12');",
+    );
+    for (condition, expected) in [
+        // A numeric column turns text that looks like a number into one.
+        ("i = '12'", "1"),
+        ("r = '1.5'", "1"),
+        ("typeof(i) || typeof(r)", "integerreal"),
+        // A text column compares numbers as text, under its own collation.
+        ("s = 'ABC'", "1"),
+        ("s > 'abb'", "1"),
+        ("s = 'ABC' COLLATE BINARY", "0"),
+        ("+s = 'ABC'", "1"),
+        ("CAST(s AS TEXT) = 'abc'", "1"),
+        // A column without affinity keeps what it was given.
+        ("b = 12", "0"),
+        ("b = '12'", "1"),
+        ("i + r", "13.5"),
+    ] {
+        let rows = query(&database, &format!("SELECT {} FROM t", condition));
+        assert_eq!(rows, [expected], "{}", condition);
+    }
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use codecrafters_sqlite::error::Error;
+use common::{assert_intact, create, query};
+
+#[test]
+fn unique_nocase_index_rejects_a_key_differing_only_in_case() {
+    let (database, path) = create(
+        "unique_nocase",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE UNIQUE);
+         INSERT INTO t(name) VALUES ('a'), ('B'), ('c');",
+    );
+    let result = database.execute("INSERT INTO t(name) VALUES ('C')");
+    assert!(matches!(result, Err(Error::Constraint(_))), "{:?}", result);
+    database
+        .execute("INSERT INTO t(name) VALUES ('d')")
+        .unwrap();
+    assert_eq!(query(&database, "SELECT count(*) FROM t"), ["4"]);
+    assert_intact(&database, &path);
+}
+
+#[test]
+fn writes_keep_nocase_and_desc_indexes_in_order() {
+    let (database, path) = create(
+        "nocase_desc",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, a INT, b TEXT);
+         CREATE INDEX ia ON t(a DESC);
+         CREATE INDEX ib ON t(b COLLATE NOCASE, a DESC);",
+    );
+    // Enough rows to split the index pages, with keys in a mix of cases.
+    for i in 0..600 {
+        let name = match i % 3 {
+            0 => format!("Name{}", i % 40),
+            1 => format!("name{}", i % 40),
+            _ => format!("NAME{}", i % 40),
+        };
+        let sql = format!("INSERT INTO t(a, b) VALUES ({}, '{}')", i % 97, name);
+        database.execute(&sql).unwrap();
+    }
+    database.execute("DELETE FROM t WHERE a % 5 = 0").unwrap();
+    database
+        .execute("UPDATE t SET b = 'renamed' WHERE a % 7 = 0")
+        .unwrap();
+    assert_intact(&database, &path);
+    let expected = query(&database, "SELECT count(*) FROM t WHERE +b = 'NAME3'");
+    assert_eq!(
+        query(&database, "SELECT count(*) FROM t WHERE b = 'NAME3'"),
+        expected
+    );
+}
+
+#[test]
+fn partial_indexes_hold_only_the_rows_meeting_their_condition() {
+    let (database, path) = create(
+        "partial",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, a INT, b INT);
+         INSERT INTO t(a, b) VALUES (1, 1), (2, -1), (2, NULL);
+         CREATE INDEX ip ON t(a) WHERE b > 0;
+         CREATE UNIQUE INDEX iu ON t(a) WHERE b IS NULL;",
+    );
+    database
+        .execute("INSERT INTO t(a, b) VALUES (3, -5), (3, 5), (1, 2)")
+        .unwrap();
+    // Only one row with a NULL b may have each value of a.
+    database
+        .execute("INSERT INTO t(a, b) VALUES (3, NULL)")
+        .unwrap();
+    let result = database.execute("INSERT INTO t(a, b) VALUES (2, NULL)");
+    assert!(matches!(result, Err(Error::Constraint(_))), "{:?}", result);
+    assert_intact(&database, &path);
+
+    database
+        .execute("DELETE FROM t WHERE a = 3 AND b < 0")
+        .unwrap();
+    database.execute("UPDATE t SET b = 0 WHERE b = 1").unwrap();
+    database.execute("UPDATE t SET b = 4 WHERE b = -1").unwrap();
+    assert_intact(&database, &path);
+    assert_eq!(
+        query(&database, "SELECT id, a, b FROM t WHERE a = 3 AND b > 0"),
+        ["5|3|5"]
+    );
+}
+
+#[test]
+fn omitted_columns_take_their_default() {
+    let (database, path) = create(
+        "defaults",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, v REAL DEFAULT 7 NOT NULL,
+             w TEXT DEFAULT -1, x DEFAULT ('a' || 'b'), y);",
+    );
+    database.execute("INSERT INTO t(id) VALUES (1)").unwrap();
+    database
+        .execute("INSERT INTO t(id, v, x) VALUES (2, 2, NULL)")
+        .unwrap();
+    assert_eq!(
+        query(&database, "SELECT id, v, w, typeof(w), x, y FROM t"),
+        ["1|7.0|-1|text|ab|", "2|2.0|-1|text||"]
+    );
+    assert_intact(&database, &path);
+}
+
+#[test]
+fn rows_failing_a_check_are_rejected() {
+    let (database, path) = create(
+        "checks",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, a INT CHECK (a > 0), b TEXT,
+             CHECK (length(b) < 4));
+         INSERT INTO t(a, b) VALUES (1, 'one'), (NULL, NULL);",
+    );
+    for sql in [
+        "INSERT INTO t(a, b) VALUES (-5, 'x')",
+        "INSERT INTO t(a, b) VALUES ('2', 'four')",
+        "UPDATE t SET a = a - 1",
+    ] {
+        let result = database.execute(sql);
+        match result {
+            Err(Error::Constraint(message)) => {
+                assert!(
+                    message.starts_with("CHECK constraint failed"),
+                    "{}",
+                    message
+                )
+            }
+            other => panic!("{} gave {:?}", sql, other),
+        }
+    }
+    database.execute("UPDATE t SET a = a + 1").unwrap();
+    assert_eq!(query(&database, "SELECT a, b FROM t"), ["2|one", "|"]);
+    assert_intact(&database, &path);
+}
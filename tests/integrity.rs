@@ -0,0 +1,96 @@
+mod common;
+
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::SqliteSchema;
+use codecrafters_sqlite::pager::Pager;
+use common::{sqlite3, temp_path};
+use std::process::Command;
+
+#[test]
+fn indexes_sqlite_orders_by_collation_and_direction_are_sound() {
+    let path = temp_path("integrity_orders");
+    let script = "
+        CREATE TABLE t(id INTEGER PRIMARY KEY, a INT, b TEXT, c TEXT COLLATE NOCASE);
+        CREATE INDEX ia ON t(a DESC, b);
+        CREATE INDEX ib ON t(b COLLATE NOCASE DESC);
+        CREATE INDEX ic ON t(c, b COLLATE RTRIM);
+        CREATE TABLE w(k TEXT COLLATE NOCASE PRIMARY KEY, v) WITHOUT ROWID;
+        WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+        INSERT INTO t SELECT i, i % 37, char(65 + i % 26, 97 + i % 7) || substr('   ', i % 3),
+            char(97 + i % 26) || (i % 9) FROM n;
+        INSERT INTO w SELECT DISTINCT upper(c), 1 FROM t WHERE id % 2 = 0;
+        INSERT OR IGNORE INTO w SELECT lower(c), 2 FROM t;
+    ";
+    let Some(result) = sqlite3(&path, &format!("{} PRAGMA integrity_check;", script)) else {
+        return;
+    };
+    assert_eq!(result, "ok");
+    let pager = Pager::open(&path).unwrap();
+    let findings = integrity_check(&pager).unwrap();
+    assert!(findings.is_empty(), "{:?}", findings);
+}
+
+/// A table with an index, and the index's row in `sqlite_schema` moved before the table's, which
+/// takes sqlite3 with its defensive checks off. The findings of our check, or None when sqlite3
+/// is not installed.
+fn index_listed_first(name: &str, schema_change: &str) -> Option<Vec<String>> {
+    let path = temp_path(name);
+    let script = format!(
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, a INT, b TEXT);
+         CREATE INDEX ta ON t(a);
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500)
+         INSERT INTO t SELECT i, i % 13, 'b' || (i % 7) FROM n;
+         PRAGMA writable_schema = ON;
+         UPDATE sqlite_schema SET rowid = 10 WHERE name = 't';
+         {}",
+        schema_change
+    );
+    let output = Command::new("sqlite3")
+        .args(["-cmd", ".dbconfig defensive off"])
+        .arg(&path)
+        .arg(&script)
+        .output()
+        .ok()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pager = Pager::open(&path).unwrap();
+    let schema = SqliteSchema::from_pager(&pager).unwrap();
+    let names: Vec<&str> = schema
+        .schema_elements
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect();
+    assert_eq!(names, ["ta", "t"]);
+    let findings = integrity_check(&pager).unwrap();
+    Some(findings.iter().map(|f| f.to_string()).collect())
+}
+
+#[test]
+fn indexes_are_checked_against_tables_listed_after_them() {
+    let Some(findings) = index_listed_first("integrity_index_first", "") else {
+        return;
+    };
+    assert!(findings.is_empty(), "{:?}", findings);
+
+    // Claiming the index holds b instead of a makes every entry disagree with its row.
+    let change = "UPDATE sqlite_schema SET sql = 'CREATE INDEX ta ON t(b)' WHERE name = 'ta';";
+    let findings = index_listed_first("integrity_index_disagrees", change).unwrap();
+    assert_eq!(findings.len(), 500, "{:?}", &findings[..3]);
+    assert!(findings.contains(&"row 1 does not match its entry in index ta".to_string()));
+}
+
+#[test]
+fn an_index_on_a_table_not_in_the_schema_is_reported() {
+    let change = "UPDATE sqlite_schema SET tbl_name = 'gone', sql = 'CREATE INDEX ta ON gone(a)'
+                  WHERE name = 'ta';";
+    let Some(findings) = index_listed_first("integrity_index_orphan", change) else {
+        return;
+    };
+    assert_eq!(
+        findings,
+        ["index ta is on table gone which is not in the schema"]
+    );
+}
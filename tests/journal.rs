@@ -0,0 +1,160 @@
+mod common;
+
+use bytes::Bytes;
+use codecrafters_sqlite::database::Database;
+use codecrafters_sqlite::error::Error;
+use codecrafters_sqlite::journal::{journal_path, write_journal};
+use codecrafters_sqlite::pager::Pager;
+use common::{
+    assert_intact, close_transaction, column, create, execute, impatient_writer, query,
+    query_pager, sample_copy, sqlite3, sqlite3_transaction, writer,
+};
+
+#[test]
+fn a_hot_journal_is_played_back_on_open() {
+    let path = sample_copy("hot_journal");
+    let original = std::fs::read(&path).unwrap();
+    let page_size = Pager::open(&path).unwrap().page_size();
+    let pages: Vec<_> = original
+        .chunks(page_size)
+        .enumerate()
+        .map(|(i, page)| (i as u64 + 1, Bytes::copy_from_slice(page)))
+        .collect();
+    // What a crash part way through a flush leaves behind: the journal is complete, and the
+    // database has been partly overwritten and grown.
+    let journal = journal_path(&path);
+    write_journal(&journal, page_size as u32, pages.len() as u32, &pages).unwrap();
+    let mut torn = original.clone();
+    torn[page_size..].fill(0xa5);
+    torn.extend(vec![0x5a; page_size]);
+    std::fs::write(&path, &torn).unwrap();
+
+    let pager = Pager::open(&path).unwrap();
+    assert!(!journal.exists());
+    assert_eq!(std::fs::read(&path).unwrap(), original);
+    assert_eq!(column(&pager, "apples", 1).len(), 4);
+    if let Some(result) = sqlite3(&path, "PRAGMA integrity_check") {
+        assert_eq!(result, "ok");
+    }
+}
+
+#[test]
+fn a_journal_without_its_header_is_not_played_back() {
+    let path = sample_copy("cold_journal");
+    // A flush that crashed before its journal was complete zeroes nothing in the database,
+    // and leaves a journal whose header was never filled in.
+    let journal = journal_path(&path);
+    std::fs::write(&journal, vec![0; 1024]).unwrap();
+    let pager = writer(&path);
+    assert_eq!(column(&pager, "apples", 1).len(), 4);
+    execute(&pager, "INSERT INTO apples(name) VALUES ('Gala')").unwrap();
+    assert!(!journal.exists() || std::fs::read(&journal).unwrap().iter().all(|&b| b == 0));
+    if let Some(result) = sqlite3(&path, "PRAGMA integrity_check; SELECT count(*) FROM apples") {
+        assert_eq!(result, "ok\n5");
+    }
+}
+
+#[test]
+fn transactions_and_savepoints_undo_what_they_are_asked_to() {
+    let path = sample_copy("savepoints");
+    let size = std::fs::metadata(&path).unwrap().len();
+    let pager = writer(&path);
+    execute(&pager, "BEGIN").unwrap();
+    execute(&pager, "INSERT INTO apples(name) VALUES ('rolled back')").unwrap();
+    execute(&pager, "DELETE FROM oranges").unwrap();
+    // Nothing reaches the file before COMMIT.
+    if let Some(count) = sqlite3(&path, "SELECT count(*) FROM apples") {
+        assert_eq!(count, "4");
+    }
+    execute(&pager, "ROLLBACK").unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+    assert_eq!(column(&pager, "apples", 1).len(), 4);
+    assert_eq!(column(&pager, "oranges", 1).len(), 6);
+
+    for sql in [
+        "SAVEPOINT a",
+        "INSERT INTO apples(name) VALUES ('outer')",
+        "SAVEPOINT b",
+        "INSERT INTO apples(name) VALUES ('inner')",
+        "ROLLBACK TO b",
+        "INSERT INTO apples(name) VALUES ('after')",
+        "RELEASE a",
+    ] {
+        execute(&pager, sql).unwrap();
+    }
+    assert!(!pager.in_transaction());
+    assert_eq!(column(&pager, "apples", 1)[4..], ["outer", "after"]);
+    // A failed statement inside a transaction only undoes itself.
+    execute(&pager, "BEGIN").unwrap();
+    execute(&pager, "INSERT INTO apples(name) VALUES ('one')").unwrap();
+    assert!(execute(
+        &pager,
+        "INSERT INTO apples(id, name) VALUES (50, 'two'), (1, 'clash')"
+    )
+    .is_err());
+    execute(&pager, "COMMIT").unwrap();
+    assert_eq!(column(&pager, "apples", 1)[4..], ["outer", "after", "one"]);
+    if let Some(result) = sqlite3(&path, "PRAGMA integrity_check; SELECT name FROM apples") {
+        assert_eq!(
+            result,
+            "ok\nGranny Smith\nFuji\nHoneycrisp\nGolden Delicious\nouter\nafter\none"
+        );
+    }
+}
+
+#[test]
+fn a_failed_commit_rolls_the_transaction_back() {
+    let (database, path) = create(
+        "failed_commit",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT);
+         INSERT INTO t(v) VALUES ('a'), ('b');",
+    );
+    drop(database);
+    // A reader in the middle of a transaction keeps the writer from getting the file to
+    // itself. Locks are per process, so the reader has to be another one.
+    let Some(reader) = sqlite3_transaction(&path, "BEGIN") else {
+        return;
+    };
+
+    let writer = impatient_writer(&path);
+    execute(&writer, "BEGIN").unwrap();
+    execute(&writer, "INSERT INTO t(v) VALUES ('c')").unwrap();
+    execute(&writer, "CREATE TABLE u(x)").unwrap();
+    let result = execute(&writer, "COMMIT");
+    assert!(matches!(result, Err(Error::Busy)), "{:?}", result);
+    assert!(!writer.in_transaction());
+    assert_eq!(query_pager(&writer, "SELECT v FROM t"), ["a", "b"]);
+    assert!(execute(&writer, "INSERT INTO u(x) VALUES (1)").is_err());
+
+    close_transaction(reader);
+    execute(&writer, "INSERT INTO t(v) VALUES ('d')").unwrap();
+    drop(writer);
+    let database = Database::open(&path).unwrap();
+    assert_eq!(
+        query(&database, "SELECT id, v FROM t"),
+        ["1|a", "2|b", "3|d"]
+    );
+    assert_intact(&database, &path);
+}
+
+#[test]
+fn a_failed_statement_gives_up_its_locks() {
+    let (database, path) = create(
+        "failed_statement",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT UNIQUE);
+         INSERT INTO t(v) VALUES ('a'), ('b');",
+    );
+    let result = database.execute("INSERT INTO t(v) VALUES ('c'), ('a')");
+    assert!(matches!(result, Err(Error::Constraint(_))), "{:?}", result);
+    assert!(!database.pager().in_transaction());
+    // Another connection can write straight away.
+    let Some(count) = sqlite3(
+        &path,
+        "INSERT INTO t(v) VALUES ('e'); SELECT count(*) FROM t",
+    ) else {
+        return;
+    };
+    assert_eq!(count, "3");
+    assert_eq!(query(&database, "SELECT v FROM t"), ["a", "b", "e"]);
+    assert_intact(&database, &path);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use codecrafters_sqlite::error::Error;
+use common::{
+    close_transaction, column, execute, impatient_writer, sample_copy, sqlite3,
+    sqlite3_transaction, writer,
+};
+use std::process::Command;
+
+#[test]
+fn sqlite_waits_for_our_write_transaction() {
+    let path = sample_copy("our_writer");
+    let pager = writer(&path);
+    execute(&pager, "BEGIN").unwrap();
+    execute(&pager, "INSERT INTO apples(name) VALUES ('pending')").unwrap();
+    // Readers still see the last commit, but no one else may start writing.
+    let Some(rows) = sqlite3(&path, "SELECT count(*) FROM apples") else {
+        return;
+    };
+    assert_eq!(rows, "4");
+    let output = Command::new("sqlite3")
+        .arg(&path)
+        .arg("INSERT INTO apples(name) VALUES ('theirs')")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("database is locked"));
+
+    execute(&pager, "COMMIT").unwrap();
+    assert_eq!(
+        sqlite3(
+            &path,
+            "INSERT INTO apples(name) VALUES ('theirs');
+             PRAGMA integrity_check;
+             SELECT group_concat(name) FROM apples WHERE id > 4"
+        ),
+        Some("ok\npending,theirs".to_string())
+    );
+}
+
+#[test]
+fn we_wait_for_sqlites_write_transaction() {
+    let path = sample_copy("their_writer");
+    let Some(shell) = sqlite3_transaction(&path, "BEGIN IMMEDIATE") else {
+        return;
+    };
+    let pager = impatient_writer(&path);
+    assert_eq!(column(&pager, "apples", 1).len(), 4);
+    let result = execute(&pager, "INSERT INTO apples(name) VALUES ('Gala')");
+    assert!(matches!(result, Err(Error::Busy)), "{:?}", result);
+    close_transaction(shell);
+
+    execute(&pager, "INSERT INTO apples(name) VALUES ('Gala')").unwrap();
+    drop(pager);
+    assert_eq!(
+        sqlite3(
+            &path,
+            "PRAGMA integrity_check; SELECT name FROM apples WHERE id > 4"
+        ),
+        Some("ok\nGala".to_string())
+    );
+}
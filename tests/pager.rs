@@ -0,0 +1,141 @@
+mod common;
+
+use codecrafters_sqlite::database::Database;
+use codecrafters_sqlite::pager::{Backend, CacheStats, Pager, PagerOptions};
+use std::path::Path;
+
+fn open(path: impl AsRef<Path>, cache_size: usize, backend: Backend) -> Pager {
+    let options = PagerOptions {
+        cache_size,
+        backend,
+        ..PagerOptions::default()
+    };
+    Pager::open_with(path, options).unwrap()
+}
+
+/// Every page of the database, as the plain read backend sees it.
+fn pages_read(path: &Path) -> Vec<Vec<u8>> {
+    let pager = open(path, 0, Backend::Read);
+    (1..=pager.page_count().unwrap())
+        .map(|page_number| pager.get_page(page_number).unwrap().to_vec())
+        .collect()
+}
+
+fn pages(pager: &Pager) -> Vec<Vec<u8>> {
+    (1..=pager.page_count().unwrap())
+        .map(|page_number| pager.get_page(page_number).unwrap().to_vec())
+        .collect()
+}
+
+/// A database spread over a few dozen pages.
+fn many_pages(name: &str) -> (Database, std::path::PathBuf) {
+    let (database, path) = common::create(name, "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT)");
+    add_rows(&database, 0..200);
+    (database, path)
+}
+
+fn add_rows(database: &Database, ids: std::ops::Range<i32>) {
+    database.execute("BEGIN").unwrap();
+    for i in ids {
+        let sql = format!("INSERT INTO t(v) VALUES ('{:0>300}')", i);
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+}
+
+#[test]
+fn the_cache_evicts_the_least_recently_used_page() {
+    let pager = open("sample.db", 2, Backend::Read);
+    let page = |page_number| pager.get_page(page_number).unwrap();
+    let stats = |hits, misses, evictions, cached_pages| CacheStats {
+        hits,
+        misses,
+        evictions,
+        cached_pages,
+    };
+
+    page(1);
+    page(2);
+    assert_eq!(pager.cache_stats(), stats(0, 2, 0, 2));
+    // Page 1 was used last, so page 2 makes way for page 3.
+    page(1);
+    page(3);
+    assert_eq!(pager.cache_stats(), stats(1, 3, 1, 2));
+    page(1);
+    assert_eq!(pager.cache_stats(), stats(2, 3, 1, 2));
+    page(2);
+    assert_eq!(pager.cache_stats(), stats(2, 4, 2, 2));
+    // Page 3 went to make room for page 2.
+    page(3);
+    assert_eq!(pager.cache_stats(), stats(2, 5, 3, 2));
+    page(2);
+    page(3);
+    assert_eq!(pager.cache_stats(), stats(4, 5, 3, 2));
+}
+
+#[test]
+fn the_cache_holds_no_more_pages_than_its_capacity() {
+    let (database, path) = many_pages("cache-capacity");
+    drop(database);
+    let expected = pages_read(&path);
+    for cache_size in [0, 1, 5, expected.len() + 10] {
+        let pager = open(&path, cache_size, Backend::Read);
+        for _ in 0..2 {
+            assert_eq!(pages(&pager), expected);
+        }
+        let stats = pager.cache_stats();
+        let cached = cache_size.min(expected.len());
+        assert_eq!(stats.cached_pages, cached, "cache_size {}", cache_size);
+        assert_eq!(stats.hits + stats.misses, 2 * expected.len() as u64);
+        if cache_size >= expected.len() {
+            // The second pass is served from the cache.
+            assert_eq!(stats.misses, expected.len() as u64);
+            assert_eq!(stats.evictions, 0);
+        } else {
+            // A scan bigger than the cache finds each page evicted before it comes round again.
+            assert_eq!(stats.hits, 0);
+            if cache_size > 0 {
+                assert_eq!(stats.evictions, (2 * expected.len() - cache_size) as u64);
+            }
+        }
+    }
+}
+
+#[test]
+fn mapped_pages_match_the_ones_read() {
+    let (database, path) = many_pages("mmap-reads");
+    drop(database);
+    let pager = open(&path, 0, Backend::Mmap);
+    assert_eq!(pager.backend(), Backend::Mmap);
+    assert_eq!(pages(&pager), pages_read(&path));
+}
+
+#[test]
+fn the_mapping_follows_the_file_as_it_grows() {
+    let (database, path) = many_pages("mmap-grow");
+    let pager = open(&path, 100, Backend::Mmap);
+    let before = pages(&pager);
+    // With no writes pending, this gives up the read lock so the database can change.
+    pager.flush().unwrap();
+
+    add_rows(&database, 200..600);
+    let after = pages(&pager);
+    assert!(after.len() > before.len());
+    assert_eq!(after, pages_read(&path));
+    assert_eq!(pager.backend(), Backend::Mmap);
+}
+
+#[test]
+fn a_shrinking_file_falls_back_to_reads() {
+    let (database, path) = many_pages("mmap-shrink");
+    let pager = open(&path, 100, Backend::Mmap);
+    let before = pages(&pager);
+    pager.flush().unwrap();
+
+    database.execute("DELETE FROM t WHERE id > 10").unwrap();
+    database.execute("VACUUM").unwrap();
+    let after = pages(&pager);
+    assert!(after.len() < before.len());
+    assert_eq!(after, pages_read(&path));
+    assert_eq!(pager.backend(), Backend::Read);
+}
@@ -0,0 +1,200 @@
+mod common;
+
+use codecrafters_sqlite::database::{CreateOptions, Database};
+use codecrafters_sqlite::error::Error;
+use codecrafters_sqlite::pager::Pager;
+use codecrafters_sqlite::query::plan;
+use codecrafters_sqlite::syntax;
+use codecrafters_sqlite::syntax::statement::Statement;
+use common::{create, query, sqlite3};
+
+/// A shop's orders, with indexes for the planner to choose from.
+const ORDERS: &str = "
+    CREATE TABLE customers(id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE, city TEXT);
+    CREATE TABLE orders(id INTEGER PRIMARY KEY, customer INT, item TEXT, qty INT, price REAL);
+    CREATE INDEX orders_customer ON orders(customer);
+    CREATE INDEX orders_item ON orders(item DESC, qty);
+    CREATE UNIQUE INDEX customers_name ON customers(name);
+";
+
+#[test]
+fn plans_give_the_rows_sqlite_gives() {
+    let (database, path) = create("planner", ORDERS);
+    let cities = ["Oslo", "Lima", "Pune", "Kyiv"];
+    for i in 1..=40 {
+        let name = if i % 2 == 0 {
+            format!("Cust{}", i)
+        } else {
+            format!("cust{}", i)
+        };
+        let sql = format!(
+            "INSERT INTO customers(name, city) VALUES ('{}', '{}')",
+            name,
+            cities[i % 4]
+        );
+        database.execute(&sql).unwrap();
+    }
+    for i in 0..600 {
+        let sql = format!(
+            "INSERT INTO orders(customer, item, qty, price) VALUES ({}, 'item{}', {}, {})",
+            i % 45,
+            i % 13,
+            i % 7,
+            (i % 11) as f64 * 1.25
+        );
+        database.execute(&sql).unwrap();
+    }
+
+    let queries = [
+        "SELECT id, qty FROM orders WHERE id = 17",
+        "SELECT id FROM orders WHERE rowid BETWEEN 20 AND 25 ORDER BY id DESC",
+        "SELECT id, item FROM orders WHERE customer = 7",
+        "SELECT id FROM orders WHERE customer = '7'",
+        "SELECT count(*) FROM orders WHERE item = 'item3' AND qty > 2",
+        "SELECT id, name FROM customers WHERE name = 'CUST12'",
+        "SELECT count(*), sum(qty), avg(price) FROM orders",
+        "SELECT customer, count(*), max(price) FROM orders GROUP BY customer HAVING count(*) > 13",
+        "SELECT DISTINCT item FROM orders ORDER BY item DESC LIMIT 5 OFFSET 2",
+        "SELECT c.name, count(o.id) FROM customers c JOIN orders o ON o.customer = c.id \
+         WHERE c.city = 'Pune' GROUP BY c.name ORDER BY 2 DESC, 1 LIMIT 4",
+        "SELECT c.id, o.id FROM customers c LEFT JOIN orders o ON o.customer = c.id AND o.qty = 6 \
+         WHERE c.id > 36 ORDER BY 1, 2",
+        "SELECT qty * 2 AS double, count(*) FROM orders GROUP BY double ORDER BY double",
+    ];
+    for sql in queries {
+        let rows = unless_ordered_sorted(sql, query(&database, sql));
+        // With the index or rowid out of reach, the same rows come from a full scan.
+        let unindexed = sql
+            .replace("id = 17", "+id = 17")
+            .replace("customer = 7", "+customer = 7")
+            .replace("item = ", "+item = ")
+            .replace("name = ", "+name = ");
+        let scanned = unless_ordered_sorted(sql, query(&database, &unindexed));
+        assert_eq!(rows, scanned, "{}", sql);
+        if let Some(expected) = sqlite3(&path, sql) {
+            let expected = expected.lines().map(str::to_string).collect();
+            assert_eq!(rows, unless_ordered_sorted(sql, expected), "{}", sql);
+        }
+    }
+}
+
+/// The rows of a query without an ORDER BY, in an order that does not depend on the plan.
+fn unless_ordered_sorted(sql: &str, mut rows: Vec<String>) -> Vec<String> {
+    if !sql.contains("ORDER BY") {
+        rows.sort();
+    }
+    rows
+}
+
+#[test]
+fn rows_stream_without_reading_the_whole_table() {
+    let path = common::temp_path("streaming");
+    let database = Database::create(&path, CreateOptions::default()).unwrap();
+    database
+        .execute("CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT)")
+        .unwrap();
+    database.execute("BEGIN").unwrap();
+    for i in 0..3000 {
+        let sql = format!("INSERT INTO t(v) VALUES ('{:0>100}')", i);
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+    drop(database);
+
+    // A fresh connection, so every page the query needs has to be read.
+    let pager = Pager::open(&path).unwrap();
+    let page_count = pager.page_count().unwrap();
+    let Statement::Select(stmt) = syntax::parse("SELECT id, v FROM t WHERE id % 2 = 0").unwrap()
+    else {
+        unreachable!();
+    };
+    let mut rows = plan(&pager, &stmt).unwrap();
+    let first = rows.next().unwrap().unwrap();
+    assert_eq!(first[0].to_string(), "2");
+    let read = pager.cache_stats().misses;
+    assert!(
+        read < 10,
+        "{} of {} pages read for one row",
+        read,
+        page_count
+    );
+    assert_eq!(rows.count(), 1499);
+    assert!(pager.cache_stats().misses >= page_count / 2);
+}
+
+#[test]
+fn subqueries_are_rejected_before_any_row_is_read() {
+    // With no rows to evaluate against, only planning can catch them.
+    let (database, _) = create("subqueries", "CREATE TABLE t(a, b); CREATE TABLE u(c);");
+    for sql in [
+        "SELECT a FROM t WHERE a IN (SELECT c FROM u)",
+        "SELECT a FROM t WHERE EXISTS (SELECT c FROM u)",
+        "SELECT (SELECT max(c) FROM u) FROM t",
+        "SELECT a FROM t ORDER BY (SELECT 1)",
+        "SELECT a, count(*) FROM t GROUP BY a HAVING count(*) > (SELECT 1)",
+        "SELECT a FROM t JOIN u ON c IN (SELECT a FROM t)",
+    ] {
+        let Statement::Select(stmt) = syntax::parse(sql).unwrap() else {
+            panic!("{} is not a query", sql);
+        };
+        let result = plan(database.pager(), &stmt).map(|rows| rows.count());
+        assert!(
+            matches!(result, Err(Error::Unsupported(_))),
+            "{}: {:?}",
+            sql,
+            result
+        );
+    }
+    for sql in [
+        "DELETE FROM t WHERE EXISTS (SELECT c FROM u)",
+        "UPDATE t SET a = (SELECT c FROM u)",
+        "UPDATE t SET a = 1 WHERE b IN (SELECT c FROM u)",
+    ] {
+        let result = database.execute(sql);
+        assert!(
+            matches!(result, Err(Error::Unsupported(_))),
+            "{}: {:?}",
+            sql,
+            result
+        );
+    }
+}
+
+#[test]
+fn counts_on_a_table_spanning_many_pages() {
+    let (database, path) = create(
+        "counts",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, v INT, pad TEXT); CREATE INDEX tv ON t(v);",
+    );
+    // Every fifth row has no v; the rest repeat 37 values.
+    let v = |i: i64| (i % 5 != 0).then_some(i % 37);
+    database.execute("BEGIN").unwrap();
+    for i in 1..=3000 {
+        let value = v(i).map_or("NULL".to_string(), |v| v.to_string());
+        let sql = format!("INSERT INTO t(v, pad) VALUES ({}, '{:0>200}')", value, i);
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+    assert!(database.pager().page_count().unwrap() > 100);
+
+    let filtered = (1..=3000).filter(|&i| v(i).is_some_and(|v| v > 30)).count();
+    let filtered = filtered.to_string();
+    let cases = [
+        ("SELECT count(*) FROM t", "3000"),
+        ("SELECT count(v) FROM t", "2400"),
+        ("SELECT count(DISTINCT v) FROM t", "37"),
+        ("SELECT count(*) FROM t WHERE v > 30", &filtered),
+        ("SELECT count(*) FROM t WHERE +v > 30", &filtered),
+        ("SELECT count(*) FROM t WHERE v IS NULL", "600"),
+        (
+            "SELECT count(*), count(v), count(pad) FROM t WHERE id > 2990",
+            "10|8|10",
+        ),
+    ];
+    for (sql, expected) in cases {
+        assert_eq!(query(&database, sql), [expected], "{}", sql);
+        if let Some(output) = sqlite3(&path, sql) {
+            assert_eq!(output, expected, "{}", sql);
+        }
+    }
+}
@@ -0,0 +1,480 @@
+mod common;
+
+use codecrafters_sqlite::btree::insert_row;
+use codecrafters_sqlite::database::{CreateOptions, Database};
+use codecrafters_sqlite::error::Error;
+use codecrafters_sqlite::eval::Collation;
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::{
+    count_entries, row_lookup, BTreeCursor, KeyOrder, Page, SeekOp, SqliteSchema, TextEncoding,
+};
+use codecrafters_sqlite::pager::Pager;
+use codecrafters_sqlite::typecodes::{decode_record, encode_record, SqlValue, TypeCode};
+use codecrafters_sqlite::utils::{decode_varint, decode_varint_checked, encode_varint};
+use proptest::prelude::*;
+use std::collections::BTreeSet;
+
+const ENCODINGS: [TextEncoding; 3] = [
+    TextEncoding::Utf8,
+    TextEncoding::Utf16le,
+    TextEncoding::Utf16be,
+];
+
+fn sql_value() -> impl Strategy<Value = SqlValue> {
+    prop_oneof![
+        Just(SqlValue::Null),
+        any::<i64>().prop_map(SqlValue::I64),
+        (-200i64..200).prop_map(SqlValue::I64),
+        any::<f64>()
+            .prop_filter("NaN is stored as NULL", |f| !f.is_nan())
+            .prop_map(SqlValue::F64),
+        ".{0,40}".prop_map(SqlValue::Text),
+        prop::collection::vec(any::<u8>(), 0..40).prop_map(SqlValue::Blob),
+    ]
+}
+
+/// Whether a decoded value is the one that was encoded. Integers come back in whichever
+/// variant matches their serial type, so they are compared by value.
+fn same_value(a: &SqlValue, b: &SqlValue) -> bool {
+    match (a, b) {
+        (SqlValue::Null, SqlValue::Null) => true,
+        (SqlValue::F64(a), SqlValue::F64(b)) => a.to_bits() == b.to_bits(),
+        (SqlValue::Text(a), SqlValue::Text(b)) => a == b,
+        (SqlValue::Blob(a), SqlValue::Blob(b)) => a == b,
+        (a, b) => a.as_i64().is_some() && a.as_i64() == b.as_i64(),
+    }
+}
+
+fn assert_same_values(expected: &[SqlValue], actual: &[SqlValue]) {
+    assert_eq!(
+        expected.len(),
+        actual.len(),
+        "{:?} vs {:?}",
+        expected,
+        actual
+    );
+    for (e, a) in expected.iter().zip(actual) {
+        assert!(same_value(e, a), "{:?} vs {:?}", expected, actual);
+    }
+}
+
+/// Length of a varint as computed by SQLite's `sqlite3VarintLen`.
+fn sqlite_varint_len(value: u64) -> usize {
+    let mut len = 1;
+    let mut rest = value >> 7;
+    while rest != 0 && len < 9 {
+        len += 1;
+        rest >>= 7;
+    }
+    len
+}
+
+proptest! {
+    #[test]
+    fn varint_round_trips(value in any::<u64>()) {
+        let bytes = encode_varint(value);
+        prop_assert_eq!(bytes.len(), sqlite_varint_len(value));
+        prop_assert_eq!(decode_varint(&bytes), (value, bytes.len()));
+        prop_assert_eq!(decode_varint_checked(&bytes), Some((value, bytes.len())));
+    }
+
+    #[test]
+    fn varint_round_trips_at_byte_boundaries(shift in 0u32..64, delta in -1i64..=1) {
+        let value = (1u64 << shift).wrapping_add_signed(delta);
+        let bytes = encode_varint(value);
+        prop_assert_eq!(bytes.len(), sqlite_varint_len(value));
+        prop_assert_eq!(decode_varint(&bytes), (value, bytes.len()));
+    }
+
+    #[test]
+    fn integers_use_the_smallest_serial_type(value in any::<i64>(), schema_format in 1u32..=4) {
+        let type_code = SqlValue::I64(value).type_code(TextEncoding::Utf8, schema_format);
+        let size = type_code.size();
+        if size == 0 {
+            prop_assert!(schema_format >= 4 && (value == 0 || value == 1));
+        } else {
+            // Sign-extending the stored bytes gives back the value...
+            let bytes = value.to_be_bytes();
+            let stored = &bytes[8 - size..];
+            let mut widened = if stored[0] & 0x80 != 0 { [0xff; 8] } else { [0; 8] };
+            widened[8 - size..].copy_from_slice(stored);
+            prop_assert_eq!(i64::from_be_bytes(widened), value);
+            // ...and the next smaller integer type would not hold it.
+            let smaller = match type_code {
+                TypeCode::I8 => 0,
+                TypeCode::I16 => 1,
+                TypeCode::I24 => 2,
+                TypeCode::I32 => 3,
+                TypeCode::I48 => 4,
+                TypeCode::I64 => 6,
+                other => return Err(TestCaseError::fail(format!("{:?}", other))),
+            };
+            if smaller > 0 {
+                let bits = smaller as u32 * 8;
+                let (min, max) = (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1);
+                prop_assert!(value < min || value > max);
+            } else if schema_format >= 4 {
+                prop_assert!(value != 0 && value != 1);
+            }
+        }
+    }
+
+    #[test]
+    fn records_round_trip(
+        values in prop::collection::vec(sql_value(), 0..80),
+        encoding in prop::sample::select(ENCODINGS.to_vec()),
+        schema_format in 1u32..=4,
+    ) {
+        let record = encode_record(&values, encoding, schema_format);
+        let decoded = decode_record(&record, encoding).unwrap();
+        assert_same_values(&values, &decoded);
+        prop_assert_eq!(encode_record(&decoded, encoding, schema_format), record);
+    }
+}
+
+fn sample_db() -> Pager {
+    Pager::from_bytes(std::fs::read("sample.db").unwrap()).unwrap()
+}
+
+/// Payload of every cell on the table leaf pages of a database.
+fn table_leaf_payloads(pager: &Pager) -> Vec<Vec<u8>> {
+    let mut payloads = Vec::new();
+    for page_number in 1..=pager.page_count().unwrap() {
+        let Ok(page) = Page::from_pager(pager, page_number) else {
+            continue;
+        };
+        if page.header.page_type != 0x0d {
+            continue;
+        }
+        for pointer in &page.pointer_array {
+            let cell = &page.data[*pointer as usize..];
+            let (payload_size, size_len) = decode_varint(cell);
+            let (_, rowid_len) = decode_varint(&cell[size_len..]);
+            let start = size_len + rowid_len;
+            // The sample database is small enough that nothing spills onto overflow pages.
+            payloads.push(cell[start..start + payload_size as usize].to_vec());
+        }
+    }
+    payloads
+}
+
+#[test]
+fn records_match_what_sqlite_wrote() {
+    let pager = sample_db();
+    let header = pager.header();
+    let payloads = table_leaf_payloads(&pager);
+    assert!(!payloads.is_empty());
+    for payload in payloads {
+        let values = decode_record(&payload, header.text_encoding).unwrap();
+        let encoded = encode_record(&values, header.text_encoding, header.schema_format);
+        assert_eq!(encoded, payload, "{:?}", values);
+    }
+}
+
+#[test]
+fn truncated_records_are_corrupt() {
+    let pager = sample_db();
+    let encoding = pager.header().text_encoding;
+    for payload in table_leaf_payloads(&pager) {
+        for len in 0..payload.len() {
+            let result = decode_record(&payload[..len], encoding);
+            assert!(
+                matches!(result, Err(Error::Corrupt(_))),
+                "{:?} -> {:?}",
+                &payload[..len],
+                result
+            );
+        }
+    }
+    for (type_code, data) in [
+        (TypeCode::I32, &[0u8, 1][..]),
+        (TypeCode::F64, &[0x40; 7][..]),
+        (TypeCode::Text(4), &b"abc"[..]),
+    ] {
+        let result = type_code.decode(data, encoding);
+        assert!(matches!(result, Err(Error::Corrupt(_))), "{:?}", result);
+    }
+}
+
+/// The script sqlite3 ran to write tests/fixtures/real_affinity.db.
+const REAL_AFFINITY: &str = "
+    CREATE TABLE t(id INTEGER PRIMARY KEY, r REAL, s TEXT);
+    CREATE INDEX ir ON t(r);
+    INSERT INTO t(r, s) VALUES (2.0, 'a'), (2.5, 'b'), ('3', 'c'), (-0.0, 'd'), (1e300, 'e'),
+        (9223372036854775807.0, 'f'), (-9223372036854775808.0, 'g'), (4611686018427387904.0, 'h'),
+        (NULL, 'i'), (7, 'j'), ('1.5e1', 'k');
+    UPDATE t SET r = r + 1 WHERE id = 2;
+    UPDATE t SET r = 10 WHERE id = 3;
+";
+
+/// Payload of every cell on a single leaf page, table or index, in key order.
+fn leaf_payloads(pager: &Pager, page_number: u64) -> Vec<Vec<u8>> {
+    let page = Page::from_pager(pager, page_number).unwrap();
+    assert!(matches!(page.header.page_type, 0x0a | 0x0d));
+    page.pointer_array
+        .iter()
+        .map(|pointer| {
+            let cell = &page.data[*pointer as usize..];
+            let (payload_size, mut start) = decode_varint(cell);
+            if page.header.page_type == 0x0d {
+                start += decode_varint(&cell[start..]).1;
+            }
+            cell[start..start + payload_size as usize].to_vec()
+        })
+        .collect()
+}
+
+#[test]
+fn real_columns_store_whole_numbers_as_sqlite_does() {
+    let fixture =
+        Pager::from_bytes(std::fs::read("tests/fixtures/real_affinity.db").unwrap()).unwrap();
+    let (database, _) = common::create("real-affinity", REAL_AFFINITY);
+    // Page 2 holds the table and page 3 its index, in both files.
+    for page_number in [2, 3] {
+        let expected = leaf_payloads(&fixture, page_number);
+        assert_eq!(expected.len(), 11);
+        assert_eq!(leaf_payloads(database.pager(), page_number), expected);
+    }
+    // The integers read back as reals.
+    let sql = "SELECT r FROM t WHERE id IN (1, 3, 4) ORDER BY id";
+    assert_eq!(common::query(&database, sql), ["2.0", "10.0", "0.0"]);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn records_round_trip_through_the_btree(
+        rows in prop::collection::vec(
+            prop_oneof![
+                prop::collection::vec(sql_value(), 0..8),
+                // Large enough to spill onto overflow pages.
+                prop::collection::vec(any::<u8>(), 4000..12000)
+                    .prop_map(|blob| vec![SqlValue::Blob(blob)]),
+            ],
+            1..40,
+        ),
+    ) {
+        let pager = sample_db();
+        let header = pager.header();
+        let schema = SqliteSchema::from_pager(&pager).unwrap();
+        let root = schema.find_table("apples").unwrap().rootpage;
+        for (i, values) in rows.iter().enumerate() {
+            let record = encode_record(values, header.text_encoding, header.schema_format);
+            insert_row(&pager, root, 1000 + i as i64, &record).unwrap();
+        }
+        for (i, values) in rows.iter().enumerate() {
+            let found = row_lookup(&pager, root, 1000 + i as i64).unwrap();
+            prop_assert!(found.is_some());
+            assert_same_values(values, &found.unwrap().values);
+        }
+        pager.flush().unwrap();
+        prop_assert!(integrity_check(&pager).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cursor_moves_and_seeks_like_an_ordered_set(
+        rowids in prop::collection::btree_set(-5000i64..5000, 0..400),
+        probes in prop::collection::vec(-6000i64..6000, 1..20),
+    ) {
+        let pager = sample_db();
+        let header = pager.header();
+        let schema = SqliteSchema::from_pager(&pager).unwrap();
+        let root = schema.find_table("apples").unwrap().rootpage;
+        let mut expected = BTreeSet::new();
+        let mut cursor = BTreeCursor::new(&pager, root);
+        let mut found = cursor.first().unwrap();
+        while found {
+            expected.insert(cursor.rowid().unwrap());
+            found = cursor.next().unwrap();
+        }
+        // Enough text per row that the table grows interior pages.
+        let record = encode_record(
+            &[SqlValue::Text("x".repeat(200))],
+            header.text_encoding,
+            header.schema_format,
+        );
+        for &rowid in rowids.difference(&expected.clone()) {
+            insert_row(&pager, root, rowid, &record).unwrap();
+            expected.insert(rowid);
+        }
+
+        let mut cursor = BTreeCursor::new(&pager, root);
+        let mut forward = Vec::new();
+        let mut found = cursor.first().unwrap();
+        while found {
+            forward.push(cursor.rowid().unwrap());
+            found = cursor.next().unwrap();
+        }
+        prop_assert_eq!(&forward, &expected.iter().copied().collect::<Vec<_>>());
+        prop_assert_eq!(count_entries(&pager, root).unwrap(), expected.len() as u64);
+        let mut backward = Vec::new();
+        let mut found = cursor.last().unwrap();
+        while found {
+            backward.push(cursor.rowid().unwrap());
+            found = cursor.prev().unwrap();
+        }
+        prop_assert_eq!(&backward, &expected.iter().rev().copied().collect::<Vec<_>>());
+
+        for probe in probes {
+            let cases = [
+                (SeekOp::GreaterEqual, expected.range(probe..).next()),
+                (SeekOp::Greater, expected.range(probe + 1..).next()),
+                (SeekOp::LessEqual, expected.range(..=probe).next_back()),
+                (SeekOp::Less, expected.range(..probe).next_back()),
+            ];
+            for (op, want) in cases {
+                let found = cursor.seek_rowid(probe, op).unwrap();
+                prop_assert_eq!(found.then(|| cursor.rowid().unwrap()), want.copied());
+            }
+            // A seek leaves the cursor able to step both ways from there.
+            if cursor.seek_rowid(probe, SeekOp::GreaterEqual).unwrap() {
+                let at = cursor.rowid().unwrap();
+                let before = expected.range(..at).next_back().copied();
+                let found = cursor.prev().unwrap();
+                prop_assert_eq!(found.then(|| cursor.rowid().unwrap()), before);
+                if found {
+                    prop_assert!(cursor.next().unwrap());
+                    prop_assert_eq!(cursor.rowid().unwrap(), at);
+                }
+            }
+        }
+    }
+}
+
+/// An index on a table of 900 rows over 512-byte pages, deep enough for interior entries, and
+/// each key in it three times: the entry for rowid `i` has key `2 * ((i - 1) / 3)`.
+fn index_with_duplicates() -> (Database, u64) {
+    let path = common::temp_path("cursor-index");
+    let options = CreateOptions {
+        page_size: 512,
+        ..CreateOptions::default()
+    };
+    let database = Database::create(&path, options).unwrap();
+    database
+        .execute("CREATE TABLE t(id INTEGER PRIMARY KEY, k INT); CREATE INDEX tk ON t(k)")
+        .unwrap();
+    database.execute("BEGIN").unwrap();
+    for i in 0..900 {
+        let sql = format!("INSERT INTO t(k) VALUES ({})", 2 * (i / 3));
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+    let root = index_root(database.pager(), "tk");
+    (database, root)
+}
+
+fn index_root(pager: &Pager, name: &str) -> u64 {
+    let schema = SqliteSchema::from_pager(pager).unwrap();
+    let element = schema.schema_elements.iter().find(|e| e.name == name);
+    element.unwrap().rootpage
+}
+
+fn entry(cursor: &BTreeCursor) -> (i64, i64) {
+    let values = cursor.record().unwrap().values;
+    (values[0].as_i64().unwrap(), values[1].as_i64().unwrap())
+}
+
+#[test]
+fn index_cursor_seeks_each_way_across_page_boundaries() {
+    let (database, root) = index_with_duplicates();
+    let pager = database.pager();
+    let root_page = Page::from_pager(pager, root).unwrap();
+    assert_eq!(root_page.header.page_type, 0x02);
+    let entries: Vec<(i64, i64)> = (1..=900).map(|i| (2 * ((i - 1) / 3), i)).collect();
+    let order = KeyOrder::new(vec![(Collation::Binary, false), (Collation::Binary, false)]);
+
+    let mut cursor = BTreeCursor::new(pager, root);
+    let mut backward = Vec::new();
+    let mut found = cursor.last().unwrap();
+    while found {
+        backward.push(entry(&cursor));
+        found = cursor.prev().unwrap();
+    }
+    assert_eq!(backward, entries.iter().rev().copied().collect::<Vec<_>>());
+
+    // Every key and every gap between keys, which takes in the first and last entries of every
+    // page, the entries on interior pages, and keys before the first entry and after the last.
+    for probe in -3..=603 {
+        let key = [SqlValue::I64(probe)];
+        let cases = [
+            (SeekOp::GreaterEqual, entries.iter().find(|e| e.0 >= probe)),
+            (SeekOp::Greater, entries.iter().find(|e| e.0 > probe)),
+            (SeekOp::LessEqual, entries.iter().rfind(|e| e.0 <= probe)),
+            (SeekOp::Less, entries.iter().rfind(|e| e.0 < probe)),
+        ];
+        for (op, want) in cases {
+            let found = cursor.seek_key(&key, &order, op).unwrap();
+            assert_eq!(
+                found.then(|| entry(&cursor)),
+                want.copied(),
+                "{:?} {}",
+                op,
+                probe
+            );
+            if !found {
+                continue;
+            }
+            // From there the cursor steps to the neighbouring entries either way.
+            let at = entries.iter().position(|e| Some(e) == want).unwrap();
+            let found = cursor.prev().unwrap();
+            let before = at.checked_sub(1).map(|i| entries[i]);
+            assert_eq!(found.then(|| entry(&cursor)), before, "{:?} {}", op, probe);
+            if found {
+                assert!(cursor.next().unwrap());
+            } else {
+                assert!(cursor.seek_key(&key, &order, op).unwrap());
+            }
+            let found = cursor.next().unwrap();
+            assert_eq!(found.then(|| entry(&cursor)), entries.get(at + 1).copied());
+        }
+    }
+
+    // A full key picks out its one entry, and the entries either side of it.
+    for (at, &(k, rowid)) in entries.iter().enumerate() {
+        let key = [SqlValue::I64(k), SqlValue::I64(rowid)];
+        assert!(cursor.seek_key(&key, &order, SeekOp::LessEqual).unwrap());
+        assert_eq!(entry(&cursor), (k, rowid));
+        let found = cursor.seek_key(&key, &order, SeekOp::Less).unwrap();
+        assert_eq!(
+            found.then(|| entry(&cursor)),
+            at.checked_sub(1).map(|i| entries[i])
+        );
+        let found = cursor.seek_key(&key, &order, SeekOp::Greater).unwrap();
+        assert_eq!(found.then(|| entry(&cursor)), entries.get(at + 1).copied());
+    }
+}
+
+#[test]
+fn cursors_on_empty_trees_find_nothing() {
+    let path = common::temp_path("cursor-empty");
+    let database = Database::create(&path, CreateOptions::default()).unwrap();
+    database
+        .execute("CREATE TABLE t(id INTEGER PRIMARY KEY, k INT); CREATE INDEX tk ON t(k)")
+        .unwrap();
+    let pager = database.pager();
+    let schema = SqliteSchema::from_pager(pager).unwrap();
+    let table = schema.find_table("t").unwrap().rootpage;
+    let index = index_root(pager, "tk");
+    let order = KeyOrder::new(vec![(Collation::Binary, false)]);
+    let ops = [
+        SeekOp::GreaterEqual,
+        SeekOp::Greater,
+        SeekOp::LessEqual,
+        SeekOp::Less,
+    ];
+    for root in [table, index] {
+        let mut cursor = BTreeCursor::new(pager, root);
+        assert!(!cursor.first().unwrap());
+        assert!(!cursor.last().unwrap());
+        assert!(!cursor.next().unwrap());
+        assert!(!cursor.prev().unwrap());
+        assert!(cursor.record().is_err());
+    }
+    for op in ops {
+        assert!(!BTreeCursor::new(pager, table).seek_rowid(1, op).unwrap());
+        let key = [SqlValue::I64(1)];
+        let mut cursor = BTreeCursor::new(pager, index);
+        assert!(!cursor.seek_key(&key, &order, op).unwrap());
+    }
+}
@@ -0,0 +1,165 @@
+use codecrafters_sqlite::syntax;
+use codecrafters_sqlite::syntax::create_index::CreateIndexStmt;
+use codecrafters_sqlite::syntax::expr::{BinaryOp, Expr};
+use codecrafters_sqlite::syntax::select::ResultColumn;
+use codecrafters_sqlite::syntax::statement::Statement;
+
+fn create_index(sql: &str) -> (CreateIndexStmt, bool) {
+    match syntax::parse(sql).unwrap() {
+        Statement::CreateIndex { stmt, unique } => (stmt, unique),
+        other => panic!("{} parsed as {:?}", sql, other),
+    }
+}
+
+#[test]
+fn create_index_if_not_exists() {
+    let (stmt, unique) = create_index("CREATE UNIQUE INDEX IF NOT EXISTS i ON t(a, b)");
+    assert!(unique);
+    assert!(stmt.if_not_exists);
+    assert_eq!(stmt.schema_name, None);
+    assert_eq!(stmt.index_name, "i");
+    assert_eq!(stmt.table_name, "t");
+    let names: Vec<_> = stmt.columns.iter().map(|c| c.name()).collect();
+    assert_eq!(names, [Some("a"), Some("b")]);
+}
+
+#[test]
+fn create_index_with_schema_name() {
+    let (stmt, unique) = create_index("CREATE INDEX main.i ON t(a)");
+    assert!(!unique);
+    assert!(!stmt.if_not_exists);
+    assert_eq!(stmt.schema_name.as_deref(), Some("main"));
+    assert_eq!(stmt.index_name, "i");
+    assert_eq!(stmt.table_name, "t");
+
+    let (stmt, _) = create_index("CREATE INDEX IF NOT EXISTS main.i ON t(a)");
+    assert!(stmt.if_not_exists);
+    assert_eq!(stmt.schema_name.as_deref(), Some("main"));
+    assert_eq!(stmt.index_name, "i");
+}
+
+#[test]
+fn create_index_without_name_is_an_error() {
+    assert!(syntax::parse("CREATE INDEX IF NOT EXISTS ON t(a)").is_err());
+    assert!(syntax::parse("CREATE INDEX main. ON t(a)").is_err());
+}
+
+#[test]
+fn create_index_column_collation_and_order() {
+    let (stmt, _) =
+        create_index("CREATE INDEX i ON t(a DESC, b COLLATE NOCASE ASC, c COLLATE rtrim DESC)");
+    let columns: Vec<_> = stmt
+        .columns
+        .iter()
+        .map(|c| (c.name(), c.collation.as_deref(), c.descending))
+        .collect();
+    assert_eq!(
+        columns,
+        [
+            (Some("a"), None, true),
+            (Some("b"), Some("NOCASE"), false),
+            (Some("c"), Some("rtrim"), true),
+        ]
+    );
+}
+
+#[test]
+fn create_index_on_an_expression() {
+    let (stmt, _) = create_index("CREATE INDEX i ON t(a + 1 DESC, lower(b)) WHERE a > 0");
+    assert_eq!(stmt.columns.len(), 2);
+    assert!(stmt.columns.iter().all(|c| c.name().is_none()));
+    assert!(stmt.columns[0].descending);
+    assert!(stmt.where_clause.is_some());
+}
+
+#[test]
+fn update_assignments_are_expressions() {
+    let stmt = match syntax::parse("UPDATE t SET a = a + 1, b = 'x' WHERE c = 2").unwrap() {
+        Statement::Update(stmt) => stmt,
+        other => panic!("parsed as {:?}", other),
+    };
+    let columns: Vec<_> = stmt
+        .assignments
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(columns, ["a", "b"]);
+    assert!(matches!(stmt.assignments[0].1, Expr::Binary { .. }));
+    assert!(stmt.where_clause.is_some());
+}
+
+/// The expression of a one-column SELECT.
+fn expr(sql: &str) -> Expr {
+    let stmt = match syntax::parse(&format!("SELECT {} FROM t", sql)).unwrap() {
+        Statement::Select(stmt) => stmt,
+        other => panic!("{} parsed as {:?}", sql, other),
+    };
+    match stmt.columns.into_iter().next() {
+        Some(ResultColumn::Expr { expr, .. }) => expr,
+        other => panic!("{} parsed as {:?}", sql, other),
+    }
+}
+
+#[test]
+fn operators_bind_by_precedence() {
+    // Each expression is parsed the same as the fully parenthesized one.
+    let tree = |sql: &str| format!("{:?}", expr(sql));
+    for (sql, grouped) in [
+        ("1 + 2 * 3", "1 + (2 * 3)"),
+        ("1 - 2 - 3", "(1 - 2) - 3"),
+        ("a || b * c", "(a || b) * c"),
+        ("-a * b", "(-a) * b"),
+        ("a < b = c > d", "(a < b) = (c > d)"),
+        ("a & b << 1 | c", "((a & b) << 1) | c"),
+        ("NOT a = b AND c OR d", "((NOT (a = b)) AND c) OR d"),
+        ("a OR b AND c", "a OR (b AND c)"),
+        ("a + 1 BETWEEN b AND c + 1", "(a + 1) BETWEEN b AND (c + 1)"),
+        ("a IS NOT b + 1", "a IS NOT (b + 1)"),
+        ("a = 1 IS NULL", "(a = 1) IS NULL"),
+        ("a LIKE b || '%' ESCAPE '!'", "a LIKE (b || '%') ESCAPE '!'"),
+        ("a IN (1, 2) = b", "(a IN (1, 2)) = b"),
+        ("x COLLATE NOCASE = y", "(x COLLATE NOCASE) = y"),
+        ("CAST(a AS INT) + 1", "(CAST(a AS INT)) + 1"),
+    ] {
+        assert_eq!(tree(sql), tree(grouped), "{}", sql);
+    }
+    assert!(matches!(
+        expr("1 + 2 * 3"),
+        Expr::Binary {
+            op: BinaryOp::Add,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn malformed_expressions_are_errors() {
+    for sql in [
+        "SELECT 1 + FROM t",
+        "SELECT (1 FROM t",
+        "SELECT a BETWEEN 1 FROM t",
+        "SELECT CASE END FROM t",
+        "SELECT a NOT BETWEEN 1 FROM t",
+        "SELECT CAST(a) FROM t",
+    ] {
+        assert!(syntax::parse(sql).is_err(), "{}", sql);
+    }
+}
+
+#[test]
+fn only_pragmas_that_change_the_database_write() {
+    for (sql, writes) in [
+        ("PRAGMA journal_mode", false),
+        ("PRAGMA main.journal_mode", false),
+        ("PRAGMA journal_mode = WAL", true),
+        ("PRAGMA JOURNAL_MODE(delete)", true),
+        ("PRAGMA wal_checkpoint", true),
+        ("PRAGMA wal_checkpoint(TRUNCATE)", true),
+        ("PRAGMA page_size", false),
+    ] {
+        let Statement::Pragma(stmt) = syntax::parse(sql).unwrap() else {
+            panic!("{} is not a PRAGMA", sql);
+        };
+        assert_eq!(stmt.writes(), writes, "{}", sql);
+    }
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::{assert_intact, create, query};
+
+#[test]
+fn update_evaluates_expressions_against_the_old_row() {
+    let (database, path) = create(
+        "update_expressions",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, v REAL, w TEXT, n INT);
+         CREATE INDEX iv ON t(v);
+         INSERT INTO t(id, v, w, n) VALUES (1, 1, 'a', 10), (2, 2.5, 'b', 20), (3, NULL, 'c', 30);",
+    );
+    database
+        .execute("UPDATE t SET v = v + 1, w = w || id, n = v * 2 WHERE id < 3")
+        .unwrap();
+    // Affinity still applies to computed values, and the rowid can be computed too.
+    database
+        .execute("UPDATE t SET n = '7' || '0', id = id + 10 WHERE id = 3")
+        .unwrap();
+    assert_eq!(
+        query(&database, "SELECT id, v, w, n, typeof(n) FROM t"),
+        [
+            "1|2.0|a1|2|integer",
+            "2|3.5|b2|5|integer",
+            "13||c|70|integer"
+        ]
+    );
+    assert_intact(&database, &path);
+}
+
+#[test]
+fn deletes_and_updates_keep_indexes_in_step() {
+    let (database, path) = create(
+        "delete_update_indexes",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, a INT, b TEXT UNIQUE, c REAL);
+         CREATE INDEX ia ON t(a, c DESC);",
+    );
+    database.execute("BEGIN").unwrap();
+    for i in 0..1500 {
+        let sql = format!(
+            "INSERT INTO t(a, b, c) VALUES ({}, 'key{:04}', {})",
+            i % 17,
+            i,
+            i as f64 / 4.0
+        );
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+    assert_eq!(
+        database
+            .execute("UPDATE t SET a = a + 100, b = b || '!' WHERE id % 3 = 0")
+            .unwrap(),
+        500
+    );
+    // Moving rows to new rowids takes their index entries along.
+    database
+        .execute("UPDATE t SET id = id + 10000 WHERE a = 5")
+        .unwrap();
+    assert_eq!(
+        database
+            .execute("DELETE FROM t WHERE c > 100 AND a < 100")
+            .unwrap(),
+        732
+    );
+    assert_intact(&database, &path);
+    assert_eq!(
+        query(&database, "SELECT count(*), sum(id), sum(a) FROM t"),
+        ["768|589618|56111"]
+    );
+    assert_eq!(
+        query(&database, "SELECT id FROM t WHERE b = 'key0005!'"),
+        ["6"]
+    );
+    assert_eq!(
+        query(&database, "SELECT count(*) FROM t WHERE a = 105"),
+        ["30"]
+    );
+}
+
+#[test]
+fn deleting_most_rows_merges_pages_away() {
+    let (database, path) = create(
+        "merge",
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT);
+         CREATE INDEX iv ON t(v);",
+    );
+    database.execute("BEGIN").unwrap();
+    for i in 0..2000 {
+        let sql = format!("INSERT INTO t(v) VALUES ('{:0>300}')", i);
+        database.execute(&sql).unwrap();
+    }
+    database.execute("COMMIT").unwrap();
+    let pages = database.pager().page_count().unwrap();
+    database
+        .execute("DELETE FROM t WHERE id % 25 != 0")
+        .unwrap();
+    assert_intact(&database, &path);
+    // What is left fits in a small fraction of the pages; the rest are free.
+    let header = database.pager().header();
+    let in_use = pages - header.freelist_count as u64;
+    assert!(
+        in_use * 10 < pages,
+        "{} of {} pages still in use",
+        in_use,
+        pages
+    );
+    assert_eq!(query(&database, "SELECT count(*) FROM t"), ["80"]);
+    database.execute("DELETE FROM t").unwrap();
+    assert_intact(&database, &path);
+    assert_eq!(database.pager().header().freelist_count as u64, pages - 3);
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use codecrafters_sqlite::execute::pragma;
+use codecrafters_sqlite::pager::{JournalMode, Pager};
+use codecrafters_sqlite::syntax;
+use codecrafters_sqlite::syntax::statement::Statement;
+use common::{column, execute, sample_copy, sqlite3, writer};
+use std::path::{Path, PathBuf};
+
+/// The rows a PRAGMA gives, each as its values in their debug form.
+fn run_pragma(pager: &Pager, sql: &str) -> Vec<String> {
+    let rows = match syntax::parse(sql).unwrap() {
+        Statement::Pragma(stmt) => pragma(pager, &stmt).unwrap(),
+        other => panic!("{} is not a PRAGMA: {:?}", sql, other),
+    };
+    rows.iter().map(|row| format!("{:?}", row)).collect()
+}
+
+fn wal_len(path: &Path) -> Option<u64> {
+    std::fs::metadata(format!("{}-wal", path.display()))
+        .ok()
+        .map(|metadata| metadata.len())
+}
+
+#[test]
+fn wal_commits_and_checkpoints_are_shared_with_sqlite() {
+    let path = sample_copy("wal");
+    let pager = writer(&path);
+    let mode = run_pragma(&pager, "PRAGMA journal_mode = WAL");
+    assert_eq!(mode, [r#"[Text("wal")]"#]);
+    assert_eq!(pager.journal_mode(), JournalMode::Wal);
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    execute(&pager, "INSERT INTO apples(name) VALUES ('ours')").unwrap();
+    execute(&pager, "UPDATE apples SET name = 'FUJI' WHERE id = 2").unwrap();
+    // Commits go to the log, not the database file.
+    assert!(wal_len(&path).is_some_and(|len| len > 0));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+
+    let Some(rows) = sqlite3(
+        &path,
+        "SELECT group_concat(name, ',') FROM apples WHERE id > 1;
+         INSERT INTO apples(name) VALUES ('theirs');",
+    ) else {
+        return;
+    };
+    assert_eq!(rows, "FUJI,Honeycrisp,Golden Delicious,ours");
+    // Their commit is seen by our next statement.
+    execute(&pager, "INSERT INTO apples(name) VALUES ('more')").unwrap();
+    execute(&pager, "DELETE FROM apples WHERE name = 'ours'").unwrap();
+    assert_eq!(
+        column(&pager, "apples", 1)[3..],
+        ["Golden Delicious", "theirs", "more"]
+    );
+
+    // A TRUNCATE checkpoint copies everything into the database and empties the log.
+    let checkpoint = run_pragma(&pager, "PRAGMA wal_checkpoint(TRUNCATE)");
+    assert!(checkpoint[0].starts_with("[I64(0), "), "{:?}", checkpoint);
+    assert_eq!(wal_len(&path), Some(0));
+    assert_eq!(
+        sqlite3(
+            &path,
+            "PRAGMA journal_mode; PRAGMA integrity_check; SELECT name FROM apples WHERE id > 3"
+        )
+        .unwrap(),
+        "wal\nok\nGolden Delicious\ntheirs\nmore"
+    );
+
+    // Leaving WAL mode checkpoints what is left and removes the log.
+    execute(&pager, "INSERT INTO apples(name) VALUES ('last')").unwrap();
+    let mode = run_pragma(&pager, "PRAGMA journal_mode = DELETE");
+    assert_eq!(mode, [r#"[Text("delete")]"#]);
+    assert_eq!(wal_len(&path), None);
+    assert_eq!(
+        sqlite3(
+            &path,
+            "PRAGMA journal_mode; PRAGMA integrity_check; SELECT count(*) FROM apples"
+        )
+        .unwrap(),
+        "delete\nok\n7"
+    );
+}
+
+#[test]
+fn wal_commits_leave_the_file_change_counter_alone() {
+    let path = sample_copy("wal-change-counter");
+    let pager = writer(&path);
+    run_pragma(&pager, "PRAGMA journal_mode = WAL");
+    let counter = pager.header().file_change_counter;
+    for name in ["one", "two", "three"] {
+        let sql = format!("INSERT INTO apples(name) VALUES ('{}')", name);
+        execute(&pager, &sql).unwrap();
+        assert_eq!(pager.header().file_change_counter, counter);
+        assert_eq!(pager.header().version_valid_for, counter);
+    }
+    // Not even once the commits are copied into the file.
+    run_pragma(&pager, "PRAGMA wal_checkpoint(TRUNCATE)");
+    let file = std::fs::read(&path).unwrap();
+    assert_eq!(file[24..28], counter.to_be_bytes());
+
+    // Back in rollback mode, every commit counts again.
+    run_pragma(&pager, "PRAGMA journal_mode = DELETE");
+    let counter = pager.header().file_change_counter;
+    execute(&pager, "INSERT INTO apples(name) VALUES ('four')").unwrap();
+    assert_eq!(pager.header().file_change_counter, counter.wrapping_add(1));
+}
+
+/// Written by sqlite3 and copied while its connection was still open, so that the log is
+/// never checkpointed: a table `t` with no rows in the database file, then three commits in
+/// the log. The first two add 'first' and 'second' in one frame each, and the third adds ten
+/// rows across frames 2 to 6.
+const WAL_FIXTURE: &str = "tests/fixtures/wal.db";
+const WAL_FRAME_SIZE: usize = 24 + 1024;
+
+/// A copy of the WAL fixture, with the byte at `offset` in the given frame of its log flipped.
+fn wal_fixture_copy(name: &str, flip: Option<(usize, usize)>) -> PathBuf {
+    let path = common::temp_path(name);
+    std::fs::copy(WAL_FIXTURE, &path).unwrap();
+    let mut log = std::fs::read(format!("{}-wal", WAL_FIXTURE)).unwrap();
+    if let Some((frame, offset)) = flip {
+        log[32 + frame * WAL_FRAME_SIZE + offset] ^= 1;
+    }
+    std::fs::write(format!("{}-wal", path.display()), log).unwrap();
+    path
+}
+
+#[test]
+fn uncheckpointed_frames_from_sqlite_are_read() {
+    let path = wal_fixture_copy("wal-fixture", None);
+    let pager = Pager::open(&path).unwrap();
+    assert_eq!(pager.journal_mode(), JournalMode::Wal);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 2048);
+    assert_eq!(pager.page_count().unwrap(), 5);
+    let values = column(&pager, "t", 1);
+    assert_eq!(values.len(), 12);
+    assert_eq!(values[..2], ["first", "second"]);
+    assert!(values[2..].iter().all(|v| v.starts_with("third")));
+}
+
+#[test]
+fn frames_with_a_bad_checksum_or_stale_salt_are_ignored() {
+    const SALT: usize = 8;
+    const PAGE: usize = 24 + 500;
+    // The log is only read up to the first bad frame, and a commit that does not finish
+    // before it counts for nothing.
+    let cases: [(&str, usize, usize, &[&str]); 5] = [
+        ("wal-checksum-first", 0, PAGE, &[]),
+        ("wal-checksum-mid-commit", 4, PAGE, &["first", "second"]),
+        ("wal-checksum-last", 6, PAGE, &["first", "second"]),
+        ("wal-salt", 1, SALT, &["first"]),
+        ("wal-salt-mid-commit", 3, SALT, &["first", "second"]),
+    ];
+    for (name, frame, offset, expected) in cases {
+        let path = wal_fixture_copy(name, Some((frame, offset)));
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(column(&pager, "t", 1), expected, "{}", name);
+        drop(pager);
+        // A fresh copy, so that sqlite3 rebuilds the wal-index rather than trusting ours.
+        let fresh = wal_fixture_copy(name, Some((frame, offset)));
+        if let Some(rows) = sqlite3(&fresh, "SELECT count(*) FROM t") {
+            assert_eq!(rows, expected.len().to_string(), "{}", name);
+        }
+    }
+}
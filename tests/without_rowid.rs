@@ -0,0 +1,78 @@
+mod common;
+
+use codecrafters_sqlite::integrity::integrity_check;
+use codecrafters_sqlite::page_io::{count_entries, key_lookup, Page, SqliteSchema, TableSchema};
+use codecrafters_sqlite::pager::Pager;
+use codecrafters_sqlite::syntax;
+use codecrafters_sqlite::syntax::statement::Statement;
+use codecrafters_sqlite::typecodes::SqlValue;
+use common::query_pager;
+
+/// Written by sqlite3 with 512-byte pages, so that the table's b-tree has interior pages:
+///
+/// ```sql
+/// CREATE TABLE words(word TEXT PRIMARY KEY, n INT, note TEXT) WITHOUT ROWID;
+/// WITH RECURSIVE c(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM c WHERE i < 599)
+/// INSERT INTO words SELECT printf('w%04d', i * 7919 % 1000), i, 'note ' || i FROM c;
+/// ```
+fn fixture() -> Pager {
+    Pager::from_bytes(std::fs::read("tests/fixtures/without_rowid.db").unwrap()).unwrap()
+}
+
+/// The rows of the fixture in key order, as `word|n|note`.
+fn expected_rows() -> Vec<String> {
+    let mut rows: Vec<(String, i64)> = (0..600)
+        .map(|i| (format!("w{:04}", i * 7919 % 1000), i))
+        .collect();
+    rows.sort();
+    rows.into_iter()
+        .map(|(word, i)| format!("{}|{}|note {}", word, i, i))
+        .collect()
+}
+
+#[test]
+fn without_rowid_tables_scan_in_key_order() {
+    let pager = fixture();
+    assert_eq!(pager.page_size(), 512);
+    let schema = SqliteSchema::from_pager(&pager).unwrap();
+    let root = schema.find_table("words").unwrap().rootpage;
+    let root_page = Page::from_pager(&pager, root).unwrap();
+    assert_eq!(
+        root_page.header.page_type, 0x02,
+        "the root is an interior page"
+    );
+
+    assert_eq!(query_pager(&pager, "SELECT * FROM words"), expected_rows());
+    assert_eq!(count_entries(&pager, root).unwrap(), 600);
+    assert_eq!(query_pager(&pager, "SELECT count(*) FROM words"), ["600"]);
+    assert!(integrity_check(&pager).unwrap().is_empty());
+}
+
+#[test]
+fn without_rowid_rows_are_found_by_primary_key() {
+    let pager = fixture();
+    let schema = SqliteSchema::from_pager(&pager).unwrap();
+    let element = schema.find_table("words").unwrap();
+    let Statement::CreateTable(ast) = syntax::parse(&element.sql).unwrap() else {
+        unreachable!();
+    };
+    let table = TableSchema::from_ast(&ast).unwrap();
+
+    let rows = expected_rows();
+    // The first and last keys, and one from every page in between.
+    for row in rows.iter().step_by(7).chain(rows.last()) {
+        let word = row.split('|').next().unwrap();
+        let sql = format!("SELECT * FROM words WHERE word = '{}'", word);
+        assert_eq!(query_pager(&pager, &sql), [row.as_str()]);
+
+        let key = [SqlValue::Text(word.to_string())];
+        let found = key_lookup(&pager, element.rootpage, &key, &table.key_order()).unwrap();
+        assert_eq!(found.len(), 1, "{}", word);
+        let found = table.row_from_record(found.into_iter().next().unwrap());
+        assert_eq!(found.values[0].to_string(), word);
+    }
+    for word in ["w", "w0001", "w0999x", "x"] {
+        let sql = format!("SELECT * FROM words WHERE word = '{}'", word);
+        assert!(query_pager(&pager, &sql).is_empty(), "{}", word);
+    }
+}